@@ -0,0 +1,363 @@
+//! Context switch and kernel service latency benchmarks.
+//!
+//! [`run`] starts an application whose `runner` thread measures, one after the other,
+//!
+//! - `clock_overhead`: two reads of the clock
+//! - `thread_resume_switch`: resuming a suspended higher priority thread until it runs
+//! - `semaphore_ping_pong`: put to a higher priority thread and get its answer
+//! - `queue_round_trip`: send to a higher priority thread and receive its answer
+//! - `mutex_inherit_handoff`: releasing a mutex with priority inheritance that a higher
+//!   priority thread waits for, until that thread owns it
+//! - `byte_pool_allocate` and `byte_pool_release`, 64 bytes
+//! - `block_pool_allocate` and `block_pool_release`, 64 byte blocks
+//! - `allocator_alloc` and `allocator_dealloc`, 64 bytes from a `ThreadXAllocator`
+//! - `tlsf_alloc` and `tlsf_dealloc`, the same from a `TlsfPool`
+//! - `allocator_alloc_fragmented` and `tlsf_alloc_fragmented`, 64 bytes from a pool
+//!   whose free memory is split into many holes that are too small. The byte pool
+//!   searches them all, TLSF does not.
+//!
+//! The times are differences of a free running 32 bit counter read by [`Config::clock`]:
+//! the DWT cycle counter on Cortex-M, a monotonic clock on the Linux port. Every benchmark
+//! ends with a [`Report`], which formats as one line of JSON:
+//!
+//! ```text
+//! {"name":"queue_round_trip","unit":"cycles","iterations":1000,"min":412,"mean":418,"max":530}
+//! ```
+//!
+//! `cargo xtask bench compare` compares two files of these lines to find regressions.
+
+#![no_std]
+
+use core::alloc::{GlobalAlloc, Layout};
+use core::fmt;
+use core::ptr::{addr_of, addr_of_mut};
+use core::sync::atomic::{AtomicPtr, AtomicU32, Ordering};
+
+use threadx_rs::allocator::ThreadXAllocator;
+use threadx_rs::mutex::Mutex;
+use threadx_rs::pool::{BlockPool, BlockPoolHandle, BytePool, BytePoolHandle};
+use threadx_rs::queue::{Queue, QueueReceiver, QueueSender};
+use threadx_rs::semaphore::{Semaphore, SemaphoreUser, SemaphoreUserHandle};
+use threadx_rs::static_cell::{TxOnceCell, TxStatic};
+use threadx_rs::tlsf::TlsfPool;
+use threadx_rs::WaitOption;
+use threadx_sys::{TX_SUCCESS, TX_THREAD, _tx_thread_identify, _tx_thread_resume, _tx_thread_suspend};
+
+/// Size of the allocations of the pool and allocator benchmarks
+const ALLOCATION: usize = 64;
+/// Allocations that split the free memory of the fragmented allocator benchmarks
+const FRAGMENTS: usize = 32;
+/// Size of those allocations, too small for an `ALLOCATION`
+const FRAGMENT: usize = 16;
+
+/// What the benchmarks need from the platform
+pub struct Config {
+    /// Low level initialization, e.g. `Board::low_level_init`
+    pub init: fn(ticks_per_second: u32),
+    /// Reads a free running counter that wraps at `u32::MAX`
+    pub clock: fn() -> u32,
+    /// Unit of the counter, e.g. "cycles" or "ns"
+    pub unit: &'static str,
+    /// Measurements per benchmark
+    pub iterations: u32,
+    /// Called with the result of each benchmark
+    pub report: fn(&Report),
+    /// Called after the last benchmark, e.g. to exit
+    pub done: fn(),
+}
+
+/// The result of one benchmark
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Report {
+    pub name: &'static str,
+    pub unit: &'static str,
+    pub iterations: u32,
+    pub min: u32,
+    pub mean: u32,
+    pub max: u32,
+}
+
+impl fmt::Display for Report {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let Report { name, unit, iterations, min, mean, max } = self;
+        write!(
+            f,
+            r#"{{"name":"{name}","unit":"{unit}","iterations":{iterations},"min":{min},"mean":{mean},"max":{max}}}"#
+        )
+    }
+}
+
+struct Stats {
+    min: u32,
+    max: u32,
+    total: u64,
+    count: u32,
+}
+
+impl Stats {
+    const fn new() -> Self {
+        Stats { min: u32::MAX, max: 0, total: 0, count: 0 }
+    }
+
+    fn add(&mut self, sample: u32) {
+        self.min = self.min.min(sample);
+        self.max = self.max.max(sample);
+        self.total += sample as u64;
+        self.count += 1;
+    }
+
+    fn report(&self, name: &'static str) {
+        let config = config();
+        let mean = self.total.checked_div(self.count as u64).unwrap_or(0) as u32;
+        let min = if self.count == 0 { 0 } else { self.min };
+        (config.report)(&Report { name, unit: config.unit, iterations: self.count, min, mean, max: self.max });
+    }
+}
+
+static CONFIG: TxOnceCell<&'static Config> = TxOnceCell::new();
+
+fn config() -> &'static Config {
+    CONFIG.get().expect("threadx-bench: not started through run")
+}
+
+fn now() -> u32 {
+    (config().clock)()
+}
+
+/// Measure `iterations` calls of `f`, which returns the time of one
+fn measure(name: &'static str, mut f: impl FnMut() -> u32) {
+    let mut stats = Stats::new();
+    for _ in 0..config().iterations {
+        stats.add(f());
+    }
+    stats.report(name);
+}
+
+/// Time of `f`
+fn time(f: impl FnOnce()) -> u32 {
+    let start = now();
+    f();
+    now().wrapping_sub(start)
+}
+
+fn suspend_self() {
+    let status = unsafe { _tx_thread_suspend(_tx_thread_identify()) };
+    assert_eq!(status, TX_SUCCESS, "threadx-bench: suspend failed");
+}
+
+fn resume(thread: &AtomicPtr<TX_THREAD>) {
+    let status = unsafe { _tx_thread_resume(thread.load(Ordering::Acquire)) };
+    assert_eq!(status, TX_SUCCESS, "threadx-bench: resume failed");
+}
+
+/// The switcher and contender threads, set before they suspend for the first time
+static SWITCHER: AtomicPtr<TX_THREAD> = AtomicPtr::new(core::ptr::null_mut());
+static CONTENDER: AtomicPtr<TX_THREAD> = AtomicPtr::new(core::ptr::null_mut());
+/// When the switcher ran or the contender got the mutex
+static REACHED_AT: AtomicU32 = AtomicU32::new(0);
+
+static ALLOCATOR_MEMORY: TxStatic<[u8; 2048]> = TxStatic::new([0; 2048]);
+static mut ALLOCATOR: ThreadXAllocator = ThreadXAllocator::new();
+static TLSF_MEMORY: TxStatic<[u8; 2048]> = TxStatic::new([0; 2048]);
+static mut TLSF: TlsfPool = TlsfPool::new();
+
+/// Allocate and release `ALLOCATION` bytes, timing both
+fn measure_allocator(allocator: &dyn GlobalAlloc, alloc_name: &'static str, dealloc_name: &'static str) {
+    let layout = Layout::from_size_align(ALLOCATION, 8).unwrap();
+    let (mut allocate, mut release) = (Stats::new(), Stats::new());
+    for _ in 0..config().iterations {
+        let start = now();
+        let ptr = unsafe { allocator.alloc(layout) };
+        let allocated = now();
+        assert!(!ptr.is_null(), "threadx-bench: allocator out of memory");
+        unsafe { allocator.dealloc(ptr, layout) };
+        allocate.add(allocated.wrapping_sub(start));
+        release.add(now().wrapping_sub(allocated));
+    }
+    allocate.report(alloc_name);
+    release.report(dealloc_name);
+}
+
+/// Like `measure_allocator`, with every other of `FRAGMENTS` small allocations released
+/// first so that the free memory in front of the large free block is split into holes
+fn measure_fragmented(allocator: &dyn GlobalAlloc, name: &'static str) {
+    let small = Layout::from_size_align(FRAGMENT, 8).unwrap();
+    let mut fragments = [core::ptr::null_mut(); FRAGMENTS];
+    for fragment in fragments.iter_mut() {
+        *fragment = unsafe { allocator.alloc(small) };
+        assert!(!fragment.is_null(), "threadx-bench: allocator out of memory");
+    }
+    for fragment in fragments.iter().step_by(2) {
+        unsafe { allocator.dealloc(*fragment, small) };
+    }
+    let layout = Layout::from_size_align(ALLOCATION, 8).unwrap();
+    measure(name, || {
+        let start = now();
+        let ptr = unsafe { allocator.alloc(layout) };
+        let elapsed = now().wrapping_sub(start);
+        assert!(!ptr.is_null(), "threadx-bench: allocator out of memory");
+        unsafe { allocator.dealloc(ptr, layout) };
+        elapsed
+    });
+    for fragment in fragments.iter().skip(1).step_by(2) {
+        unsafe { allocator.dealloc(*fragment, small) };
+    }
+}
+
+/// Run the benchmarks. Initializes ThreadX and, on a target, does not return.
+pub fn run(config: &'static Config) {
+    if CONFIG.set(config).is_err() {
+        panic!("threadx-bench: run called twice");
+    }
+    app::start();
+}
+
+// The helper threads have a higher priority than the runner, so they run as soon as the
+// runner makes them ready and are waiting again when it continues.
+#[threadx_rs::app]
+mod app {
+    use super::*;
+
+    #[init]
+    fn init(ticks_per_second: u32) {
+        (config().init)(ticks_per_second);
+    }
+
+    #[semaphore(initial = 0)]
+    static PING: Semaphore;
+
+    #[semaphore(initial = 0)]
+    static PONG: Semaphore;
+
+    #[queue(capacity = 1)]
+    static REQUEST: Queue<u32>;
+
+    #[queue(capacity = 1)]
+    static RESPONSE: Queue<u32>;
+
+    #[mutex(inherit = true)]
+    static LOCK: Mutex<()> = Mutex::new(());
+
+    #[byte_pool(size = 256)]
+    static BYTES: BytePool;
+
+    #[block_pool(block_size = 64, blocks = 2)]
+    static BLOCKS: BlockPool;
+
+    #[thread(priority = 5, stack = 1024)]
+    fn switcher() {
+        SWITCHER.store(unsafe { _tx_thread_identify() }, Ordering::Release);
+        loop {
+            suspend_self();
+            REACHED_AT.store(now(), Ordering::Release);
+        }
+    }
+
+    #[thread(priority = 5, stack = 1024)]
+    fn semaphore_echo(ping: SemaphoreUserHandle, pong: SemaphoreUserHandle) {
+        loop {
+            ping.get(WaitOption::WaitForever).unwrap();
+            pong.put().unwrap();
+        }
+    }
+
+    #[thread(priority = 5, stack = 1024)]
+    fn queue_echo(request: QueueReceiver<u32>, response: QueueSender<u32>) {
+        loop {
+            let message = request.receive(WaitOption::WaitForever).unwrap();
+            response.send(message, WaitOption::WaitForever).unwrap();
+        }
+    }
+
+    #[thread(priority = 5, stack = 1024)]
+    fn contender(lock: &'static Mutex<()>) {
+        CONTENDER.store(unsafe { _tx_thread_identify() }, Ordering::Release);
+        loop {
+            suspend_self();
+            let _guard = lock.lock(WaitOption::WaitForever).unwrap();
+            REACHED_AT.store(now(), Ordering::Release);
+        }
+    }
+
+    #[thread(priority = 10, stack = 2048)]
+    fn runner(
+        ping: SemaphoreUserHandle,
+        pong: SemaphoreUserHandle,
+        request: QueueSender<u32>,
+        response: QueueReceiver<u32>,
+        lock: &'static Mutex<()>,
+        bytes: &'static BytePoolHandle,
+        blocks: &'static BlockPoolHandle,
+    ) {
+        measure("clock_overhead", || time(|| {}));
+
+        measure("thread_resume_switch", || {
+            let start = now();
+            resume(&SWITCHER);
+            REACHED_AT.load(Ordering::Acquire).wrapping_sub(start)
+        });
+
+        measure("semaphore_ping_pong", || {
+            time(|| {
+                ping.put().unwrap();
+                pong.get(WaitOption::WaitForever).unwrap();
+            })
+        });
+
+        measure("queue_round_trip", || {
+            time(|| {
+                request.send(1, WaitOption::WaitForever).unwrap();
+                response.receive(WaitOption::WaitForever).unwrap();
+            })
+        });
+
+        measure("mutex_inherit_handoff", || {
+            let guard = lock.lock(WaitOption::WaitForever).unwrap();
+            // The contender blocks on the mutex and the runner inherits its priority
+            resume(&CONTENDER);
+            let start = now();
+            drop(guard);
+            REACHED_AT.load(Ordering::Acquire).wrapping_sub(start)
+        });
+
+        let (mut allocate, mut release) = (Stats::new(), Stats::new());
+        for _ in 0..config().iterations {
+            let start = now();
+            let memory = bytes.allocate(ALLOCATION, false).unwrap().consume();
+            let allocated = now();
+            bytes.release(memory).unwrap();
+            allocate.add(allocated.wrapping_sub(start));
+            release.add(now().wrapping_sub(allocated));
+        }
+        allocate.report("byte_pool_allocate");
+        release.report("byte_pool_release");
+
+        let (mut allocate, mut release) = (Stats::new(), Stats::new());
+        for _ in 0..config().iterations {
+            let start = now();
+            let block = blocks.allocate(false).unwrap();
+            let allocated = now();
+            blocks.release(block).unwrap();
+            allocate.add(allocated.wrapping_sub(start));
+            release.add(now().wrapping_sub(allocated));
+        }
+        allocate.report("block_pool_allocate");
+        release.report("block_pool_release");
+
+        // Not global allocators, so only the benchmarks use them
+        let allocator = unsafe {
+            (*addr_of_mut!(ALLOCATOR)).initialize(ALLOCATOR_MEMORY.take().unwrap()).unwrap();
+            &*addr_of!(ALLOCATOR)
+        };
+        let tlsf = unsafe {
+            (*addr_of_mut!(TLSF)).initialize(c"tlsf", TLSF_MEMORY.take().unwrap()).unwrap();
+            &*addr_of!(TLSF)
+        };
+        measure_allocator(allocator, "allocator_alloc", "allocator_dealloc");
+        measure_allocator(tlsf, "tlsf_alloc", "tlsf_dealloc");
+        measure_fragmented(allocator, "allocator_alloc_fragmented");
+        measure_fragmented(tlsf, "tlsf_alloc_fragmented");
+
+        (config().done)();
+    }
+}
@@ -0,0 +1,58 @@
+// Runs the benchmarks on threadx-sim with a clock that advances by one on every read, so
+// every measurement takes exactly the reads in between. The numbers say nothing about
+// ThreadX, this checks that every benchmark runs to the end and reports.
+
+use std::sync::atomic::{AtomicU32, Ordering};
+use std::sync::Mutex;
+
+use threadx_bench::{Config, Report};
+use threadx_rs::sim::Harness;
+
+static CLOCK: AtomicU32 = AtomicU32::new(0);
+static REPORTS: Mutex<Vec<String>> = Mutex::new(Vec::new());
+static DONE: AtomicU32 = AtomicU32::new(0);
+
+static CONFIG: Config = Config {
+    init: |_| {},
+    clock: || CLOCK.fetch_add(1, Ordering::Relaxed),
+    unit: "reads",
+    iterations: 20,
+    report: |report: &Report| REPORTS.lock().unwrap().push(report.to_string()),
+    done: || {
+        DONE.fetch_add(1, Ordering::Relaxed);
+    },
+};
+
+#[test]
+fn every_benchmark_reports() {
+    Harness::run(|| threadx_bench::run(&CONFIG));
+    assert_eq!(DONE.load(Ordering::Relaxed), 1);
+    let reports = REPORTS.lock().unwrap();
+    let names: Vec<&str> = reports.iter().map(|report| report.split('"').nth(3).unwrap()).collect();
+    assert_eq!(
+        names,
+        [
+            "clock_overhead",
+            "thread_resume_switch",
+            "semaphore_ping_pong",
+            "queue_round_trip",
+            "mutex_inherit_handoff",
+            "byte_pool_allocate",
+            "byte_pool_release",
+            "block_pool_allocate",
+            "block_pool_release",
+            "allocator_alloc",
+            "allocator_dealloc",
+            "tlsf_alloc",
+            "tlsf_dealloc",
+            "allocator_alloc_fragmented",
+            "tlsf_alloc_fragmented",
+        ]
+    );
+    // Each measurement is two reads, by the runner or by the runner and the helper thread,
+    // with nothing reading in between
+    assert_eq!(reports[0], r#"{"name":"clock_overhead","unit":"reads","iterations":20,"min":1,"mean":1,"max":1}"#);
+    for report in reports.iter() {
+        assert!(report.ends_with(r#""iterations":20,"min":1,"mean":1,"max":1}"#), "{report}");
+    }
+}
@@ -0,0 +1,3 @@
+fn main() {
+    threadx_build::generate("threadx.toml").unwrap();
+}
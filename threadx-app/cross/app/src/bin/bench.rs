@@ -0,0 +1,31 @@
+#![no_main]
+#![no_std]
+
+// Kernel benchmarks, see threadx-bench. The times are cycles of the DWT cycle counter,
+// which the board enables in low_level_init. Each result is a line of JSON:
+//
+//     cargo run --release --bin bench
+//
+// QEMU does not model the cycle counter, so there the benchmarks only show that they run.
+
+use board::{Board, LowLevelInit};
+use cortex_m::peripheral::DWT;
+use defmt::{println, Display2Format};
+use threadx_bench::{Config, Report};
+
+static CONFIG: Config = Config {
+    init: |ticks_per_second| {
+        Board::low_level_init(ticks_per_second).unwrap();
+    },
+    clock: DWT::cycle_count,
+    unit: "cycles",
+    iterations: 1000,
+    report: |report: &Report| println!("{}", Display2Format(report)),
+    done: || threadx_app::exit(),
+};
+
+#[cortex_m_rt::entry]
+fn main() -> ! {
+    threadx_bench::run(&CONFIG);
+    threadx_app::exit()
+}
@@ -1,150 +1,114 @@
 #![no_main]
 #![no_std]
 
-use board::{BoardStm32f103c8BluePill, LowLevelInit};
+use core::ptr::addr_of_mut;
+
+use board::{Board, LowLevelInit};
 
 use defmt::println;
 use threadx_rs::WaitOption;
 use threadx_rs::allocator::ThreadXAllocator;
 use threadx_rs::mutex::Mutex;
-use threadx_rs::pool::{BlockPool, BytePool, BytePoolHandle};
-
-use threadx_rs::queue::Queue;
-use threadx_rs::semaphore::{Semaphore, SemaphoreOwner, SemaphoreUser};
-use threadx_rs::thread::{Thread, sleep};
-use threadx_rs::tx_str;
+use threadx_rs::pool::{BlockPool, BlockPoolHandle, BytePool, BytePoolHandle};
+use threadx_rs::queue::{Queue, QueueReceiver, QueueSender};
+use threadx_rs::semaphore::{Semaphore, SemaphoreUser, SemaphoreUserHandle};
+use threadx_rs::thread::sleep;
 
 extern crate alloc;
 use alloc::boxed::Box;
 
-#[cortex_m_rt::entry]
-fn main() -> ! {
-    defmt::println!("Hello, world!");
-
-    let tx = threadx_rs::Builder::new(
-        |ticks_per_second| {
-            BoardStm32f103c8BluePill::low_level_init(ticks_per_second).unwrap();
-            static mut HEAP: [u8; 4096*3] = [0u8; 4096*3];
-            unsafe { HEAP.as_mut_slice() }
-        },
-        // Start of Application definition
-        |mem_start| {
-            defmt::println!("Define application. Memory starts at: {} with length:{}", mem_start.as_ptr(), mem_start.len());
-            static mut BP1: BytePool = BytePool::new();
-            static mut BP2:BytePool = BytePool::new();
-         
-            let (bp_mem , next)= mem_start.split_at_mut(1024);
-            
-            let mut bp = unsafe{BP1.initialize(tx_str!("pool1"), bp_mem).unwrap()};
-            let task_mem = bp.allocate(256, true).unwrap();
-            let task2_mem = bp.allocate(256, true).unwrap();
-
-            
-            let (global_alloc_mem, next) = next.split_at_mut(1024);
-            //let  heap_bytepool : BytePoolHandle = unsafe{BP1.initialize(tx_str!("pool2"), bp1_mem).unwrap()};
-            #[global_allocator]
-            static mut GLOBAL: ThreadXAllocator = ThreadXAllocator::new();
-            unsafe{GLOBAL.initialize(global_alloc_mem).unwrap()};
-
+#[global_allocator]
+static mut GLOBAL: ThreadXAllocator = ThreadXAllocator::new();
+
+// The heap is larger than the objects and threads need, the rest goes to the global
+// allocator in `define`
+#[threadx_rs::app(heap = 4096 * 3)]
+mod app {
+    use super::*;
+
+    #[init]
+    fn init(ticks_per_second: u32) {
+        Board::low_level_init(ticks_per_second).unwrap();
+    }
+
+    #[byte_pool(size = 512)]
+    static POOL: BytePool;
+
+    #[block_pool(block_size = 16, blocks = 4)]
+    static BLOCKS: BlockPool;
+
+    #[mutex(inherit = true)]
+    static COUNTER: Mutex<i32> = Mutex::new(0);
+
+    #[queue(capacity = 16)]
+    static QUEUE: Queue<u32>;
+
+    #[semaphore(initial = 0)]
+    static SEM: Semaphore;
+
+    #[define]
+    fn define(memory: &'static mut [u8]) {
+        println!("Global allocator gets {} bytes", memory.len());
+        unsafe { (*addr_of_mut!(GLOBAL)).initialize(memory).unwrap() };
+        let dyn_a = Box::new([10u8; 10]);
+        println!("Boxed {} bytes", dyn_a.len());
+    }
+
+    #[thread(priority = 1, stack = 512)]
+    fn thread1(sem: SemaphoreUserHandle, counter: &'static Mutex<i32>, queue: QueueSender<u32>, pool: &'static BytePoolHandle) {
+        let memory = pool.allocate(256, true).unwrap().consume();
+        println!("Allocated {} bytes from the byte pool", memory.len());
+        pool.release(memory).unwrap();
+
+        let mut arg: u32 = 0;
+        let mut local_counter = 0;
+        loop {
+            if sem.get(WaitOption::WaitForever).is_ok() {
+                println!("Semaphore acquired");
+            }
+            arg += 1;
+            println!("Thread:{}", arg);
+            {
+                let mut v = counter.lock(WaitOption::WaitForever).unwrap();
+                *v += 1;
+                println!("Value is now:{}", *v);
+            }
+            local_counter += 1;
+            queue.send(local_counter, WaitOption::WaitForever).unwrap();
+            sleep(core::time::Duration::from_millis(500)).unwrap();
+        }
+    }
+
+    #[thread(priority = 1, stack = 512)]
+    fn thread2(sem: SemaphoreUserHandle, counter: &'static Mutex<i32>, queue: QueueReceiver<u32>, blocks: &'static BlockPoolHandle) {
+        let block = blocks.allocate(true).unwrap();
+        println!("Allocated block with length {}", block.len());
+        blocks.release(block).unwrap();
+
+        let arg: u32 = 1;
+        loop {
+            sem.put().unwrap();
+            println!("Thread:{:#08x}", arg);
             {
-                let dyn_a = Box::new([10u8;10]);
+                let mut v = counter.lock(WaitOption::WaitForever).unwrap();
+                *v -= 1;
+                println!("Value is now:{}", *v);
             }
+            if let Ok(rx) = queue.receive(WaitOption::NoWait) {
+                println!("Received:{}", rx);
+            } else {
+                println!("No message");
+            }
+            sleep(core::time::Duration::from_millis(200)).unwrap();
+        }
+    }
+}
 
+#[cortex_m_rt::entry]
+fn main() -> ! {
+    defmt::println!("Hello, world!");
 
-            let (bp2_mem, next) = next.split_at_mut(1024);
-            let mut bp2 = unsafe{BP2.initialize(tx_str!("pool3"), bp2_mem).unwrap()};
-            let mem = bp2.allocate(512, true).unwrap();
-
-            static mut BLOCK_POOL: BlockPool = BlockPool::new();
-
-            let mut block_pool_handle = unsafe {
-                BLOCK_POOL.initialize(tx_str!("block_pool"), 16, next).unwrap()
-            };
-
-            let block1 = block_pool_handle.allocate(true).unwrap();
-
-            println!("Allocate block 1 with length {}", block1.len());
-
-            static mut thread : Thread = Thread::new();
-            
-            static mut MUTEX : Mutex<i32> = Mutex::new(0);
-            unsafe{MUTEX.initialize(tx_str!("test"), true).unwrap()};
-
-            static mut QUEUE : Queue<u32> = Queue::new();
-            let (sender, receiver) = unsafe{QUEUE.initialize(tx_str!("queue"), mem).unwrap()};
-            
-            static mut SEM : Semaphore = Semaphore::new();
-            let sem_owner = unsafe {
-                SEM.initialize(tx_str!("sem"), 0).unwrap()
-            };
-
-            
-            let thread_func = move || {
-
-                let mut arg : u32 = 0;                
-                let mut local_counter = 0;
-                
-                let sem_user = sem_owner.get_semaphore_user();
-                
-                println!("Thread:{}", arg);
-                loop {
-                    if sem_user.get(WaitOption::WaitForever).is_ok() {
-                        println!("Semaphore acquired");
-                    }
-                    arg = arg + 1;
-                    println!("Thread:{}", arg);
-                    {
-                        let mut v = unsafe{MUTEX.lock(WaitOption::WaitForever).unwrap()};
-                        *v = *v + 1;
-                        println!("Value is now:{}", *v);
-                    }
-                    local_counter = local_counter + 1;
-                    sender.send(local_counter , WaitOption::WaitForever).unwrap();
-                    sleep(core::time::Duration::from_millis(500)).unwrap();
-                }
-            };
-
-            
-
-            let th_handle = unsafe {
-                thread.initialize(tx_str!("thread1"), thread_func, task_mem, 1, 1, 0, true).unwrap()
-            };
-
-            let thread2_fn = move || {
-                let arg : u32 = 1;    
-                let sem_user = sem_owner.get_semaphore_user();
-                loop {
-                    sem_user.put().unwrap();
-                    println!("Thread:{:#08x}", arg);
-                    {
-                        let mut v = unsafe{MUTEX.lock(WaitOption::WaitForever).unwrap()};
-                        *v = *v - 1;
-                        println!("Value is now:{}", *v);
-                    }
-                    if let Ok(rx) = receiver.receive(WaitOption::NoWait) {
-                        println!("Received:{}", rx);
-                    } else {
-                        println!("No message");
-                    }
-                    
-                    sleep(core::time::Duration::from_millis(200)).unwrap();
-                    
-                }
-            };
-            static mut thread2 : Thread = Thread::new();
-            let th2_handle = unsafe {
-                thread2.initialize(tx_str!("thread1"), thread2_fn, task2_mem, 1, 1, 0, true).unwrap()
-            };
-
-        },
-    );
-
-    tx.initialize();
+    app::start();
     println!("Exit");
     threadx_app::exit()
 }
-
-
-
-
-
@@ -0,0 +1,105 @@
+#![no_main]
+#![no_std]
+
+// The target side of threadx-rpc over UART0 of the qemu board, for testing the host side
+// end to end. `cargo xtask test host-target qemu` runs it with UART0 on a TCP socket:
+//
+//     qemu-system-arm ... -serial tcp::4000,server=on,wait=off -kernel rpc
+//     host-target-tests tcp:localhost:4000
+
+use core::convert::Infallible;
+use core::time::Duration;
+
+use board::{Board, LowLevelInit, Uart0Rx, Uart0Tx};
+
+use defmt::println;
+use threadx_rpc::target::{dispatch, receive, Read, Write};
+use threadx_rpc::{command, Frame, Status, MAX_PAYLOAD};
+use threadx_rs::queue::{Queue, QueueReceiver, QueueSender};
+use threadx_rs::thread::sleep;
+
+/// Answered with the payload reversed, see `host_target_tests::REVERSE`
+const REVERSE: u8 = command::APPLICATION;
+
+fn handle(command: u8, request: &[u8], response: &mut [u8; MAX_PAYLOAD]) -> Result<usize, Status> {
+    match command {
+        REVERSE if request.is_empty() => Err(Status::BadRequest),
+        REVERSE => {
+            for (to, from) in response.iter_mut().zip(request.iter().rev()) {
+                *to = *from;
+            }
+            Ok(request.len())
+        }
+        _ => Err(Status::UnknownCommand),
+    }
+}
+
+/// Polls the receiver once a tick while it is empty. QEMU holds the next bytes back until
+/// the receiver has been read, so none are lost.
+struct Link<T>(T);
+
+impl Read for Link<Uart0Rx> {
+    type Error = Infallible;
+
+    fn read(&mut self, buffer: &mut [u8]) -> Result<usize, Infallible> {
+        loop {
+            let mut len = 0;
+            while len < buffer.len() {
+                let Some(byte) = self.0.read_byte() else {
+                    break;
+                };
+                buffer[len] = byte;
+                len += 1;
+            }
+            if len > 0 {
+                return Ok(len);
+            }
+            sleep(Duration::from_millis(10)).unwrap();
+        }
+    }
+}
+
+impl Write for Link<Uart0Tx> {
+    type Error = Infallible;
+
+    fn write_all(&mut self, bytes: &[u8]) -> Result<(), Infallible> {
+        for &byte in bytes {
+            self.0.write_byte(byte);
+        }
+        Ok(())
+    }
+}
+
+#[threadx_rs::app]
+mod app {
+    use super::*;
+
+    #[init]
+    fn init(ticks_per_second: u32) {
+        Board::low_level_init(ticks_per_second).unwrap();
+    }
+
+    #[queue(capacity = 4)]
+    static REQUESTS: Queue<Frame>;
+
+    #[thread(priority = 2, stack = 2048)]
+    fn receiver(requests: QueueSender<Frame>) {
+        let mut link = Link(Uart0Rx::take().unwrap());
+        receive(&mut link, &requests).unwrap();
+    }
+
+    #[thread(priority = 1, stack = 2048)]
+    fn dispatcher(requests: QueueReceiver<Frame>) {
+        let mut link = Link(Uart0Tx::take().unwrap());
+        dispatch(&mut link, &requests, handle).unwrap();
+    }
+}
+
+#[cortex_m_rt::entry]
+fn main() -> ! {
+    println!("threadx-rpc on UART0");
+
+    app::start();
+    println!("Exit");
+    threadx_app::exit()
+}
@@ -0,0 +1,36 @@
+#![no_main]
+#![no_std]
+
+// The threads, timer and event flags of this example are described in threadx.toml
+
+use board::{Board, LowLevelInit};
+
+use defmt::{debug, println};
+use threadx_rs::WaitOption;
+use threadx_rs::event_flags::GetOption;
+
+include!(concat!(env!("OUT_DIR"), "/threadx_system.rs"));
+
+fn board_init(ticks_per_second: u32) {
+    Board::low_level_init(ticks_per_second).unwrap();
+}
+
+fn heartbeat() {
+    system::events().publish(1).unwrap();
+}
+
+fn worker() {
+    loop {
+        let event = system::events().get(1, GetOption::WaitAllAndClear, WaitOption::WaitForever).unwrap();
+        debug!("Got Event 1 : {}", event);
+    }
+}
+
+#[cortex_m_rt::entry]
+fn main() -> ! {
+    println!("Hello, world!");
+
+    system::start();
+    println!("Exit");
+    threadx_app::exit()
+}
@@ -3,9 +3,13 @@
 
 use cortex_m_semihosting::debug;
 
+#[cfg(feature = "stm32f103")]
 use defmt_rtt as _; // global logger
+#[cfg(feature = "qemu")]
+use defmt_semihosting as _; // global logger, QEMU has no RTT
 
 // TODO(5) adjust HAL import
+#[cfg(feature = "stm32f103")]
 use stm32f1xx_hal as _; // memory layout
 
 use panic_probe as _;
@@ -0,0 +1,20 @@
+//! Puts the memory layout of the selected board on the linker search path as memory.x,
+//! where the link.x of cortex-m-rt includes it. Sets `armv6m` on Cortex-M0/M0+ like the
+//! cortex-m crate does, as they lack the configurable fault handlers.
+
+use std::env;
+use std::fs;
+use std::path::PathBuf;
+
+fn main() {
+    let layout = if env::var_os("CARGO_FEATURE_QEMU").is_some() { "memory/mps2-an385.x" } else { "memory/stm32f103c8.x" };
+    let out = PathBuf::from(env::var_os("OUT_DIR").unwrap());
+    fs::copy(layout, out.join("memory.x")).unwrap();
+    println!("cargo:rustc-link-search={}", out.display());
+    println!("cargo:rerun-if-changed=memory");
+
+    println!("cargo:rustc-check-cfg=cfg(armv6m)");
+    if env::var("TARGET").unwrap().starts_with("thumbv6m-") {
+        println!("cargo:rustc-cfg=armv6m");
+    }
+}
@@ -0,0 +1,8 @@
+/* memory.x - Linker script for the Arm MPS2 AN385 (Cortex-M3) as emulated by QEMU */
+MEMORY
+{
+  /* ZBT SSRAM1, QEMU loads the program there and the vector table is at 0 */
+  FLASH : ORIGIN = 0x00000000, LENGTH = 4M
+  /* ZBT SSRAM2 and SSRAM3 */
+  RAM : ORIGIN = 0x20000000, LENGTH = 4M
+}
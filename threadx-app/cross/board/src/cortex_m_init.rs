@@ -0,0 +1,79 @@
+// Low level initialization that is the same on every Cortex-M. A board configures its
+// clocks, then passes the core clock and the core peripherals to `CortexMLowLevelInit`.
+
+use core::ffi::c_void;
+use cortex_m::peripheral::scb::SystemHandler;
+use cortex_m::peripheral::syst::SystClkSource;
+use cortex_m::peripheral::{SCB, SYST};
+
+use crate::_stack_start;
+
+/// The SysTick reload value is 24 bits wide
+const SYST_RELOAD_MAX: u32 = 0x00FF_FFFF;
+
+/// Sets up the parts of a Cortex-M that ThreadX relies on:
+///
+/// - SysTick interrupts at the tick rate, counting the core clock
+/// - PendSV and SVCall at the lowest priority, so that the context switch never preempts
+///   an interrupt handler, and SysTick above them
+/// - `_tx_thread_system_stack_ptr` at the top of the stack that `main` runs on
+///
+/// ```ignore
+/// let clocks = /* board specific */;
+/// // cp: &mut cortex_m::Peripherals
+/// CortexMLowLevelInit::new(clocks.hclk().raw(), ticks_per_second).init(&mut cp.SYST, &mut cp.SCB)?;
+/// ```
+pub struct CortexMLowLevelInit {
+    core_clock_hz: u32,
+    ticks_per_second: u32,
+}
+
+impl CortexMLowLevelInit {
+    pub const fn new(core_clock_hz: u32, ticks_per_second: u32) -> Self {
+        CortexMLowLevelInit { core_clock_hz, ticks_per_second }
+    }
+
+    /// Fails if SysTick can not generate the tick rate from the core clock
+    pub fn init(&self, syst: &mut SYST, scb: &mut SCB) -> Result<(), ()> {
+        let reload = self.reload().ok_or(())?;
+        set_system_stack();
+
+        syst.disable_counter();
+        syst.set_clock_source(SystClkSource::Core);
+        syst.set_reload(reload);
+        syst.clear_current();
+        syst.enable_counter();
+        syst.enable_interrupt();
+
+        unsafe {
+            // Faults are not delayed by any interrupt
+            #[cfg(not(armv6m))]
+            {
+                scb.set_priority(SystemHandler::MemoryManagement, 0);
+                scb.set_priority(SystemHandler::BusFault, 0);
+                scb.set_priority(SystemHandler::UsageFault, 0);
+            }
+            scb.set_priority(SystemHandler::SVCall, 0xFF);
+            scb.set_priority(SystemHandler::PendSV, 0xFF);
+            scb.set_priority(SystemHandler::SysTick, 0x40);
+        }
+        defmt::println!("Low level init. Core clock: {} Hz Ticks per second: {}", self.core_clock_hz, self.ticks_per_second);
+        Ok(())
+    }
+
+    fn reload(&self) -> Option<u32> {
+        let cycles = self.core_clock_hz.checked_div(self.ticks_per_second)?;
+        let reload = cycles.checked_sub(1)?;
+        (reload > 0 && reload <= SYST_RELOAD_MAX).then_some(reload)
+    }
+}
+
+// cortex-m-rt puts the initial stack pointer at `_stack_start`. The stack of `main` becomes
+// the system stack of ThreadX, used by the interrupt handlers once the threads run.
+fn set_system_stack() {
+    unsafe {
+        let stack_start = &_stack_start as *const u32 as u32;
+        threadx_sys::_tx_thread_system_stack_ptr = stack_start as *mut c_void;
+        defmt::println!("Stack at: {=u32:#x}", stack_start);
+    }
+}
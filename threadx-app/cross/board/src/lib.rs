@@ -1,92 +1,55 @@
 #![no_std]
-use core::arch::asm;
-use core::ffi::c_void;
-use cortex_m::interrupt::{InterruptNumber, Nr};
-use cortex_m::peripheral::NVIC;
-use cortex_m::peripheral::syst::SystClkSource;
-use cortex_m_rt::{exception, heap_start};
-use stm32f1xx_hal::flash::FlashExt;
-use stm32f1xx_hal::pac;
-use stm32f1xx_hal::rcc::RccExt;
-use stm32f1xx_hal::time::Hertz;
 
+// The board is selected with a feature, `Board` is the selected one. build.rs provides
+// the matching memory.x.
+#[cfg(all(feature = "stm32f103", feature = "qemu"))]
+compile_error!("Select exactly one board feature");
+
+mod cortex_m_init;
+#[cfg(feature = "qemu")]
+mod mps2_an385;
+#[cfg(feature = "stm32f103")]
+mod stm32f103;
+
+pub use cortex_m_init::CortexMLowLevelInit;
+#[cfg(feature = "qemu")]
+pub use mps2_an385::{exit_on_breakpoint, BoardMps2An385 as Board, BoardMps2An385, Uart0Rx, Uart0Tx};
+#[cfg(feature = "stm32f103")]
+pub use stm32f103::{BoardStm32f103c8BluePill as Board, BoardStm32f103c8BluePill};
 
 /// Low level initialization. The low level initialization function will
 /// perform basic low level initialization of the hardware.
 pub trait LowLevelInit {
+    /// The device peripherals that the clock setup consumes, `()` if it needs none
+    type Device;
+
     /// The input is the number of ticks per second that ThreadX will be
-    /// expecting. The output is a pointer to a slice that is used as the
-    /// heap memory. This function is also exptected to set
+    /// expecting. This function is also exptected to set
     /// the system stack pointer. The variable is exposed by threadx_sys
-    /// as threadx_sys::_tx_thread_system_stack_ptr 
-    fn low_level_init(ticks_per_second: u32) -> Result<(),()>;
+    /// as threadx_sys::_tx_thread_system_stack_ptr
+    ///
+    /// The peripherals are the ones the application has taken itself, so it can keep
+    /// using the rest of them. The core peripherals are only borrowed.
+    ///
+    /// `CortexMLowLevelInit` does all of this but the clock setup.
+    fn low_level_init_with(ticks_per_second: u32, core: &mut cortex_m::Peripherals, device: Self::Device) -> Result<(),()>;
+
+    /// Take the device peripherals that `low_level_init_with` needs from the HAL
+    fn take_device() -> Option<Self::Device>;
+
+    /// `low_level_init_with` peripherals taken here. Fails if the application has already
+    /// taken them. The core peripherals are handed back to the application, the device
+    /// peripherals other than `Device` can not be taken anymore.
+    fn low_level_init(ticks_per_second: u32) -> Result<cortex_m::Peripherals,()> {
+        let mut core = cortex_m::Peripherals::take().ok_or(())?;
+        let device = Self::take_device().ok_or(())?;
+        Self::low_level_init_with(ticks_per_second, &mut core, device)?;
+        Ok(core)
+    }
 }
 
-// cortexm-rt crate defines the _stack_start function. Due to the action of flip-link, the stack pointer 
+// cortexm-rt crate defines the _stack_start function. Due to the action of flip-link, the stack pointer
 // is moved lower down in memory after leaving space for the bss and data sections.
 extern "C" {
     static _stack_start: u32;
 }
-
-pub struct BoardStm32f103c8BluePill;
-
-impl LowLevelInit for BoardStm32f103c8BluePill {
-    fn low_level_init(ticks_per_second: u32) -> Result<(),()> {
-
-        unsafe {
-        let stack_start = &_stack_start as *const u32 as u32;
-            threadx_sys::_tx_thread_system_stack_ptr = stack_start as *mut c_void;
-            defmt::println!("Low level init.  Stack at: {=u32:#x} Ticks per second:{}",stack_start, ticks_per_second);
-
-            defmt::println!("Stack size {}",    stack_start - 0x2000_0000);
-        }
-
-        
-            let p = pac::Peripherals::take().unwrap();
-            let rcc = p.RCC.constrain();
-            let mut flash = p.FLASH.constrain();
-
-            let clocks = rcc
-                .cfgr
-                .use_hse(Hertz::MHz(8))
-                .sysclk(Hertz::MHz(72))
-                .hclk(Hertz::MHz(64))
-                .pclk1(Hertz::MHz(36))
-                .pclk2(Hertz::MHz(64))
-                .freeze(&mut flash.acr);
-
-            let cp = cortex_m::Peripherals::take().unwrap();
-            let mut syst = cp.SYST;
-            let mut nvic = cp.NVIC;
-            let mut dcb = cp.DCB;
-            dcb.enable_trace();
-            let mut dbg = cp.DWT;
-            // configures the system timer to trigger a SysTick exception every second
-            dbg.enable_cycle_counter();
-
-            syst.set_clock_source(SystClkSource::Core);
-            syst.set_reload( (72_000_000 / ticks_per_second) - 1);
-            syst.enable_counter();
-            syst.enable_interrupt();
-
-
-            defmt::println!("Low level init");
-
-            //Set up the priorities for SysTick and PendSV and SVC
-            unsafe {
-                asm!(
-                    "MOV     r0, #0xE000E000",
-                    "LDR     r1, =0x00000000",
-                    "STR     r1, [r0, #0xD18]",
-                    "LDR     r1, =0xFF000000",
-                    "STR     r1, [r0, #0xD1C]",
-                    "LDR     r1, =0x40FF0000",
-                    "STR     r1, [r0, #0xD20]",
-                );
-            }
-            defmt::println!("Int prio set");
-            Ok(())
-            
-    }
-}
-
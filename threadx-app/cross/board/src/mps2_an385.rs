@@ -0,0 +1,121 @@
+// Arm MPS2 with the AN385 Cortex-M3 image, as emulated by QEMU:
+//
+//     qemu-system-arm -cpu cortex-m3 -machine mps2-an385 -nographic \
+//         -semihosting-config enable=on,target=native -kernel <elf>
+//
+// There is no debug probe, so output goes through semihosting and the application ends
+// QEMU with a semihosting exit. UART0 is the first `-serial` of QEMU.
+
+use core::sync::atomic::{AtomicBool, Ordering};
+
+use cortex_m::peripheral::DCB;
+use cortex_m_rt::exception;
+use cortex_m_semihosting::debug;
+
+use crate::{CortexMLowLevelInit, LowLevelInit};
+
+/// QEMU clocks the core of the AN385 with 25 MHz
+const SYSCLK_HZ: u32 = 25_000_000;
+/// UART0, a CMSDK APB UART
+const UART0: usize = 0x4000_4000;
+const UART_DATA: usize = 0x00;
+const UART_STATE: usize = 0x04;
+const UART_CTRL: usize = 0x08;
+const UART_BAUDDIV: usize = 0x10;
+const UART_STATE_TX_FULL: u32 = 1 << 0;
+const UART_STATE_RX_FULL: u32 = 1 << 1;
+const UART_CTRL_TX_RX_ENABLE: u32 = 0b11;
+/// QEMU does not pace the UART, but rejects a divider below 16
+const UART_BAUDDIV_MIN: u32 = 16;
+/// DEMCR.MON_EN
+const DEMCR_MON_EN: u32 = 1 << 16;
+
+pub struct BoardMps2An385;
+
+impl LowLevelInit for BoardMps2An385 {
+    /// The clock is fixed, so only the core peripherals are used
+    type Device = ();
+
+    fn low_level_init_with(ticks_per_second: u32, cp: &mut cortex_m::Peripherals, (): ()) -> Result<(),()> {
+        CortexMLowLevelInit::new(SYSCLK_HZ, ticks_per_second).init(&mut cp.SYST, &mut cp.SCB)?;
+        exit_on_breakpoint();
+        Ok(())
+    }
+
+    fn take_device() -> Option<()> {
+        Some(())
+    }
+}
+
+/// Make a `bkpt` end QEMU successfully. probe-rs treats a breakpoint as the end of the
+/// program, which is how defmt-test reports that all tests passed. Without a debugger
+/// the breakpoint raises the DebugMonitor exception once it is enabled here.
+pub fn exit_on_breakpoint() {
+    unsafe { (*DCB::PTR).demcr.modify(|demcr| demcr | DEMCR_MON_EN) };
+}
+
+#[exception]
+fn DebugMonitor() {
+    loop {
+        debug::exit(debug::EXIT_SUCCESS);
+    }
+}
+
+/// The receiving half of UART0. The receiver holds a single byte and there are no
+/// interrupts, so it has to be polled faster than the bytes arrive.
+pub struct Uart0Rx {
+    _private: (),
+}
+
+/// The transmitting half of UART0
+pub struct Uart0Tx {
+    _private: (),
+}
+
+fn uart0(register: usize) -> *mut u32 {
+    (UART0 + register) as *mut u32
+}
+
+/// Claim a half of UART0 and enable the UART. False if the half was taken before.
+fn uart0_claim(taken: &AtomicBool) -> bool {
+    if taken.swap(true, Ordering::Relaxed) {
+        return false;
+    }
+    // Enabling both directions again is harmless
+    unsafe {
+        uart0(UART_BAUDDIV).write_volatile(UART_BAUDDIV_MIN);
+        uart0(UART_CTRL).write_volatile(UART_CTRL_TX_RX_ENABLE);
+    }
+    true
+}
+
+impl Uart0Rx {
+    /// Fails the second time, there is only one UART0
+    pub fn take() -> Option<Uart0Rx> {
+        static TAKEN: AtomicBool = AtomicBool::new(false);
+        uart0_claim(&TAKEN).then_some(Uart0Rx { _private: () })
+    }
+
+    /// The received byte, if there is one
+    pub fn read_byte(&mut self) -> Option<u8> {
+        unsafe {
+            (uart0(UART_STATE).read_volatile() & UART_STATE_RX_FULL != 0).then(|| uart0(UART_DATA).read_volatile() as u8)
+        }
+    }
+}
+
+impl Uart0Tx {
+    /// Fails the second time, there is only one UART0
+    pub fn take() -> Option<Uart0Tx> {
+        static TAKEN: AtomicBool = AtomicBool::new(false);
+        uart0_claim(&TAKEN).then_some(Uart0Tx { _private: () })
+    }
+
+    /// Send a byte, waiting while the transmitter is full
+    pub fn write_byte(&mut self, byte: u8) {
+        unsafe {
+            while uart0(UART_STATE).read_volatile() & UART_STATE_TX_FULL != 0 {}
+            uart0(UART_DATA).write_volatile(byte as u32);
+        }
+    }
+}
@@ -0,0 +1,37 @@
+use stm32f1xx_hal::flash::FlashExt;
+use stm32f1xx_hal::pac;
+use stm32f1xx_hal::rcc::RccExt;
+use stm32f1xx_hal::time::Hertz;
+
+use crate::{CortexMLowLevelInit, LowLevelInit};
+
+pub struct BoardStm32f103c8BluePill;
+
+impl LowLevelInit for BoardStm32f103c8BluePill {
+    /// The clocks are configured through RCC and the flash wait states
+    type Device = (pac::RCC, pac::FLASH);
+
+    fn low_level_init_with(ticks_per_second: u32, cp: &mut cortex_m::Peripherals, (rcc, flash): Self::Device) -> Result<(),()> {
+        let rcc = rcc.constrain();
+        let mut flash = flash.constrain();
+
+        let clocks = rcc
+            .cfgr
+            .use_hse(Hertz::MHz(8))
+            .sysclk(Hertz::MHz(72))
+            .hclk(Hertz::MHz(64))
+            .pclk1(Hertz::MHz(36))
+            .pclk2(Hertz::MHz(64))
+            .freeze(&mut flash.acr);
+
+        cp.DCB.enable_trace();
+        cp.DWT.enable_cycle_counter();
+
+        // SysTick counts the AHB clock
+        CortexMLowLevelInit::new(clocks.hclk().raw(), ticks_per_second).init(&mut cp.SYST, &mut cp.SCB)
+    }
+
+    fn take_device() -> Option<Self::Device> {
+        pac::Peripherals::take().map(|p| (p.RCC, p.FLASH))
+    }
+}
@@ -0,0 +1,325 @@
+//! Runs tests in ThreadX threads on the target.
+//!
+//! The kernel never returns, so it can not be started from a defmt-test test. [`run`]
+//! instead boots ThreadX once and a supervisor thread runs each test in its own thread:
+//!
+//! ```ignore
+//! target_tests::tests! {
+//!     #[test]
+//!     fn sleeps() {
+//!         sleep(Duration::from_millis(10)).unwrap();
+//!     }
+//!
+//!     #[test(stack = 2048, timeout_ms = 100)]
+//!     fn blocks() { ... }
+//! }
+//!
+//! #[cortex_m_rt::entry]
+//! fn main() -> ! {
+//!     target_tests::run(|ticks_per_second| { Board::low_level_init(ticks_per_second).unwrap(); }, TESTS)
+//! }
+//! ```
+//!
+//! A test passes when its function returns and fails when it panics. A test that does not
+//! finish within its timeout hangs; its thread is terminated, which also releases the
+//! mutexes it owns, and the next test runs. The results are logged over defmt and the
+//! program exits through semihosting, with failure if any test failed or hung.
+#![no_std]
+
+use core::ffi::c_void;
+use core::mem::MaybeUninit;
+use core::panic::PanicInfo;
+use core::ptr::{addr_of_mut, null_mut};
+use core::time::Duration;
+
+use cortex_m_semihosting::debug;
+use threadx_rs::planner::MemoryPlanner;
+use threadx_rs::time::TxTicks;
+use threadx_rs::Builder;
+use threadx_sys::{
+    _tx_event_flags_create, _tx_event_flags_get, _tx_event_flags_set, _tx_thread_create,
+    _tx_thread_delete, _tx_thread_identify, _tx_thread_suspend, _tx_thread_terminate,
+    TX_AUTO_START, TX_EVENT_FLAGS_GROUP, TX_NO_EVENTS, TX_NO_TIME_SLICE, TX_OR, TX_OR_CLEAR,
+    TX_SUCCESS, TX_THREAD, UINT, ULONG,
+};
+
+pub const DEFAULT_STACK: usize = 1024;
+pub const DEFAULT_PRIORITY: u32 = 16;
+pub const DEFAULT_TIMEOUT: Duration = Duration::from_secs(1);
+
+/// Memory for the supervisor, the helper and the test stacks. Test stacks are taken from
+/// what the other two leave.
+const HEAP_SIZE: usize = 8 * 1024;
+const SUPERVISOR_STACK: usize = 1024;
+/// Above every test, so that it can terminate a test that does not yield
+const SUPERVISOR_PRIORITY: u32 = 0;
+const HELPER_STACK: usize = 1024;
+
+const PASSED: ULONG = 1 << 0;
+const FAILED: ULONG = 1 << 1;
+
+/// A test function with the settings of its thread
+pub struct Test {
+    name: &'static str,
+    function: fn(),
+    stack: usize,
+    priority: u32,
+    timeout: Duration,
+}
+
+impl Test {
+    pub const fn new(name: &'static str, function: fn()) -> Self {
+        Test { name, function, stack: DEFAULT_STACK, priority: DEFAULT_PRIORITY, timeout: DEFAULT_TIMEOUT }
+    }
+
+    /// Stack size of the test thread in bytes
+    pub const fn stack(mut self, bytes: usize) -> Self {
+        self.stack = bytes;
+        self
+    }
+
+    /// Priority of the test thread. 0 is rejected, the supervisor runs at 0 and must be
+    /// able to preempt the test.
+    pub const fn priority(mut self, priority: u32) -> Self {
+        assert!(priority > SUPERVISOR_PRIORITY, "Test priority 0 is reserved for the supervisor");
+        self.priority = priority;
+        self
+    }
+
+    /// Time after which the test hangs
+    pub const fn timeout_ms(mut self, milliseconds: u64) -> Self {
+        self.timeout = Duration::from_millis(milliseconds);
+        self
+    }
+}
+
+/// Define test functions and a `TESTS` slice for [`run`]. `#[test]` takes the settings of
+/// [`Test`] as `stack = N`, `priority = P` and `timeout_ms = T`.
+#[macro_export]
+macro_rules! tests {
+    ($(#[test $(($($setting:ident = $value:expr),* $(,)?))?] fn $name:ident() $body:block)*) => {
+        $(fn $name() $body)*
+
+        static TESTS: &[$crate::Test] = &[
+            $($crate::Test::new(stringify!($name), $name) $($(.$setting($value))*)?),*
+        ];
+    };
+}
+
+#[derive(Clone, Copy, PartialEq)]
+enum Outcome {
+    Passed,
+    Failed,
+    Hung,
+}
+
+static mut INIT: fn(u32) = |_| {};
+static mut TESTS: &[Test] = &[];
+static mut TEST_STACK: (*mut u8, usize) = (null_mut(), 0);
+static mut HELPER_STACK_MEMORY: (*mut u8, usize) = (null_mut(), 0);
+static mut HELPER: Option<fn()> = None;
+
+static mut SUPERVISOR_THREAD: MaybeUninit<TX_THREAD> = MaybeUninit::uninit();
+static mut TEST_THREAD: MaybeUninit<TX_THREAD> = MaybeUninit::uninit();
+static mut HELPER_THREAD: MaybeUninit<TX_THREAD> = MaybeUninit::uninit();
+static mut DONE: MaybeUninit<TX_EVENT_FLAGS_GROUP> = MaybeUninit::uninit();
+
+/// Boot ThreadX and run the tests one after the other. `init` is the low level
+/// initialization of the board, e.g. `Board::low_level_init`. Does not return.
+pub fn run(init: fn(u32), tests: &'static [Test]) -> ! {
+    unsafe {
+        INIT = init;
+        TESTS = tests;
+    }
+    Builder::new(low_level_init, define).initialize();
+    exit(false)
+}
+
+/// Start a helper thread for the current test at `priority`, e.g. one that blocks on an
+/// object the test holds. There is one helper per test, it is terminated with the test.
+pub fn spawn(function: fn(), priority: u32) {
+    assert!(priority > SUPERVISOR_PRIORITY, "Helper priority 0 is reserved for the supervisor");
+    unsafe {
+        let helper = HELPER;
+        assert!(helper.is_none(), "Only one helper thread per test");
+        HELPER = Some(function);
+        let (stack, len) = HELPER_STACK_MEMORY;
+        check(_tx_thread_create(
+            helper_thread(),
+            c"helper".as_ptr() as *mut _,
+            Some(helper_entry),
+            0,
+            stack as *mut c_void,
+            len as ULONG,
+            priority as UINT,
+            priority as UINT,
+            TX_NO_TIME_SLICE,
+            TX_AUTO_START,
+        ));
+    }
+}
+
+fn low_level_init(ticks_per_second: u32) -> &'static mut [u8] {
+    static mut HEAP: [u8; HEAP_SIZE] = [0; HEAP_SIZE];
+    unsafe {
+        INIT(ticks_per_second);
+        &mut *addr_of_mut!(HEAP)
+    }
+}
+
+fn define(memory: &'static mut [u8]) {
+    let mut plan = MemoryPlanner::new(memory);
+    let supervisor_stack = plan.stack(SUPERVISOR_STACK).unwrap().consume();
+    let helper_stack = plan.stack(HELPER_STACK).unwrap().consume();
+    let test_stack = plan.into_remaining();
+    unsafe {
+        HELPER_STACK_MEMORY = (helper_stack.as_mut_ptr(), helper_stack.len());
+        TEST_STACK = (test_stack.as_mut_ptr(), test_stack.len());
+        check(_tx_event_flags_create(done(), c"tests".as_ptr() as *mut _));
+        check(_tx_thread_create(
+            addr_of_mut!(SUPERVISOR_THREAD) as *mut TX_THREAD,
+            c"supervisor".as_ptr() as *mut _,
+            Some(supervise),
+            0,
+            supervisor_stack.as_mut_ptr() as *mut c_void,
+            supervisor_stack.len() as ULONG,
+            SUPERVISOR_PRIORITY as UINT,
+            SUPERVISOR_PRIORITY as UINT,
+            TX_NO_TIME_SLICE,
+            TX_AUTO_START,
+        ));
+    }
+}
+
+unsafe extern "C" fn supervise(_input: ULONG) {
+    let tests = TESTS;
+    let mut failures = 0;
+    for (index, test) in tests.iter().enumerate() {
+        let outcome = run_test(index, test);
+        match outcome {
+            Outcome::Passed => defmt::println!("PASS {}", test.name),
+            Outcome::Failed => defmt::println!("FAIL {}", test.name),
+            Outcome::Hung => defmt::println!("HANG {} after {} ms", test.name, test.timeout.as_millis() as u64),
+        }
+        if outcome != Outcome::Passed {
+            failures += 1;
+        }
+    }
+    defmt::println!("{} tests, {} passed, {} failed or hung", tests.len(), tests.len() - failures, failures);
+    exit(failures == 0)
+}
+
+unsafe fn run_test(index: usize, test: &Test) -> Outcome {
+    let (stack, len) = TEST_STACK;
+    let outcome = if test.stack > len {
+        defmt::error!("{}: a stack of {} bytes does not fit into {}", test.name, test.stack, len);
+        Outcome::Failed
+    } else {
+        check(_tx_thread_create(
+            test_thread(),
+            c"test".as_ptr() as *mut _,
+            Some(test_entry),
+            index as ULONG,
+            stack as *mut c_void,
+            test.stack as ULONG,
+            test.priority as UINT,
+            test.priority as UINT,
+            TX_NO_TIME_SLICE,
+            TX_AUTO_START,
+        ));
+        let mut flags: ULONG = 0;
+        let timeout = TxTicks::from(test.timeout).ticks() as ULONG;
+        let status = _tx_event_flags_get(done(), PASSED | FAILED, TX_OR_CLEAR, &mut flags, timeout);
+        // Terminating a thread that completed does nothing
+        _tx_thread_terminate(test_thread());
+        check(_tx_thread_delete(test_thread()));
+        match status {
+            TX_SUCCESS if flags & FAILED == 0 => Outcome::Passed,
+            TX_NO_EVENTS => Outcome::Hung,
+            _ => Outcome::Failed,
+        }
+    };
+    let helper = HELPER;
+    if helper.is_some() {
+        HELPER = None;
+        _tx_thread_terminate(helper_thread());
+        check(_tx_thread_delete(helper_thread()));
+    }
+    outcome
+}
+
+unsafe extern "C" fn test_entry(index: ULONG) {
+    (TESTS[index as usize].function)();
+    _tx_event_flags_set(done(), PASSED, TX_OR);
+}
+
+unsafe extern "C" fn helper_entry(_input: ULONG) {
+    if let Some(function) = HELPER {
+        function();
+    }
+}
+
+fn test_thread() -> *mut TX_THREAD {
+    unsafe { addr_of_mut!(TEST_THREAD) as *mut TX_THREAD }
+}
+
+fn helper_thread() -> *mut TX_THREAD {
+    unsafe { addr_of_mut!(HELPER_THREAD) as *mut TX_THREAD }
+}
+
+fn done() -> *mut TX_EVENT_FLAGS_GROUP {
+    unsafe { addr_of_mut!(DONE) as *mut TX_EVENT_FLAGS_GROUP }
+}
+
+fn check(status: UINT) {
+    if status != TX_SUCCESS {
+        defmt::panic!("ThreadX call failed with {}", status);
+    }
+}
+
+fn exit(success: bool) -> ! {
+    loop {
+        debug::exit(if success { debug::EXIT_SUCCESS } else { debug::EXIT_FAILURE });
+    }
+}
+
+/// A panic in the test or its helper fails the test and leaves the thread to the
+/// supervisor. Anywhere else it ends the run.
+#[panic_handler]
+fn panic(info: &PanicInfo) -> ! {
+    defmt::error!("{}", defmt::Display2Format(info));
+    unsafe {
+        let current = _tx_thread_identify();
+        if current == test_thread() || current == helper_thread() {
+            _tx_event_flags_set(done(), FAILED, TX_OR);
+            _tx_thread_suspend(current);
+        }
+    }
+    exit(false)
+}
+
+// defmt::panic! goes through the panic handler, which prints the message
+#[defmt::panic_handler]
+fn defmt_panic() -> ! {
+    core::panic!()
+}
+
+#[cfg(target_arch = "arm")]
+mod exceptions {
+    use cortex_m_rt::{exception, ExceptionFrame};
+
+    #[exception]
+    fn SysTick() {
+        unsafe { threadx_rs::tx_timer_interrupt() };
+    }
+
+    #[exception]
+    fn PendSV() {
+        unsafe { threadx_rs::tx_pendsv_handler() };
+    }
+
+    #[exception]
+    unsafe fn HardFault(_frame: &ExceptionFrame) -> ! {
+        super::exit(false)
+    }
+}
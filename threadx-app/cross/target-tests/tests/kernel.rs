@@ -0,0 +1,104 @@
+#![no_main]
+#![no_std]
+
+// ThreadX tests running in their own threads on the selected board, see target_tests::run
+
+#[cfg(feature = "stm32f103")]
+use defmt_rtt as _;
+#[cfg(feature = "qemu")]
+use defmt_semihosting as _;
+#[cfg(feature = "stm32f103")]
+use stm32f1xx_hal as _; // memory layout
+
+use core::sync::atomic::{AtomicU32, Ordering};
+use core::time::Duration;
+
+use board::{Board, LowLevelInit};
+use defmt::{assert, assert_eq, unwrap};
+use threadx_rs::mutex::Mutex;
+use threadx_rs::planner::MemoryPlanner;
+use threadx_rs::queue::{Queue, QueueReceiver, QueueSendError, QueueSender};
+use threadx_rs::static_cell::{TxOnceCell, TxStatic};
+use threadx_rs::thread::sleep;
+use threadx_rs::time::Instant;
+use threadx_rs::WaitOption;
+
+target_tests::tests! {
+    #[test(timeout_ms = 500)]
+    fn queue_receive_blocks_until_a_message_arrives() {
+        static QUEUE: TxStatic<Queue<u32>> = TxStatic::new(Queue::new());
+        static MEMORY: TxStatic<[u8; 64]> = TxStatic::new([0; 64]);
+        static SENDER: TxOnceCell<QueueSender<u32>> = TxOnceCell::new();
+
+        let mut plan = MemoryPlanner::new(unwrap!(MEMORY.take()));
+        let (sender, receiver) = unwrap!(QUEUE.take()).initialize(c"receive", plan.queue::<u32>(2).unwrap()).unwrap();
+        assert!(SENDER.set(sender).is_ok());
+
+        let start = Instant::now();
+        target_tests::spawn(
+            || {
+                sleep(Duration::from_millis(50)).unwrap();
+                unwrap!(SENDER.get()).send(7, WaitOption::NoWait).unwrap();
+            },
+            20,
+        );
+        assert_eq!(receiver.receive(WaitOption::WaitForever).unwrap(), 7);
+        assert!(start.elapsed() >= Duration::from_millis(50));
+    }
+
+    #[test(timeout_ms = 500)]
+    fn queue_send_blocks_while_full() {
+        static QUEUE: TxStatic<Queue<u32>> = TxStatic::new(Queue::new());
+        static MEMORY: TxStatic<[u8; 64]> = TxStatic::new([0; 64]);
+        static RECEIVER: TxOnceCell<QueueReceiver<u32>> = TxOnceCell::new();
+        static RECEIVED: AtomicU32 = AtomicU32::new(0);
+
+        let mut plan = MemoryPlanner::new(unwrap!(MEMORY.take()));
+        let (sender, receiver) = unwrap!(QUEUE.take()).initialize(c"send", plan.queue::<u32>(1).unwrap()).unwrap();
+        assert!(RECEIVER.set(receiver).is_ok());
+
+        // The helper has a lower priority, it only runs while the test is blocked
+        target_tests::spawn(
+            || {
+                let message = unwrap!(RECEIVER.get()).receive(WaitOption::WaitForever).unwrap();
+                RECEIVED.store(message, Ordering::Relaxed);
+            },
+            20,
+        );
+        sender.send(1, WaitOption::NoWait).unwrap();
+        assert!(matches!(sender.send(2, WaitOption::NoWait), Err(QueueSendError::Full(2))));
+        // The receive of the helper puts the second message into the queue and resumes the
+        // test right away, before the helper records anything. The queue shows what it took.
+        sender.send(2, WaitOption::WaitForever).unwrap();
+        assert_eq!(unwrap!(RECEIVER.get()).receive(WaitOption::NoWait).unwrap(), 2);
+        // Sleeping lets the helper finish
+        sleep(Duration::from_millis(10)).unwrap();
+        assert_eq!(RECEIVED.load(Ordering::Relaxed), 1);
+    }
+
+    #[test(timeout_ms = 500)]
+    fn mutex_blocks_a_higher_priority_locker() {
+        static MUTEX: TxStatic<Mutex<u32>> = TxStatic::new(Mutex::new(0));
+        static SHARED: TxOnceCell<&'static Mutex<u32>> = TxOnceCell::new();
+
+        let mutex = unwrap!(MUTEX.take()).initialize(c"mutex", true).unwrap();
+        assert!(SHARED.set(mutex).is_ok());
+
+        let guard = mutex.lock(WaitOption::WaitForever).unwrap();
+        // The helper preempts the test and blocks on the mutex
+        target_tests::spawn(
+            || {
+                *unwrap!(SHARED.get()).lock(WaitOption::WaitForever).unwrap() += 1;
+            },
+            10,
+        );
+        assert_eq!(*guard, 0);
+        drop(guard);
+        assert_eq!(*mutex.lock(WaitOption::NoWait).unwrap(), 1);
+    }
+}
+
+#[cortex_m_rt::entry]
+fn main() -> ! {
+    target_tests::run(|ticks_per_second| { Board::low_level_init(ticks_per_second).unwrap(); }, TESTS)
+}
@@ -0,0 +1,41 @@
+#![no_main]
+#![no_std]
+
+// ThreadX tests running on the Arm MPS2 AN385 emulated by QEMU, see `cargo xtask test qemu`
+
+use defmt_semihosting as _;
+use panic_probe as _;
+
+#[defmt_test::tests]
+mod tests {
+    use core::time::Duration;
+    use defmt::{assert, assert_eq, unwrap};
+    use threadx_rs::planner::{byte_pool_size, MemoryPlanner, REGION_ALIGN};
+    use threadx_rs::time::TxTicks;
+    use threadx_sys::TX_TIMER_TICKS_PER_SECOND;
+
+    #[init]
+    fn init() {
+        // defmt-test ends with a breakpoint once all tests passed
+        board::exit_on_breakpoint();
+    }
+
+    #[test]
+    fn durations_round_up_to_ticks() {
+        let tick = Duration::from_secs(1) / TX_TIMER_TICKS_PER_SECOND;
+        assert_eq!(TxTicks::from(tick).ticks(), 1);
+        assert_eq!(TxTicks::from(tick + Duration::from_nanos(1)).ticks(), 2);
+        assert!(Duration::from(TxTicks::new(TX_TIMER_TICKS_PER_SECOND)) == Duration::from_secs(1));
+    }
+
+    #[test]
+    fn planner_aligns_regions() {
+        static mut MEMORY: [u8; 1024] = [0; 1024];
+        let mut plan = MemoryPlanner::new(unsafe { &mut *core::ptr::addr_of_mut!(MEMORY) });
+        let pool = unwrap!(plan.byte_pool(byte_pool_size(&[100])));
+        let stack = unwrap!(plan.region(256));
+        assert!(pool.as_ptr() as usize % REGION_ALIGN == 0);
+        assert!(stack.as_ptr() as usize % REGION_ALIGN == 0);
+        assert!(plan.region(4096).is_err());
+    }
+}
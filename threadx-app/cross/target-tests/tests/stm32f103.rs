@@ -7,13 +7,10 @@ use defmt_rtt as _;
 use panic_probe as _;
 use stm32f1xx_hal as _; // memory layout
 
-use threadx_rs::Builder;
-
 #[defmt_test::tests]
 mod tests {
     use core::time::Duration;
     use defmt::{assert_eq, unwrap};
-    use threadx_rs::Builder;
 
     struct Board{}
 
@@ -21,7 +18,7 @@ mod tests {
     #[init]
     fn init() -> Board {
 
-        //let tx = Builder::new(low_level_init_cb, app_define_cb)
+        // The kernel does not return, tests that need it are in kernel.rs
 
         Board {  }
     }
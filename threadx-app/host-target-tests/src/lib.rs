@@ -0,0 +1,281 @@
+//! Host side of the threadx-rpc protocol. A [`Client`] sends requests to the application
+//! on the target and waits for the responses:
+//!
+//! ```no_run
+//! let mut client = host_target_tests::open("tcp:localhost:4000").unwrap();
+//! client.ping(b"hello").unwrap();
+//! ```
+//!
+//! The link is a byte stream to the target:
+//!
+//! - `tcp:host:port`, the `rpc` binary of the Linux port or QEMU with `-serial tcp::port,server`
+//! - `rtt:host:port`, an RTT channel that OpenOCD already serves over TCP
+//! - `rtt:interface.cfg,target.cfg`, RTT through an OpenOCD started with these configuration
+//!   files, see [`Rtt`]
+//! - `serial:/dev/ttyUSB0@115200`, a UART at the given baud rate, or without `@baud` a line
+//!   that is already set up, such as the pty of QEMU with `-serial pty`
+
+use std::fmt;
+use std::fs::{File, OpenOptions};
+use std::io::{self, Read, Write};
+use std::net::TcpStream;
+use std::process::{Child, Command, Stdio};
+use std::time::{Duration, Instant};
+
+use threadx_rpc::{command, Decoder, Frame, FrameError, Status, MAX_FRAME};
+
+/// Application command of the `rpc` test applications, answered with the payload
+/// reversed. An empty payload is a bad request.
+pub const REVERSE: u8 = command::APPLICATION;
+
+/// How long `call` waits for a response
+pub const TIMEOUT: Duration = Duration::from_secs(2);
+/// How long reads of a link block before `call` checks its deadline
+const READ_TIMEOUT: Duration = Duration::from_millis(100);
+
+#[derive(Debug)]
+pub enum Error {
+    /// Opening, reading or writing the link failed
+    Io(io::Error),
+    /// The request does not fit into a frame
+    Frame(FrameError),
+    /// The target answered with an error
+    Status(Status),
+    /// The target answered with a status this client does not know
+    UnknownStatus(u8),
+    /// The target did not answer in time
+    Timeout,
+    /// `open` does not know the link
+    Link(String),
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Error::Io(error) => write!(f, "link failed: {error}"),
+            Error::Frame(error) => write!(f, "invalid request: {error}"),
+            Error::Status(status) => write!(f, "target answered {status:?}"),
+            Error::UnknownStatus(code) => write!(f, "target answered unknown status {code:#04x}"),
+            Error::Timeout => f.write_str("target did not answer"),
+            Error::Link(link) => write!(f, "unknown link `{link}`, expected tcp:, rtt: or serial:"),
+        }
+    }
+}
+
+impl std::error::Error for Error {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            Error::Io(error) => Some(error),
+            Error::Frame(error) => Some(error),
+            _ => None,
+        }
+    }
+}
+
+impl From<io::Error> for Error {
+    fn from(error: io::Error) -> Self {
+        Error::Io(error)
+    }
+}
+
+/// Sends requests over a link and matches the responses by their sequence number.
+/// Reads must time out, so that a lost frame does not block the client.
+pub struct Client<T> {
+    link: T,
+    decoder: Decoder,
+    seq: u8,
+}
+
+impl<T: Read + Write> Client<T> {
+    pub fn new(link: T) -> Self {
+        Client { link, decoder: Decoder::new(), seq: 0 }
+    }
+
+    /// Send `command` with `payload` and return the payload of the response. Responses to
+    /// earlier requests that timed out, and damaged frames, are skipped.
+    pub fn call(&mut self, command: u8, payload: &[u8]) -> Result<Vec<u8>, Error> {
+        self.seq = self.seq.wrapping_add(1);
+        let request = Frame::new(self.seq, command, payload).map_err(Error::Frame)?;
+        let mut buffer = [0; MAX_FRAME];
+        self.link.write_all(request.encode(&mut buffer))?;
+        self.link.flush()?;
+
+        let deadline = Instant::now() + TIMEOUT;
+        while Instant::now() < deadline {
+            let len = match self.link.read(&mut buffer) {
+                Ok(0) => return Err(io::Error::from(io::ErrorKind::UnexpectedEof).into()),
+                Ok(len) => len,
+                Err(error) if matches!(error.kind(), io::ErrorKind::WouldBlock | io::ErrorKind::TimedOut) => continue,
+                Err(error) if error.kind() == io::ErrorKind::Interrupted => continue,
+                Err(error) => return Err(error.into()),
+            };
+            for &byte in &buffer[..len] {
+                match self.decoder.push(byte) {
+                    Some(Ok(response)) if response.seq == self.seq => return response_payload(&response),
+                    _ => {}
+                }
+            }
+        }
+        Err(Error::Timeout)
+    }
+
+    /// The target echoes the payload
+    pub fn ping(&mut self, payload: &[u8]) -> Result<(), Error> {
+        let response = self.call(command::PING, payload)?;
+        if response != payload {
+            return Err(Error::Status(Status::Failed));
+        }
+        Ok(())
+    }
+
+    /// The ThreadX tick count of the target
+    pub fn ticks(&mut self) -> Result<u32, Error> {
+        let response = self.call(command::TICKS, &[])?;
+        let ticks = response.try_into().map_err(|_| Error::Status(Status::BadRequest))?;
+        Ok(u32::from_le_bytes(ticks))
+    }
+}
+
+fn response_payload(response: &Frame) -> Result<Vec<u8>, Error> {
+    match Status::try_from(response.code) {
+        Ok(Status::Ok) => Ok(response.payload().to_vec()),
+        Ok(status) => Err(Error::Status(status)),
+        Err(code) => Err(Error::UnknownStatus(code)),
+    }
+}
+
+fn tcp_link(address: &str) -> Result<TcpStream, io::Error> {
+    let stream = TcpStream::connect(address)?;
+    stream.set_nodelay(true)?;
+    stream.set_read_timeout(Some(READ_TIMEOUT))?;
+    Ok(stream)
+}
+
+impl Client<TcpStream> {
+    /// Connect to a TCP link, e.g. `localhost:4000`
+    pub fn connect(address: &str) -> Result<Self, Error> {
+        Ok(Client::new(tcp_link(address)?))
+    }
+}
+
+impl Client<File> {
+    /// Open a UART or pty. The line is set to raw mode with `stty`, and to `baud` if given.
+    /// Without it the baud rate is left as it is.
+    pub fn open_serial(path: &str, baud: Option<u32>) -> Result<Self, Error> {
+        let mut stty = Command::new("stty");
+        stty.args(["-F", path, "raw", "-echo", "min", "0", "time", "1"]);
+        if let Some(baud) = baud {
+            stty.arg(baud.to_string());
+        }
+        if !stty.status()?.success() {
+            return Err(io::Error::other(format!("stty failed for {path}")).into());
+        }
+        Ok(Client::new(OpenOptions::new().read(true).write(true).open(path)?))
+    }
+}
+
+impl Client<Rtt> {
+    /// Start OpenOCD with `configs` and connect to its RTT server, see [`Rtt`]
+    pub fn open_rtt(configs: &[&str]) -> Result<Self, Error> {
+        Ok(Client::new(Rtt::start(configs)?))
+    }
+}
+
+/// An RTT channel of the target, served over TCP by an OpenOCD that is started with the
+/// given configuration files, e.g. `interface/stlink.cfg` and `target/stm32f1x.cfg`, and
+/// stopped when the link is dropped. The target application reads the requests from down
+/// channel `CHANNEL` and writes the responses to up channel `CHANNEL`; channel 0
+/// carries the defmt log.
+pub struct Rtt {
+    openocd: Child,
+    stream: TcpStream,
+}
+
+impl Rtt {
+    /// The RTT channel that carries the frames
+    pub const CHANNEL: u32 = 1;
+    /// The port the RTT server of OpenOCD listens on
+    pub const PORT: u16 = 9091;
+    /// Where OpenOCD looks for the RTT control block: the start and size of the RAM
+    /// of an STM32F103C8
+    const SEARCH: (u32, u32) = (0x2000_0000, 0x5000);
+
+    pub fn start(configs: &[&str]) -> Result<Self, Error> {
+        let mut openocd = Command::new("openocd");
+        for config in configs {
+            openocd.args(["-f", config]);
+        }
+        let (address, size) = Self::SEARCH;
+        let mut openocd = openocd
+            .args(["-c", "init"])
+            .args(["-c", &format!(r#"rtt setup {address:#x} {size:#x} "SEGGER RTT""#)])
+            .args(["-c", "rtt start"])
+            .args(["-c", &format!("rtt server start {} {}", Self::PORT, Self::CHANNEL)])
+            .stdout(Stdio::null())
+            .stderr(Stdio::null())
+            .spawn()?;
+
+        // OpenOCD has to connect to the probe and find the control block first
+        let deadline = Instant::now() + TIMEOUT;
+        let stream = loop {
+            match tcp_link(&format!("localhost:{}", Self::PORT)) {
+                Ok(stream) => break stream,
+                Err(_) if Instant::now() < deadline => std::thread::sleep(READ_TIMEOUT),
+                Err(error) => {
+                    let _ = openocd.kill();
+                    let _ = openocd.wait();
+                    return Err(error.into());
+                }
+            }
+        };
+        Ok(Rtt { openocd, stream })
+    }
+}
+
+impl Read for Rtt {
+    fn read(&mut self, buffer: &mut [u8]) -> io::Result<usize> {
+        self.stream.read(buffer)
+    }
+}
+
+impl Write for Rtt {
+    fn write(&mut self, bytes: &[u8]) -> io::Result<usize> {
+        self.stream.write(bytes)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.stream.flush()
+    }
+}
+
+impl Drop for Rtt {
+    fn drop(&mut self) {
+        let _ = self.openocd.kill();
+        let _ = self.openocd.wait();
+    }
+}
+
+/// Open the link described by `link`, see the crate documentation
+pub fn open(link: &str) -> Result<Client<Box<dyn Link>>, Error> {
+    let link: Box<dyn Link> = match link.split_once(':') {
+        Some(("tcp", address)) => Box::new(Client::connect(address)?.link),
+        Some(("rtt", configs)) if configs.ends_with(".cfg") => {
+            Box::new(Client::open_rtt(&configs.split(',').collect::<Vec<_>>())?.link)
+        }
+        Some(("rtt", address)) => Box::new(Client::connect(address)?.link),
+        Some(("serial", line)) => {
+            let (path, baud) = match line.split_once('@') {
+                Some((path, baud)) => (path, Some(baud.parse().map_err(|_| Error::Link(link.into()))?)),
+                None => (line, None),
+            };
+            Box::new(Client::open_serial(path, baud)?.link)
+        }
+        _ => return Err(Error::Link(link.into())),
+    };
+    Ok(Client::new(link))
+}
+
+/// A byte stream to the target
+pub trait Link: Read + Write + Send {}
+
+impl<T: Read + Write + Send> Link for T {}
@@ -1,7 +1,34 @@
 // combined host and target tests. The target image of the app is flashed to the target
 // and this host application is run on the host. The host application communicates with the
 // target application via some mechanism such as serial port or network. This enables end to end testing
+//
+//     host-target-tests tcp:localhost:4000
+//
+// `cargo xtask test host-target` runs it against the `rpc` binary of the Linux port,
+// `cargo xtask test host-target qemu` against the `rpc` application in QEMU.
 
-fn main() {
-    println!("Hello, world!");
+use std::process::ExitCode;
+
+fn main() -> ExitCode {
+    let Some(link) = std::env::args().nth(1) else {
+        eprintln!("usage: host-target-tests <tcp:host:port | rtt:host:port | rtt:interface.cfg,target.cfg | serial:path[@baud]>");
+        return ExitCode::FAILURE;
+    };
+    let result = host_target_tests::open(&link).and_then(|mut client| {
+        client.ping(b"threadx")?;
+        if client.call(host_target_tests::REVERSE, b"threadx")? != b"xdaerht" {
+            return Err(host_target_tests::Error::Status(threadx_rpc::Status::Failed));
+        }
+        client.ticks()
+    });
+    match result {
+        Ok(ticks) => {
+            println!("target is up, tick {ticks}");
+            ExitCode::SUCCESS
+        }
+        Err(error) => {
+            eprintln!("{link}: {error}");
+            ExitCode::FAILURE
+        }
+    }
 }
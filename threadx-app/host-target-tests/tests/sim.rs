@@ -0,0 +1,75 @@
+// The client against the target side of the protocol running on threadx-sim, linked over
+// a loopback TCP socket like the Linux port. The dispatcher is a simulated thread. The
+// socket is read on a std thread, which feeds the request queue like an interrupt handler
+// would, as a simulated thread blocking in a read would hold the simulated CPU.
+
+use std::io::Read;
+use std::net::{TcpListener, TcpStream};
+use std::sync::OnceLock;
+use std::thread;
+
+use host_target_tests::{Client, Error, REVERSE};
+use threadx_rpc::target::{dispatch, Receiver};
+use threadx_rpc::{Frame, Status, MAX_PAYLOAD};
+use threadx_rs::queue::{Queue, QueueReceiver};
+use threadx_rs::sim::Harness;
+
+/// Claims to have written more than the response can hold
+const OVERLONG: u8 = REVERSE + 1;
+
+/// The target end of the socket
+static LINK: OnceLock<TcpStream> = OnceLock::new();
+
+fn handle(command: u8, request: &[u8], response: &mut [u8; MAX_PAYLOAD]) -> Result<usize, Status> {
+    match command {
+        REVERSE if request.is_empty() => Err(Status::BadRequest),
+        REVERSE => {
+            for (to, from) in response.iter_mut().zip(request.iter().rev()) {
+                *to = *from;
+            }
+            Ok(request.len())
+        }
+        OVERLONG => Ok(MAX_PAYLOAD + 1),
+        _ => Err(Status::UnknownCommand),
+    }
+}
+
+#[threadx_rs::app]
+mod rpc_app {
+    use super::*;
+
+    #[queue(capacity = 4)]
+    pub static REQUESTS: Queue<Frame>;
+
+    #[thread(priority = 1, stack = 4096)]
+    fn dispatcher(requests: QueueReceiver<Frame>) {
+        let mut link = LINK.get().unwrap().try_clone().unwrap();
+        dispatch(&mut link, &requests, handle).unwrap();
+    }
+}
+
+#[test]
+fn client_talks_to_the_dispatcher() {
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let mut client = Client::connect(&listener.local_addr().unwrap().to_string()).unwrap();
+    LINK.set(listener.accept().unwrap().0).unwrap();
+
+    let _harness = Harness::start(rpc_app::start);
+    let mut receiver = Receiver::new(rpc_app::REQUESTS.get().unwrap().0.clone());
+    let mut link = LINK.get().unwrap().try_clone().unwrap();
+    thread::spawn(move || {
+        let mut buffer = [0; 64];
+        while let Ok(len @ 1..) = link.read(&mut buffer) {
+            receiver.push(&buffer[..len]);
+        }
+    });
+
+    client.ping(b"hello").unwrap();
+    client.ping(&[0; MAX_PAYLOAD]).unwrap();
+    assert_eq!(client.ticks().unwrap(), 0);
+    assert_eq!(client.call(REVERSE, b"abc").unwrap(), b"cba");
+    assert!(matches!(client.call(REVERSE, b""), Err(Error::Status(Status::BadRequest))));
+    assert!(matches!(client.call(0x7F, b""), Err(Error::Status(Status::UnknownCommand))));
+    assert!(matches!(client.call(OVERLONG, b""), Err(Error::Status(Status::Failed))));
+    assert!(matches!(client.call(REVERSE, &[0; MAX_PAYLOAD + 1]), Err(Error::Frame(_))));
+}
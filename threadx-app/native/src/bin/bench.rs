@@ -0,0 +1,30 @@
+// Kernel benchmarks on the Linux port, see threadx-bench. The times are nanoseconds of the
+// monotonic clock and each result is a line of JSON on stdout:
+//
+//     cargo run --release --bin bench > bench.jsonl
+
+use std::sync::OnceLock;
+use std::time::Instant;
+
+use threadx_bench::{Config, Report};
+
+static START: OnceLock<Instant> = OnceLock::new();
+
+/// Nanoseconds since the first read, wrapping after about 4 seconds like a cycle counter
+fn clock() -> u32 {
+    START.get_or_init(Instant::now).elapsed().as_nanos() as u32
+}
+
+static CONFIG: Config = Config {
+    init: |_| {},
+    clock,
+    unit: "ns",
+    iterations: 1000,
+    report: |report: &Report| println!("{report}"),
+    // The kernel never returns to main
+    done: || std::process::exit(0),
+};
+
+fn main() {
+    threadx_bench::run(&CONFIG);
+}
@@ -0,0 +1,104 @@
+// The target side of threadx-rpc on the Linux port, for testing the host side end to end.
+// It listens on the TCP address given as the first argument, prints the address once a
+// client can connect, answers that one client and exits when it disconnects:
+//
+//     cargo run --bin rpc -- 127.0.0.1:4000
+//     host-target-tests tcp:127.0.0.1:4000
+
+use std::io::{self, Read, Write};
+use std::net::{TcpListener, TcpStream};
+use std::sync::OnceLock;
+use std::time::Duration;
+
+use threadx_rpc::target::{dispatch, receive};
+use threadx_rpc::{command, Frame, Status, MAX_PAYLOAD};
+use threadx_rs::queue::{Queue, QueueReceiver, QueueSender};
+use threadx_rs::thread::sleep;
+
+/// The connection to the client, non-blocking
+static LINK: OnceLock<TcpStream> = OnceLock::new();
+
+/// Answered with the payload reversed, see `host_target_tests::REVERSE`
+const REVERSE: u8 = command::APPLICATION;
+
+fn handle(command: u8, request: &[u8], response: &mut [u8; MAX_PAYLOAD]) -> Result<usize, Status> {
+    match command {
+        REVERSE if request.is_empty() => Err(Status::BadRequest),
+        REVERSE => {
+            for (to, from) in response.iter_mut().zip(request.iter().rev()) {
+                *to = *from;
+            }
+            Ok(request.len())
+        }
+        _ => Err(Status::UnknownCommand),
+    }
+}
+
+/// The socket, polled once a tick while it would block. A ThreadX thread that blocks in a
+/// system call keeps the CPU of the port, so no other thread would run.
+struct Polled(TcpStream);
+
+fn wait_a_tick() -> io::Result<()> {
+    sleep(Duration::from_millis(10)).map_err(|error| io::Error::other(format!("{error:?}")))
+}
+
+impl Read for Polled {
+    fn read(&mut self, buffer: &mut [u8]) -> io::Result<usize> {
+        loop {
+            match self.0.read(buffer) {
+                Err(error) if matches!(error.kind(), io::ErrorKind::WouldBlock | io::ErrorKind::Interrupted) => wait_a_tick()?,
+                result => return result,
+            }
+        }
+    }
+}
+
+impl Write for Polled {
+    fn write(&mut self, bytes: &[u8]) -> io::Result<usize> {
+        loop {
+            match self.0.write(bytes) {
+                Err(error) if matches!(error.kind(), io::ErrorKind::WouldBlock | io::ErrorKind::Interrupted) => wait_a_tick()?,
+                result => return result,
+            }
+        }
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.0.flush()
+    }
+}
+
+fn link() -> Polled {
+    Polled(LINK.get().unwrap().try_clone().unwrap())
+}
+
+#[threadx_rs::app(heap = 65536)]
+mod app {
+    use super::*;
+
+    #[queue(capacity = 4)]
+    static REQUESTS: Queue<Frame>;
+
+    #[thread(priority = 2, stack = 16384)]
+    fn receiver(requests: QueueSender<Frame>) {
+        receive(&mut link(), &requests).unwrap();
+        // The client disconnected. The kernel never returns to main.
+        std::process::exit(0);
+    }
+
+    #[thread(priority = 1, stack = 16384)]
+    fn dispatcher(requests: QueueReceiver<Frame>) {
+        dispatch(&mut link(), &requests, handle).unwrap();
+    }
+}
+
+fn main() {
+    let address = std::env::args().nth(1).unwrap_or_else(|| "127.0.0.1:4000".into());
+    let listener = TcpListener::bind(&address).unwrap();
+    println!("Listening on {}", listener.local_addr().unwrap());
+    let (link, _) = listener.accept().unwrap();
+    link.set_nodelay(true).unwrap();
+    link.set_nonblocking(true).unwrap();
+    LINK.set(link).unwrap();
+    app::start();
+}
@@ -0,0 +1,49 @@
+// Queue example running on the ThreadX Linux port. ThreadX threads are pthreads and the
+// tick comes from the timer thread of the port, so this runs as a normal Linux process.
+
+use std::time::Duration;
+
+use threadx_rs::queue::{Queue, QueueReceiver, QueueSender};
+use threadx_rs::thread::sleep;
+use threadx_rs::WaitOption;
+
+/// Number of messages sent before the application exits
+const MESSAGES: u32 = 5;
+
+#[threadx_rs::app(heap = 65536)]
+mod app {
+    use super::*;
+
+    #[init]
+    fn init(ticks_per_second: u32) {
+        println!("Ticks per second: {}", ticks_per_second);
+    }
+
+    #[queue(capacity = 4)]
+    static QUEUE: Queue<u32>;
+
+    #[thread(priority = 1, stack = 4096)]
+    fn producer(queue: QueueSender<u32>) {
+        for count in 1..=MESSAGES {
+            queue.send(count, WaitOption::WaitForever).unwrap();
+            sleep(Duration::from_millis(50)).unwrap();
+        }
+    }
+
+    #[thread(priority = 2, stack = 4096)]
+    fn consumer(queue: QueueReceiver<u32>) {
+        loop {
+            let count = queue.receive(WaitOption::WaitForever).unwrap();
+            println!("Received {}", count);
+            if count == MESSAGES {
+                println!("Done");
+                // The kernel never returns to main
+                std::process::exit(0);
+            }
+        }
+    }
+}
+
+fn main() {
+    app::start();
+}
@@ -0,0 +1,64 @@
+// Runs the example and the benchmarks against the real kernel. The kernel never returns
+// once started, so each runs in its own process.
+
+use std::process::{Command, Stdio};
+use std::time::{Duration, Instant};
+
+/// Run a binary of this package to its exit and return its output
+fn run_to_completion(exe: &str) -> String {
+    let mut child = Command::new(exe).stdout(Stdio::piped()).spawn().expect("Unable to start the program");
+
+    let deadline = Instant::now() + Duration::from_secs(10);
+    let status = loop {
+        if let Some(status) = child.try_wait().unwrap() {
+            break status;
+        }
+        if Instant::now() > deadline {
+            child.kill().unwrap();
+            panic!("{exe} did not exit within 10 seconds");
+        }
+        std::thread::sleep(Duration::from_millis(20));
+    };
+
+    let output = child.wait_with_output().unwrap();
+    let stdout = String::from_utf8(output.stdout).unwrap();
+    assert!(status.success(), "exited with {status}:\n{stdout}");
+    stdout
+}
+
+#[test]
+fn queue_example_runs_to_completion() {
+    let stdout = run_to_completion(env!("CARGO_BIN_EXE_threadx-native"));
+    let received: Vec<&str> = stdout.lines().filter(|line| line.starts_with("Received")).collect();
+    assert_eq!(received, ["Received 1", "Received 2", "Received 3", "Received 4", "Received 5"]);
+    assert!(stdout.contains("Done"));
+}
+
+#[test]
+fn benchmarks_report_every_result() {
+    let stdout = run_to_completion(env!("CARGO_BIN_EXE_bench"));
+    let reports: Vec<&str> = stdout.lines().filter(|line| line.starts_with(r#"{"name":"#)).collect();
+    let names: Vec<&str> = reports.iter().map(|report| report.split('"').nth(3).unwrap()).collect();
+    assert_eq!(
+        names,
+        [
+            "clock_overhead",
+            "thread_resume_switch",
+            "semaphore_ping_pong",
+            "queue_round_trip",
+            "mutex_inherit_handoff",
+            "byte_pool_allocate",
+            "byte_pool_release",
+            "block_pool_allocate",
+            "block_pool_release",
+            "allocator_alloc",
+            "allocator_dealloc",
+            "tlsf_alloc",
+            "tlsf_dealloc",
+            "allocator_alloc_fragmented",
+            "tlsf_alloc_fragmented",
+        ],
+        "{stdout}"
+    );
+    assert!(reports.iter().all(|report| report.contains(r#""unit":"ns","iterations":1000,"#)), "{stdout}");
+}
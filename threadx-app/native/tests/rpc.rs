@@ -0,0 +1,35 @@
+// The client of host-target-tests against the `rpc` binary, the target side of threadx-rpc
+// on the real kernel, over a TCP socket
+
+use std::io::{BufRead, BufReader};
+use std::process::{Command, Stdio};
+use std::time::Duration;
+
+use host_target_tests::{Client, Error, REVERSE};
+use threadx_rpc::{Status, MAX_PAYLOAD};
+
+#[test]
+fn client_talks_to_the_rpc_binary() {
+    let mut target = Command::new(env!("CARGO_BIN_EXE_rpc"))
+        .arg("127.0.0.1:0")
+        .stdout(Stdio::piped())
+        .spawn()
+        .expect("Unable to start the rpc binary");
+    let mut line = String::new();
+    BufReader::new(target.stdout.take().unwrap()).read_line(&mut line).unwrap();
+    let address = line.trim().strip_prefix("Listening on ").unwrap_or_else(|| panic!("unexpected output {line}"));
+
+    let mut client = Client::connect(address).unwrap();
+    client.ping(b"hello").unwrap();
+    client.ping(&[0; MAX_PAYLOAD]).unwrap();
+    let ticks = client.ticks().unwrap();
+    std::thread::sleep(Duration::from_millis(100));
+    assert!(client.ticks().unwrap() > ticks);
+    assert_eq!(client.call(REVERSE, b"abc").unwrap(), b"cba");
+    assert!(matches!(client.call(REVERSE, b""), Err(Error::Status(Status::BadRequest))));
+    assert!(matches!(client.call(0x7F, b""), Err(Error::Status(Status::UnknownCommand))));
+
+    // The target exits once the client is gone
+    drop(client);
+    assert!(target.wait().unwrap().success());
+}
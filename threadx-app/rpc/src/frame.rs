@@ -0,0 +1,243 @@
+use core::fmt;
+use core::mem;
+
+/// Largest payload of a frame. A frame fits into a threadx-rs queue message, which is at
+/// most 16 words.
+pub const MAX_PAYLOAD: usize = 56;
+/// seq, code, payload and CRC
+const MAX_RAW: usize = 2 + MAX_PAYLOAD + 2;
+/// Largest encoded frame: COBS adds one byte per 254, then comes the terminating 0
+pub const MAX_FRAME: usize = MAX_RAW + 1 + 1;
+
+/// A request or response, see the crate documentation
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Frame {
+    pub seq: u8,
+    /// The command of a request, the `Status` of a response
+    pub code: u8,
+    len: u8,
+    payload: [u8; MAX_PAYLOAD],
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum FrameError {
+    /// The payload is longer than `MAX_PAYLOAD`
+    TooLong,
+    /// The frame is not valid COBS
+    Encoding,
+    /// The frame is too short to hold the header and CRC
+    TooShort,
+    /// The CRC does not match, the frame was damaged
+    Crc,
+}
+
+impl fmt::Display for FrameError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            FrameError::TooLong => "frame too long",
+            FrameError::Encoding => "invalid frame encoding",
+            FrameError::TooShort => "frame too short",
+            FrameError::Crc => "frame CRC mismatch",
+        })
+    }
+}
+
+#[cfg(feature = "std")]
+impl std::error::Error for FrameError {}
+
+impl Frame {
+    pub fn new(seq: u8, code: u8, payload: &[u8]) -> Result<Frame, FrameError> {
+        if payload.len() > MAX_PAYLOAD {
+            return Err(FrameError::TooLong);
+        }
+        let mut frame = Frame { seq, code, len: payload.len() as u8, payload: [0; MAX_PAYLOAD] };
+        frame.payload[..payload.len()].copy_from_slice(payload);
+        Ok(frame)
+    }
+
+    pub fn payload(&self) -> &[u8] {
+        &self.payload[..self.len as usize]
+    }
+
+    /// Encode the frame for the wire, including the terminating 0
+    pub fn encode<'a>(&self, buffer: &'a mut [u8; MAX_FRAME]) -> &'a [u8] {
+        let mut raw = [0; MAX_RAW];
+        let len = 2 + self.len as usize;
+        raw[0] = self.seq;
+        raw[1] = self.code;
+        raw[2..len].copy_from_slice(self.payload());
+        let crc = crc16(&raw[..len]);
+        raw[len..len + 2].copy_from_slice(&crc.to_le_bytes());
+
+        let encoded = cobs_encode(&raw[..len + 2], buffer);
+        buffer[encoded] = 0;
+        &buffer[..encoded + 1]
+    }
+
+    /// Decode a frame without its terminating 0
+    fn decode(encoded: &[u8]) -> Result<Frame, FrameError> {
+        let mut raw = [0; MAX_RAW];
+        let len = cobs_decode(encoded, &mut raw)?;
+        if len < 4 {
+            return Err(FrameError::TooShort);
+        }
+        let crc = u16::from_le_bytes([raw[len - 2], raw[len - 1]]);
+        if crc16(&raw[..len - 2]) != crc {
+            return Err(FrameError::Crc);
+        }
+        Frame::new(raw[0], raw[1], &raw[2..len - 2])
+    }
+}
+
+/// Reassembles frames from the received bytes
+pub struct Decoder {
+    buffer: [u8; MAX_FRAME - 1],
+    len: usize,
+    overflow: bool,
+}
+
+impl Decoder {
+    pub const fn new() -> Self {
+        Decoder { buffer: [0; MAX_FRAME - 1], len: 0, overflow: false }
+    }
+
+    /// Feed one received byte. At the end of each frame this returns the frame, or the
+    /// error if it was damaged.
+    pub fn push(&mut self, byte: u8) -> Option<Result<Frame, FrameError>> {
+        if byte != 0 {
+            match self.buffer.get_mut(self.len) {
+                Some(slot) => {
+                    *slot = byte;
+                    self.len += 1;
+                }
+                None => self.overflow = true,
+            }
+            return None;
+        }
+        let len = mem::replace(&mut self.len, 0);
+        if mem::replace(&mut self.overflow, false) {
+            return Some(Err(FrameError::TooLong));
+        }
+        // Terminators in a row, e.g. from a sender that resynchronizes with one
+        if len == 0 {
+            return None;
+        }
+        Some(Frame::decode(&self.buffer[..len]))
+    }
+}
+
+impl Default for Decoder {
+    fn default() -> Self {
+        Decoder::new()
+    }
+}
+
+/// CRC-16/CCITT-FALSE: polynomial 0x1021, initial value 0xFFFF
+pub fn crc16(bytes: &[u8]) -> u16 {
+    let mut crc = 0xFFFF_u16;
+    for &byte in bytes {
+        crc ^= (byte as u16) << 8;
+        for _ in 0..8 {
+            crc = if crc & 0x8000 != 0 { (crc << 1) ^ 0x1021 } else { crc << 1 };
+        }
+    }
+    crc
+}
+
+/// Returns the encoded length. `output` must be longer than `input` by one byte per 254.
+fn cobs_encode(input: &[u8], output: &mut [u8]) -> usize {
+    let mut code_index = 0;
+    let mut write = 1;
+    let mut code = 1_u8;
+    for &byte in input {
+        if byte != 0 {
+            output[write] = byte;
+            write += 1;
+            code += 1;
+        }
+        if byte == 0 || code == 0xFF {
+            output[code_index] = code;
+            code_index = write;
+            write += 1;
+            code = 1;
+        }
+    }
+    output[code_index] = code;
+    write
+}
+
+/// Returns the decoded length
+fn cobs_decode(input: &[u8], output: &mut [u8]) -> Result<usize, FrameError> {
+    let mut read = 0;
+    let mut write = 0;
+    while read < input.len() {
+        let code = input[read] as usize;
+        let end = read + code;
+        if code == 0 || end > input.len() {
+            return Err(FrameError::Encoding);
+        }
+        let block = &input[read + 1..end];
+        output.get_mut(write..write + block.len()).ok_or(FrameError::TooLong)?.copy_from_slice(block);
+        write += block.len();
+        read = end;
+        // Every block but the last and the full ones stands for a 0
+        if code != 0xFF && read < input.len() {
+            *output.get_mut(write).ok_or(FrameError::TooLong)? = 0;
+            write += 1;
+        }
+    }
+    Ok(write)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn decode_all(decoder: &mut Decoder, bytes: &[u8]) -> Option<Result<Frame, FrameError>> {
+        bytes.iter().filter_map(|&byte| decoder.push(byte)).last()
+    }
+
+    #[test]
+    fn crc_matches_the_check_value() {
+        assert_eq!(crc16(b"123456789"), 0x29B1);
+    }
+
+    #[test]
+    fn frames_survive_the_round_trip() {
+        let mut buffer = [0; MAX_FRAME];
+        let mut decoder = Decoder::new();
+        for payload in [&[][..], &[0, 0, 1, 0], &[0xFF; MAX_PAYLOAD], &[0; MAX_PAYLOAD]] {
+            let frame = Frame::new(7, 0x42, payload).unwrap();
+            let encoded = frame.encode(&mut buffer);
+            assert!(encoded.len() <= MAX_FRAME);
+            assert_eq!(encoded.iter().position(|&byte| byte == 0), Some(encoded.len() - 1));
+            assert_eq!(decode_all(&mut decoder, encoded), Some(Ok(frame)));
+        }
+    }
+
+    #[test]
+    fn decoder_resynchronizes_after_garbage() {
+        let mut buffer = [0; MAX_FRAME];
+        let frame = Frame::new(1, 2, b"ping").unwrap();
+        let mut decoder = Decoder::new();
+        assert_eq!(decode_all(&mut decoder, &[0x13, 0x37, 0x00]), Some(Err(FrameError::Encoding)));
+        assert_eq!(decode_all(&mut decoder, &[0xAA; 100]), None);
+        assert_eq!(decoder.push(0), Some(Err(FrameError::TooLong)));
+        assert_eq!(decode_all(&mut decoder, frame.encode(&mut buffer)), Some(Ok(frame)));
+    }
+
+    #[test]
+    fn damaged_frames_are_rejected() {
+        let mut buffer = [0; MAX_FRAME];
+        let len = Frame::new(1, 2, b"ping").unwrap().encode(&mut buffer).len();
+        buffer[3] ^= 0x01;
+        assert_eq!(decode_all(&mut Decoder::new(), &buffer[..len]), Some(Err(FrameError::Crc)));
+        assert_eq!(decode_all(&mut Decoder::new(), &[0x02, 0x01, 0x00]), Some(Err(FrameError::TooShort)));
+        assert_eq!(Frame::new(1, 2, &[0; MAX_PAYLOAD + 1]), Err(FrameError::TooLong));
+    }
+
+    #[test]
+    fn frames_fit_into_a_queue_message() {
+        assert!(core::mem::size_of::<Frame>() <= 16 * 4);
+    }
+}
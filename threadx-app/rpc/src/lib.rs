@@ -0,0 +1,65 @@
+//! Framed request/response protocol between a host and a threadx-rs application.
+//!
+//! The host sends a request with a command, the target answers it with a response that
+//! carries the same sequence number and a [`Status`]. Both are a [`Frame`]:
+//!
+//! ```text
+//! | seq | code | payload, up to MAX_PAYLOAD bytes | CRC-16/CCITT of the rest, LE |
+//! ```
+//!
+//! On the wire a frame is COBS encoded and ends with a 0 byte. A receiver that starts in
+//! the middle of a frame, or loses bytes, picks up again at the next frame. Damaged
+//! frames are dropped and the host retries after its timeout.
+//!
+//! Any byte stream carries the frames: a UART, an RTT channel, or a pty or TCP socket on
+//! the Linux port and QEMU. The `threadx` feature adds the target side in [`target`], the
+//! host side is the client in host-target-tests.
+
+#![no_std]
+
+#[cfg(feature = "std")]
+extern crate std;
+
+mod frame;
+#[cfg(feature = "threadx")]
+pub mod target;
+
+pub use frame::{crc16, Decoder, Frame, FrameError, MAX_FRAME, MAX_PAYLOAD};
+
+/// Commands that every target answers. Applications number their own commands from
+/// [`command::APPLICATION`].
+pub mod command {
+    /// Answers with the payload of the request
+    pub const PING: u8 = 0x01;
+    /// Answers with the ThreadX tick count, a little endian u32
+    pub const TICKS: u8 = 0x02;
+    /// First application defined command
+    pub const APPLICATION: u8 = 0x40;
+}
+
+/// Outcome of a request, the `code` of the response
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+#[repr(u8)]
+pub enum Status {
+    Ok = 0,
+    /// The target does not know the command
+    UnknownCommand = 1,
+    /// The payload does not fit the command
+    BadRequest = 2,
+    /// The command was carried out and failed
+    Failed = 3,
+}
+
+impl TryFrom<u8> for Status {
+    type Error = u8;
+
+    fn try_from(code: u8) -> Result<Self, u8> {
+        match code {
+            0 => Ok(Status::Ok),
+            1 => Ok(Status::UnknownCommand),
+            2 => Ok(Status::BadRequest),
+            3 => Ok(Status::Failed),
+            _ => Err(code),
+        }
+    }
+}
@@ -0,0 +1,146 @@
+//! Target side. Received bytes are decoded into a `Queue<Frame>` of requests and a dispatch
+//! thread answers them in order:
+//!
+//! ```ignore
+//! #[queue(capacity = 4)]
+//! pub static REQUESTS: Queue<Frame>;
+//!
+//! #[thread(priority = 10, stack = 2048)]
+//! fn rpc(requests: QueueReceiver<Frame>) {
+//!     threadx_rpc::target::dispatch(&mut uart_tx, &requests, handle).unwrap();
+//! }
+//! ```
+//!
+//! The bytes come from a UART interrupt or an RTT poll through a [`Receiver`], or from a
+//! thread that blocks on the link in [`receive`]. The link is anything that implements
+//! [`Read`] and [`Write`]; with the `std` feature that includes the `std::io` types, such as
+//! a `TcpStream` on the Linux port.
+
+use threadx_rs::queue::{QueueReceiveError, QueueReceiver, QueueSendError, QueueSender};
+use threadx_rs::time::Instant;
+use threadx_rs::WaitOption;
+
+use crate::{command, Decoder, Frame, Status, MAX_FRAME, MAX_PAYLOAD};
+
+/// Answers an application command with `request` as the payload. Writes the response
+/// payload to `response` and returns its length.
+pub type Handler = fn(command: u8, request: &[u8], response: &mut [u8; MAX_PAYLOAD]) -> Result<usize, Status>;
+
+/// The receiving half of a link. Blocks until at least one byte arrived, 0 bytes mean
+/// that the link was closed.
+pub trait Read {
+    type Error;
+    fn read(&mut self, buffer: &mut [u8]) -> Result<usize, Self::Error>;
+}
+
+/// The sending half of a link
+pub trait Write {
+    type Error;
+    fn write_all(&mut self, bytes: &[u8]) -> Result<(), Self::Error>;
+}
+
+#[cfg(feature = "std")]
+impl<T: std::io::Read> Read for T {
+    type Error = std::io::Error;
+
+    fn read(&mut self, buffer: &mut [u8]) -> Result<usize, std::io::Error> {
+        std::io::Read::read(self, buffer)
+    }
+}
+
+#[cfg(feature = "std")]
+impl<T: std::io::Write> Write for T {
+    type Error = std::io::Error;
+
+    fn write_all(&mut self, bytes: &[u8]) -> Result<(), std::io::Error> {
+        std::io::Write::write_all(self, bytes)?;
+        std::io::Write::flush(self)
+    }
+}
+
+#[derive(Debug)]
+pub enum Error<E> {
+    /// Reading or writing the link failed
+    Link(E),
+    /// Queueing a request failed
+    Send(QueueSendError<Frame>),
+    /// Taking a request failed
+    Receive(QueueReceiveError),
+}
+
+/// Decodes received bytes and queues the requests. Does not block, so it can be fed from
+/// an interrupt handler.
+pub struct Receiver {
+    decoder: Decoder,
+    requests: QueueSender<Frame>,
+}
+
+impl Receiver {
+    pub const fn new(requests: QueueSender<Frame>) -> Self {
+        Receiver { decoder: Decoder::new(), requests }
+    }
+
+    /// Damaged frames are dropped, as are requests that find the queue full. The host
+    /// retries them after its timeout.
+    pub fn push(&mut self, bytes: &[u8]) {
+        for &byte in bytes {
+            if let Some(Ok(request)) = self.decoder.push(byte) {
+                let _ = self.requests.send(request, WaitOption::NoWait);
+            }
+        }
+    }
+}
+
+/// Read requests from `link` and queue them, waiting while the queue is full. Returns
+/// when the link is closed.
+pub fn receive<R: Read>(link: &mut R, requests: &QueueSender<Frame>) -> Result<(), Error<R::Error>> {
+    let mut decoder = Decoder::new();
+    let mut buffer = [0; MAX_FRAME];
+    loop {
+        let len = link.read(&mut buffer).map_err(Error::Link)?;
+        if len == 0 {
+            return Ok(());
+        }
+        for &byte in &buffer[..len] {
+            if let Some(Ok(request)) = decoder.push(byte) {
+                requests.send(request, WaitOption::WaitForever).map_err(Error::Send)?;
+            }
+        }
+    }
+}
+
+/// Answer the queued requests in order and write the responses to `link`. Does not return
+/// unless the link fails.
+pub fn dispatch<W: Write>(link: &mut W, requests: &QueueReceiver<Frame>, handler: Handler) -> Result<(), Error<W::Error>> {
+    let mut buffer = [0; MAX_FRAME];
+    loop {
+        let request = requests.receive(WaitOption::WaitForever).map_err(Error::Receive)?;
+        link.write_all(answer(&request, handler).encode(&mut buffer)).map_err(Error::Link)?;
+    }
+}
+
+/// The response to `request`. The built-in commands are answered here, the others by
+/// `handler`.
+pub fn answer(request: &Frame, handler: Handler) -> Frame {
+    let mut payload = [0; MAX_PAYLOAD];
+    let result = match request.code {
+        command::PING => {
+            payload[..request.payload().len()].copy_from_slice(request.payload());
+            Ok(request.payload().len())
+        }
+        command::TICKS => {
+            payload[..4].copy_from_slice(&Instant::now().ticks().to_le_bytes());
+            Ok(4)
+        }
+        command => handler(command, request.payload(), &mut payload),
+    };
+    match result {
+        Ok(len) => match payload.get(..len) {
+            Some(payload) => Frame::new(request.seq, Status::Ok as u8, payload),
+            // The handler claims more than it could have written
+            None => Frame::new(request.seq, Status::Failed as u8, &[]),
+        },
+        Err(status) => Frame::new(request.seq, status as u8, &[]),
+    }
+    .unwrap_or_else(|_| Frame::new(request.seq, Status::Failed as u8, &[]).unwrap())
+}
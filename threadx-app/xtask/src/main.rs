@@ -1,10 +1,31 @@
 #![allow(dead_code)]
 #![deny(unused_must_use)]
 
-use std::{env, path::PathBuf};
+use std::{
+    env, fs,
+    io::{BufRead, BufReader},
+    net::TcpStream,
+    path::{Path, PathBuf},
+    process::{Child, Command, Stdio},
+    thread,
+    time::{Duration, Instant},
+};
 
+use anyhow::bail;
 use xshell::cmd;
 
+/// The emulated machine for `test qemu`, the `qemu` board of the cross workspace
+const QEMU: &str = "qemu-system-arm -cpu cortex-m3 -machine mps2-an385 -nographic -semihosting-config enable=on,target=native -kernel";
+/// The applications run forever, so they pass if they don't fault within this time
+const QEMU_TIMEOUT: Duration = Duration::from_secs(10);
+const RUNNER: &str = "CARGO_TARGET_THUMBV7M_NONE_EABI_RUNNER";
+/// The `rpc` application built for the `qemu` board, relative to the cross workspace
+const QEMU_RPC_ELF: &str = "target/thumbv7m-none-eabi/release/rpc";
+/// The TCP port that QEMU connects UART0 of the `rpc` application to
+const QEMU_RPC_PORT: u16 = 4000;
+/// Percent a benchmark may get slower before `bench compare` fails
+const BENCH_THRESHOLD: f64 = 10.0;
+
 fn main() -> Result<(), anyhow::Error> {
     let args = env::args().skip(1).collect::<Vec<_>>();
     let args = args.iter().map(|s| &**s).collect::<Vec<_>>();
@@ -13,9 +34,18 @@ fn main() -> Result<(), anyhow::Error> {
         ["test", "all"] => test_all(),
         ["test", "host"] => test_host(),
         ["test", "host-target"] => test_host_target(),
+        ["test", "host-target", "qemu"] => test_host_target_qemu(),
         ["test", "target"] => test_target(),
+        ["test", "qemu"] => test_qemu(),
+        ["qemu-run", "--timeout-ok", elf] => qemu_run(elf, true),
+        ["qemu-run", elf] => qemu_run(elf, false),
+        ["bench", "compare", baseline, current] => bench_compare(baseline, current, BENCH_THRESHOLD),
+        ["bench", "compare", "--threshold", threshold, baseline, current] => {
+            bench_compare(baseline, current, threshold.parse()?)
+        }
         _ => {
-            println!("USAGE cargo xtask test [all|host|host-target|target]");
+            println!("USAGE cargo xtask test [all|host|host-target [qemu]|target|qemu]");
+            println!("      cargo xtask bench compare [--threshold PERCENT] BASELINE CURRENT");
             Ok(())
         }
     }
@@ -30,15 +60,76 @@ fn test_all() -> Result<(), anyhow::Error> {
 fn test_host() -> Result<(), anyhow::Error> {
     let _p = xshell::pushd(root_dir())?;
     cmd!("cargo test --workspace --exclude host-target-tests").run()?;
+
+    // Outside the workspace, its tests need the sim feature
+    let _p = xshell::pushd(root_dir().join("bench"))?;
+    cmd!("cargo test --features sim").run()?;
     Ok(())
 }
 
+/// Run host-target-tests against the `rpc` binary of the Linux port
 fn test_host_target() -> Result<(), anyhow::Error> {
-    flash()?;
+    let native = root_dir().join("native");
+    {
+        let _p = xshell::pushd(&native)?;
+        cmd!("cargo build --bin rpc").run()?;
+    }
+    let mut target = Command::new(native.join("target/debug/rpc"))
+        .arg("127.0.0.1:0")
+        .stdout(Stdio::piped())
+        .spawn()?;
+    let mut line = String::new();
+    BufReader::new(target.stdout.take().unwrap()).read_line(&mut line)?;
+    let Some(address) = line.trim().strip_prefix("Listening on ") else {
+        target.kill()?;
+        bail!("rpc did not start listening: {line}");
+    };
 
-    let _p = xshell::pushd(root_dir())?;
-    cmd!("cargo test -p host-target-tests").run()?;
+    let result = host_target(&format!("tcp:{address}"));
+    if result.is_err() {
+        target.kill()?;
+    }
+    // The target exits once the client disconnects
+    let status = target.wait()?;
+    result?;
+    if !status.success() {
+        bail!("rpc failed: {status}");
+    }
+    Ok(())
+}
 
+/// Run host-target-tests against the `rpc` application on the `qemu` board, with UART0
+/// on a TCP socket
+fn test_host_target_qemu() -> Result<(), anyhow::Error> {
+    let cross = root_dir().join("cross");
+    {
+        let _p = xshell::pushd(&cross)?;
+        cmd!("cargo build --release -p threadx-app --no-default-features --features qemu --bin rpc").run()?;
+    }
+    let elf = cross.join(QEMU_RPC_ELF);
+    let serial = format!("tcp::{QEMU_RPC_PORT},server=on,wait=off");
+    let (mut qemu, mut decoder) = qemu(&elf, &["-serial", &serial])?;
+
+    // QEMU listens once the machine is up. It accepts the next client after this one.
+    let address = format!("localhost:{QEMU_RPC_PORT}");
+    let deadline = Instant::now() + QEMU_TIMEOUT;
+    while TcpStream::connect(&address).is_err() {
+        if Instant::now() >= deadline {
+            break;
+        }
+        thread::sleep(Duration::from_millis(100));
+    }
+
+    let result = host_target(&format!("tcp:{address}"));
+    qemu.kill()?;
+    qemu.wait()?;
+    decoder.wait()?;
+    result
+}
+
+fn host_target(link: &str) -> Result<(), anyhow::Error> {
+    let _p = xshell::pushd(root_dir())?;
+    cmd!("cargo run -p host-target-tests -- {link}").run()?;
     Ok(())
 }
 
@@ -48,12 +139,132 @@ fn test_target() -> Result<(), anyhow::Error> {
     Ok(())
 }
 
-fn flash() -> Result<(), anyhow::Error> {
+/// Run the applications of `app/src/bin` and the target tests on the `qemu` board, with
+/// `xtask qemu-run` as the cargo runner
+fn test_qemu() -> Result<(), anyhow::Error> {
     let _p = xshell::pushd(root_dir().join("cross"))?;
-    cmd!("cargo flash --chip STM32F103C8 --release").run()?;
+    let runner = format!("{} qemu-run", env::current_exe()?.display());
+
+    {
+        let _e = xshell::pushenv(RUNNER, format!("{runner} --timeout-ok"));
+        for bin in xshell::read_dir("app/src/bin")? {
+            let bin = bin.file_stem().unwrap().to_string_lossy().into_owned();
+            cmd!("cargo run --release -p threadx-app --no-default-features --features qemu --bin {bin}").run()?;
+        }
+    }
+
+    let _e = xshell::pushenv(RUNNER, runner);
+    cmd!("cargo test -p target-tests --no-default-features --features qemu").run()?;
+    Ok(())
+}
+
+/// Run an ELF in QEMU and decode its defmt output. Passes if the program exits through
+/// semihosting with success, or if it still runs after `QEMU_TIMEOUT` with `timeout_ok`.
+fn qemu_run(elf: &str, timeout_ok: bool) -> Result<(), anyhow::Error> {
+    let (mut qemu, mut decoder) = qemu(Path::new(elf), &[])?;
+
+    let deadline = Instant::now() + QEMU_TIMEOUT;
+    let status = loop {
+        if let Some(status) = qemu.try_wait()? {
+            break Some(status);
+        }
+        if Instant::now() >= deadline {
+            qemu.kill()?;
+            qemu.wait()?;
+            break None;
+        }
+        thread::sleep(Duration::from_millis(100));
+    };
+    decoder.wait()?;
+
+    match status {
+        Some(status) if status.success() => Ok(()),
+        Some(status) => bail!("{elf} failed in QEMU: {status}"),
+        None if timeout_ok => {
+            println!("{elf} ran for {QEMU_TIMEOUT:?} without a fault");
+            Ok(())
+        }
+        None => bail!("{elf} timed out in QEMU after {QEMU_TIMEOUT:?}"),
+    }
+}
+
+/// Start QEMU with `elf` and decode its defmt output. `args` go before the QEMU arguments,
+/// which end with `-kernel`.
+fn qemu(elf: &Path, args: &[&str]) -> Result<(Child, Child), anyhow::Error> {
+    let mut qemu_args = QEMU.split_whitespace();
+    let mut qemu = Command::new(qemu_args.next().unwrap())
+        .args(args)
+        .args(qemu_args)
+        .arg(elf)
+        .stdout(Stdio::piped())
+        .spawn()?;
+    let decoder = Command::new("defmt-print")
+        .arg("-e")
+        .arg(elf)
+        .stdin(qemu.stdout.take().unwrap())
+        .spawn()?;
+    Ok((qemu, decoder))
+}
+
+/// Compare the results of two benchmark runs, the JSON lines printed by threadx-bench.
+/// Fails if a benchmark is missing or its minimum got more than `threshold` percent
+/// slower. The minimum is least disturbed by interrupts and, on the Linux port, by the host.
+fn bench_compare(baseline: &str, current: &str, threshold: f64) -> Result<(), anyhow::Error> {
+    let baseline = bench_results(baseline)?;
+    let current = bench_results(current)?;
+    if baseline.is_empty() {
+        bail!("no benchmark results in the baseline");
+    }
+
+    let mut regressions = 0;
+    println!("{:<24} {:>12} {:>12} {:>8}", "benchmark", "baseline", "current", "change");
+    for (name, unit, before) in &baseline {
+        let Some((_, current_unit, after)) = current.iter().find(|(current_name, ..)| current_name == name) else {
+            println!("{name:<24} {before:>12} {:>12}", "missing");
+            regressions += 1;
+            continue;
+        };
+        if current_unit != unit {
+            bail!("{name} is measured in {unit} in the baseline and in {current_unit} now");
+        }
+        let change = (*after as f64 - *before as f64) * 100.0 / (*before).max(1) as f64;
+        let regressed = change > threshold;
+        regressions += regressed as usize;
+        let flag = if regressed { "  REGRESSION" } else { "" };
+        println!("{name:<24} {before:>12} {after:>12} {change:>+7.1}%{flag}");
+    }
+
+    if regressions > 0 {
+        bail!("{regressions} benchmarks regressed by more than {threshold}%");
+    }
     Ok(())
 }
 
+/// Name, unit and minimum of each result in `path`. Other lines, such as log messages,
+/// are skipped.
+fn bench_results(path: &str) -> Result<Vec<(String, String, u64)>, anyhow::Error> {
+    let mut results = Vec::new();
+    for line in fs::read_to_string(path)?.lines() {
+        let Some(start) = line.find(r#"{"name":"#) else {
+            continue;
+        };
+        let json = &line[start..];
+        let (Some(name), Some(unit), Some(min)) = (json_field(json, "name"), json_field(json, "unit"), json_field(json, "min")) else {
+            bail!("{path}: invalid result {json}");
+        };
+        results.push((name.to_string(), unit.to_string(), min.parse()?));
+    }
+    Ok(results)
+}
+
+/// The value of `key` in a flat JSON object without nested objects or escaped quotes
+fn json_field<'a>(json: &'a str, key: &str) -> Option<&'a str> {
+    let key = format!(r#""{key}":"#);
+    let value = &json[json.find(&key)? + key.len()..];
+    let end = value.find([',', '}'])?;
+    Some(value[..end].trim_matches('"'))
+}
+
 fn root_dir() -> PathBuf {
     let mut xtask_dir = PathBuf::from(env!("CARGO_MANIFEST_DIR"));
     xtask_dir.pop();
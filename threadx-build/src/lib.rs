@@ -0,0 +1,486 @@
+//! Generate the kernel objects of a threadx-rs application from a `threadx.toml` file.
+//!
+//! The file lists the threads, queues, pools, timers and other kernel objects of the
+//! application. [`generate`] turns it into a `system` module that uses the `app`
+//! macro of threadx-rs, so the `macros` feature must be enabled. Call it from `build.rs`:
+//!
+//! ```ignore
+//! fn main() {
+//!     threadx_build::generate("threadx.toml").unwrap();
+//! }
+//! ```
+//!
+//! and include the module in the application:
+//!
+//! ```ignore
+//! include!(concat!(env!("OUT_DIR"), "/threadx_system.rs"));
+//!
+//! #[cortex_m_rt::entry]
+//! fn main() -> ! {
+//!     system::start();
+//!     ...
+//! }
+//! ```
+//!
+//! Setting `THREADX_TOML` selects a different file, e.g. one per product variant.
+//!
+//! ```toml
+//! heap = 16384                     # optional, by default exactly what is needed
+//! init = "crate::board_init"       # optional fn(ticks_per_second: u32)
+//! define = "crate::define"         # optional fn(memory: &'static mut [u8])
+//!
+//! [[thread]]
+//! name = "producer"
+//! entry = "crate::tasks::producer" # fn()
+//! priority = 5
+//! stack = 1024
+//! preempt_threshold = 5            # optional, defaults to priority
+//! time_slice = 0                   # optional
+//!
+//! [[queue]]
+//! name = "cmd"
+//! message = "crate::Cmd"
+//! capacity = 8
+//!
+//! [[mutex]]
+//! name = "config"
+//! type = "crate::Config"
+//! value = "crate::Config::new()"
+//! inherit = true                   # optional
+//!
+//! [[event_flags]]
+//! name = "events"
+//!
+//! [[semaphore]]
+//! name = "ready"
+//! initial = 0                      # optional
+//!
+//! [[byte_pool]]
+//! name = "buffers"
+//! size = 4096
+//!
+//! [[block_pool]]
+//! name = "frames"
+//! block_size = 64
+//! blocks = 16
+//!
+//! [[timer]]
+//! name = "heartbeat"
+//! callback = "crate::heartbeat"    # fn()
+//! initial_ms = 1000
+//! period_ms = 1000                 # optional, 0 for a one shot timer
+//! auto_start = true                # optional
+//! ```
+//!
+//! Entry and callback functions take no arguments. They reach the objects through the
+//! typed handles of the generated module: `system::cmd_sender()`,
+//! `system::cmd_receiver()`, `system::config()`, `system::events()`,
+//! `system::ready()`, `system::buffers()` and `system::frames()`.
+
+use std::fmt::{self, Display, Write};
+use std::path::{Path, PathBuf};
+
+use serde::Deserialize;
+
+/// Name of the generated file in `OUT_DIR`
+pub const OUTPUT_FILE: &str = "threadx_system.rs";
+
+/// Environment variable that overrides the path passed to [`generate`]
+pub const CONFIG_ENV: &str = "THREADX_TOML";
+
+#[derive(Debug)]
+pub enum Error {
+    Io(PathBuf, std::io::Error),
+    Toml(toml::de::Error),
+    /// The file parsed but describes an invalid application
+    Invalid(String),
+}
+
+impl Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Error::Io(path, e) => write!(f, "{}: {e}", path.display()),
+            Error::Toml(e) => write!(f, "{e}"),
+            Error::Invalid(message) => write!(f, "{message}"),
+        }
+    }
+}
+
+impl std::error::Error for Error {}
+
+fn invalid(message: String) -> Error {
+    Error::Invalid(message)
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Config {
+    pub heap: Option<usize>,
+    pub init: Option<String>,
+    pub define: Option<String>,
+    #[serde(default, rename = "thread")]
+    pub threads: Vec<ThreadConfig>,
+    #[serde(default, rename = "queue")]
+    pub queues: Vec<QueueConfig>,
+    #[serde(default, rename = "mutex")]
+    pub mutexes: Vec<MutexConfig>,
+    #[serde(default)]
+    pub event_flags: Vec<EventFlagsConfig>,
+    #[serde(default, rename = "semaphore")]
+    pub semaphores: Vec<SemaphoreConfig>,
+    #[serde(default, rename = "byte_pool")]
+    pub byte_pools: Vec<BytePoolConfig>,
+    #[serde(default, rename = "block_pool")]
+    pub block_pools: Vec<BlockPoolConfig>,
+    #[serde(default, rename = "timer")]
+    pub timers: Vec<TimerConfig>,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct ThreadConfig {
+    pub name: String,
+    pub entry: String,
+    pub priority: u32,
+    pub stack: usize,
+    pub preempt_threshold: Option<u32>,
+    #[serde(default)]
+    pub time_slice: u32,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct QueueConfig {
+    pub name: String,
+    pub message: String,
+    pub capacity: usize,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct MutexConfig {
+    pub name: String,
+    #[serde(rename = "type")]
+    pub ty: String,
+    pub value: String,
+    #[serde(default = "default_true")]
+    pub inherit: bool,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct EventFlagsConfig {
+    pub name: String,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct SemaphoreConfig {
+    pub name: String,
+    #[serde(default)]
+    pub initial: u32,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct BytePoolConfig {
+    pub name: String,
+    pub size: usize,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct BlockPoolConfig {
+    pub name: String,
+    pub block_size: usize,
+    pub blocks: usize,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct TimerConfig {
+    pub name: String,
+    pub callback: String,
+    pub initial_ms: u64,
+    #[serde(default)]
+    pub period_ms: u64,
+    #[serde(default = "default_true")]
+    pub auto_start: bool,
+}
+
+fn default_true() -> bool {
+    true
+}
+
+fn is_identifier(name: &str) -> bool {
+    let mut chars = name.chars();
+    matches!(chars.next(), Some(c) if c.is_ascii_alphabetic() || c == '_')
+        && chars.all(|c| c.is_ascii_alphanumeric() || c == '_')
+}
+
+const NOT_STARTED: &str = "system::start has not created the kernel objects yet";
+
+impl Config {
+    pub fn parse(source: &str) -> Result<Self, Error> {
+        toml::from_str(source).map_err(Error::Toml)
+    }
+
+    /// Names of all declarations, in order, with what they are
+    fn names(&self) -> impl Iterator<Item = (&str, &str)> {
+        let threads = self.threads.iter().map(|t| (t.name.as_str(), "thread"));
+        let queues = self.queues.iter().map(|q| (q.name.as_str(), "queue"));
+        let mutexes = self.mutexes.iter().map(|m| (m.name.as_str(), "mutex"));
+        let event_flags = self.event_flags.iter().map(|e| (e.name.as_str(), "event_flags"));
+        let semaphores = self.semaphores.iter().map(|s| (s.name.as_str(), "semaphore"));
+        let byte_pools = self.byte_pools.iter().map(|p| (p.name.as_str(), "byte_pool"));
+        let block_pools = self.block_pools.iter().map(|p| (p.name.as_str(), "block_pool"));
+        let timers = self.timers.iter().map(|t| (t.name.as_str(), "timer"));
+        threads
+            .chain(queues)
+            .chain(mutexes)
+            .chain(event_flags)
+            .chain(semaphores)
+            .chain(byte_pools)
+            .chain(block_pools)
+            .chain(timers)
+    }
+
+    /// Checks that can be made on the file alone. Priorities against `TX_MAX_PRIORITIES`,
+    /// stack sizes and the heap size are checked when the generated module is compiled.
+    pub fn validate(&self) -> Result<(), Error> {
+        let mut seen: Vec<String> = Vec::new();
+        for (name, kind) in self.names() {
+            if !is_identifier(name) {
+                return Err(invalid(format!("{kind} `{name}`: names must be Rust identifiers")));
+            }
+            let lower = name.to_lowercase();
+            if seen.contains(&lower) {
+                return Err(invalid(format!("{kind} `{name}`: the name is used more than once")));
+            }
+            seen.push(lower);
+        }
+        for thread in &self.threads {
+            if thread.preempt_threshold.is_some_and(|t| t > thread.priority) {
+                return Err(invalid(format!(
+                    "thread `{}`: preempt_threshold must not be greater than priority",
+                    thread.name
+                )));
+            }
+        }
+        for queue in &self.queues {
+            if queue.capacity == 0 {
+                return Err(invalid(format!("queue `{}`: capacity must not be 0", queue.name)));
+            }
+        }
+        for timer in &self.timers {
+            if timer.initial_ms == 0 {
+                return Err(invalid(format!("timer `{}`: initial_ms must not be 0", timer.name)));
+            }
+        }
+        Ok(())
+    }
+
+    /// The source of the `system` module
+    pub fn to_rust(&self) -> Result<String, Error> {
+        self.validate()?;
+        let mut out = String::new();
+        // Writing to a String does not fail
+        let _ = self.write_module(&mut out);
+        Ok(out)
+    }
+
+    fn write_module(&self, out: &mut String) -> fmt::Result {
+        writeln!(out, "// Generated by threadx-build. Do not edit.")?;
+        match self.heap {
+            Some(heap) => writeln!(out, "#[::threadx_rs::app(heap = {heap})]")?,
+            None => writeln!(out, "#[::threadx_rs::app]")?,
+        }
+        writeln!(out, "pub mod system {{")?;
+        writeln!(out, "    #[allow(unused_imports)]")?;
+        writeln!(out, "    use ::threadx_rs::semaphore::SemaphoreOwner;")?;
+        if let Some(init) = &self.init {
+            writeln!(out, "    #[init]")?;
+            writeln!(out, "    fn init(ticks_per_second: u32) {{ {init}(ticks_per_second) }}")?;
+        }
+        if let Some(define) = &self.define {
+            writeln!(out, "    #[define]")?;
+            writeln!(out, "    fn define(memory: &'static mut [u8]) {{ {define}(memory) }}")?;
+        }
+        for queue in &self.queues {
+            let (name, message) = (&queue.name, &queue.message);
+            let cell = name.to_uppercase();
+            writeln!(out, "    #[queue(capacity = {})]", queue.capacity)?;
+            writeln!(out, "    pub static {cell}: ::threadx_rs::queue::Queue<{message}>;")?;
+            writeln!(out, "    /// Sending end of queue `{name}`")?;
+            writeln!(
+                out,
+                "    pub fn {name}_sender() -> ::threadx_rs::queue::QueueSender<{message}> {{ {cell}.get().expect({NOT_STARTED:?}).0.clone() }}"
+            )?;
+            writeln!(out, "    /// Receiving end of queue `{name}`")?;
+            writeln!(
+                out,
+                "    pub fn {name}_receiver() -> ::threadx_rs::queue::QueueReceiver<{message}> {{ {cell}.get().expect({NOT_STARTED:?}).1.clone() }}"
+            )?;
+        }
+        for mutex in &self.mutexes {
+            let (name, ty) = (&mutex.name, &mutex.ty);
+            let cell = name.to_uppercase();
+            writeln!(out, "    #[mutex(inherit = {})]", mutex.inherit)?;
+            writeln!(
+                out,
+                "    pub static {cell}: ::threadx_rs::mutex::Mutex<{ty}> = ::threadx_rs::mutex::Mutex::new({});",
+                mutex.value
+            )?;
+            writeln!(out, "    /// Mutex `{name}`")?;
+            writeln!(
+                out,
+                "    pub fn {name}() -> &'static ::threadx_rs::mutex::Mutex<{ty}> {{ {cell}.get().expect({NOT_STARTED:?}) }}"
+            )?;
+        }
+        for flags in &self.event_flags {
+            let name = &flags.name;
+            let cell = name.to_uppercase();
+            writeln!(out, "    #[event_flags]")?;
+            writeln!(out, "    pub static {cell}: ::threadx_rs::event_flags::EventFlagsGroup;")?;
+            writeln!(out, "    /// Event flags group `{name}`")?;
+            writeln!(
+                out,
+                "    pub fn {name}() -> &'static ::threadx_rs::event_flags::EventFlagsGroup {{ {cell}.get().expect({NOT_STARTED:?}) }}"
+            )?;
+        }
+        for semaphore in &self.semaphores {
+            let name = &semaphore.name;
+            let cell = name.to_uppercase();
+            writeln!(out, "    #[semaphore(initial = {})]", semaphore.initial)?;
+            writeln!(out, "    pub static {cell}: ::threadx_rs::semaphore::Semaphore;")?;
+            writeln!(out, "    /// Semaphore `{name}`")?;
+            writeln!(
+                out,
+                "    pub fn {name}() -> ::threadx_rs::semaphore::SemaphoreUserHandle {{ {cell}.get().expect({NOT_STARTED:?}).get_semaphore_user() }}"
+            )?;
+        }
+        for pool in &self.byte_pools {
+            let name = &pool.name;
+            let cell = name.to_uppercase();
+            writeln!(out, "    #[byte_pool(size = {})]", pool.size)?;
+            writeln!(out, "    pub static {cell}: ::threadx_rs::pool::BytePool;")?;
+            writeln!(out, "    /// Byte pool `{name}`")?;
+            writeln!(
+                out,
+                "    pub fn {name}() -> &'static ::threadx_rs::pool::BytePoolHandle {{ {cell}.get().expect({NOT_STARTED:?}) }}"
+            )?;
+        }
+        for pool in &self.block_pools {
+            let name = &pool.name;
+            let cell = name.to_uppercase();
+            writeln!(out, "    #[block_pool(block_size = {}, blocks = {})]", pool.block_size, pool.blocks)?;
+            writeln!(out, "    pub static {cell}: ::threadx_rs::pool::BlockPool;")?;
+            writeln!(out, "    /// Block pool `{name}`")?;
+            writeln!(
+                out,
+                "    pub fn {name}() -> &'static ::threadx_rs::pool::BlockPoolHandle {{ {cell}.get().expect({NOT_STARTED:?}) }}"
+            )?;
+        }
+        for thread in &self.threads {
+            write!(out, "    #[thread(priority = {}, stack = {}", thread.priority, thread.stack)?;
+            if let Some(threshold) = thread.preempt_threshold {
+                write!(out, ", preempt_threshold = {threshold}")?;
+            }
+            writeln!(out, ", time_slice = {})]", thread.time_slice)?;
+            writeln!(out, "    fn {}() {{ {}(); }}", thread.name, thread.entry)?;
+        }
+        for timer in &self.timers {
+            writeln!(
+                out,
+                "    #[timer(initial_ms = {}, period_ms = {}, auto_start = {})]",
+                timer.initial_ms, timer.period_ms, timer.auto_start
+            )?;
+            writeln!(out, "    fn {}() {{ {}(); }}", timer.name, timer.callback)?;
+        }
+        writeln!(out, "}}")
+    }
+}
+
+/// Read the system description at `path`, or at the path in `THREADX_TOML` if it is set,
+/// and write the `system` module to `OUT_DIR`. Meant to be called from `build.rs`.
+/// Returns the path of the generated file.
+pub fn generate(path: impl AsRef<Path>) -> Result<PathBuf, Error> {
+    println!("cargo:rerun-if-env-changed={CONFIG_ENV}");
+    let path = std::env::var_os(CONFIG_ENV).map(PathBuf::from).unwrap_or_else(|| path.as_ref().to_path_buf());
+    println!("cargo:rerun-if-changed={}", path.display());
+    let out_dir = std::env::var_os("OUT_DIR")
+        .map(PathBuf::from)
+        .ok_or_else(|| invalid("OUT_DIR is not set, generate must be called from build.rs".into()))?;
+    generate_to(&path, &out_dir.join(OUTPUT_FILE))?;
+    Ok(out_dir.join(OUTPUT_FILE))
+}
+
+/// Read the system description at `path` and write the `system` module to `output`
+pub fn generate_to(path: &Path, output: &Path) -> Result<(), Error> {
+    let source = std::fs::read_to_string(path).map_err(|e| Error::Io(path.to_path_buf(), e))?;
+    let module = Config::parse(&source)?.to_rust()?;
+    std::fs::write(output, module).map_err(|e| Error::Io(output.to_path_buf(), e))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const EXAMPLE: &str = r#"
+        heap = 8192
+        init = "crate::board_init"
+
+        [[thread]]
+        name = "producer"
+        entry = "crate::producer"
+        priority = 5
+        stack = 1024
+
+        [[queue]]
+        name = "cmd"
+        message = "u32"
+        capacity = 8
+
+        [[timer]]
+        name = "heartbeat"
+        callback = "crate::heartbeat"
+        initial_ms = 100
+        period_ms = 100
+    "#;
+
+    #[test]
+    fn generates_app_module() {
+        let module = Config::parse(EXAMPLE).unwrap().to_rust().unwrap();
+        assert!(module.contains("#[::threadx_rs::app(heap = 8192)]"));
+        assert!(module.contains("fn init(ticks_per_second: u32) { crate::board_init(ticks_per_second) }"));
+        assert!(module.contains("#[queue(capacity = 8)]\n    pub static CMD: ::threadx_rs::queue::Queue<u32>;"));
+        assert!(module.contains("pub fn cmd_sender() -> ::threadx_rs::queue::QueueSender<u32>"));
+        assert!(module.contains("#[thread(priority = 5, stack = 1024, time_slice = 0)]\n    fn producer() { crate::producer(); }"));
+        assert!(module.contains("#[timer(initial_ms = 100, period_ms = 100, auto_start = true)]"));
+    }
+
+    #[test]
+    fn rejects_unknown_fields() {
+        let error = Config::parse("[[thread]]\nname = \"t\"\nentry = \"f\"\npriority = 1\nstack = 512\nprio = 2\n").unwrap_err();
+        assert!(error.to_string().contains("unknown field `prio`"), "{error}");
+    }
+
+    #[test]
+    fn rejects_duplicate_names() {
+        let config = Config::parse(
+            "[[queue]]\nname = \"cmd\"\nmessage = \"u32\"\ncapacity = 1\n[[event_flags]]\nname = \"CMD\"\n",
+        )
+        .unwrap();
+        let error = config.to_rust().unwrap_err();
+        assert!(error.to_string().contains("used more than once"), "{error}");
+    }
+
+    #[test]
+    fn rejects_preempt_threshold_above_priority() {
+        let config = Config::parse(
+            "[[thread]]\nname = \"t\"\nentry = \"f\"\npriority = 4\nstack = 512\npreempt_threshold = 6\n",
+        )
+        .unwrap();
+        assert!(config.to_rust().unwrap_err().to_string().contains("preempt_threshold"));
+    }
+}
@@ -0,0 +1,706 @@
+//! Parsing and code generation for `#[app]`.
+
+use proc_macro2::{Span, TokenStream};
+use quote::{format_ident, quote, quote_spanned};
+use syn::parse::{Parse, ParseStream, Parser};
+use syn::punctuated::Punctuated;
+use syn::spanned::Spanned;
+use syn::{
+    Attribute, Error, Expr, FnArg, GenericArgument, Ident, Item, ItemFn, ItemMod, Meta, MetaNameValue, Pat,
+    PathArguments, Result, Token, Type, Visibility,
+};
+
+/// The `name = value` arguments of an attribute
+struct Args {
+    span: Span,
+    values: Vec<(Ident, Expr)>,
+}
+
+impl Args {
+    fn from_tokens(span: Span, tokens: TokenStream) -> Result<Self> {
+        let pairs = Punctuated::<MetaNameValue, Token![,]>::parse_terminated.parse2(tokens)?;
+        let mut values: Vec<(Ident, Expr)> = Vec::new();
+        for pair in pairs {
+            let name = pair.path.require_ident()?.clone();
+            if values.iter().any(|(n, _)| *n == name) {
+                return Err(Error::new(name.span(), format!("duplicate argument `{name}`")));
+            }
+            values.push((name, pair.value));
+        }
+        Ok(Args { span, values })
+    }
+
+    fn from_attribute(attr: &Attribute) -> Result<Self> {
+        match &attr.meta {
+            Meta::Path(_) => Ok(Args { span: attr.span(), values: Vec::new() }),
+            Meta::List(list) => Self::from_tokens(attr.span(), list.tokens.clone()),
+            Meta::NameValue(_) => Err(Error::new(attr.span(), "expected `name(argument = value, ..)`")),
+        }
+    }
+
+    fn take(&mut self, name: &str) -> Option<Expr> {
+        let index = self.values.iter().position(|(n, _)| n == name)?;
+        Some(self.values.remove(index).1)
+    }
+
+    fn require(&mut self, name: &str) -> Result<Expr> {
+        self.take(name)
+            .ok_or_else(|| Error::new(self.span, format!("missing argument `{name}`")))
+    }
+
+    /// Fails on arguments that were not taken
+    fn finish(self) -> Result<()> {
+        match self.values.first() {
+            Some((name, _)) => Err(Error::new(name.span(), format!("unknown argument `{name}`"))),
+            None => Ok(()),
+        }
+    }
+}
+
+/// Last segment of the path of an attribute, so that `#[queue]` and `#[threadx::queue]`
+/// are treated the same
+fn attribute_name(attr: &Attribute) -> String {
+    attr.path().segments.last().map(|s| s.ident.to_string()).unwrap_or_default()
+}
+
+/// Remove the first attribute named one of `names` from `attrs`
+fn take_attribute(attrs: &mut Vec<Attribute>, names: &[&str]) -> Option<Attribute> {
+    let index = attrs.iter().position(|a| names.contains(&attribute_name(a).as_str()))?;
+    Some(attrs.remove(index))
+}
+
+/// The type argument of a type like `Queue<T>`, if its last segment is `name`
+fn type_argument<'a>(ty: &'a Type, name: &str) -> Option<&'a Type> {
+    let Type::Path(path) = ty else { return None };
+    let segment = path.path.segments.last()?;
+    if segment.ident != name {
+        return None;
+    }
+    let PathArguments::AngleBracketed(args) = &segment.arguments else { return None };
+    args.args.iter().find_map(|arg| match arg {
+        GenericArgument::Type(ty) => Some(ty),
+        _ => None,
+    })
+}
+
+fn type_name(ty: &Type) -> Option<String> {
+    match ty {
+        Type::Path(path) => path.path.segments.last().map(|s| s.ident.to_string()),
+        Type::Reference(reference) => type_name(&reference.elem),
+        _ => None,
+    }
+}
+
+/// A `static` in the app module. Kernel objects are declared without a value, which is
+/// not valid Rust, so syn hands these over as tokens.
+struct StaticDecl {
+    attrs: Vec<Attribute>,
+    vis: Visibility,
+    ident: Ident,
+    ty: Type,
+    init: Option<Expr>,
+}
+
+impl Parse for StaticDecl {
+    fn parse(input: ParseStream) -> Result<Self> {
+        let attrs = input.call(Attribute::parse_outer)?;
+        let vis = input.parse()?;
+        input.parse::<Token![static]>()?;
+        if input.peek(Token![mut]) {
+            return Err(input.error("kernel objects can not be `static mut`"));
+        }
+        let ident = input.parse()?;
+        input.parse::<Token![:]>()?;
+        let ty = input.parse()?;
+        let init = if input.parse::<Option<Token![=]>>()?.is_some() { Some(input.parse()?) } else { None };
+        input.parse::<Token![;]>()?;
+        Ok(StaticDecl { attrs, vis, ident, ty, init })
+    }
+}
+
+enum ObjectKind {
+    Queue { message: Box<Type>, capacity: Expr },
+    Mutex { inherit: Expr },
+    EventFlags,
+    Semaphore { initial: Expr },
+    BytePool { size: Expr },
+    BlockPool { block_size: Expr, blocks: Expr },
+}
+
+const OBJECT_ATTRIBUTES: &[&str] = &["queue", "mutex", "event_flags", "semaphore", "byte_pool", "block_pool"];
+
+struct Object {
+    decl: StaticDecl,
+    kind: ObjectKind,
+}
+
+impl Object {
+    fn from_decl(mut decl: StaticDecl, attr: Attribute) -> Result<Self> {
+        let mut args = Args::from_attribute(&attr)?;
+        let kind = match attribute_name(&attr).as_str() {
+            "queue" => {
+                let message = type_argument(&decl.ty, "Queue")
+                    .ok_or_else(|| Error::new(decl.ty.span(), "a `#[queue]` must have the type `Queue<T>`"))?;
+                let message = Box::new(message.clone());
+                ObjectKind::Queue { message, capacity: args.require("capacity")? }
+            }
+            "mutex" => {
+                if decl.init.is_none() {
+                    return Err(Error::new(decl.ident.span(), "a `#[mutex]` needs a value, e.g. `= Mutex::new(0)`"));
+                }
+                ObjectKind::Mutex { inherit: args.take("inherit").unwrap_or_else(|| syn::parse_quote!(true)) }
+            }
+            "event_flags" => ObjectKind::EventFlags,
+            "semaphore" => ObjectKind::Semaphore { initial: args.take("initial").unwrap_or_else(|| syn::parse_quote!(0)) },
+            "byte_pool" => ObjectKind::BytePool { size: args.require("size")? },
+            "block_pool" => ObjectKind::BlockPool { block_size: args.require("block_size")?, blocks: args.require("blocks")? },
+            _ => unreachable!(),
+        };
+        args.finish()?;
+        if decl.init.is_none() {
+            let ty = &decl.ty;
+            decl.init = Some(syn::parse_quote!(<#ty>::new()));
+        }
+        Ok(Object { decl, kind })
+    }
+
+    /// Type of the handles stored in the `TxOnceCell` named after the object
+    fn handle_type(&self) -> TokenStream {
+        let ty = &self.decl.ty;
+        match &self.kind {
+            ObjectKind::Queue { message, .. } => quote! {
+                (::threadx_rs::queue::QueueSender<#message>, ::threadx_rs::queue::QueueReceiver<#message>)
+            },
+            ObjectKind::Mutex { .. } | ObjectKind::EventFlags => quote!(&'static #ty),
+            ObjectKind::Semaphore { .. } => quote!(::threadx_rs::semaphore::SemaphoreOwnerHandle),
+            ObjectKind::BytePool { .. } => quote!(::threadx_rs::pool::BytePoolHandle),
+            ObjectKind::BlockPool { .. } => quote!(::threadx_rs::pool::BlockPoolHandle),
+        }
+    }
+
+    /// Expression that passes the object to a thread parameter of type `ty`
+    fn argument(&self, ty: &Type) -> Result<TokenStream> {
+        let ident = &self.decl.ident;
+        let handles = quote!(#ident.get().unwrap());
+        let name = type_name(ty).unwrap_or_default();
+        match &self.kind {
+            ObjectKind::Queue { .. } => match name.as_str() {
+                "QueueSender" => Ok(quote!(#handles.0.clone())),
+                "QueueReceiver" => Ok(quote!(#handles.1.clone())),
+                _ => Err(Error::new(ty.span(), format!("queue `{ident}` can be passed as `QueueSender` or `QueueReceiver`"))),
+            },
+            ObjectKind::Mutex { .. } | ObjectKind::EventFlags => Ok(quote!(*#handles)),
+            ObjectKind::Semaphore { .. } => match name.as_str() {
+                "SemaphoreUserHandle" => Ok(quote!(::threadx_rs::semaphore::SemaphoreOwner::get_semaphore_user(#handles))),
+                "SemaphoreOwnerHandle" => Ok(quote!(*#handles)),
+                _ => Err(Error::new(
+                    ty.span(),
+                    format!("semaphore `{ident}` can be passed as `SemaphoreOwnerHandle` or `SemaphoreUserHandle`"),
+                )),
+            },
+            ObjectKind::BytePool { .. } | ObjectKind::BlockPool { .. } => Ok(handles),
+        }
+    }
+
+    /// Storage that must fit into the heap
+    fn required_memory(&self) -> Option<TokenStream> {
+        match &self.kind {
+            ObjectKind::Queue { message, capacity } => Some(quote!(::threadx_rs::planner::queue_size::<#message>(#capacity))),
+            ObjectKind::BytePool { size } => Some(quote!(::threadx_rs::planner::region_size(#size))),
+            ObjectKind::BlockPool { block_size, blocks } => {
+                Some(quote!(::threadx_rs::planner::block_pool_size(#block_size, #blocks)))
+            }
+            _ => None,
+        }
+    }
+
+    fn checks(&self) -> TokenStream {
+        match &self.kind {
+            ObjectKind::Queue { capacity, .. } => {
+                let message = format!("queue `{}`: capacity must not be 0", self.decl.ident);
+                quote_spanned! {capacity.span()=>
+                    #[allow(clippy::assertions_on_constants)]
+                    const _: () = {
+                        let capacity: usize = #capacity;
+                        ::core::assert!(capacity > 0, #message);
+                    };
+                }
+            }
+            _ => TokenStream::new(),
+        }
+    }
+
+    fn cell(&self) -> TokenStream {
+        let StaticDecl { attrs, vis, ident, .. } = &self.decl;
+        let handle = self.handle_type();
+        quote! {
+            #(#attrs)*
+            #vis static #ident: ::threadx_rs::static_cell::TxOnceCell<#handle> = ::threadx_rs::static_cell::TxOnceCell::new();
+        }
+    }
+
+    fn create(&self) -> TokenStream {
+        let StaticDecl { ident, ty, init, .. } = &self.decl;
+        let name = ident.to_string();
+        let handles = match &self.kind {
+            ObjectKind::Queue { message, capacity } => {
+                let fits = format!("threadx::app: storage of queue `{name}` does not fit");
+                quote! {
+                    let memory = plan.queue::<#message>(#capacity).expect(#fits);
+                    OBJECT.take().unwrap().initialize(::threadx_rs::tx_str!(#name), memory)
+                }
+            }
+            ObjectKind::Mutex { inherit } => {
+                quote!(OBJECT.take().unwrap().initialize(::threadx_rs::tx_str!(#name), #inherit))
+            }
+            ObjectKind::EventFlags => quote!(OBJECT.take().unwrap().initialize(::threadx_rs::tx_str!(#name))),
+            ObjectKind::Semaphore { initial } => {
+                quote!(OBJECT.take().unwrap().initialize(::threadx_rs::tx_str!(#name), #initial))
+            }
+            ObjectKind::BytePool { size } => {
+                let fits = format!("threadx::app: byte pool `{name}` does not fit");
+                quote! {
+                    let memory = plan.byte_pool(#size).expect(#fits);
+                    OBJECT.take().unwrap().initialize(::threadx_rs::tx_str!(#name), memory)
+                }
+            }
+            ObjectKind::BlockPool { block_size, blocks } => {
+                let fits = format!("threadx::app: block pool `{name}` does not fit");
+                quote! {
+                    let memory = plan.block_pool(#block_size, #blocks).expect(#fits);
+                    OBJECT.take().unwrap().initialize(::threadx_rs::tx_str!(#name), #block_size, memory)
+                }
+            }
+        };
+        let failed = format!("threadx::app: creating `{name}` failed");
+        quote! {
+            {
+                static OBJECT: ::threadx_rs::static_cell::TxStatic<#ty> = ::threadx_rs::static_cell::TxStatic::new(#init);
+                let handles = { #handles }.expect(#failed);
+                let _ = #ident.set(handles);
+            }
+        }
+    }
+}
+
+/// Arguments for the parameters of a thread or timer function, looked up by name
+fn arguments(func: &ItemFn, objects: &[Object]) -> Result<Vec<TokenStream>> {
+    func.sig
+        .inputs
+        .iter()
+        .map(|input| {
+            let FnArg::Typed(arg) = input else {
+                return Err(Error::new(input.span(), "thread and timer functions can not take `self`"));
+            };
+            let Pat::Ident(pat) = &*arg.pat else {
+                return Err(Error::new(arg.pat.span(), "parameters must be named after an object"));
+            };
+            let name = pat.ident.to_string().to_uppercase();
+            let object = objects
+                .iter()
+                .find(|o| o.decl.ident.to_string().to_uppercase() == name)
+                .ok_or_else(|| Error::new(pat.ident.span(), format!("no object named `{name}` in this module")))?;
+            object.argument(&arg.ty)
+        })
+        .collect()
+}
+
+struct Thread {
+    func: ItemFn,
+    priority: Expr,
+    stack: Expr,
+    preempt_threshold: Expr,
+    time_slice: Expr,
+}
+
+impl Thread {
+    fn from_fn(func: ItemFn, attr: Attribute) -> Result<Self> {
+        let mut args = Args::from_attribute(&attr)?;
+        let priority = args.require("priority")?;
+        let stack = args.require("stack")?;
+        let preempt_threshold = args.take("preempt_threshold").unwrap_or_else(|| priority.clone());
+        let time_slice = args.take("time_slice").unwrap_or_else(|| syn::parse_quote!(0));
+        args.finish()?;
+        Ok(Thread { func, priority, stack, preempt_threshold, time_slice })
+    }
+
+    /// Priorities and stack sizes are checked when the constants are evaluated, so they
+    /// can be given as expressions
+    fn checks(&self) -> TokenStream {
+        let Thread { func, priority, stack, preempt_threshold, .. } = self;
+        let name = &func.sig.ident;
+        let priority_message = format!("thread `{name}`: priority must be below TX_MAX_PRIORITIES");
+        let threshold_message = format!("thread `{name}`: preempt_threshold must not be greater than priority");
+        let stack_message = format!("thread `{name}`: stack must be at least TX_MINIMUM_STACK bytes");
+        let priority_check = quote_spanned! {priority.span()=>
+            #[allow(clippy::assertions_on_constants)]
+            const _: () = {
+                let priority: u32 = #priority;
+                ::core::assert!(priority < ::threadx_rs::__private::TX_MAX_PRIORITIES, #priority_message);
+            };
+        };
+        let threshold_check = quote_spanned! {preempt_threshold.span()=>
+            #[allow(clippy::assertions_on_constants)]
+            const _: () = {
+                let priority: u32 = #priority;
+                let preempt_threshold: u32 = #preempt_threshold;
+                ::core::assert!(preempt_threshold <= priority, #threshold_message);
+            };
+        };
+        let stack_check = quote_spanned! {stack.span()=>
+            #[allow(clippy::assertions_on_constants)]
+            const _: () = {
+                let stack: usize = #stack;
+                ::core::assert!(stack >= ::threadx_rs::__private::TX_MINIMUM_STACK as usize, #stack_message);
+            };
+        };
+        quote!(#priority_check #threshold_check #stack_check)
+    }
+
+    fn create(&self, objects: &[Object]) -> Result<TokenStream> {
+        let Thread { func, priority, stack, preempt_threshold, time_slice } = self;
+        let ident = &func.sig.ident;
+        let name = ident.to_string();
+        let arguments = arguments(func, objects)?;
+        let fits = format!("threadx::app: stack of thread `{name}` does not fit");
+        let failed = format!("threadx::app: creating thread `{name}` failed");
+        Ok(quote! {
+            {
+                static THREAD: ::threadx_rs::static_cell::TxStatic<::threadx_rs::thread::Thread> =
+                    ::threadx_rs::static_cell::TxStatic::new(::threadx_rs::thread::Thread::new());
+                let stack = plan.stack(#stack).expect(#fits);
+                THREAD
+                    .take()
+                    .unwrap()
+                    .initialize(
+                        ::threadx_rs::tx_str!(#name),
+                        || {
+                            #ident(#(#arguments),*);
+                        },
+                        stack,
+                        #priority,
+                        #preempt_threshold,
+                        #time_slice,
+                        true,
+                    )
+                    .expect(#failed);
+            }
+        })
+    }
+}
+
+struct Timer {
+    func: ItemFn,
+    initial_ms: Expr,
+    period_ms: Expr,
+    auto_start: Expr,
+}
+
+impl Timer {
+    fn from_fn(func: ItemFn, attr: Attribute) -> Result<Self> {
+        let mut args = Args::from_attribute(&attr)?;
+        let initial_ms = args.require("initial_ms")?;
+        let period_ms = args.take("period_ms").unwrap_or_else(|| syn::parse_quote!(0));
+        let auto_start = args.take("auto_start").unwrap_or_else(|| syn::parse_quote!(true));
+        args.finish()?;
+        Ok(Timer { func, initial_ms, period_ms, auto_start })
+    }
+
+    fn checks(&self) -> TokenStream {
+        let initial_ms = &self.initial_ms;
+        let message = format!("timer `{}`: initial_ms must not be 0", self.func.sig.ident);
+        quote_spanned! {initial_ms.span()=>
+            #[allow(clippy::assertions_on_constants)]
+            const _: () = {
+                let initial_ms: u64 = #initial_ms;
+                ::core::assert!(initial_ms > 0, #message);
+            };
+        }
+    }
+
+    fn create(&self, objects: &[Object]) -> Result<TokenStream> {
+        let Timer { func, initial_ms, period_ms, auto_start } = self;
+        let ident = &func.sig.ident;
+        let name = ident.to_string();
+        let arguments = arguments(func, objects)?;
+        let failed = format!("threadx::app: creating timer `{name}` failed");
+        // The expiration closure captures nothing, so the reference to it is promoted
+        // to a `'static` one
+        Ok(quote! {
+            {
+                static TIMER: ::threadx_rs::static_cell::TxStatic<::threadx_rs::timer::Timer> =
+                    ::threadx_rs::static_cell::TxStatic::new(::threadx_rs::timer::Timer::new());
+                TIMER
+                    .take()
+                    .unwrap()
+                    .initialize(
+                        ::threadx_rs::tx_str!(#name),
+                        &|_| {
+                            #ident(#(#arguments),*);
+                        },
+                        0,
+                        ::core::time::Duration::from_millis(#initial_ms),
+                        ::core::time::Duration::from_millis(#period_ms),
+                        #auto_start,
+                    )
+                    .expect(#failed);
+            }
+        })
+    }
+}
+
+struct App {
+    heap: Option<Expr>,
+    module: ItemMod,
+    items: Vec<Item>,
+    objects: Vec<Object>,
+    threads: Vec<Thread>,
+    timers: Vec<Timer>,
+    init: Option<Ident>,
+    define: Option<Ident>,
+}
+
+fn set_once(slot: &mut Option<Ident>, func: &ItemFn, what: &str) -> Result<()> {
+    if slot.is_some() {
+        return Err(Error::new(func.sig.ident.span(), format!("only one `#[{what}]` function is allowed")));
+    }
+    *slot = Some(func.sig.ident.clone());
+    Ok(())
+}
+
+fn parse(args: TokenStream, item: TokenStream) -> Result<App> {
+    let mut args = Args::from_tokens(Span::call_site(), args)?;
+    let heap = args.take("heap");
+    args.finish()?;
+
+    let mut module: ItemMod = syn::parse2(item)?;
+    let Some((_, content)) = module.content.take() else {
+        return Err(Error::new(module.ident.span(), "`#[app]` needs a module with a body"));
+    };
+
+    let mut app = App { heap, module, items: Vec::new(), objects: Vec::new(), threads: Vec::new(), timers: Vec::new(), init: None, define: None };
+    for item in content {
+        let decl = match item {
+            Item::Fn(mut func) => {
+                match take_attribute(&mut func.attrs, &["thread", "timer", "init", "define"]) {
+                    Some(attr) if attribute_name(&attr) == "thread" => {
+                        app.threads.push(Thread::from_fn(func, attr)?);
+                    }
+                    Some(attr) if attribute_name(&attr) == "timer" => {
+                        app.timers.push(Timer::from_fn(func, attr)?);
+                    }
+                    Some(attr) if attribute_name(&attr) == "init" => {
+                        set_once(&mut app.init, &func, "init")?;
+                        app.items.push(Item::Fn(func));
+                    }
+                    Some(_) => {
+                        set_once(&mut app.define, &func, "define")?;
+                        app.items.push(Item::Fn(func));
+                    }
+                    None => app.items.push(Item::Fn(func)),
+                }
+                continue;
+            }
+            Item::Static(s) if s.attrs.iter().any(|a| OBJECT_ATTRIBUTES.contains(&attribute_name(a).as_str())) => {
+                if !matches!(s.mutability, syn::StaticMutability::None) {
+                    return Err(Error::new(s.ident.span(), "kernel objects can not be `static mut`"));
+                }
+                StaticDecl { attrs: s.attrs, vis: s.vis, ident: s.ident, ty: *s.ty, init: Some(*s.expr) }
+            }
+            Item::Verbatim(tokens) => match syn::parse2::<StaticDecl>(tokens.clone()) {
+                Ok(decl) => decl,
+                Err(_) => {
+                    app.items.push(Item::Verbatim(tokens));
+                    continue;
+                }
+            },
+            item => {
+                app.items.push(item);
+                continue;
+            }
+        };
+        let mut decl = decl;
+        let attr = take_attribute(&mut decl.attrs, OBJECT_ATTRIBUTES)
+            .ok_or_else(|| Error::new(decl.ident.span(), "a `static` without a value must be a kernel object"))?;
+        app.objects.push(Object::from_decl(decl, attr)?);
+    }
+    Ok(app)
+}
+
+pub fn expand(args: TokenStream, item: TokenStream) -> Result<TokenStream> {
+    let app = parse(args, item)?;
+    let App { heap, module, items, objects, threads, timers, init, define } = &app;
+
+    let cells = objects.iter().map(Object::cell);
+    let object_checks = objects.iter().map(Object::checks);
+    let thread_checks = threads.iter().map(Thread::checks);
+    let timer_checks = timers.iter().map(Timer::checks);
+    let functions = threads.iter().map(|t| &t.func).chain(timers.iter().map(|t| &t.func));
+    let creates = objects.iter().map(Object::create);
+    let thread_creates = threads.iter().map(|t| t.create(objects)).collect::<Result<Vec<_>>>()?;
+    let timer_creates = timers.iter().map(|t| t.create(objects)).collect::<Result<Vec<_>>>()?;
+
+    let required = objects
+        .iter()
+        .filter_map(Object::required_memory)
+        .chain(threads.iter().map(|t| {
+            let stack = &t.stack;
+            quote!(::threadx_rs::planner::stack_size(#stack))
+        }));
+    let heap_size = match heap {
+        Some(heap) => quote!(#heap),
+        None => quote!(__THREADX_REQUIRED + ::threadx_rs::planner::REGION_ALIGN - 1),
+    };
+    let init_call = match init {
+        Some(init) => quote!(#init(ticks_per_second);),
+        None => quote!(let _ = ticks_per_second;),
+    };
+    let define_call = define.as_ref().map(|define| quote!(#define(plan.into_remaining());));
+
+    let ItemMod { attrs, vis, unsafety, mod_token, ident, .. } = module;
+    let heap_ident = format_ident!("__THREADX_HEAP");
+    Ok(quote! {
+        #(#attrs)*
+        #vis #unsafety #mod_token #ident {
+            #(#items)*
+            #(#functions)*
+            #(#cells)*
+            #(#object_checks)*
+            #(#thread_checks)*
+            #(#timer_checks)*
+
+            #[doc(hidden)]
+            const __THREADX_REQUIRED: usize = 0 #(+ #required)*;
+
+            /// Size of the memory handed to ThreadX for the objects and threads of this module
+            pub const HEAP_SIZE: usize = #heap_size;
+
+            const _: () = ::threadx_rs::planner::Budget::<HEAP_SIZE, __THREADX_REQUIRED>::OK;
+
+            /// Initialize ThreadX with the objects and threads of this module. Does not return.
+            pub fn start() {
+                static #heap_ident: ::threadx_rs::static_cell::TxStatic<[u8; HEAP_SIZE]> =
+                    ::threadx_rs::static_cell::TxStatic::new([0; HEAP_SIZE]);
+                ::threadx_rs::Builder::new(
+                    |ticks_per_second| {
+                        #init_call
+                        #heap_ident.take().expect("threadx::app: start called twice").as_mut_slice()
+                    },
+                    |memory| {
+                        #[allow(unused_mut, unused_variables)]
+                        let mut plan = ::threadx_rs::planner::MemoryPlanner::new(memory);
+                        #(#creates)*
+                        #(#thread_creates)*
+                        #(#timer_creates)*
+                        #define_call
+                    },
+                )
+                .initialize()
+            }
+        }
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn error(args: TokenStream, item: TokenStream) -> String {
+        match expand(args, item) {
+            Ok(_) => panic!("expected an error"),
+            Err(e) => e.to_string(),
+        }
+    }
+
+    #[test]
+    fn declarations_are_collected() {
+        let app = parse(
+            quote!(heap = 4096),
+            quote! {
+                mod app {
+                    use super::*;
+                    #[init]
+                    fn init(ticks_per_second: u32) {}
+                    #[queue(capacity = 8)]
+                    static CMD: Queue<Cmd>;
+                    #[mutex(inherit = false)]
+                    static COUNT: Mutex<u32> = Mutex::new(0);
+                    #[threadx::thread(priority = 5, stack = 1024)]
+                    fn producer(cmd: QueueSender<Cmd>, count: &'static Mutex<u32>) {}
+                }
+            },
+        )
+        .unwrap();
+        assert_eq!(app.objects.len(), 2);
+        assert_eq!(app.threads.len(), 1);
+        assert_eq!(app.init.unwrap(), "init");
+        // `use` and the init function are kept as they are
+        assert_eq!(app.items.len(), 2);
+        assert!(app.threads[0].func.attrs.is_empty());
+    }
+
+    #[test]
+    fn pools_and_timers_are_collected() {
+        let app = parse(
+            TokenStream::new(),
+            quote! {
+                mod app {
+                    #[byte_pool(size = 1024)]
+                    static BUFFERS: BytePool;
+                    #[block_pool(block_size = 64, blocks = 4)]
+                    static FRAMES: BlockPool;
+                    #[timer(initial_ms = 100, period_ms = 100)]
+                    fn heartbeat(buffers: &'static BytePoolHandle) {}
+                }
+            },
+        )
+        .unwrap();
+        assert_eq!(app.objects.len(), 2);
+        assert_eq!(app.timers.len(), 1);
+        assert!(arguments(&app.timers[0].func, &app.objects).is_ok());
+    }
+
+    #[test]
+    fn thread_arguments_must_match_objects() {
+        let message = error(
+            TokenStream::new(),
+            quote! {
+                mod app {
+                    #[thread(priority = 1, stack = 512)]
+                    fn consumer(cmd: QueueReceiver<u32>) {}
+                }
+            },
+        );
+        assert!(message.contains("no object named `CMD`"), "{message}");
+    }
+
+    #[test]
+    fn queue_arguments_need_a_queue_end() {
+        let message = error(
+            TokenStream::new(),
+            quote! {
+                mod app {
+                    #[queue(capacity = 4)]
+                    static CMD: Queue<u32>;
+                    #[thread(priority = 1, stack = 512)]
+                    fn consumer(cmd: u32) {}
+                }
+            },
+        );
+        assert!(message.contains("`QueueSender` or `QueueReceiver`"), "{message}");
+    }
+
+    #[test]
+    fn thread_attributes_are_validated() {
+        let missing = error(TokenStream::new(), quote!(mod app { #[thread(stack = 512)] fn t() {} }));
+        assert!(missing.contains("missing argument `priority`"), "{missing}");
+        let unknown = error(TokenStream::new(), quote!(mod app { #[thread(priority = 1, stack = 512, prio = 2)] fn t() {} }));
+        assert!(unknown.contains("unknown argument `prio`"), "{unknown}");
+    }
+
+    #[test]
+    fn mutex_needs_a_value() {
+        let message = error(TokenStream::new(), quote!(mod app { #[mutex] static M: Mutex<u32>; }));
+        assert!(message.contains("needs a value"), "{message}");
+    }
+}
@@ -0,0 +1,84 @@
+//! Attribute macros for threadx-rs applications.
+//!
+//! Enable the `macros` feature of `threadx-rs` and use them as `threadx_rs::app` and
+//! `threadx_rs::thread`. [`macro@app`] describes the whole application in one module:
+//!
+//! ```ignore
+//! #[threadx_rs::app(heap = 4096)]
+//! mod app {
+//!     use super::*;
+//!
+//!     #[init]
+//!     fn init(ticks_per_second: u32) {
+//!         Board::low_level_init(ticks_per_second).unwrap();
+//!     }
+//!
+//!     #[queue(capacity = 8)]
+//!     static CMD: Queue<Cmd>;
+//!
+//!     #[thread(priority = 5, stack = 1024)]
+//!     fn producer(cmd: QueueSender<Cmd>) { ... }
+//!
+//!     #[thread(priority = 6, stack = 1024)]
+//!     fn consumer(cmd: QueueReceiver<Cmd>) { ... }
+//! }
+//!
+//! #[cortex_m_rt::entry]
+//! fn main() -> ! {
+//!     app::start();
+//!     ...
+//! }
+//! ```
+
+extern crate proc_macro;
+
+use proc_macro::TokenStream;
+
+mod app;
+
+/// Generate the application definition for the kernel objects and threads declared in a
+/// module.
+///
+/// The module may contain
+///
+/// - `#[init] fn init(ticks_per_second: u32)`, called from the low level initialization.
+/// - `#[define] fn define(memory: &'static mut [u8])`, called at the end of the
+///   application definition with the memory that was not used by the declarations.
+/// - `#[queue(capacity = N)] static NAME: Queue<T>;`
+/// - `#[mutex(inherit = true)] static NAME: Mutex<T> = Mutex::new(value);`
+/// - `#[event_flags] static NAME: EventFlagsGroup;`
+/// - `#[semaphore(initial = N)] static NAME: Semaphore;`
+/// - `#[byte_pool(size = N)] static NAME: BytePool;`
+/// - `#[block_pool(block_size = N, blocks = M)] static NAME: BlockPool;`
+/// - `#[thread(priority = P, stack = S, preempt_threshold = T, time_slice = N)] fn name(..)`.
+///   `preempt_threshold` defaults to `priority` and `time_slice` to 0.
+/// - `#[timer(initial_ms = N, period_ms = M, auto_start = true)] fn name(..)`, called from
+///   the timer thread. A `period_ms` of 0, the default, makes a one shot timer.
+///
+/// Each declared object becomes a `TxOnceCell` with the same name holding its handles:
+/// `(QueueSender<T>, QueueReceiver<T>)`, `&'static Mutex<T>`, `&'static EventFlagsGroup`,
+/// `SemaphoreOwnerHandle`, `BytePoolHandle` or `BlockPoolHandle`. Parameters of thread
+/// and timer functions are matched to objects by name, ignoring case, and receive a
+/// `QueueSender` or `QueueReceiver`, the mutex or event flags group, a
+/// `SemaphoreOwnerHandle` or `SemaphoreUserHandle`, or a `&'static` pool handle.
+///
+/// Pool memory, queue storage and stacks are taken from a heap of `heap` bytes with a `MemoryPlanner`.
+/// Without `heap` the heap is exactly as large as needed. Plans that do not fit, invalid
+/// priorities and stacks below `TX_MINIMUM_STACK` fail to compile. The module gets a
+/// `pub fn start()` that initializes ThreadX and does not return.
+#[proc_macro_attribute]
+pub fn app(args: TokenStream, item: TokenStream) -> TokenStream {
+    app::expand(args.into(), item.into()).unwrap_or_else(syn::Error::into_compile_error).into()
+}
+
+/// Marks a thread function inside an [`macro@app`] module.
+#[proc_macro_attribute]
+pub fn thread(_args: TokenStream, item: TokenStream) -> TokenStream {
+    let mut tokens: proc_macro2::TokenStream = item.into();
+    let error = syn::Error::new(
+        proc_macro2::Span::call_site(),
+        "`#[thread]` can only be used on functions inside an `#[app]` module",
+    );
+    tokens.extend(error.into_compile_error());
+    tokens.into()
+}
@@ -1,23 +1,68 @@
-use core::{alloc::{GlobalAlloc, Layout}, ffi::{c_void, CStr}, mem::MaybeUninit};
+use core::{alloc::{GlobalAlloc, Layout}, mem::MaybeUninit, ptr::NonNull};
 use crate::{tx_checked_call, tx_str};
-use threadx_sys::{_tx_byte_allocate, TX_WAIT_FOREVER, ULONG, _tx_byte_release, TX_BYTE_POOL, _tx_byte_pool_create};
-use defmt::{error, println};
+use crate::pool::{aligned_pool_memory, byte_pool_alloc, byte_pool_release, byte_pool_usable_size, BytePoolHandle, BytePoolInfo};
+use crate::thread::{execution_context, ExecutionContext};
+use threadx_sys::{ULONG, TX_BYTE_POOL, _tx_byte_pool_create};
+use crate::log::error;
 use crate::error::TxError;
-use num_traits::FromPrimitive;
+
+/// Information passed to the out of memory hook of the `ThreadXAllocator`
+#[derive(Clone, Copy, Debug)]
+pub struct OomReport {
+    /// The layout that could not be allocated
+    pub layout: Layout,
+    /// The context the allocation was attempted from. Allocations from timers and
+    /// ISRs are always refused.
+    pub context: ExecutionContext,
+    /// State of the pool at the time of the failure, if it could be read
+    pub pool: Option<BytePoolInfo>,
+}
+
+#[cfg(feature = "defmt")]
+impl defmt::Format for OomReport {
+    fn format(&self, f: defmt::Formatter) {
+        defmt::write!(
+            f,
+            "OomReport {{ size: {}, align: {}, context: {}, pool: {} }}",
+            self.layout.size(),
+            self.layout.align(),
+            self.context,
+            self.pool
+        )
+    }
+}
+
+pub type OomHook = fn(&OomReport);
 
 /// ThreadX allocator for Rust. Instantiate this struct and use it as the global allocator.
-/// 
+///
 ///  `
 ///  #[global_allocator]
 ///  static mut GLOBAL: ThreadXAllocator = ThreadXAllocator::new();
 ///  unsafe{GLOBAL.initialize(bp1_mem).unwrap()};
 ///  `
-pub struct ThreadXAllocator(MaybeUninit<TX_BYTE_POOL>);
+///
+/// The allocator never suspends. Allocations are made with `TX_NO_WAIT` and a failed
+/// allocation returns null, which Rust turns into a call to the alloc error handler.
+/// Allocations are only served from threads and during initialization. Calls from
+/// timer expiration functions or ISRs return null because ThreadX does not allow byte
+/// pool services in those contexts.
+pub struct ThreadXAllocator {
+    pool: MaybeUninit<TX_BYTE_POOL>,
+    oom_hook: Option<OomHook>,
+    initialized: bool,
+}
 unsafe impl Sync for ThreadXAllocator {}
 
 impl ThreadXAllocator {
     pub const fn new() -> Self {
-        ThreadXAllocator(MaybeUninit::<TX_BYTE_POOL>::uninit())
+        ThreadXAllocator { pool: MaybeUninit::<TX_BYTE_POOL>::uninit(), oom_hook: None, initialized: false }
+    }
+
+    /// Install a function that is called whenever an allocation fails. The hook
+    /// is called from the context of the failed allocation and must not allocate.
+    pub const fn with_oom_hook(hook: OomHook) -> Self {
+        ThreadXAllocator { pool: MaybeUninit::<TX_BYTE_POOL>::uninit(), oom_hook: Some(hook), initialized: false }
     }
 
     pub fn initialize(
@@ -25,37 +70,72 @@ impl ThreadXAllocator {
         pool_memory: &mut [u8],
     ) -> Result<(), TxError> {
 
-        let pool_ptr = self.0.as_mut_ptr();
-        if pool_ptr.is_null() {
-            panic!("Pool ptr is null");
-        }
-        unsafe {
-            if !(*pool_ptr).tx_byte_pool_start.is_null() {
-                panic!("Pool is already initialized");
-            }
+        if self.initialized {
+            panic!("Pool is already initialized");
         }
+        let pool_ptr = self.pool.as_mut_ptr();
+        let pool_memory = aligned_pool_memory(pool_memory);
         tx_checked_call!(_tx_byte_pool_create(
             pool_ptr,
             tx_str!("global").as_ptr() as *mut i8,
             pool_memory.as_mut_ptr() as *mut core::ffi::c_void,
             pool_memory.len() as ULONG
-        ))
+        ))?;
+        self.initialized = true;
+        Ok(())
+    }
+
+    fn pool_ptr(&self) -> *mut TX_BYTE_POOL {
+        self.pool.as_ptr() as *mut _
+    }
+
+    /// Returns the number of available bytes and fragments in the pool
+    pub fn info(&self) -> Result<BytePoolInfo, TxError> {
+        BytePoolHandle::new(self.pool_ptr()).info()
+    }
+
+    fn report_oom(&self, layout: Layout, context: ExecutionContext) {
+        let report = OomReport { layout, context, pool: self.info().ok() };
+        match self.oom_hook {
+            Some(hook) => hook(&report),
+            None => error!("ThreadXAllocator: out of memory {}", report),
+        }
     }
 }
 
 unsafe impl GlobalAlloc for ThreadXAllocator {
     unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
-        let mut ptr: *mut c_void = core::ptr::null_mut() as *mut c_void;
-        tx_checked_call!(_tx_byte_allocate(
-            self.0.as_ptr() as *mut _, 
-            &mut ptr,
-            layout.size() as ULONG,
-            TX_WAIT_FOREVER
-        ))
-        .map(|_| ptr as *mut u8 ).unwrap()
+        let context = execution_context();
+        if matches!(context, ExecutionContext::Timer | ExecutionContext::Isr) {
+            self.report_oom(layout, context);
+            return core::ptr::null_mut();
+        }
+        match byte_pool_alloc(self.pool_ptr(), layout, false) {
+            Ok(ptr) => ptr.as_ptr(),
+            Err(_) => {
+                self.report_oom(layout, context);
+                core::ptr::null_mut()
+            }
+        }
     }
 
-    unsafe fn dealloc(&self, ptr: *mut u8, _layout: Layout) {
-        tx_checked_call!(_tx_byte_release(ptr as *mut c_void)).unwrap()
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        if byte_pool_release(NonNull::new_unchecked(ptr), layout).is_err() {
+            error!("ThreadXAllocator: unable to release {}", ptr);
+        }
+    }
+
+    unsafe fn realloc(&self, ptr: *mut u8, layout: Layout, new_size: usize) -> *mut u8 {
+        // Grow or shrink in place if the existing block is large enough
+        if new_size <= byte_pool_usable_size(NonNull::new_unchecked(ptr), layout) {
+            return ptr;
+        }
+        let new_layout = Layout::from_size_align_unchecked(new_size, layout.align());
+        let new_ptr = self.alloc(new_layout);
+        if !new_ptr.is_null() {
+            core::ptr::copy_nonoverlapping(ptr, new_ptr, layout.size());
+            self.dealloc(ptr, layout);
+        }
+        new_ptr
     }
-}
\ No newline at end of file
+}
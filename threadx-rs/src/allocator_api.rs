@@ -0,0 +1,84 @@
+//! `Allocator` implementations for byte pools.
+//!
+//! With these, collections can draw from a dedicated byte pool instead of the global
+//! allocator, for example `Vec::new_in(&net_pool)` or `Box::new_in(x, &dsp_pool)`. This
+//! keeps the memory budgets of subsystems apart so a leak in one cannot starve another.
+//!
+//! The nightly `core::alloc::Allocator` trait is enabled with the `nightly` feature and
+//! the stable `allocator_api2::alloc::Allocator` mirror with the `allocator-api2` feature.
+//! Allocations never suspend and fail when called from a timer or an ISR.
+
+use core::alloc::Layout;
+use core::ptr::NonNull;
+
+use crate::pool::{byte_pool_alloc, byte_pool_release, byte_pool_usable_size, BytePoolHandle};
+use crate::thread::{execution_context, ExecutionContext};
+
+fn allocate(pool: &BytePoolHandle, layout: Layout) -> Option<NonNull<[u8]>> {
+    if layout.size() == 0 {
+        let dangling = unsafe { NonNull::new_unchecked(layout.align() as *mut u8) };
+        return Some(NonNull::slice_from_raw_parts(dangling, 0));
+    }
+    if matches!(execution_context(), ExecutionContext::Timer | ExecutionContext::Isr) {
+        return None;
+    }
+    let ptr = unsafe { byte_pool_alloc(pool.inner(), layout, false) }.ok()?;
+    let len = unsafe { byte_pool_usable_size(ptr, layout) };
+    Some(NonNull::slice_from_raw_parts(ptr, len))
+}
+
+unsafe fn deallocate(ptr: NonNull<u8>, layout: Layout) {
+    if layout.size() == 0 {
+        return;
+    }
+    if byte_pool_release(ptr, layout).is_err() {
+        crate::log::error!("BytePoolHandle: unable to release {}", ptr.as_ptr());
+    }
+}
+
+unsafe fn grow(
+    pool: &BytePoolHandle,
+    ptr: NonNull<u8>,
+    old_layout: Layout,
+    new_layout: Layout,
+) -> Option<NonNull<[u8]>> {
+    if old_layout.size() != 0 && old_layout.align() == new_layout.align() {
+        let len = byte_pool_usable_size(ptr, old_layout);
+        if new_layout.size() <= len {
+            return Some(NonNull::slice_from_raw_parts(ptr, len));
+        }
+    }
+    let new_ptr = allocate(pool, new_layout)?;
+    core::ptr::copy_nonoverlapping(ptr.as_ptr(), new_ptr.as_ptr() as *mut u8, old_layout.size());
+    deallocate(ptr, old_layout);
+    Some(new_ptr)
+}
+
+macro_rules! impl_allocator {
+    ($allocator:path, $error:path) => {
+        unsafe impl $allocator for BytePoolHandle {
+            fn allocate(&self, layout: Layout) -> Result<NonNull<[u8]>, $error> {
+                allocate(self, layout).ok_or($error)
+            }
+
+            unsafe fn deallocate(&self, ptr: NonNull<u8>, layout: Layout) {
+                deallocate(ptr, layout)
+            }
+
+            unsafe fn grow(
+                &self,
+                ptr: NonNull<u8>,
+                old_layout: Layout,
+                new_layout: Layout,
+            ) -> Result<NonNull<[u8]>, $error> {
+                grow(self, ptr, old_layout, new_layout).ok_or($error)
+            }
+        }
+    };
+}
+
+#[cfg(feature = "nightly")]
+impl_allocator!(core::alloc::Allocator, core::alloc::AllocError);
+
+#[cfg(feature = "allocator-api2")]
+impl_allocator!(allocator_api2::alloc::Allocator, allocator_api2::alloc::AllocError);
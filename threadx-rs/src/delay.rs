@@ -0,0 +1,289 @@
+//! `embedded-hal` delay implementation on top of ThreadX.
+//!
+//! Drivers that are generic over `embedded_hal::delay::DelayNs` usually block the CPU
+//! with a busy loop. [`Delay`] instead puts the calling thread to sleep for all whole
+//! ticks of the requested delay so that other threads can run. The remainder that is
+//! shorter than a tick is busy-waited on a cycle counter if one was provided, otherwise
+//! the delay is rounded up to the next tick.
+//!
+//! A delay starts somewhere within the current tick, so sleeping `n` ticks may only
+//! last a little more than `n - 1` ticks. Without a cycle counter [`Delay`] sleeps one
+//! tick more than requested, with one it busy-waits until the full delay has passed
+//! on the counter, measured from the start of the delay.
+
+use core::cell::UnsafeCell;
+use core::future::Future;
+use core::marker::PhantomPinned;
+use core::pin::Pin;
+use core::task::{Context, Poll, Waker};
+use core::time::Duration;
+
+use threadx_sys::{
+    TX_AUTO_ACTIVATE, TX_TIMER, TX_TIMER_TICKS_PER_SECOND, ULONG, _tx_thread_sleep, _tx_timer_activate,
+    _tx_timer_change, _tx_timer_create, _tx_timer_deactivate, _tx_timer_delete,
+};
+
+use crate::time::{duration_to_ticks, Instant};
+use crate::tx_checked_call;
+
+use crate::log::error;
+
+/// A free running cycle counter such as the Cortex-M DWT `CYCCNT` register.
+/// `read` must return the current count and `frequency` is the rate in Hz at
+/// which the counter is incremented. The counter is expected to wrap at `u32::MAX`.
+///
+/// ```ignore
+/// let cycles = CycleCounter::new(|| cortex_m::peripheral::DWT::cycle_count(), 72_000_000);
+/// ```
+#[derive(Clone, Copy)]
+pub struct CycleCounter {
+    read: fn() -> u32,
+    frequency: u32,
+}
+
+impl CycleCounter {
+    pub const fn new(read: fn() -> u32, frequency: u32) -> Self {
+        CycleCounter { read, frequency }
+    }
+
+    /// Spin until `cycles` have passed since the counter read `start`. `plan` keeps
+    /// `cycles` below half the counter range, so the wrapping difference is
+    /// sufficient even if the sleep before overshoots by a tick.
+    fn spin_until(&self, start: u32, cycles: u32) {
+        while (self.read)().wrapping_sub(start) < cycles {
+            core::hint::spin_loop();
+        }
+    }
+}
+
+/// How a delay is split between sleeping and busy-waiting
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum Plan {
+    /// Sleep this many ticks, one more than the delay rounded up to whole ticks
+    Sleep(u32),
+    /// Sleep the whole ticks of the delay, then busy-wait until `cycles` have passed
+    /// since the start of the delay
+    SleepThenSpin { ticks: u32, cycles: u32 },
+}
+
+fn plan(d: Duration, ticks_per_second: u32, frequency: Option<u32>) -> Plan {
+    if d.is_zero() {
+        return Plan::Sleep(0);
+    }
+    let sleep = Plan::Sleep(duration_to_ticks(d, ticks_per_second).saturating_add(1));
+    let Some(frequency) = frequency else {
+        return sleep;
+    };
+    let cycles = (d.as_nanos() * frequency as u128).div_ceil(1_000_000_000);
+    if cycles > (u32::MAX / 2) as u128 {
+        // Longer than the counter can measure, the sub-tick precision does not matter
+        return sleep;
+    }
+    let ticks = d.as_nanos() * ticks_per_second as u128 / 1_000_000_000;
+    Plan::SleepThenSpin { ticks: ticks as u32, cycles: cycles as u32 }
+}
+
+/// Delay provider that sleeps the calling ThreadX thread.
+///
+/// This must only be used from thread context. Calling it from an ISR, a timer
+/// callback or during initialization fails and the delay is skipped.
+#[derive(Clone, Copy, Default)]
+pub struct Delay {
+    cycle_counter: Option<CycleCounter>,
+}
+
+impl Delay {
+    /// A delay that rounds every request up to whole ticks.
+    pub const fn new() -> Self {
+        Delay { cycle_counter: None }
+    }
+
+    /// A delay that busy-waits on `cycle_counter` for the part of a request
+    /// that is shorter than one tick.
+    pub const fn with_cycle_counter(cycle_counter: CycleCounter) -> Self {
+        Delay { cycle_counter: Some(cycle_counter) }
+    }
+
+    fn sleep_ticks(ticks: u32) {
+        if ticks == 0 {
+            return;
+        }
+        if tx_checked_call!(_tx_thread_sleep(ticks as ULONG)).is_err() {
+            error!("Delay: unable to sleep for {} ticks", ticks);
+        }
+    }
+
+    fn plan(&self, d: Duration) -> Plan {
+        plan(d, TX_TIMER_TICKS_PER_SECOND, self.cycle_counter.map(|counter| counter.frequency))
+    }
+
+    fn delay(&mut self, d: Duration) {
+        match (self.plan(d), self.cycle_counter) {
+            (Plan::SleepThenSpin { ticks, cycles }, Some(cycle_counter)) => {
+                let start = (cycle_counter.read)();
+                Self::sleep_ticks(ticks);
+                cycle_counter.spin_until(start, cycles);
+            }
+            (Plan::Sleep(ticks), _) | (Plan::SleepThenSpin { ticks, .. }, None) => Self::sleep_ticks(ticks),
+        }
+    }
+
+    async fn delay_async(&mut self, d: Duration) {
+        match (self.plan(d), self.cycle_counter) {
+            (Plan::SleepThenSpin { ticks, cycles }, Some(cycle_counter)) => {
+                let start = (cycle_counter.read)();
+                TickSleep::new(ticks).await;
+                cycle_counter.spin_until(start, cycles);
+            }
+            (Plan::Sleep(ticks), _) | (Plan::SleepThenSpin { ticks, .. }, None) => TickSleep::new(ticks).await,
+        }
+    }
+}
+
+/// Waits until the tick counter reaches a deadline. A one-shot ThreadX timer wakes the
+/// task when it expires, so the polling thread is free to run other tasks meanwhile.
+struct TickSleep {
+    deadline: Instant,
+    timer: UnsafeCell<TX_TIMER>,
+    /// Read by the expiration function, only replaced while the timer is inactive
+    waker: UnsafeCell<Option<Waker>>,
+    created: bool,
+    // The timer refers to the future by address
+    _pinned: PhantomPinned,
+}
+
+impl TickSleep {
+    fn new(ticks: u32) -> Self {
+        TickSleep {
+            deadline: Instant::from_ticks(Instant::now().ticks().wrapping_add(ticks)),
+            timer: UnsafeCell::new(unsafe { core::mem::zeroed() }),
+            waker: UnsafeCell::new(None),
+            created: false,
+            _pinned: PhantomPinned,
+        }
+    }
+
+    unsafe extern "C" fn expired(input: ULONG) {
+        let sleep = input as usize as *const TickSleep;
+        if let Some(waker) = &*(*sleep).waker.get() {
+            waker.wake_by_ref();
+        }
+    }
+}
+
+impl Future for TickSleep {
+    type Output = ();
+
+    fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<()> {
+        // SAFETY: nothing is moved out, the timer keeps pointing at this future
+        let this = unsafe { self.get_unchecked_mut() };
+        let remaining = this.deadline.ticks_since(Instant::now());
+        if remaining <= 0 {
+            return Poll::Ready(());
+        }
+        let timer = this.timer.get();
+        if this.created {
+            // Keep the expiration function away from the waker while it is replaced
+            let _ = tx_checked_call!(_tx_timer_deactivate(timer));
+        }
+        unsafe { *this.waker.get() = Some(cx.waker().clone()) };
+        let armed = if this.created {
+            tx_checked_call!(_tx_timer_change(timer, remaining as ULONG, 0))
+                .and_then(|_| tx_checked_call!(_tx_timer_activate(timer)))
+        } else {
+            let input = this as *const TickSleep as usize as ULONG;
+            tx_checked_call!(_tx_timer_create(
+                timer,
+                c"delay".as_ptr() as *mut _,
+                Some(TickSleep::expired as unsafe extern "C" fn(ULONG)),
+                input,
+                remaining as ULONG,
+                0,
+                TX_AUTO_ACTIVATE
+            ))
+            .map(|_| this.created = true)
+        };
+        if armed.is_err() {
+            // Like the blocking delay, skip the delay instead of waiting forever
+            error!("Delay: unable to wait for {} ticks", remaining);
+            return Poll::Ready(());
+        }
+        Poll::Pending
+    }
+}
+
+impl Drop for TickSleep {
+    fn drop(&mut self) {
+        if self.created {
+            let _ = tx_checked_call!(_tx_timer_delete(self.timer.get()));
+        }
+    }
+}
+
+impl embedded_hal::delay::DelayNs for Delay {
+    fn delay_ns(&mut self, ns: u32) {
+        self.delay(Duration::from_nanos(ns as u64))
+    }
+
+    fn delay_us(&mut self, us: u32) {
+        self.delay(Duration::from_micros(us as u64))
+    }
+
+    fn delay_ms(&mut self, ms: u32) {
+        self.delay(Duration::from_millis(ms as u64))
+    }
+}
+
+/// The async delay does not block the thread that polls it. A ThreadX timer wakes the
+/// task once the whole ticks have passed, so an executor can run other tasks in the
+/// meantime. The sub-tick remainder is still busy-waited on the cycle counter.
+impl embedded_hal_async::delay::DelayNs for Delay {
+    async fn delay_ns(&mut self, ns: u32) {
+        self.delay_async(Duration::from_nanos(ns as u64)).await
+    }
+
+    async fn delay_us(&mut self, us: u32) {
+        self.delay_async(Duration::from_micros(us as u64)).await
+    }
+
+    async fn delay_ms(&mut self, ms: u32) {
+        self.delay_async(Duration::from_millis(ms as u64)).await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // One cycle per millisecond
+    const KHZ: u32 = 1_000;
+
+    #[test]
+    fn without_a_counter_sleeps_one_tick_more() {
+        assert_eq!(plan(Duration::ZERO, 100, None), Plan::Sleep(0));
+        assert_eq!(plan(Duration::from_nanos(1), 100, None), Plan::Sleep(2));
+        assert_eq!(plan(Duration::from_millis(10), 100, None), Plan::Sleep(2));
+        assert_eq!(plan(Duration::from_millis(15), 100, None), Plan::Sleep(3));
+    }
+
+    #[test]
+    fn with_a_counter_sleeps_whole_ticks_and_spins_from_the_start() {
+        assert_eq!(plan(Duration::from_millis(5), 100, Some(KHZ)), Plan::SleepThenSpin { ticks: 0, cycles: 5 });
+        assert_eq!(plan(Duration::from_millis(10), 100, Some(KHZ)), Plan::SleepThenSpin { ticks: 1, cycles: 10 });
+        assert_eq!(plan(Duration::from_millis(25), 100, Some(KHZ)), Plan::SleepThenSpin { ticks: 2, cycles: 25 });
+        // Partial cycles round up
+        assert_eq!(
+            plan(Duration::from_nanos(1_500_000), 100, Some(KHZ)),
+            Plan::SleepThenSpin { ticks: 0, cycles: 2 }
+        );
+    }
+
+    #[test]
+    fn delays_the_counter_can_not_measure_only_sleep() {
+        let half_range = Duration::from_millis((u32::MAX / 2) as u64 + 1);
+        assert_eq!(
+            plan(half_range, 100, Some(KHZ)),
+            Plan::Sleep(duration_to_ticks(half_range, 100) + 1)
+        );
+    }
+}
@@ -1,47 +1,416 @@
-use num_derive::FromPrimitive;
-use thiserror_no_std::Error;
+//! Errors of the kernel services.
+//!
+//! A [`TxError`] tells what went wrong as an [`ErrorKind`] and keeps the context of the
+//! failing call: the raw status that ThreadX returned, the service that returned it and the
+//! name of the object it was called on. It is `Display` and, with the `defmt` feature,
+//! `defmt::Format`:
+//!
+//! ```text
+//! _tx_queue_send on `requests` failed: QueueFull (0xb)
+//! ```
+//!
+//! The services whose errors are part of normal operation, such as a full queue or an
+//! empty pool, return their own error enum that can be matched exhaustively, e.g.
+//! [`QueueSendError`](crate::queue::QueueSendError). Anything else ends up in its `Kernel`
+//! variant.
+//!
+//! With the `core-error` feature the errors implement `core::error::Error`.
 
+use core::ffi::{c_char, CStr};
+use core::fmt;
+
+use threadx_sys::*;
 
 pub type TxResult = Result<(), TxError>;
 
-#[repr(u32)]
-#[derive(Error,FromPrimitive,Debug)]
-pub enum TxError {
-    Deleted = threadx_sys::TX_DELETED,
-    PoolError = threadx_sys::TX_POOL_ERROR,
-    PtrError = threadx_sys::TX_PTR_ERROR,
-    WaitError = threadx_sys::TX_WAIT_ERROR,
-    SizeError = threadx_sys::TX_SIZE_ERROR,
-    GroupError = threadx_sys::TX_GROUP_ERROR,
-    NoEvents = threadx_sys::TX_NO_EVENTS,
-    OptionError = threadx_sys::TX_OPTION_ERROR,
-    QueueError = threadx_sys::TX_QUEUE_ERROR,
-    QueueEmpty = threadx_sys::TX_QUEUE_EMPTY,
-    QueueFull = threadx_sys::TX_QUEUE_FULL,
-    SemaphoreError = threadx_sys::TX_SEMAPHORE_ERROR,
-    NoInstance = threadx_sys::TX_NO_INSTANCE,
-    ThreadError = threadx_sys::TX_THREAD_ERROR,
-    PriorityError = threadx_sys::TX_PRIORITY_ERROR,
-    NoMemoryOrStartError = threadx_sys::TX_NO_MEMORY,
-    //StartError = threadx_sys::TX_START_ERROR, // threadx has 0x10 defined twice with two different values
-    DeleteError = threadx_sys::TX_DELETE_ERROR,
-    ResumeError = threadx_sys::TX_RESUME_ERROR,
-    CallerError = threadx_sys::TX_CALLER_ERROR,
-    SuspendError = threadx_sys::TX_SUSPEND_ERROR,
-    TimerError = threadx_sys::TX_TIMER_ERROR,
-    TickError = threadx_sys::TX_TICK_ERROR,
-    ActivateError = threadx_sys::TX_ACTIVATE_ERROR,
-    ThreshError = threadx_sys::TX_THRESH_ERROR,
-    SuspendLifted = threadx_sys::TX_SUSPEND_LIFTED,
-    WaitAborted = threadx_sys::TX_WAIT_ABORTED,
-    WaitAbortError = threadx_sys::TX_WAIT_ABORT_ERROR,
-    MutexError = threadx_sys::TX_MUTEX_ERROR,
-    NotAvailable = threadx_sys::TX_NOT_AVAILABLE,
-    NotOwned = threadx_sys::TX_NOT_OWNED,
-    InheritError = threadx_sys::TX_INHERIT_ERROR,
-    NotDone = threadx_sys::TX_NOT_DONE,
-    CeilingExceeded = threadx_sys::TX_CEILING_EXCEEDED,
-    InvalidCeiling = threadx_sys::TX_INVALID_CEILING,
-    FeatureNotEnabled = threadx_sys::TX_FEATURE_NOT_ENABLED,
-    Unknown = 0xFE,
-}
\ No newline at end of file
+/// What went wrong, one for each ThreadX status
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum ErrorKind {
+    Deleted,
+    PoolError,
+    PtrError,
+    WaitError,
+    SizeError,
+    GroupError,
+    NoEvents,
+    OptionError,
+    QueueError,
+    QueueEmpty,
+    QueueFull,
+    SemaphoreError,
+    NoInstance,
+    ThreadError,
+    PriorityError,
+    NoMemory,
+    /// Invalid auto start of `_tx_thread_create`. ThreadX uses the status of `NoMemory`.
+    StartError,
+    DeleteError,
+    ResumeError,
+    CallerError,
+    SuspendError,
+    TimerError,
+    TickError,
+    ActivateError,
+    ThreshError,
+    SuspendLifted,
+    WaitAborted,
+    WaitAbortError,
+    MutexError,
+    NotAvailable,
+    NotOwned,
+    InheritError,
+    NotDone,
+    CeilingExceeded,
+    InvalidCeiling,
+    FeatureNotEnabled,
+    /// A status this crate does not know, see [`TxError::code`]
+    Unknown,
+}
+
+impl ErrorKind {
+    /// The kind of `status` as returned by `service`
+    pub fn from_status(service: &str, status: UINT) -> Self {
+        match status {
+            TX_DELETED => ErrorKind::Deleted,
+            TX_POOL_ERROR => ErrorKind::PoolError,
+            TX_PTR_ERROR => ErrorKind::PtrError,
+            TX_WAIT_ERROR => ErrorKind::WaitError,
+            TX_SIZE_ERROR => ErrorKind::SizeError,
+            TX_GROUP_ERROR => ErrorKind::GroupError,
+            TX_NO_EVENTS => ErrorKind::NoEvents,
+            TX_OPTION_ERROR => ErrorKind::OptionError,
+            TX_QUEUE_ERROR => ErrorKind::QueueError,
+            TX_QUEUE_EMPTY => ErrorKind::QueueEmpty,
+            TX_QUEUE_FULL => ErrorKind::QueueFull,
+            TX_SEMAPHORE_ERROR => ErrorKind::SemaphoreError,
+            TX_NO_INSTANCE => ErrorKind::NoInstance,
+            TX_THREAD_ERROR => ErrorKind::ThreadError,
+            TX_PRIORITY_ERROR => ErrorKind::PriorityError,
+            // Thread creation does not allocate, so there the status is a start error
+            TX_START_ERROR if service.ends_with("thread_create") => ErrorKind::StartError,
+            TX_NO_MEMORY => ErrorKind::NoMemory,
+            TX_DELETE_ERROR => ErrorKind::DeleteError,
+            TX_RESUME_ERROR => ErrorKind::ResumeError,
+            TX_CALLER_ERROR => ErrorKind::CallerError,
+            TX_SUSPEND_ERROR => ErrorKind::SuspendError,
+            TX_TIMER_ERROR => ErrorKind::TimerError,
+            TX_TICK_ERROR => ErrorKind::TickError,
+            TX_ACTIVATE_ERROR => ErrorKind::ActivateError,
+            TX_THRESH_ERROR => ErrorKind::ThreshError,
+            TX_SUSPEND_LIFTED => ErrorKind::SuspendLifted,
+            TX_WAIT_ABORTED => ErrorKind::WaitAborted,
+            TX_WAIT_ABORT_ERROR => ErrorKind::WaitAbortError,
+            TX_MUTEX_ERROR => ErrorKind::MutexError,
+            TX_NOT_AVAILABLE => ErrorKind::NotAvailable,
+            TX_NOT_OWNED => ErrorKind::NotOwned,
+            TX_INHERIT_ERROR => ErrorKind::InheritError,
+            TX_NOT_DONE => ErrorKind::NotDone,
+            TX_CEILING_EXCEEDED => ErrorKind::CeilingExceeded,
+            TX_INVALID_CEILING => ErrorKind::InvalidCeiling,
+            TX_FEATURE_NOT_ENABLED => ErrorKind::FeatureNotEnabled,
+            _ => ErrorKind::Unknown,
+        }
+    }
+
+    /// The ThreadX status of this kind. `Unknown` has none and uses 0xFE.
+    pub const fn status(self) -> UINT {
+        match self {
+            ErrorKind::Deleted => TX_DELETED,
+            ErrorKind::PoolError => TX_POOL_ERROR,
+            ErrorKind::PtrError => TX_PTR_ERROR,
+            ErrorKind::WaitError => TX_WAIT_ERROR,
+            ErrorKind::SizeError => TX_SIZE_ERROR,
+            ErrorKind::GroupError => TX_GROUP_ERROR,
+            ErrorKind::NoEvents => TX_NO_EVENTS,
+            ErrorKind::OptionError => TX_OPTION_ERROR,
+            ErrorKind::QueueError => TX_QUEUE_ERROR,
+            ErrorKind::QueueEmpty => TX_QUEUE_EMPTY,
+            ErrorKind::QueueFull => TX_QUEUE_FULL,
+            ErrorKind::SemaphoreError => TX_SEMAPHORE_ERROR,
+            ErrorKind::NoInstance => TX_NO_INSTANCE,
+            ErrorKind::ThreadError => TX_THREAD_ERROR,
+            ErrorKind::PriorityError => TX_PRIORITY_ERROR,
+            ErrorKind::NoMemory => TX_NO_MEMORY,
+            ErrorKind::StartError => TX_START_ERROR,
+            ErrorKind::DeleteError => TX_DELETE_ERROR,
+            ErrorKind::ResumeError => TX_RESUME_ERROR,
+            ErrorKind::CallerError => TX_CALLER_ERROR,
+            ErrorKind::SuspendError => TX_SUSPEND_ERROR,
+            ErrorKind::TimerError => TX_TIMER_ERROR,
+            ErrorKind::TickError => TX_TICK_ERROR,
+            ErrorKind::ActivateError => TX_ACTIVATE_ERROR,
+            ErrorKind::ThreshError => TX_THRESH_ERROR,
+            ErrorKind::SuspendLifted => TX_SUSPEND_LIFTED,
+            ErrorKind::WaitAborted => TX_WAIT_ABORTED,
+            ErrorKind::WaitAbortError => TX_WAIT_ABORT_ERROR,
+            ErrorKind::MutexError => TX_MUTEX_ERROR,
+            ErrorKind::NotAvailable => TX_NOT_AVAILABLE,
+            ErrorKind::NotOwned => TX_NOT_OWNED,
+            ErrorKind::InheritError => TX_INHERIT_ERROR,
+            ErrorKind::NotDone => TX_NOT_DONE,
+            ErrorKind::CeilingExceeded => TX_CEILING_EXCEEDED,
+            ErrorKind::InvalidCeiling => TX_INVALID_CEILING,
+            ErrorKind::FeatureNotEnabled => TX_FEATURE_NOT_ENABLED,
+            ErrorKind::Unknown => 0xFE,
+        }
+    }
+}
+
+impl fmt::Display for ErrorKind {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        fmt::Debug::fmt(self, f)
+    }
+}
+
+/// Longest object name kept by a `TxError`, longer names are cut
+const OBJECT_NAME_LEN: usize = 16;
+
+/// A copy of the name of a kernel object, so the error outlives the object. Public for
+/// `tx_checked_call!`.
+#[doc(hidden)]
+#[derive(Clone, Copy, PartialEq, Eq)]
+pub struct ObjectName {
+    bytes: [u8; OBJECT_NAME_LEN],
+    len: u8,
+}
+
+impl ObjectName {
+    pub const NONE: ObjectName = ObjectName { bytes: [0; OBJECT_NAME_LEN], len: 0 };
+
+    /// Safety: `name` is null or points to a C string
+    unsafe fn from_ptr(name: *const c_char) -> Self {
+        if name.is_null() {
+            return ObjectName::NONE;
+        }
+        Self::from_c_str(CStr::from_ptr(name))
+    }
+
+    fn from_c_str(name: &CStr) -> Self {
+        let name = name.to_bytes();
+        let mut len = name.len().min(OBJECT_NAME_LEN);
+        // Do not cut a character in half
+        while core::str::from_utf8(&name[..len]).is_err() {
+            len -= 1;
+        }
+        let mut bytes = [0; OBJECT_NAME_LEN];
+        bytes[..len].copy_from_slice(&name[..len]);
+        ObjectName { bytes, len: len as u8 }
+    }
+
+    fn as_str(&self) -> Option<&str> {
+        let name = core::str::from_utf8(&self.bytes[..self.len as usize]).ok()?;
+        (!name.is_empty()).then_some(name)
+    }
+}
+
+/// Anything a kernel service is called on that has a name: the control blocks after
+/// creation and the name passed to the create call. Public for `tx_checked_call!`.
+#[doc(hidden)]
+pub trait Named {
+    /// # Safety
+    ///
+    /// A control block pointer must be null or point to a control block created by the
+    /// kernel, whose name is null or a C string. The wrappers only pass such pointers to
+    /// `tx_checked_call!`, which is the only caller.
+    unsafe fn object_name(&self) -> ObjectName;
+}
+
+impl Named for &CStr {
+    unsafe fn object_name(&self) -> ObjectName {
+        ObjectName::from_c_str(self)
+    }
+}
+
+macro_rules! named_control_blocks {
+    ($($block:ty => $name:ident),* $(,)?) => {
+        $(
+            impl Named for *mut $block {
+                unsafe fn object_name(&self) -> ObjectName {
+                    if self.is_null() {
+                        return ObjectName::NONE;
+                    }
+                    ObjectName::from_ptr((**self).$name)
+                }
+            }
+        )*
+    };
+}
+
+named_control_blocks! {
+    TX_BLOCK_POOL => tx_block_pool_name,
+    TX_BYTE_POOL => tx_byte_pool_name,
+    TX_EVENT_FLAGS_GROUP => tx_event_flags_group_name,
+    TX_MUTEX => tx_mutex_name,
+    TX_QUEUE => tx_queue_name,
+    TX_SEMAPHORE => tx_semaphore_name,
+    TX_THREAD => tx_thread_name,
+    TX_TIMER => tx_timer_name,
+}
+
+/// The error of a kernel service, or of a wrapper before it called the kernel
+#[derive(Clone, Copy, PartialEq, Eq)]
+pub struct TxError {
+    kind: ErrorKind,
+    code: UINT,
+    service: Option<&'static str>,
+    object: ObjectName,
+}
+
+impl TxError {
+    /// The error of `service`, which returned `code`. Called by `tx_checked_call!`.
+    #[doc(hidden)]
+    pub fn from_call(service: &'static str, code: UINT, object: ObjectName) -> Self {
+        TxError { kind: ErrorKind::from_status(service, code), code, service: Some(service), object }
+    }
+
+    pub fn kind(&self) -> ErrorKind {
+        self.kind
+    }
+
+    /// The status returned by ThreadX, also when its kind is `Unknown`
+    pub fn code(&self) -> UINT {
+        self.code
+    }
+
+    /// The ThreadX function that failed, e.g. `_tx_queue_send`. `None` if the wrapper
+    /// failed before calling the kernel.
+    pub fn service(&self) -> Option<&'static str> {
+        self.service
+    }
+
+    /// The name of the object the service was called on, cut to 16 bytes
+    pub fn object(&self) -> Option<&str> {
+        self.object.as_str()
+    }
+}
+
+/// An error found by a wrapper without calling the kernel
+impl From<ErrorKind> for TxError {
+    fn from(kind: ErrorKind) -> Self {
+        TxError { kind, code: kind.status(), service: None, object: ObjectName::NONE }
+    }
+}
+
+impl fmt::Debug for TxError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let mut debug = f.debug_struct("TxError");
+        debug.field("kind", &self.kind).field("code", &format_args!("{:#x}", self.code));
+        if let Some(service) = self.service {
+            debug.field("service", &service);
+        }
+        if let Some(object) = self.object() {
+            debug.field("object", &object);
+        }
+        debug.finish()
+    }
+}
+
+impl fmt::Display for TxError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match (self.service, self.object()) {
+            (Some(service), Some(object)) => write!(f, "{service} on `{object}` failed: ")?,
+            (Some(service), None) => write!(f, "{service} failed: ")?,
+            (None, _) => {}
+        }
+        write!(f, "{:?} ({:#x})", self.kind, self.code)
+    }
+}
+
+#[cfg(feature = "defmt")]
+impl defmt::Format for TxError {
+    fn format(&self, f: defmt::Formatter) {
+        match (self.service, self.object()) {
+            (Some(service), Some(object)) => defmt::write!(f, "{=str} on `{=str}` failed: ", service, object),
+            (Some(service), None) => defmt::write!(f, "{=str} failed: ", service),
+            (None, _) => {}
+        }
+        defmt::write!(f, "{} ({=u32:#x})", self.kind, self.code as u32)
+    }
+}
+
+#[cfg(feature = "core-error")]
+impl core::error::Error for TxError {}
+
+/// The error enum of a service that waits for something: the thing was not available, the
+/// object was deleted or the wait was aborted. Any other error is `Kernel`.
+macro_rules! wait_error {
+    (
+        $(#[$meta:meta])*
+        pub enum $name:ident {
+            $(#[$unavailable_meta:meta])*
+            $unavailable:ident = $kind:ident, $message:literal
+        }
+    ) => {
+        $(#[$meta])*
+        #[derive(Clone, Copy, Debug, PartialEq, Eq)]
+        #[cfg_attr(feature = "defmt", derive(defmt::Format))]
+        pub enum $name {
+            $(#[$unavailable_meta])*
+            $unavailable,
+            /// The object was deleted while the thread waited
+            Deleted,
+            /// The wait was aborted by `tx_thread_wait_abort`
+            WaitAborted,
+            Kernel($crate::error::TxError),
+        }
+
+        impl From<$crate::error::TxError> for $name {
+            fn from(error: $crate::error::TxError) -> Self {
+                match error.kind() {
+                    $crate::error::ErrorKind::$kind => $name::$unavailable,
+                    $crate::error::ErrorKind::Deleted => $name::Deleted,
+                    $crate::error::ErrorKind::WaitAborted => $name::WaitAborted,
+                    _ => $name::Kernel(error),
+                }
+            }
+        }
+
+        impl core::fmt::Display for $name {
+            fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+                match self {
+                    $name::$unavailable => f.write_str($message),
+                    $name::Deleted => f.write_str("deleted while waiting"),
+                    $name::WaitAborted => f.write_str("wait aborted"),
+                    $name::Kernel(error) => core::fmt::Display::fmt(error, f),
+                }
+            }
+        }
+
+        #[cfg(feature = "core-error")]
+        impl core::error::Error for $name {}
+    };
+}
+
+pub(crate) use wait_error;
+
+#[cfg(test)]
+mod tests {
+    extern crate std;
+    use std::string::ToString;
+
+    use super::*;
+
+    #[test]
+    fn start_error_is_only_returned_by_thread_create() {
+        assert_eq!(ErrorKind::from_status("_tx_thread_create", 0x10), ErrorKind::StartError);
+        assert_eq!(ErrorKind::from_status("_tx_byte_allocate", 0x10), ErrorKind::NoMemory);
+    }
+
+    #[test]
+    fn unknown_statuses_keep_their_code() {
+        let error = TxError::from_call("_tx_queue_send", 0x42, ObjectName::NONE);
+        assert_eq!(error.kind(), ErrorKind::Unknown);
+        assert_eq!(error.code(), 0x42);
+        assert_eq!(error.to_string(), "_tx_queue_send failed: Unknown (0x42)");
+    }
+
+    #[test]
+    fn long_object_names_are_cut() {
+        let name = ObjectName::from_c_str(c"a_rather_long_queue_name");
+        let error = TxError::from_call("_tx_queue_send", TX_QUEUE_FULL, name);
+        assert_eq!(error.object(), Some("a_rather_long_qu"));
+        assert_eq!(error.to_string(), "_tx_queue_send on `a_rather_long_qu` failed: QueueFull (0xb)");
+        assert_eq!(TxError::from(ErrorKind::SizeError).to_string(), "SizeError (0x5)");
+    }
+}
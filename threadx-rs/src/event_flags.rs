@@ -3,16 +3,15 @@ use core::marker::PhantomData;
 use core::mem::MaybeUninit;
 use core::ptr;
 
-use threadx_sys::{_tx_event_flags_delete, _tx_event_flags_get, ULONG, _tx_event_flags_set, _tx_event_flags_set_notify};
+use threadx_sys::{_tx_event_flags_delete, _tx_event_flags_get, UINT, ULONG, _tx_event_flags_set, _tx_event_flags_set_notify};
 use threadx_sys::{TX_EVENT_FLAGS_GROUP,_tx_event_flags_create};
 
 use crate::tx_checked_call;
 
 use super::WaitOption;
 use super::error::TxError;
-use defmt::{debug, println, trace};
-use defmt::error;
-use num_traits::FromPrimitive;
+use crate::error::{wait_error, ErrorKind};
+use crate::log::{debug, println, trace};
 
 #[derive(Copy,Clone)]
 #[repr(u32)]
@@ -29,49 +28,63 @@ pub enum SetOption {
     SetAndClear = threadx_sys::TX_AND,
     SetAny = threadx_sys::TX_OR,
 }
-pub struct EventFlagsGroup(pub MaybeUninit<TX_EVENT_FLAGS_GROUP>);
+wait_error! {
+    /// Error of the `get` of an event flags group
+    pub enum EventFlagsGetError {
+        /// The requested flags are not set and the caller did not wait
+        NoEvents = NoEvents, "no events"
+    }
+}
+
+pub struct EventFlagsGroup {
+    group: MaybeUninit<TX_EVENT_FLAGS_GROUP>,
+    initialized: bool,
+}
+
+// SAFETY: a group is 32 flags and the kernel's list of waiting threads. The pointers
+// in the control block are only followed by the kernel, so nothing ties the group to
+// the thread that creates it.
+unsafe impl Send for EventFlagsGroup {}
+// `publish` and `get` only call into the kernel, which serializes access
+unsafe impl Sync for EventFlagsGroup {}
 
 impl EventFlagsGroup {
     pub const fn new() -> Self{
-        EventFlagsGroup(core::mem::MaybeUninit::uninit())
+        EventFlagsGroup { group: core::mem::MaybeUninit::uninit(), initialized: false }
     }
 
-    pub fn initialize(&'static mut self, name: &CStr) -> Result<(),TxError> {
-        let group_ptr = self.0.as_mut_ptr();
-        if group_ptr.is_null() {
-            panic!("EventFlagsGroup ptr is null");
-        }
-        unsafe {
-            if !(*group_ptr).tx_event_flags_group_name.is_null() {
-                panic!("EventFlagsGroup is already initialized");
-            }
+    /// Create the ThreadX event flags group. The returned reference is used to publish
+    /// and get events.
+    pub fn initialize(&'static mut self, name: &'static CStr) -> Result<&'static Self,TxError> {
+        if self.initialized {
+            panic!("EventFlagsGroup is already initialized");
         }
+        let group_ptr = self.group.as_mut_ptr();
         trace!("EventFlagsGroup::initialize: ptr is: {}",group_ptr);
-        tx_checked_call!(_tx_event_flags_create(
+        tx_checked_call!(name => _tx_event_flags_create(
             group_ptr,
             name.as_ptr() as *mut i8
         ))?;
-        Ok(())
+        self.initialized = true;
+        Ok(self)
     }
 
     pub fn publish(&'static self, flags_to_set: u32) -> Result<(),TxError> {
-        let group_ptr = self.0.as_ptr();
-        let group_ptr = group_ptr as *mut TX_EVENT_FLAGS_GROUP;
-        if group_ptr.is_null() {
-            return Err(TxError::Unknown);
+        if !self.initialized {
+            return Err(ErrorKind::GroupError.into());
         }
-        tx_checked_call!(_tx_event_flags_set(group_ptr, flags_to_set,0))
+        let group_ptr = self.group.as_ptr() as *mut TX_EVENT_FLAGS_GROUP;
+        tx_checked_call!(group_ptr => _tx_event_flags_set(group_ptr, flags_to_set as ULONG,0))
     }
 
-    pub fn get(&'static self, requested_flags: u32, get_option: GetOption, wait_option: WaitOption) -> Result<u32,TxError> {
-        let group_ptr = self.0.as_ptr();
-        let group_ptr = group_ptr as *mut TX_EVENT_FLAGS_GROUP;
-        if group_ptr.is_null() {
-            return Err(TxError::Unknown);
+    pub fn get(&'static self, requested_flags: u32, get_option: GetOption, wait_option: WaitOption) -> Result<u32,EventFlagsGetError> {
+        if !self.initialized {
+            return Err(EventFlagsGetError::Kernel(ErrorKind::GroupError.into()));
         }
-        let mut actual_flags = 0u32;
-        tx_checked_call!(_tx_event_flags_get(group_ptr, requested_flags, get_option as ULONG, &mut actual_flags, wait_option as ULONG))?;
-        Ok(actual_flags)
+        let group_ptr = self.group.as_ptr() as *mut TX_EVENT_FLAGS_GROUP;
+        let mut actual_flags: ULONG = 0;
+        tx_checked_call!(group_ptr => _tx_event_flags_get(group_ptr, requested_flags as ULONG, get_option as UINT, &mut actual_flags, wait_option as ULONG))?;
+        Ok(actual_flags as u32)
     }
 }
 
@@ -93,19 +106,19 @@ impl <'a>EventFlagsGroupHandle<'a> {
     pub fn delete(self) -> Result<(),TxError> {
         // convert reference to pointer
         let self_ptr = self.0 as *const TX_EVENT_FLAGS_GROUP as *mut TX_EVENT_FLAGS_GROUP;
-        tx_checked_call!(_tx_event_flags_delete(self_ptr))
+        tx_checked_call!(self_ptr => _tx_event_flags_delete(self_ptr))
     }
 
-    pub fn get(&self, requested_flags: u32, get_option: GetOption, wait_option: WaitOption) -> Result<u32,TxError> {
+    pub fn get(&self, requested_flags: u32, get_option: GetOption, wait_option: WaitOption) -> Result<u32,EventFlagsGetError> {
         debug!("EventFlagsGroupHandle::get requested_flags: {:?}",requested_flags);
-        let mut actual_flags = 0u32;
+        let mut actual_flags: ULONG = 0;
         
         println!("1");
         
         println!("EventFlagsGroupHandle::get self_ptr: {}",self.0);
         println!("Foo");
-        tx_checked_call!(_tx_event_flags_get(self.0, requested_flags, get_option as ULONG, &mut actual_flags, wait_option as ULONG))?;
-        Ok(actual_flags)
+        tx_checked_call!(self.0 => _tx_event_flags_get(self.0, requested_flags as ULONG, get_option as UINT, &mut actual_flags, wait_option as ULONG))?;
+        Ok(actual_flags as u32)
     }
 
     pub fn set(&self, flags_to_set: u32, set_option: SetOption) -> Result<(),TxError> {
@@ -116,13 +129,13 @@ impl <'a>EventFlagsGroupHandle<'a> {
         //println!("EventFlagsGroupHandle::get self_ptr: {}",self_ptr);
         println!("Bar");
         
-        tx_checked_call!(_tx_event_flags_set(self.0, flags_to_set,2))
+        tx_checked_call!(self.0 => _tx_event_flags_set(self.0, flags_to_set as ULONG,2))
     }
 
     pub fn on_notify(&mut self, mut notify: fn(EventFlagsGroupHandle)) -> Result<(),TxError> {
         let trampoline = get_notify_trampoline(&notify);
         let self_ptr = self.0 as *const TX_EVENT_FLAGS_GROUP as *mut TX_EVENT_FLAGS_GROUP;
-        tx_checked_call!(_tx_event_flags_set_notify(self_ptr, Some(trampoline)))
+        tx_checked_call!(self_ptr => _tx_event_flags_set_notify(self_ptr, Some(trampoline)))
     }
 }
 
@@ -1,3 +1,6 @@
+// Cortex-M exception handlers, see threadx_sys
+#![cfg(target_arch = "arm")]
+
 use cortex_m_rt::exception;
 
 #[exception]
@@ -0,0 +1,212 @@
+//! Fault injection for kernel service calls.
+//!
+//! With the `fault-injection` feature every kernel service called by this crate first asks
+//! the injector whether it should fail. A failing call is not made, it returns the error of
+//! the [`Fault`] instead, so the error paths of an application can be run on purpose:
+//!
+//! ```ignore
+//! static SCRIPT: [Fault; 2] = [
+//!     Fault::nth("_tx_byte_allocate", 3, ErrorKind::NoMemory),
+//!     Fault::one_in("_tx_queue_send", 10, ErrorKind::QueueFull),
+//! ];
+//!
+//! fault::install(&SCRIPT, 42);
+//! ```
+//!
+//! A service is named by its ThreadX function, e.g. `_tx_queue_send` for
+//! `QueueSender::send`. Each fault of the script counts the calls of its service, the first
+//! one that triggers decides the error. Random faults are drawn from the seed given to
+//! [`install`], so a failing run repeats with the same seed.
+//!
+//! The injector is updated with interrupts disabled.
+
+use threadx_sys::{TX_INT_DISABLE, UINT, _tx_thread_interrupt_control};
+
+use crate::error::ErrorKind;
+use crate::log::debug;
+
+/// Longest script that `install` accepts
+pub const MAX_FAULTS: usize = 8;
+
+/// When a fault triggers, counted in calls of its service
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum Trigger {
+    /// The nth call, counting from 1
+    Nth(u32),
+    /// Every call after the first n
+    After(u32),
+    /// One in n calls on average
+    OneIn(u32),
+}
+
+/// A rule of the injection script
+#[derive(Clone, Copy, Debug)]
+pub struct Fault {
+    service: &'static str,
+    trigger: Trigger,
+    status: UINT,
+}
+
+impl Fault {
+    /// Fail the `n`th call of `service`, counting from 1
+    pub const fn nth(service: &'static str, n: u32, kind: ErrorKind) -> Self {
+        Fault { service, trigger: Trigger::Nth(n), status: kind.status() }
+    }
+
+    /// Fail every call of `service` after the first `n`. With 0 every call fails.
+    pub const fn after(service: &'static str, n: u32, kind: ErrorKind) -> Self {
+        Fault { service, trigger: Trigger::After(n), status: kind.status() }
+    }
+
+    /// Fail calls of `service` at random, one in `n` on average
+    pub const fn one_in(service: &'static str, n: u32, kind: ErrorKind) -> Self {
+        assert!(n > 0, "Fault::one_in needs n > 0");
+        Fault { service, trigger: Trigger::OneIn(n), status: kind.status() }
+    }
+}
+
+struct Injector {
+    script: &'static [Fault],
+    /// Calls of the service of each fault
+    calls: [u32; MAX_FAULTS],
+    /// xorshift32 state, never 0
+    random: u32,
+    injected: u32,
+}
+
+impl Injector {
+    const fn new(script: &'static [Fault], seed: u32) -> Self {
+        // xorshift is stuck at 0
+        let random = if seed == 0 { 0x9E37_79B9 } else { seed };
+        Injector { script, calls: [0; MAX_FAULTS], random, injected: 0 }
+    }
+
+    fn next_random(&mut self) -> u32 {
+        let mut x = self.random;
+        x ^= x << 13;
+        x ^= x >> 17;
+        x ^= x << 5;
+        self.random = x;
+        x
+    }
+
+    /// The status `service` should fail with, if any
+    fn check(&mut self, service: &str) -> Option<UINT> {
+        let mut status = None;
+        for (index, fault) in self.script.iter().enumerate() {
+            if fault.service != service {
+                continue;
+            }
+            self.calls[index] += 1;
+            let calls = self.calls[index];
+            let triggered = match fault.trigger {
+                Trigger::Nth(n) => calls == n,
+                Trigger::After(n) => calls > n,
+                Trigger::OneIn(n) => self.next_random().is_multiple_of(n),
+            };
+            if triggered && status.is_none() {
+                status = Some(fault.status);
+            }
+        }
+        if status.is_some() {
+            self.injected += 1;
+        }
+        status
+    }
+}
+
+// Only accessed with interrupts disabled
+static mut INJECTOR: Option<Injector> = None;
+
+fn with_injector<R>(f: impl FnOnce(&mut Option<Injector>) -> R) -> R {
+    let posture = unsafe { _tx_thread_interrupt_control(TX_INT_DISABLE) };
+    let result = f(unsafe { &mut *core::ptr::addr_of_mut!(INJECTOR) });
+    unsafe { _tx_thread_interrupt_control(posture) };
+    result
+}
+
+/// Start injecting the faults of `script`, replacing the previous script. `seed` drives
+/// the random faults.
+pub fn install(script: &'static [Fault], seed: u32) {
+    assert!(script.len() <= MAX_FAULTS, "fault script longer than MAX_FAULTS");
+    with_injector(|injector| *injector = Some(Injector::new(script, seed)));
+}
+
+/// Stop injecting faults
+pub fn clear() {
+    with_injector(|injector| *injector = None);
+}
+
+/// Faults injected since the script was installed
+pub fn injected() -> u32 {
+    with_injector(|injector| injector.as_ref().map_or(0, |injector| injector.injected))
+}
+
+/// Called by `tx_checked_call!` before calling `service`. Returns the status to return
+/// instead of making the call.
+pub(crate) fn inject(service: &'static str) -> Option<UINT> {
+    let status = with_injector(|injector| injector.as_mut()?.check(service));
+    if let Some(status) = status {
+        debug!("Injecting status {} into {}", status, service);
+    }
+    status
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn run(injector: &mut Injector, service: &str, calls: usize) -> [Option<UINT>; 8] {
+        let mut results = [None; 8];
+        for result in results.iter_mut().take(calls) {
+            *result = injector.check(service);
+        }
+        results
+    }
+
+    #[test]
+    fn nth_and_after_count_the_calls_of_their_service() {
+        static SCRIPT: [Fault; 2] = [
+            Fault::nth("_tx_byte_allocate", 3, ErrorKind::NoMemory),
+            Fault::after("_tx_queue_send", 2, ErrorKind::QueueFull),
+        ];
+        let mut injector = Injector::new(&SCRIPT, 1);
+        let no_memory = Some(ErrorKind::NoMemory.status());
+        let full = Some(ErrorKind::QueueFull.status());
+        assert_eq!(run(&mut injector, "_tx_byte_allocate", 4), [None, None, no_memory, None, None, None, None, None]);
+        assert_eq!(run(&mut injector, "_tx_queue_send", 4), [None, None, full, full, None, None, None, None]);
+        assert_eq!(injector.check("_tx_queue_receive"), None);
+        assert_eq!(injector.injected, 3);
+    }
+
+    #[test]
+    fn the_first_triggered_fault_decides() {
+        static SCRIPT: [Fault; 2] = [
+            Fault::nth("_tx_queue_send", 2, ErrorKind::QueueFull),
+            Fault::after("_tx_queue_send", 1, ErrorKind::Deleted),
+        ];
+        let mut injector = Injector::new(&SCRIPT, 1);
+        let full = Some(ErrorKind::QueueFull.status());
+        let deleted = Some(ErrorKind::Deleted.status());
+        assert_eq!(run(&mut injector, "_tx_queue_send", 3)[..3], [None, full, deleted]);
+    }
+
+    #[test]
+    fn random_faults_repeat_with_the_seed() {
+        static SCRIPT: [Fault; 1] = [Fault::one_in("_tx_queue_send", 3, ErrorKind::QueueFull)];
+        let pattern = |seed| {
+            let mut injector = Injector::new(&SCRIPT, seed);
+            let mut failed = 0_u64;
+            for call in 0..64 {
+                if injector.check("_tx_queue_send").is_some() {
+                    failed |= 1 << call;
+                }
+            }
+            failed
+        };
+        assert_eq!(pattern(7), pattern(7));
+        assert_ne!(pattern(7), pattern(8));
+        assert!((10..40).contains(&pattern(7).count_ones()));
+        assert_ne!(pattern(0), 0);
+    }
+}
@@ -0,0 +1,391 @@
+//! Heap debugging for byte pool allocations.
+//!
+//! With the `heap-debug` feature every allocation made through `BytePoolHandle`,
+//! `PoolBox`, `PoolVec`, the byte pool `Allocator` and the `ThreadXAllocator` carries a
+//! header in front of the returned memory and a guard behind it. The header records the
+//! allocating thread and the requested size and links the allocation into a list of live
+//! allocations.
+//!
+//! The guards are checked when the memory is released. A buffer overrun is reported at
+//! the release of the damaged allocation, together with the thread that allocated it,
+//! instead of surfacing later as a corrupted pool inside `_tx_byte_release`. [`report`]
+//! prints all live allocations grouped by thread and checks the guards of every one of
+//! them.
+//!
+//! Each allocation costs `size_of::<Header>() + GUARD_SIZE` bytes of pool memory plus
+//! alignment padding. The list of live allocations is updated with interrupts disabled.
+
+use core::alloc::Layout;
+use core::ffi::CStr;
+use core::ptr::NonNull;
+
+use threadx_sys::{TX_BYTE_POOL, TX_INT_DISABLE, TX_THREAD, _tx_thread_identify, _tx_thread_interrupt_control};
+
+use crate::pool::{aligned_byte_pool_alloc, aligned_byte_pool_release};
+
+use super::error::{ErrorKind, TxError};
+use crate::log::{error, println};
+
+const GUARD_SIZE: usize = 8;
+/// Pattern written in front of and behind every live allocation
+const GUARD: [u8; GUARD_SIZE] = [0xFD; GUARD_SIZE];
+/// Pattern written over the front guard when an allocation is released. Finding it on
+/// release means the memory was released twice.
+const RELEASED: [u8; GUARD_SIZE] = [0xDD; GUARD_SIZE];
+
+/// Placed directly in front of the memory handed out, so `guard` ends where the
+/// allocation starts.
+#[repr(C)]
+struct Header {
+    next: *mut Header,
+    prev: *mut Header,
+    thread: *mut TX_THREAD,
+    size: usize,
+    guard: [u8; GUARD_SIZE],
+}
+
+/// Pool memory used by the header and guard of an allocation with an alignment of
+/// at most `BYTE_POOL_ALIGN`
+pub(crate) const ALLOCATION_OVERHEAD: usize = core::mem::size_of::<Header>() + GUARD_SIZE;
+
+// Only accessed with interrupts disabled
+static mut LIVE: *mut Header = core::ptr::null_mut();
+static mut LIVE_BYTES: usize = 0;
+static mut PEAK_BYTES: usize = 0;
+
+fn with_interrupts_disabled<R>(f: impl FnOnce() -> R) -> R {
+    let posture = unsafe { _tx_thread_interrupt_control(TX_INT_DISABLE) };
+    let result = f();
+    unsafe { _tx_thread_interrupt_control(posture) };
+    result
+}
+
+/// The layout of the block taken from the pool for `layout` and the offset of the
+/// memory handed out within it. The offset only depends on the alignment.
+fn debug_layout(layout: Layout) -> Option<(Layout, usize)> {
+    let (with_header, offset) = Layout::new::<Header>().extend(layout).ok()?;
+    let (outer, _) = with_header.extend(Layout::new::<[u8; GUARD_SIZE]>()).ok()?;
+    Some((outer, offset))
+}
+
+unsafe fn header_of(ptr: NonNull<u8>) -> *mut Header {
+    (ptr.as_ptr() as *mut Header).sub(1)
+}
+
+unsafe fn rear_guard(header: *const Header) -> [u8; GUARD_SIZE] {
+    let end = (header.add(1) as *const u8).add((*header).size);
+    (end as *const [u8; GUARD_SIZE]).read_unaligned()
+}
+
+/// Which guard of an allocation was overwritten, if any. The rear guard is only
+/// checked if the header is intact, otherwise the size can not be trusted.
+unsafe fn damage(header: *const Header) -> Option<&'static str> {
+    if (*header).guard != GUARD {
+        Some("front guard")
+    } else if rear_guard(header) != GUARD {
+        Some("rear guard")
+    } else {
+        None
+    }
+}
+
+fn thread_name(thread: *mut TX_THREAD) -> &'static str {
+    if thread.is_null() {
+        return "<initialization>";
+    }
+    let name = unsafe { (*thread).tx_thread_name };
+    if name.is_null() {
+        return "<unnamed>";
+    }
+    unsafe { CStr::from_ptr(name) }.to_str().unwrap_or("<invalid name>")
+}
+
+pub(crate) unsafe fn alloc(pool: *mut TX_BYTE_POOL, layout: Layout, wait: bool) -> Result<NonNull<u8>, TxError> {
+    let (outer, offset) = debug_layout(layout).ok_or(ErrorKind::SizeError)?;
+    let block = aligned_byte_pool_alloc(pool, outer, wait)?;
+    let ptr = NonNull::new_unchecked(block.as_ptr().add(offset));
+    let header = header_of(ptr);
+    header.write(Header {
+        next: core::ptr::null_mut(),
+        prev: core::ptr::null_mut(),
+        thread: _tx_thread_identify(),
+        size: layout.size(),
+        guard: GUARD,
+    });
+    (ptr.as_ptr().add(layout.size()) as *mut [u8; GUARD_SIZE]).write_unaligned(GUARD);
+    with_interrupts_disabled(|| {
+        (*header).next = LIVE;
+        if !LIVE.is_null() {
+            (*LIVE).prev = header;
+        }
+        LIVE = header;
+        LIVE_BYTES += layout.size();
+        PEAK_BYTES = PEAK_BYTES.max(LIVE_BYTES);
+    });
+    Ok(ptr)
+}
+
+/// Check the guards and release the allocation. A damaged guard or a second release of
+/// the same memory is logged with the allocating thread and passed to the corruption
+/// hook. The memory is then kept out of the pool, which can no longer be trusted with
+/// it, and `PtrError` is returned. This never panics as it is reached from
+/// `GlobalAlloc::dealloc`.
+pub(crate) unsafe fn release(ptr: NonNull<u8>, layout: Layout) -> Result<(), TxError> {
+    let (outer, offset) = debug_layout(layout).ok_or(ErrorKind::SizeError)?;
+    let header = header_of(ptr);
+    if (*header).guard == RELEASED {
+        error!("heap: {} released twice", ptr.as_ptr());
+        report_corruption(Corruption { ptr: ptr.as_ptr(), damage: "released twice" });
+        return Err(ErrorKind::PtrError.into());
+    }
+    if let Some(damage) = damage(header) {
+        // The links of a damaged header are not followed
+        error!(
+            "heap: {=str} of {} overwritten, allocated by {=str}, released by {=str}",
+            damage,
+            ptr.as_ptr(),
+            thread_name((*header).thread),
+            thread_name(_tx_thread_identify())
+        );
+        report_corruption(Corruption { ptr: ptr.as_ptr(), damage });
+        return Err(ErrorKind::PtrError.into());
+    }
+    with_interrupts_disabled(|| {
+        let Header { next, prev, size, .. } = *header;
+        if prev.is_null() {
+            LIVE = next;
+        } else {
+            (*prev).next = next;
+        }
+        if !next.is_null() {
+            (*next).prev = prev;
+        }
+        LIVE_BYTES -= size;
+        (*header).guard = RELEASED;
+    });
+    aligned_byte_pool_release(NonNull::new_unchecked(ptr.as_ptr().sub(offset)), outer)
+}
+
+/// The requested size. Growing in place would move the rear guard, so it is not allowed.
+pub(crate) unsafe fn usable_size(ptr: NonNull<u8>, _layout: Layout) -> usize {
+    (*header_of(ptr)).size
+}
+
+/// Memory allocated by a single thread
+#[derive(Clone, Copy, Debug, Default)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct HeapUsage {
+    pub allocations: usize,
+    pub bytes: usize,
+}
+
+/// A damaged allocation found on release, passed to the [`CorruptionHook`]
+#[derive(Clone, Copy, Debug)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct Corruption {
+    /// The memory that was released
+    pub ptr: *mut u8,
+    /// `"released twice"`, `"front guard"` or `"rear guard"`
+    pub damage: &'static str,
+}
+
+pub type CorruptionHook = fn(&Corruption);
+
+// Only accessed with interrupts disabled
+static mut CORRUPTION_HOOK: Option<CorruptionHook> = None;
+
+/// Install a function that is called when a damaged allocation is released, e.g. to
+/// reset the system. The hook is called from the context of the release and must not
+/// allocate.
+pub fn set_corruption_hook(hook: CorruptionHook) {
+    with_interrupts_disabled(|| unsafe { CORRUPTION_HOOK = Some(hook) });
+}
+
+fn report_corruption(corruption: Corruption) {
+    if let Some(hook) = with_interrupts_disabled(|| unsafe { CORRUPTION_HOOK }) {
+        hook(&corruption);
+    }
+}
+
+/// Summary returned by [`report`]
+#[derive(Clone, Copy, Debug, Default)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct HeapReport {
+    /// Number of live allocations
+    pub allocations: usize,
+    /// Sum of the sizes of all live allocations
+    pub bytes: usize,
+    /// High water mark of `bytes`
+    pub peak_bytes: usize,
+    /// Number of live allocations with an overwritten guard
+    pub corrupted: usize,
+}
+
+/// Iterate over the live allocations. The walk stops at an allocation with a damaged
+/// front guard because its links can not be trusted.
+unsafe fn for_each_live(mut f: impl FnMut(*mut Header)) {
+    let mut header = LIVE;
+    while !header.is_null() {
+        f(header);
+        if (*header).guard != GUARD {
+            break;
+        }
+        header = (*header).next;
+    }
+}
+
+unsafe fn usage_of(thread: *mut TX_THREAD) -> HeapUsage {
+    let mut usage = HeapUsage::default();
+    for_each_live(|header| {
+        if (*header).thread == thread && (*header).guard == GUARD {
+            usage.allocations += 1;
+            usage.bytes += (*header).size;
+        }
+    });
+    usage
+}
+
+/// Memory currently allocated by the calling thread. During initialization this is the
+/// memory allocated before the kernel was started.
+pub fn thread_usage() -> HeapUsage {
+    with_interrupts_disabled(|| unsafe { usage_of(_tx_thread_identify()) })
+}
+
+/// Print all live allocations grouped by the thread that allocated them and check the
+/// guards of every allocation. Interrupts are disabled while the report is printed, so
+/// this is meant for debugging sessions and not for production code.
+pub fn report() -> HeapReport {
+    with_interrupts_disabled(|| unsafe {
+        let mut report = HeapReport { peak_bytes: PEAK_BYTES, ..Default::default() };
+        println!("heap: live allocations");
+        for_each_live(|owner| {
+            let thread = (*owner).thread;
+            // Print each thread once, at its most recent allocation
+            if thread_seen_before(owner) {
+                return;
+            }
+            let usage = usage_of(thread);
+            println!(
+                "  {=str}: {} allocations, {} bytes",
+                thread_name(thread),
+                usage.allocations,
+                usage.bytes
+            );
+            for_each_live(|header| {
+                if (*header).thread != thread {
+                    return;
+                }
+                match damage(header) {
+                    Some(damage) => {
+                        report.corrupted += 1;
+                        error!("    {}: {=str} overwritten", header.add(1), damage);
+                    }
+                    None => println!("    {}: {} bytes", header.add(1), (*header).size),
+                }
+            });
+        });
+        for_each_live(|header| {
+            if (*header).guard == GUARD {
+                report.allocations += 1;
+                report.bytes += (*header).size;
+            }
+        });
+        println!(
+            "heap: {} allocations, {} bytes, peak {} bytes, {} corrupted",
+            report.allocations,
+            report.bytes,
+            report.peak_bytes,
+            report.corrupted
+        );
+        report
+    })
+}
+
+/// True if an allocation in front of `owner` in the live list has the same thread
+unsafe fn thread_seen_before(owner: *mut Header) -> bool {
+    let mut header = LIVE;
+    while header != owner {
+        if (*header).thread == (*owner).thread {
+            return true;
+        }
+        header = (*header).next;
+    }
+    false
+}
+
+#[cfg(test)]
+mod tests {
+    use core::sync::atomic::{AtomicUsize, Ordering};
+
+    use super::*;
+
+    #[test]
+    fn header_ends_where_the_allocation_starts() {
+        for align in [1, 2, 4, 8, 16, 64] {
+            let layout = Layout::from_size_align(10, align).unwrap();
+            let (outer, offset) = debug_layout(layout).unwrap();
+            assert!(offset >= core::mem::size_of::<Header>());
+            assert_eq!(offset % align, 0);
+            assert_eq!((offset - core::mem::size_of::<Header>()) % core::mem::align_of::<Header>(), 0);
+            assert!(outer.size() >= offset + layout.size() + GUARD_SIZE);
+            assert!(outer.align() >= align);
+        }
+    }
+
+    /// An allocation laid out like `alloc` does, outside of any pool
+    #[repr(C)]
+    struct Allocation {
+        header: Header,
+        memory: [u8; 16],
+        rear: [u8; GUARD_SIZE],
+    }
+
+    impl Allocation {
+        fn new() -> Self {
+            let header = Header {
+                next: core::ptr::null_mut(),
+                prev: core::ptr::null_mut(),
+                thread: core::ptr::null_mut(),
+                size: 16,
+                guard: GUARD,
+            };
+            Allocation { header, memory: [0; 16], rear: GUARD }
+        }
+
+        fn ptr(&mut self) -> NonNull<u8> {
+            NonNull::from(&mut self.memory).cast()
+        }
+    }
+
+    #[test]
+    fn damaged_guards_are_found() {
+        let mut allocation = Allocation::new();
+        assert_eq!(unsafe { damage(header_of(allocation.ptr())) }, None);
+        allocation.rear[0] = 0;
+        assert_eq!(unsafe { damage(header_of(allocation.ptr())) }, Some("rear guard"));
+        allocation.header.guard[GUARD_SIZE - 1] = 0;
+        assert_eq!(unsafe { damage(header_of(allocation.ptr())) }, Some("front guard"));
+    }
+
+    static CORRUPTIONS: AtomicUsize = AtomicUsize::new(0);
+
+    #[test]
+    fn release_reports_overruns_and_double_releases() {
+        set_corruption_hook(|_| {
+            CORRUPTIONS.fetch_add(1, Ordering::Relaxed);
+        });
+        let layout = Layout::new::<[u8; 16]>();
+
+        let mut overrun = Allocation::new();
+        overrun.rear[3] = 0;
+        let error = unsafe { release(overrun.ptr(), layout) }.unwrap_err();
+        assert_eq!(error.kind(), ErrorKind::PtrError);
+        assert_eq!(CORRUPTIONS.load(Ordering::Relaxed), 1);
+
+        let mut released = Allocation::new();
+        released.header.guard = RELEASED;
+        let error = unsafe { release(released.ptr(), layout) }.unwrap_err();
+        assert_eq!(error.kind(), ErrorKind::PtrError);
+        assert_eq!(CORRUPTIONS.load(Ordering::Relaxed), 2);
+    }
+}
@@ -1,10 +1,17 @@
 #![no_std]
+#![cfg_attr(feature = "nightly", feature(allocator_api))]
 use core::ffi::c_void;
 
 use threadx_sys::_tx_initialize_kernel_enter;
 
+/// Host implementation of the kernel services, with the test `Harness`
+#[cfg(feature = "sim")]
+pub use threadx_sim as sim;
+
 
 pub mod pool;
+pub mod pool_box;
+pub mod object_pool;
 pub mod thread;
 pub mod error;
 pub mod time;
@@ -13,11 +20,57 @@ pub mod mutex;
 pub mod queue;
 pub mod semaphore;
 pub mod allocator;
+pub mod planner;
+pub mod static_cell;
+#[doc(hidden)]
+pub mod log;
+#[cfg(feature = "heap-debug")]
+pub mod heap;
+#[cfg(any(feature = "nightly", feature = "allocator-api2"))]
+pub mod allocator_api;
+#[cfg(feature = "tlsf")]
+pub mod tlsf;
 pub mod timer;
+#[cfg(feature = "embedded-hal")]
+pub mod delay;
+#[cfg(feature = "fault-injection")]
+pub mod fault;
 
 pub use threadx_sys::_tx_timer_interrupt as tx_timer_interrupt;
+#[cfg(target_arch = "arm")]
 pub use threadx_sys::__tx_PendSVHandler as tx_pendsv_handler;
 
+#[cfg(feature = "macros")]
+pub use threadx_macros::{app, thread};
+
+// Used by the code generated by the `app` macro
+#[cfg(feature = "macros")]
+#[doc(hidden)]
+pub mod __private {
+    pub use threadx_sys::{TX_MAX_PRIORITIES, TX_MINIMUM_STACK};
+}
+
+// Used by `tx_checked_call!`, which is also expanded in crates that do not depend on
+// threadx-sys or do not have the features of this crate
+#[doc(hidden)]
+pub mod __checked_call {
+    pub use threadx_sys::{TX_SUCCESS, UINT};
+
+    /// The status that an injected fault returns instead of calling `service`
+    #[inline(always)]
+    pub fn injected(service: &'static str) -> Option<UINT> {
+        #[cfg(feature = "fault-injection")]
+        {
+            crate::fault::inject(service)
+        }
+        #[cfg(not(feature = "fault-injection"))]
+        {
+            let _ = service;
+            None
+        }
+    }
+}
+
 
 
 /// Initialize ThreadX
@@ -73,19 +126,19 @@ impl Builder
         unsafe{DEFINE_CB = Some(self.app_define_cb)};
 
         unsafe { _tx_initialize_kernel_enter() };
-        defmt::error!("ThreadX kernel should never return from _tx_initialize_kernel_enter");
+        crate::log::error!("ThreadX kernel should never return from _tx_initialize_kernel_enter");
     }
 }
 
 // This variable is defined by threadx and is used to store a pointer to the unused memory
+#[cfg(target_arch = "arm")]
 extern "C" {
     static mut _tx_initialize_unused_memory: *mut c_void;
 }
 
-/// This function is called by threadx for low level initialization
-/// such as setting up the heap and initializing the interrupt priorities
-#[no_mangle]
-unsafe extern "C" fn _tx_initialize_low_level() {
+/// Call the low level initialization callback and keep the memory it returns for
+/// `tx_application_define`. Returns the start of that memory.
+unsafe fn low_level_init() -> *mut u8 {
     // call the low level initialization callback. This callback returns the memory that
     // is available for the application to use. 
     // Safety: This callback is called only after we initialize the INIT_CB in the initialize function
@@ -94,14 +147,28 @@ unsafe extern "C" fn _tx_initialize_low_level() {
     let heap_start = mem.as_mut_ptr();
     // we need to store it locally to keep track of the size.
     HEAP = Some(mem);
-    _tx_initialize_unused_memory = heap_start as *mut c_void;
+    heap_start
+}
 
+/// This function is called by threadx for low level initialization
+/// such as setting up the heap and initializing the interrupt priorities
+#[cfg(target_arch = "arm")]
+#[no_mangle]
+unsafe extern "C" fn _tx_initialize_low_level() {
+    _tx_initialize_unused_memory = low_level_init() as *mut c_void;
 }
 
 
 
 #[no_mangle]
-unsafe extern "C" fn tx_application_define(mem_start: *mut c_void ) {
+unsafe extern "C" fn tx_application_define(_mem_start: *mut c_void ) {
+    #[cfg(target_arch = "arm")]
+    let mem_start = _mem_start;
+    // The Linux port brings its own _tx_initialize_low_level, which sets up the
+    // scheduler and the timer thread that drives the tick. The low level initialization
+    // callback is called here instead and its memory replaces the memory of the port.
+    #[cfg(not(target_arch = "arm"))]
+    let mem_start = low_level_init() as *mut c_void;
     // Call the application definition callback
     // Safety: This callback is called only after we initialize the DEFINE_CB in the initialize function
     // and it can never be `None`
@@ -125,27 +192,57 @@ macro_rules! tx_str {
     }
 }
 
+/// Call a ThreadX service and turn its status into a `TxResult`. Errors are logged with
+/// the service and, given as `object => _tx_service(..)`, the name of the object.
 #[macro_export]
 macro_rules! tx_checked_call {
-    ($func:ident($($arg:expr),*)) => {
+    // The arguments are evaluated in the scrutinee of a `match`, so that only the call
+    // itself is in the `unsafe` block and temporaries in the arguments live until the call
+    // has returned. `arg` is a different variable in every expansion.
+    (@bind $object:expr, $func:ident, [$($bound:ident)*], [$($value:expr),*], []) => {
+        $crate::tx_checked_call!(@call $object, $func, [$($bound)*], [$($value),*])
+    };
+    (@bind $object:expr, $func:ident, [$($bound:ident)*], [$($value:expr),*], [$first:expr $(, $rest:expr)*]) => {
+        $crate::tx_checked_call!(@bind $object, $func, [$($bound)* arg], [$($value,)* $first], [$($rest),*])
+    };
+    (@call $object:expr, $func:ident, [$($arg:ident)*], [$($value:expr),*]) => {
         {
-            use defmt::error;
-            use defmt::trace;
-            let ret = unsafe { $func($($arg),*) };
-            if ret != threadx_sys::TX_SUCCESS {
-                
-                error!("ThreadX call {} returned {}", stringify!($func), ret);
-                crate::error::TxResult::Err(TxError::from_u32(ret).unwrap_or(TxError::Unknown))
+            use $crate::log::error;
+            use $crate::log::trace;
+            #[allow(clippy::match_single_binding)]
+            let ret = match ($($value,)*) {
+                // An injected fault replaces the call
+                ($($arg,)*) => match $crate::__checked_call::injected(stringify!($func)) {
+                    Some(status) => status,
+                    None => unsafe { $func($($arg),*) },
+                },
+            };
+            if ret != $crate::__checked_call::TX_SUCCESS {
+                // The name is only looked up on failure
+                let error = $crate::error::TxError::from_call(stringify!($func), ret, $object);
+                error!("ThreadX call failed: {}", error);
+                $crate::error::TxResult::Err(error)
             } else {
                 trace!("ThreadX call {} Success", stringify!($func));
-                crate::error::TxResult::Ok(())
+                $crate::error::TxResult::Ok(())
             }
         }
+    };
+    ($func:ident($($arg:expr),* $(,)?)) => {
+        $crate::tx_checked_call!(@bind $crate::error::ObjectName::NONE, $func, [], [], [$($arg),*])
+    };
+    ($object:expr => $func:ident($($arg:expr),* $(,)?)) => {
+        $crate::tx_checked_call!(@bind {
+            let object = &$object;
+            // Safety: the object is the name given to a create call or the control block
+            // of an object the call was made on, see `Named`
+            unsafe { $crate::error::Named::object_name(object) }
+        }, $func, [], [], [$($arg),*])
     }
 }
 
 #[repr(u32)]
 pub enum WaitOption {
-    WaitForever = threadx_sys::TX_WAIT_FOREVER,
-    NoWait = threadx_sys::TX_NO_WAIT,
+    WaitForever = threadx_sys::TX_WAIT_FOREVER as u32,
+    NoWait = threadx_sys::TX_NO_WAIT as u32,
 }
\ No newline at end of file
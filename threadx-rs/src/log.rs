@@ -0,0 +1,39 @@
+//! Logging macros used by the crate. They are the defmt macros with the `defmt`
+//! feature. Without it, for example on a Linux host where there is no defmt logger,
+//! the messages are dropped. The arguments are still type checked but not evaluated.
+//!
+//! The module is public because the exported `tx_checked_call!` logs through it.
+
+#[cfg(feature = "defmt")]
+pub use defmt::{debug, error, println, trace};
+
+#[cfg(not(feature = "defmt"))]
+#[doc(hidden)]
+#[macro_export]
+macro_rules! __log_discard {
+    ($fmt:literal $(, $arg:expr)* $(,)?) => {
+        if false {
+            $(let _ = &$arg;)*
+        }
+    };
+}
+
+#[cfg(not(feature = "defmt"))]
+#[doc(hidden)]
+#[macro_export]
+macro_rules! __log_trace { ($($t:tt)*) => { $crate::__log_discard!($($t)*) }; }
+#[cfg(not(feature = "defmt"))]
+#[doc(hidden)]
+#[macro_export]
+macro_rules! __log_debug { ($($t:tt)*) => { $crate::__log_discard!($($t)*) }; }
+#[cfg(not(feature = "defmt"))]
+#[doc(hidden)]
+#[macro_export]
+macro_rules! __log_error { ($($t:tt)*) => { $crate::__log_discard!($($t)*) }; }
+#[cfg(not(feature = "defmt"))]
+#[doc(hidden)]
+#[macro_export]
+macro_rules! __log_println { ($($t:tt)*) => { $crate::__log_discard!($($t)*) }; }
+
+#[cfg(not(feature = "defmt"))]
+pub use crate::{__log_debug as debug, __log_error as error, __log_println as println, __log_trace as trace};
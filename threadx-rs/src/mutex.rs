@@ -26,11 +26,10 @@ use crate::tx_checked_call;
 
 use super::WaitOption;
 use super::error::TxError;
-use defmt::debug;
-use defmt::error;
-use num_traits::FromPrimitive;
-use thiserror_no_std::Error;
+use crate::error::{wait_error, ErrorKind};
+use crate::log::error;
 use threadx_sys::TX_MUTEX;
+use threadx_sys::ULONG;
 use threadx_sys::_tx_mutex_create;
 use threadx_sys::_tx_mutex_delete;
 use threadx_sys::_tx_mutex_get;
@@ -39,8 +38,9 @@ use threadx_sys::_tx_mutex_put;
 pub struct Mutex<T> {
     inner : UnsafeCell<T>,
     mutex : UnsafeCell<MaybeUninit<TX_MUTEX>>,
+    initialized : bool,
 }
-//unsafe impl<T: Send> Send for Mutex<T> {}
+unsafe impl<T: Send> Send for Mutex<T> {}
 unsafe impl<T: Send> Sync for Mutex<T> {}
 
 pub struct MutexGuard<'a,T> {
@@ -65,7 +65,8 @@ impl<T> Drop for MutexGuard<'_, T> {
     fn drop(&mut self) {
         let mutex_ptr = self.mutex.mutex.get();
         if let Some(mutex_ptr) = unsafe{mutex_ptr.as_mut()} {
-            if tx_checked_call!(_tx_mutex_put(mutex_ptr.as_mut_ptr())).is_err() {
+            let mutex_ptr = mutex_ptr.as_mut_ptr();
+            if tx_checked_call!(mutex_ptr => _tx_mutex_put(mutex_ptr)).is_err() {
                 error!("MutexGuard::drop failed to put mutex");
             }
         } else {
@@ -74,10 +75,13 @@ impl<T> Drop for MutexGuard<'_, T> {
     }
 }
 
-#[derive(Error,Debug)]
-pub enum MutexError {
-    MutexError(TxError),
-    PoisonError,
+wait_error! {
+    /// Error of `Mutex::lock`. Locking a mutex that was not initialized fails with a
+    /// `MutexError` kernel error.
+    pub enum MutexLockError {
+        /// Another thread owns the mutex and the caller did not wait
+        NotAvailable = NotAvailable, "mutex not available"
+    }
 }
 
 impl <T>Mutex<T> {
@@ -85,43 +89,41 @@ impl <T>Mutex<T> {
         Self {
             inner : UnsafeCell::new(inner),
             mutex : UnsafeCell::new(MaybeUninit::<TX_MUTEX>::uninit()),
+            initialized : false,
         }
     }
 
-    pub fn initialize(&'static mut self, name: &CStr, inherit: bool) -> Result<(),TxError> {
-
-        
-        unsafe {
-            if !self.mutex.get_mut().as_ptr().as_ref().unwrap().tx_mutex_name.is_null() {
-                panic!("Mutex is already initialized");
-            }
+    /// Create the ThreadX mutex. The returned reference is used to lock the mutex.
+    pub fn initialize(&'static mut self, name: &'static CStr, inherit: bool) -> Result<&'static Self,TxError> {
+        if self.initialized {
+            panic!("Mutex is already initialized");
         }
         let mutex_ptr = self.mutex.get_mut().as_mut_ptr();
 
-        tx_checked_call!(_tx_mutex_create(
+        tx_checked_call!(name => _tx_mutex_create(
             mutex_ptr,
             name.as_ptr() as *mut i8,
             inherit as u32
-        ))
+        ))?;
+        self.initialized = true;
+        Ok(self)
     }
 
-    pub fn lock(&'static self, wait_option: WaitOption) -> Result<MutexGuard<T>,MutexError> {
-        let mut mutex_ptr = self.mutex.get();
+    pub fn lock(&'static self, wait_option: WaitOption) -> Result<MutexGuard<'static, T>,MutexLockError> {
+        let mutex_ptr = self.mutex.get();
         
         if let Some(mutex_ptr) = unsafe{mutex_ptr.as_mut()} {
             let mutex_ptr = mutex_ptr.as_mut_ptr();
-            unsafe {
-                if (*mutex_ptr).tx_mutex_name.is_null() {
-                    return Err(MutexError::PoisonError);
-                }
+            if !self.initialized {
+                return Err(MutexLockError::Kernel(ErrorKind::MutexError.into()));
             }
-            let result = tx_checked_call!(_tx_mutex_get(mutex_ptr,wait_option as u32));
+            let result = tx_checked_call!(mutex_ptr => _tx_mutex_get(mutex_ptr,wait_option as ULONG));
             match result {
                 Ok(_) => Ok(MutexGuard{mutex:self}),
-                Err(e) => Err(MutexError::MutexError(e))
+                Err(e) => Err(MutexLockError::from(e))
             }
         } else {
-            return Err(MutexError::PoisonError);
+            return Err(MutexLockError::Kernel(ErrorKind::MutexError.into()));
         }
     }
 }
@@ -132,7 +134,7 @@ impl <T>Drop for Mutex<T> {
         if mutex_ptr.is_null() {
             panic!("Mutex ptr is null");
         }
-        let _ = tx_checked_call!(_tx_mutex_delete(mutex_ptr));
+        let _ = tx_checked_call!(mutex_ptr => _tx_mutex_delete(mutex_ptr));
     }
 }
 
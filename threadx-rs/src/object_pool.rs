@@ -0,0 +1,240 @@
+//! Typed fixed-size object pool built on a ThreadX block pool.
+//!
+//! An [`ObjectPool`] holds the control block and the memory for `N` objects of type `T`.
+//! Block size and pool memory, including the pointer that ThreadX places in front of
+//! every block, are computed at compile time so the pool can never hold fewer objects
+//! than requested. Allocation and release take constant time.
+//!
+//! ```ignore
+//! static mut MESSAGES: ObjectPool<Message, 8> = ObjectPool::new();
+//! let pool = unsafe { MESSAGES.initialize(tx_str!("messages")).unwrap() };
+//! let msg = pool.allocate(Message::default(), false).map_err(|(_, error)| error)?;
+//! // the block is released when `msg` is dropped
+//! ```
+
+use core::ffi::CStr;
+use core::fmt;
+use core::marker::PhantomData;
+use core::mem::{align_of, size_of, ManuallyDrop, MaybeUninit};
+use core::ops::{Deref, DerefMut};
+use core::ptr::NonNull;
+
+use threadx_sys::{
+    TX_BLOCK_POOL, TX_NO_WAIT, TX_WAIT_FOREVER, ULONG, _tx_block_allocate, _tx_block_pool_create,
+    _tx_block_release,
+};
+
+use crate::pool::{block_pool_info, BLOCK_HEADER_SIZE};
+use crate::tx_checked_call;
+
+use super::error::TxError;
+use crate::error::ErrorKind;
+use crate::pool::AllocateError;
+use crate::log::error;
+
+/// Layout of a single block as seen by Rust. The header is placed so that it
+/// ends exactly where `value` starts.
+#[repr(C)]
+struct Block<T> {
+    header: MaybeUninit<*mut u8>,
+    value: MaybeUninit<T>,
+}
+
+/// The pool memory. When `T` is more strictly aligned than the header, the pool
+/// handed to ThreadX starts part way into the first block and ends the same amount
+/// past the last one. `tail` makes room for that.
+#[repr(C)]
+struct Storage<T, const N: usize> {
+    blocks: [Block<T>; N],
+    tail: MaybeUninit<*mut u8>,
+}
+
+pub struct ObjectPool<T, const N: usize> {
+    pool: MaybeUninit<TX_BLOCK_POOL>,
+    storage: MaybeUninit<Storage<T, N>>,
+    initialized: bool,
+}
+
+// SAFETY: the `T` values are stored inline and handed out as `Pooled<T>` to any
+// thread, which is sound when `T` is `Send`. The block pool itself belongs to no thread.
+unsafe impl<T: Send, const N: usize> Send for ObjectPool<T, N> {}
+
+impl<T, const N: usize> ObjectPool<T, N> {
+    /// Offset of the value within a block
+    const VALUE_OFFSET: usize = if align_of::<T>() > BLOCK_HEADER_SIZE {
+        align_of::<T>()
+    } else {
+        BLOCK_HEADER_SIZE
+    };
+    /// Distance between consecutive blocks
+    const STRIDE: usize = size_of::<Block<T>>();
+
+    /// The block size passed to ThreadX. This is a multiple of the header size
+    /// so ThreadX does not round it up any further.
+    pub const BLOCK_SIZE: usize = Self::STRIDE - BLOCK_HEADER_SIZE;
+    /// The amount of memory passed to ThreadX to hold exactly `N` blocks
+    pub const POOL_SIZE: usize = Self::STRIDE * N;
+    /// The total memory taken by the pool storage
+    pub const MEMORY_SIZE: usize = size_of::<Storage<T, N>>();
+
+    pub const fn new() -> Self {
+        const { assert!(size_of::<T>() > 0 && N > 0, "ObjectPool needs a non zero sized T and N > 0") };
+        ObjectPool {
+            pool: MaybeUninit::uninit(),
+            storage: MaybeUninit::uninit(),
+            initialized: false,
+        }
+    }
+
+    pub fn initialize(&'static mut self, name: &'static CStr) -> Result<ObjectPoolHandle<T>, TxError> {
+        if self.initialized {
+            panic!("ObjectPool is already initialized");
+        }
+        let pool_ptr = self.pool.as_mut_ptr();
+        let pool_start = unsafe {
+            (self.storage.as_mut_ptr() as *mut u8).add(Self::VALUE_OFFSET - BLOCK_HEADER_SIZE)
+        };
+        tx_checked_call!(name => _tx_block_pool_create(
+            pool_ptr,
+            name.as_ptr() as *mut i8,
+            Self::BLOCK_SIZE as ULONG,
+            pool_start as *mut core::ffi::c_void,
+            Self::POOL_SIZE as ULONG
+        ))
+        .map(|_| {
+            self.initialized = true;
+            ObjectPoolHandle(pool_ptr, PhantomData)
+        })
+    }
+}
+
+impl<T, const N: usize> Default for ObjectPool<T, N> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// Handle used to allocate objects from an initialized `ObjectPool`
+pub struct ObjectPoolHandle<T>(*mut TX_BLOCK_POOL, PhantomData<T>);
+
+impl<T> Clone for ObjectPoolHandle<T> {
+    fn clone(&self) -> Self {
+        *self
+    }
+}
+impl<T> Copy for ObjectPoolHandle<T> {}
+
+unsafe impl<T: Send> Send for ObjectPoolHandle<T> {}
+unsafe impl<T: Send> Sync for ObjectPoolHandle<T> {}
+
+impl<T> ObjectPoolHandle<T> {
+    /// Move `value` into a free block. If `wait` is true the calling thread is
+    /// suspended until a block is released. On failure `value` is handed back
+    /// together with the error.
+    pub fn allocate(&self, value: T, wait: bool) -> Result<Pooled<T>, (T, AllocateError)> {
+        let mut ptr: *mut core::ffi::c_void = core::ptr::null_mut();
+        if let Err(error) = tx_checked_call!(self.0 => _tx_block_allocate(
+            self.0,
+            &mut ptr,
+            if wait { TX_WAIT_FOREVER } else { TX_NO_WAIT }
+        )) {
+            return Err((value, error.into()));
+        }
+        let Some(ptr) = NonNull::new(ptr as *mut T) else {
+            return Err((value, TxError::from(ErrorKind::PtrError).into()));
+        };
+        unsafe { ptr.as_ptr().write(value) };
+        Ok(Pooled(ptr))
+    }
+
+    /// Number of free objects in the pool
+    pub fn available(&self) -> Result<usize, TxError> {
+        block_pool_info(self.0).map(|info| info.available_blocks)
+    }
+
+    /// Number of objects the pool can hold
+    pub fn total(&self) -> Result<usize, TxError> {
+        block_pool_info(self.0).map(|info| info.total_blocks)
+    }
+}
+
+/// An object allocated from an `ObjectPool`. Dropping it drops the `T` and
+/// returns the block to the pool.
+pub struct Pooled<T>(NonNull<T>);
+
+unsafe impl<T: Send> Send for Pooled<T> {}
+unsafe impl<T: Sync> Sync for Pooled<T> {}
+
+impl<T> Pooled<T> {
+    /// Move the value out and return the block to the pool.
+    pub fn into_inner(this: Self) -> T {
+        let this = ManuallyDrop::new(this);
+        let value = unsafe { this.0.as_ptr().read() };
+        release(this.0);
+        value
+    }
+}
+
+fn release<T>(ptr: NonNull<T>) {
+    if tx_checked_call!(_tx_block_release(ptr.as_ptr() as *mut core::ffi::c_void)).is_err() {
+        error!("Failed to release object pool block");
+    }
+}
+
+impl<T> Deref for Pooled<T> {
+    type Target = T;
+
+    fn deref(&self) -> &T {
+        unsafe { self.0.as_ref() }
+    }
+}
+
+impl<T> DerefMut for Pooled<T> {
+    fn deref_mut(&mut self) -> &mut T {
+        unsafe { self.0.as_mut() }
+    }
+}
+
+impl<T> Drop for Pooled<T> {
+    fn drop(&mut self) {
+        unsafe { core::ptr::drop_in_place(self.0.as_ptr()) };
+        release(self.0);
+    }
+}
+
+impl<T: fmt::Debug> fmt::Debug for Pooled<T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        fmt::Debug::fmt(&**self, f)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn block_layout_small_types() {
+        type P = ObjectPool<u8, 4>;
+        assert_eq!(P::VALUE_OFFSET, BLOCK_HEADER_SIZE);
+        assert_eq!(P::BLOCK_SIZE % BLOCK_HEADER_SIZE, 0);
+        assert_ne!(P::BLOCK_SIZE, 0);
+        assert_eq!(P::POOL_SIZE, 4 * (P::BLOCK_SIZE + BLOCK_HEADER_SIZE));
+        const { assert!(P::MEMORY_SIZE >= P::POOL_SIZE) };
+    }
+
+    #[test]
+    fn block_layout_over_aligned_types() {
+        #[repr(align(32))]
+        struct Aligned(#[allow(dead_code)] [u8; 40]);
+        type P = ObjectPool<Aligned, 3>;
+        assert_eq!(P::VALUE_OFFSET, 32);
+        assert_eq!(P::STRIDE % 32, 0);
+        const { assert!(P::BLOCK_SIZE >= size_of::<Aligned>()) };
+        // every value starts on an aligned address and the last block fits
+        let start = P::VALUE_OFFSET - BLOCK_HEADER_SIZE;
+        for i in 0..3 {
+            assert_eq!((start + i * P::STRIDE + BLOCK_HEADER_SIZE) % 32, 0);
+        }
+        assert!(start + P::POOL_SIZE <= P::MEMORY_SIZE);
+    }
+}
@@ -0,0 +1,222 @@
+//! Splitting the application define memory into regions for pools, queues and stacks.
+//!
+//! ThreadX keeps bookkeeping inside the memory handed to byte pools, block pools and
+//! queues. The `const fn` helpers in this module compute how much memory a pool needs
+//! for a given set of allocations, including that overhead, and [`MemoryPlanner`]
+//! carves aligned regions of those sizes out of the memory passed to the `AppDefineCb`.
+//!
+//! ```ignore
+//! const POOL: usize = byte_pool_size(&[256, 256]);
+//! const QUEUE: usize = queue_size::<u32>(16);
+//!
+//! |mem_start| {
+//!     let mut plan = MemoryPlanner::new(mem_start);
+//!     let pool = unsafe { BP.initialize(tx_str!("pool"), plan.byte_pool(POOL).unwrap()).unwrap() };
+//!     let queue_mem = plan.queue::<u32>(16).unwrap();
+//!     let stack = plan.stack(512).unwrap();
+//!     ...
+//! }
+//! ```
+//!
+//! If the size of the memory is known at compile time, [`Budget`] checks the plan while
+//! compiling:
+//!
+//! ```ignore
+//! const _: () = Budget::<HEAP_SIZE, { POOL + QUEUE + stack_size(512) }>::OK;
+//! ```
+
+use core::mem::size_of;
+
+use threadx_sys::ULONG;
+
+use crate::pool::{MemoryBlock, BLOCK_HEADER_SIZE, BYTE_BLOCK_HEADER_SIZE, BYTE_POOL_ALIGN};
+use crate::queue::Queue;
+
+/// Every region handed out by the planner starts on this alignment and its size is
+/// rounded up to it. This satisfies the stack alignment required by the Cortex-M ABI.
+pub const REGION_ALIGN: usize = 8;
+
+/// ThreadX rejects byte pools smaller than this (`TX_BYTE_POOL_MIN`)
+const BYTE_POOL_MIN: usize = 100;
+
+const fn round_up(size: usize, align: usize) -> usize {
+    size.div_ceil(align) * align
+}
+
+/// Memory taken by a region of `size` bytes from `MemoryPlanner::region` or `byte_pool`
+pub const fn region_size(size: usize) -> usize {
+    round_up(size, REGION_ALIGN)
+}
+
+/// Pool memory taken by a single byte pool allocation of `size` bytes with an alignment
+/// of at most `BYTE_POOL_ALIGN`. With the `heap-debug` feature this includes the guards.
+pub const fn byte_pool_allocation_size(size: usize) -> usize {
+    #[cfg(feature = "heap-debug")]
+    let size = size + crate::heap::ALLOCATION_OVERHEAD;
+    round_up(if size == 0 { 1 } else { size }, BYTE_POOL_ALIGN) + BYTE_BLOCK_HEADER_SIZE
+}
+
+/// Size of a byte pool that can hold all of `allocations` at the same time. ThreadX
+/// marks the end of the pool with one more block header.
+///
+/// Byte pools are first fit, so this is only exact if the allocations are not released
+/// and made again in a different order. Leave some slack for pools that are used as a heap.
+pub const fn byte_pool_size(allocations: &[usize]) -> usize {
+    let mut size = BYTE_BLOCK_HEADER_SIZE;
+    let mut i = 0;
+    while i < allocations.len() {
+        size += byte_pool_allocation_size(allocations[i]);
+        i += 1;
+    }
+    round_up(if size < BYTE_POOL_MIN { BYTE_POOL_MIN } else { size }, REGION_ALIGN)
+}
+
+/// Size of a block pool with `blocks` blocks of `block_size` bytes. ThreadX rounds the
+/// block size up to whole words and stores a pointer in front of every block.
+pub const fn block_pool_size(block_size: usize, blocks: usize) -> usize {
+    round_up(blocks * (round_up(block_size, size_of::<ULONG>()) + BLOCK_HEADER_SIZE), REGION_ALIGN)
+}
+
+/// Size of the storage of a `Queue<T>` with room for `messages` messages
+pub const fn queue_size<T>(messages: usize) -> usize {
+    round_up(messages * Queue::<T>::MESSAGE_WORDS * size_of::<ULONG>(), REGION_ALIGN)
+}
+
+/// Size of a thread stack of at least `size` bytes
+pub const fn stack_size(size: usize) -> usize {
+    round_up(size, REGION_ALIGN)
+}
+
+/// Compile time check that a plan fits. `REQUIRED` is the sum of the sizes computed by
+/// the helpers in this module. Room for aligning the start of the memory is added.
+pub struct Budget<const AVAILABLE: usize, const REQUIRED: usize>;
+
+impl<const AVAILABLE: usize, const REQUIRED: usize> Budget<AVAILABLE, REQUIRED> {
+    pub const OK: () = assert!(
+        REQUIRED + REGION_ALIGN - 1 <= AVAILABLE,
+        "The memory plan needs more memory than is available"
+    );
+}
+
+/// Returned when a region does not fit into the remaining memory
+#[derive(Clone, Copy, Debug)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct PlanError {
+    /// What the region was planned for
+    pub region: &'static str,
+    /// Bytes needed for the region, including alignment
+    pub requested: usize,
+    /// Bytes that were left
+    pub available: usize,
+}
+
+/// Hands out aligned regions of a block of memory, typically the memory given to the
+/// `AppDefineCb`. Regions are taken from the front and never returned.
+pub struct MemoryPlanner {
+    memory: &'static mut [u8],
+    used: usize,
+}
+
+impl MemoryPlanner {
+    pub fn new(memory: &'static mut [u8]) -> Self {
+        MemoryPlanner { memory, used: 0 }
+    }
+
+    /// Bytes that have not been handed out yet
+    pub fn remaining(&self) -> usize {
+        self.memory.len()
+    }
+
+    /// Bytes handed out so far, including alignment padding
+    pub fn used(&self) -> usize {
+        self.used
+    }
+
+    fn take(&mut self, region: &'static str, size: usize) -> Result<&'static mut [u8], PlanError> {
+        let padding = self.memory.as_ptr().align_offset(REGION_ALIGN);
+        let requested = padding.saturating_add(round_up(size, REGION_ALIGN));
+        if requested > self.memory.len() {
+            return Err(PlanError { region, requested, available: self.memory.len() });
+        }
+        let memory = core::mem::take(&mut self.memory);
+        let (region, rest) = memory.split_at_mut(requested);
+        self.memory = rest;
+        self.used += requested;
+        Ok(&mut region[padding..])
+    }
+
+    /// A region of `size` bytes for anything not covered by the other methods
+    pub fn region(&mut self, size: usize) -> Result<&'static mut [u8], PlanError> {
+        self.take("region", size)
+    }
+
+    /// Memory for a `BytePool` or the `ThreadXAllocator`. Use `byte_pool_size` to
+    /// compute `size`.
+    pub fn byte_pool(&mut self, size: usize) -> Result<&'static mut [u8], PlanError> {
+        self.take("byte pool", size)
+    }
+
+    /// Memory for a `BlockPool` with `blocks` blocks of `block_size` bytes
+    pub fn block_pool(&mut self, block_size: usize, blocks: usize) -> Result<&'static mut [u8], PlanError> {
+        self.take("block pool", block_pool_size(block_size, blocks))
+    }
+
+    /// Storage for a `Queue<T>` with room for `messages` messages
+    pub fn queue<T>(&mut self, messages: usize) -> Result<MemoryBlock, PlanError> {
+        self.take("queue", queue_size::<T>(messages)).map(MemoryBlock::new)
+    }
+
+    /// A thread stack of at least `size` bytes
+    pub fn stack(&mut self, size: usize) -> Result<MemoryBlock, PlanError> {
+        self.take("stack", stack_size(size)).map(MemoryBlock::new)
+    }
+
+    /// The memory that was not handed out
+    pub fn into_remaining(self) -> &'static mut [u8] {
+        self.memory
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn byte_pool_size_covers_headers() {
+        let size = byte_pool_size(&[256, 256, 3]);
+        assert!(size >= 256 + 256 + 4 + 4 * BYTE_BLOCK_HEADER_SIZE);
+        assert_eq!(size % REGION_ALIGN, 0);
+        assert_eq!(byte_pool_size(&[]), round_up(BYTE_POOL_MIN, REGION_ALIGN));
+    }
+
+    #[test]
+    fn block_pool_size_rounds_blocks_to_words() {
+        assert_eq!(block_pool_size(13, 4), round_up(4 * (16 + BLOCK_HEADER_SIZE), REGION_ALIGN));
+    }
+
+    #[test]
+    fn queue_size_uses_whole_words() {
+        assert_eq!(queue_size::<u32>(3), round_up(3 * size_of::<ULONG>(), REGION_ALIGN));
+        assert_eq!(queue_size::<[u8; 6]>(2), 2 * 6usize.div_ceil(size_of::<ULONG>()) * size_of::<ULONG>());
+    }
+
+    #[test]
+    fn planner_aligns_and_reports_exhaustion() {
+        static mut MEMORY: [u8; 256] = [0; 256];
+        #[allow(static_mut_refs)]
+        let mut plan = MemoryPlanner::new(unsafe { &mut MEMORY[1..] });
+        let a = plan.region(10).unwrap();
+        assert_eq!(a.as_ptr() as usize % REGION_ALIGN, 0);
+        assert_eq!(a.len(), 16);
+        let stack = plan.stack(100).unwrap().consume();
+        assert_eq!(stack.as_ptr() as usize % REGION_ALIGN, 0);
+        let err = plan.byte_pool(1024).unwrap_err();
+        assert_eq!(err.available, plan.remaining());
+        assert_eq!(plan.used() + plan.remaining(), 255);
+    }
+
+    #[test]
+    fn budget_accepts_fitting_plans() {
+        let _: () = Budget::<1024, { byte_pool_size(&[256]) + stack_size(512) }>::OK;
+    }
+}
@@ -1,63 +1,78 @@
 use core::{
+    alloc::Layout,
     borrow::BorrowMut,
     cell::{Cell, OnceCell, RefCell},
     ffi::{c_void, CStr},
     mem::MaybeUninit,
     pin::Pin,
+    ptr::NonNull,
 };
 
 use threadx_sys::{
     _tx_block_allocate, _tx_block_pool_create, _tx_block_release, _tx_byte_allocate,
     _tx_byte_pool_create, _tx_byte_release, TX_BLOCK_POOL, TX_BYTE_POOL, TX_NO_WAIT,
     TX_WAIT_FOREVER, ULONG, _tx_block_pool_delete, _tx_byte_pool_delete, _tx_block_pool_info_get, _tx_block_pool_prioritize,
+    _tx_byte_pool_info_get, _tx_byte_pool_prioritize,
 };
 
 use crate::tx_checked_call;
 
 use super::error::TxError;
-use defmt::debug;
-use defmt::error;
-use num_traits::FromPrimitive;
+use crate::error::{wait_error, ErrorKind};
+
+pub struct BytePool {
+    pool: MaybeUninit<TX_BYTE_POOL>,
+    initialized: bool,
+}
+// SAFETY: the control block points into the `'static` pool memory handed to
+// `initialize`. The kernel serves allocations to whichever thread asks, the pool has no
+// owning thread.
+unsafe impl Send for BytePool {}
 
-pub struct BytePool(MaybeUninit<TX_BYTE_POOL>);
 impl BytePool {
     /// Create a new BytePool. This is a const function because we want to create static instances
     /// of the byte pool. Rust code will never access the inner structure directly, so we leave
     /// it as uninitialized, even though we know that it will be initialized by the threadx call.
     /// This will also prevent rust from trying to drop the inner structure.
     pub const fn new() -> Self {
-        BytePool(MaybeUninit::<TX_BYTE_POOL>::uninit())
+        BytePool { pool: MaybeUninit::<TX_BYTE_POOL>::uninit(), initialized: false }
     }
 
     /// Initialize the byte pool.
     pub fn initialize(
         &'static mut self,
-        name: &CStr,
+        name: &'static CStr,
         pool_memory: &mut [u8],
     ) -> Result<BytePoolHandle, TxError> {
-        //let mut pool = self.0;
-        let pool_ptr = self.0.as_mut_ptr();
-        if pool_ptr.is_null() {
-            panic!("Pool ptr is null");
+        if self.initialized {
+            panic!("Pool is already initialized");
         }
-        unsafe {
-            if !(*pool_ptr).tx_byte_pool_start.is_null() {
-                panic!("Pool is already initialized");
-            }
-        }
-        defmt::println!(
+        let pool_ptr = self.pool.as_mut_ptr();
+        let pool_memory = aligned_pool_memory(pool_memory);
+        crate::log::println!(
             "Pool ptr: {} name:{} memory:{}",
             pool_ptr,
             name.as_ptr(),
             pool_memory.as_mut_ptr()
         );
-        tx_checked_call!(_tx_byte_pool_create(
+        tx_checked_call!(name => _tx_byte_pool_create(
             pool_ptr,
             name.as_ptr() as *mut i8,
             pool_memory.as_mut_ptr() as *mut core::ffi::c_void,
             pool_memory.len() as ULONG
         ))
-        .map(|_| BytePoolHandle::new(pool_ptr))
+        .map(|_| {
+            self.initialized = true;
+            BytePoolHandle::new(pool_ptr)
+        })
+    }
+}
+
+wait_error! {
+    /// Error of the `allocate` of the pools
+    pub enum AllocateError {
+        /// The pool has no memory left for the request and the caller did not wait
+        NoMemory = NoMemory, "no memory"
     }
 }
 
@@ -65,7 +80,7 @@ pub struct MemoryBlock(&'static mut [u8]);
 
 impl MemoryBlock {
     // Not public as it is constructued by the BytePoolHandle
-    fn new(mem: &'static mut [u8]) -> Self {
+    pub(crate) fn new(mem: &'static mut [u8]) -> Self {
         MemoryBlock(mem)
     }
     
@@ -76,9 +91,13 @@ impl MemoryBlock {
 
 pub struct BytePoolHandle(*mut TX_BYTE_POOL);
 
+// Allocations are serialized by the kernel, so the handle can be used from any thread
+unsafe impl Send for BytePoolHandle {}
+unsafe impl Sync for BytePoolHandle {}
+
 impl BytePoolHandle {
 
-    fn new(ptr : *mut TX_BYTE_POOL) -> Self {
+    pub(crate) fn new(ptr : *mut TX_BYTE_POOL) -> Self {
         assert!(!ptr.is_null(),"Pool ptr is null");
         BytePoolHandle(ptr)
     }
@@ -87,65 +106,209 @@ impl BytePoolHandle {
         self.0
     }
 
-    pub fn allocate(&self, size: usize, wait: bool) -> Result<MemoryBlock, TxError> {
-        let mut ptr: *mut c_void = core::ptr::null_mut() as *mut c_void;
-        tx_checked_call!(_tx_byte_allocate(
+    pub fn allocate(&self, size: usize, wait: bool) -> Result<MemoryBlock, AllocateError> {
+        let layout = Layout::from_size_align(size, BYTE_POOL_ALIGN).map_err(|_| TxError::from(ErrorKind::SizeError))?;
+        let ptr = unsafe { byte_pool_alloc(self.0, layout, wait) }?;
+        Ok(MemoryBlock(unsafe { core::slice::from_raw_parts_mut(ptr.as_ptr(), size) }))
+    }
+
+    pub fn release(&self, mem: &mut [u8]) -> Result<(), TxError> {
+        let layout = Layout::from_size_align(mem.len(), BYTE_POOL_ALIGN).map_err(|_| ErrorKind::SizeError)?;
+        let ptr = NonNull::new(mem.as_mut_ptr()).ok_or(ErrorKind::PtrError)?;
+        unsafe { byte_pool_release(ptr, layout) }
+    }
+
+    /// Allocate memory for `layout`. The returned pointer must be released with
+    /// `release_layout` using the same layout.
+    pub(crate) fn allocate_layout(&self, layout: Layout, wait: bool) -> Result<NonNull<u8>, TxError> {
+        unsafe { byte_pool_alloc(self.0, layout, wait) }
+    }
+
+    /// Returns the number of available bytes and the number of fragments in the pool.
+    /// Note that the available bytes are spread over the fragments and each fragment
+    /// carries ThreadX's block header, so the largest possible allocation is smaller.
+    pub fn info(&self) -> Result<BytePoolInfo, TxError> {
+        let mut available_bytes: ULONG = 0;
+        let mut fragments: ULONG = 0;
+        let mut suspended_count: ULONG = 0;
+        tx_checked_call!(self.0 => _tx_byte_pool_info_get(
             self.0,
-            &mut ptr,
-            size as ULONG,
-            if wait { TX_WAIT_FOREVER } else { TX_NO_WAIT }
+            core::ptr::null_mut(),
+            &mut available_bytes,
+            &mut fragments,
+            core::ptr::null_mut(),
+            &mut suspended_count,
+            core::ptr::null_mut()
         ))
-        .map(|_| MemoryBlock(unsafe { core::slice::from_raw_parts_mut(ptr as *mut u8, size) }))
+        .map(|_| BytePoolInfo {
+            available_bytes: available_bytes as usize,
+            fragments: fragments as usize,
+            suspended_count: suspended_count as usize,
+        })
     }
 
-    pub fn release(&self, mem: &mut [u8]) -> Result<(), TxError> {
-        tx_checked_call!(_tx_byte_release(mem.as_mut_ptr() as *mut c_void))
+    /// Move the highest priority thread waiting on this pool to the front of the
+    /// suspension list so that it is served first on the next release.
+    pub fn prioritize(&self) -> Result<(), TxError> {
+        tx_checked_call!(self.0 => _tx_byte_pool_prioritize(self.0))
     }
 
     pub fn delete(self) -> Result<(), TxError> {
-        tx_checked_call!(_tx_byte_pool_delete(self.0))
+        tx_checked_call!(self.0 => _tx_byte_pool_delete(self.0))
     }
 }
 
-pub struct BlockPool(MaybeUninit<TX_BLOCK_POOL>);
+/// Alignment that ThreadX guarantees for byte pool allocations, provided the
+/// pool memory itself is aligned to it.
+pub(crate) const BYTE_POOL_ALIGN: usize = core::mem::align_of::<ULONG>();
+
+/// ThreadX places a pointer to the next block and the owning pool in front of
+/// every allocated block.
+pub(crate) const BYTE_BLOCK_HEADER_SIZE: usize = core::mem::size_of::<*mut u8>() + core::mem::size_of::<ULONG>();
+
+/// ThreadX aligns allocations relative to the start of the pool. Aligning the
+/// pool memory means that small alignments never need any padding.
+pub(crate) fn aligned_pool_memory(pool_memory: &mut [u8]) -> &mut [u8] {
+    let skip = pool_memory.as_ptr().align_offset(BYTE_POOL_ALIGN).min(pool_memory.len());
+    &mut pool_memory[skip..]
+}
+
+/// Allocations with an alignment larger than what ThreadX guarantees are padded.
+/// The distance to the start of the ThreadX block is stored in the word just
+/// in front of the returned pointer.
+fn is_padded(layout: &Layout) -> bool {
+    layout.align() > BYTE_POOL_ALIGN
+}
+
+// All byte pool allocations made by this crate go through `byte_pool_alloc`,
+// `byte_pool_release` and `byte_pool_usable_size`. The heap debugger wraps them
+// when the `heap-debug` feature is enabled.
+#[cfg(not(feature = "heap-debug"))]
+pub(crate) use self::{
+    aligned_byte_pool_alloc as byte_pool_alloc, aligned_byte_pool_release as byte_pool_release,
+    aligned_byte_pool_usable_size as byte_pool_usable_size,
+};
+#[cfg(feature = "heap-debug")]
+pub(crate) use crate::heap::{
+    alloc as byte_pool_alloc, release as byte_pool_release, usable_size as byte_pool_usable_size,
+};
+
+/// Allocate memory for `layout` from `pool` without ever suspending unless `wait` is set.
+/// Alignments larger than what ThreadX provides are padded.
+pub(crate) unsafe fn aligned_byte_pool_alloc(
+    pool: *mut TX_BYTE_POOL,
+    layout: Layout,
+    wait: bool,
+) -> Result<NonNull<u8>, TxError> {
+    let padding = if is_padded(&layout) {
+        layout.align() - 1 + core::mem::size_of::<usize>()
+    } else {
+        0
+    };
+    let size = layout.size().max(1).checked_add(padding).ok_or(ErrorKind::SizeError)?;
+    let mut block: *mut c_void = core::ptr::null_mut();
+    tx_checked_call!(pool => _tx_byte_allocate(
+        pool,
+        &mut block,
+        size as ULONG,
+        if wait { TX_WAIT_FOREVER } else { TX_NO_WAIT }
+    ))?;
+    let block = block as *mut u8;
+    if padding == 0 {
+        return NonNull::new(block).ok_or(ErrorKind::PtrError.into());
+    }
+    // Leave room for the offset in front of the aligned pointer
+    let ptr = block.add(core::mem::size_of::<usize>());
+    let ptr = ptr.add(ptr.align_offset(layout.align()));
+    (ptr as *mut usize).sub(1).write(ptr as usize - block as usize);
+    NonNull::new(ptr).ok_or(ErrorKind::PtrError.into())
+}
+
+/// The start of the ThreadX block that holds `ptr`
+unsafe fn byte_block_start(ptr: *mut u8, layout: &Layout) -> *mut u8 {
+    if is_padded(layout) {
+        let offset = (ptr as *const usize).sub(1).read();
+        ptr.sub(offset)
+    } else {
+        ptr
+    }
+}
+
+/// Release memory obtained from `aligned_byte_pool_alloc` with the same layout.
+pub(crate) unsafe fn aligned_byte_pool_release(ptr: NonNull<u8>, layout: Layout) -> Result<(), TxError> {
+    let block = byte_block_start(ptr.as_ptr(), &layout);
+    tx_checked_call!(_tx_byte_release(block as *mut c_void))
+}
+
+/// Number of bytes usable at `ptr` without reallocating. This relies on the layout of
+/// ThreadX byte pools, where the first word of the block header points to the next block.
+#[cfg_attr(feature = "heap-debug", allow(dead_code))]
+pub(crate) unsafe fn aligned_byte_pool_usable_size(ptr: NonNull<u8>, layout: Layout) -> usize {
+    let block = byte_block_start(ptr.as_ptr(), &layout);
+    let next = (block.sub(BYTE_BLOCK_HEADER_SIZE) as *const *mut u8).read();
+    next as usize - ptr.as_ptr() as usize
+}
+
+/// Snapshot of the state of a byte pool as returned by `BytePoolHandle::info`
+#[derive(Clone, Copy, Debug)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct BytePoolInfo {
+    pub available_bytes: usize,
+    pub fragments: usize,
+    pub suspended_count: usize,
+}
+
+/// ThreadX stores a pointer in front of every block of a block pool
+pub(crate) const BLOCK_HEADER_SIZE: usize = core::mem::size_of::<*mut u8>();
+
+pub struct BlockPool {
+    pool: MaybeUninit<TX_BLOCK_POOL>,
+    initialized: bool,
+}
+
+// SAFETY: as for `BytePool`, the control block only points into the `'static` block
+// memory and the kernel hands blocks to any thread.
+unsafe impl Send for BlockPool {}
 
 impl BlockPool {
     pub const fn new() -> Self {
-        BlockPool(core::mem::MaybeUninit::uninit())
+        BlockPool { pool: MaybeUninit::uninit(), initialized: false }
     }
 
     pub fn initialize(
         &'static mut self,
-        name: &CStr,
+        name: &'static CStr,
         block_size: usize,
         pool_memory: &mut [u8],
     ) -> Result<BlockPoolHandle, TxError> {
-        let pool_ptr = self.0.as_mut_ptr();
-        if pool_ptr.is_null() {
-            panic!("Pool ptr is null");
+        if self.initialized {
+            panic!("Pool is already initialized");
         }
-        unsafe {
-            if !(*pool_ptr).tx_block_pool_start.is_null() {
-                panic!("Pool is already initialized");
-            }
-        }
-        tx_checked_call!(_tx_block_pool_create(
+        let pool_ptr = self.pool.as_mut_ptr();
+        tx_checked_call!(name => _tx_block_pool_create(
             pool_ptr,
             name.as_ptr() as *mut i8,
             block_size as ULONG,
             pool_memory.as_mut_ptr() as *mut core::ffi::c_void,
             pool_memory.len() as ULONG
         ))
-        .map(|_| BlockPoolHandle(pool_ptr))
+        .map(|_| {
+            self.initialized = true;
+            BlockPoolHandle(pool_ptr)
+        })
     }
 }
 
 pub struct BlockPoolHandle(*mut TX_BLOCK_POOL);
 
+// Allocations are serialized by the kernel, so the handle can be used from any thread
+unsafe impl Send for BlockPoolHandle {}
+unsafe impl Sync for BlockPoolHandle {}
+
 impl BlockPoolHandle {
-    pub fn allocate(&mut self, wait: bool) -> Result<&'static mut [u8], TxError> {
+    pub fn allocate(&self, wait: bool) -> Result<&'static mut [u8], AllocateError> {
         let mut ptr: *mut c_void = core::ptr::null_mut() as *mut c_void;
-        tx_checked_call!(_tx_block_allocate(
+        tx_checked_call!(self.0 => _tx_block_allocate(
             self.0,
             &mut ptr,
             if wait { TX_WAIT_FOREVER } else { TX_NO_WAIT }
@@ -156,10 +319,11 @@ impl BlockPoolHandle {
                 (*self.0).tx_block_pool_block_size as usize,
             )
         })
+        .map_err(AllocateError::from)
     }
 
-    pub fn release(&mut self, mem: &'static mut [u8]) -> Result<(), TxError> {
-        tx_checked_call!(_tx_block_release(mem.as_mut_ptr() as *mut c_void))
+    pub fn release(&self, mem: &'static mut [u8]) -> Result<(), TxError> {
+        tx_checked_call!(self.0 => _tx_block_release(mem.as_mut_ptr() as *mut c_void))
     }
 
     /*
@@ -173,14 +337,48 @@ impl BlockPoolHandle {
 #define tx_block_release                            _tx_block_release
      */
 
-    pub fn prioritize(&mut self, mem: &'static mut [u8]) -> Result<(), TxError> {
-        tx_checked_call!(_tx_block_pool_prioritize(self.0))
+    pub fn prioritize(&self) -> Result<(), TxError> {
+        tx_checked_call!(self.0 => _tx_block_pool_prioritize(self.0))
+    }
+
+    /// Returns the number of available and total blocks in the pool.
+    pub fn info(&self) -> Result<BlockPoolInfo, TxError> {
+        block_pool_info(self.0)
     }
 
     // Free the block pool
     pub fn delete(self) -> Result<(), TxError> {
-        tx_checked_call!(_tx_block_pool_delete(self.0))
+        tx_checked_call!(self.0 => _tx_block_pool_delete(self.0))
     }
 
 
 }
+
+/// Snapshot of the state of a block pool as returned by `BlockPoolHandle::info`
+#[derive(Clone, Copy, Debug)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct BlockPoolInfo {
+    pub available_blocks: usize,
+    pub total_blocks: usize,
+    pub suspended_count: usize,
+}
+
+pub(crate) fn block_pool_info(pool: *mut TX_BLOCK_POOL) -> Result<BlockPoolInfo, TxError> {
+    let mut available_blocks: ULONG = 0;
+    let mut total_blocks: ULONG = 0;
+    let mut suspended_count: ULONG = 0;
+    tx_checked_call!(pool => _tx_block_pool_info_get(
+        pool,
+        core::ptr::null_mut(),
+        &mut available_blocks,
+        &mut total_blocks,
+        core::ptr::null_mut(),
+        &mut suspended_count,
+        core::ptr::null_mut()
+    ))
+    .map(|_| BlockPoolInfo {
+        available_blocks: available_blocks as usize,
+        total_blocks: total_blocks as usize,
+        suspended_count: suspended_count as usize,
+    })
+}
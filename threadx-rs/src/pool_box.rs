@@ -0,0 +1,229 @@
+//! Owned allocations from a ThreadX byte pool.
+//!
+//! [`PoolBox`] and [`PoolVec`] behave like `Box` and `Vec` but draw their memory
+//! from a specific [`BytePoolHandle`] instead of the global allocator. The memory is
+//! returned to the pool with `_tx_byte_release` when the value is dropped, so it can
+//! neither be released twice nor be released into a different pool.
+//!
+//! The pool must not be deleted while allocations from it are still alive.
+
+use core::alloc::Layout;
+use core::fmt;
+use core::marker::PhantomData;
+use core::mem::ManuallyDrop;
+use core::ops::{Deref, DerefMut};
+use core::ptr::NonNull;
+
+use threadx_sys::TX_BYTE_POOL;
+
+use crate::pool::{byte_pool_release, BytePoolHandle};
+
+use super::error::{ErrorKind, TxError};
+use crate::log::error;
+
+fn release(ptr: NonNull<u8>, layout: Layout) {
+    if layout.size() == 0 {
+        // zero sized values are never allocated from the pool
+        return;
+    }
+    if unsafe { byte_pool_release(ptr, layout) }.is_err() {
+        error!("Failed to release byte pool memory {}", ptr.as_ptr());
+    }
+}
+
+/// A pointer type for a single `T` allocated from a byte pool.
+pub struct PoolBox<T> {
+    ptr: NonNull<T>,
+    _marker: PhantomData<T>,
+}
+
+unsafe impl<T: Send> Send for PoolBox<T> {}
+unsafe impl<T: Sync> Sync for PoolBox<T> {}
+
+impl<T> PoolBox<T> {
+    /// Allocate memory from `pool` and move `value` into it. If `wait` is true the
+    /// calling thread is suspended until enough memory is available. On failure
+    /// `value` is dropped.
+    pub fn new_in(value: T, pool: &BytePoolHandle, wait: bool) -> Result<Self, TxError> {
+        let ptr = if core::mem::size_of::<T>() == 0 {
+            NonNull::dangling()
+        } else {
+            pool.allocate_layout(Layout::new::<T>(), wait)?.cast::<T>()
+        };
+        unsafe { ptr.as_ptr().write(value) };
+        Ok(PoolBox { ptr, _marker: PhantomData })
+    }
+
+    /// Move the value out of the pool and release the memory.
+    pub fn into_inner(this: Self) -> T {
+        let this = ManuallyDrop::new(this);
+        let value = unsafe { this.ptr.as_ptr().read() };
+        release(this.ptr.cast(), Layout::new::<T>());
+        value
+    }
+}
+
+impl<T> Deref for PoolBox<T> {
+    type Target = T;
+
+    fn deref(&self) -> &T {
+        unsafe { self.ptr.as_ref() }
+    }
+}
+
+impl<T> DerefMut for PoolBox<T> {
+    fn deref_mut(&mut self) -> &mut T {
+        unsafe { self.ptr.as_mut() }
+    }
+}
+
+impl<T> Drop for PoolBox<T> {
+    fn drop(&mut self) {
+        unsafe { core::ptr::drop_in_place(self.ptr.as_ptr()) };
+        release(self.ptr.cast(), Layout::new::<T>());
+    }
+}
+
+impl<T: fmt::Debug> fmt::Debug for PoolBox<T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        fmt::Debug::fmt(&**self, f)
+    }
+}
+
+/// A growable array allocated from a byte pool.
+///
+/// When the vector grows, a new block is allocated from the same pool, the elements
+/// are moved and the old block is released. Growing suspends the calling thread if
+/// the vector was created with `wait` set to true.
+pub struct PoolVec<T> {
+    ptr: NonNull<T>,
+    len: usize,
+    capacity: usize,
+    pool: *mut TX_BYTE_POOL,
+    wait: bool,
+    _marker: PhantomData<T>,
+}
+
+unsafe impl<T: Send> Send for PoolVec<T> {}
+unsafe impl<T: Sync> Sync for PoolVec<T> {}
+
+impl<T> PoolVec<T> {
+    /// Create an empty vector. No memory is allocated until the first element is pushed.
+    pub fn new_in(pool: &BytePoolHandle, wait: bool) -> Self {
+        PoolVec {
+            ptr: NonNull::dangling(),
+            len: 0,
+            capacity: if core::mem::size_of::<T>() == 0 { usize::MAX } else { 0 },
+            pool: pool.inner(),
+            wait,
+            _marker: PhantomData,
+        }
+    }
+
+    /// Create an empty vector with room for at least `capacity` elements.
+    pub fn with_capacity_in(capacity: usize, pool: &BytePoolHandle, wait: bool) -> Result<Self, TxError> {
+        let mut v = Self::new_in(pool, wait);
+        v.reserve_exact(capacity)?;
+        Ok(v)
+    }
+
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    pub fn capacity(&self) -> usize {
+        self.capacity
+    }
+
+    /// Make sure that at least `additional` more elements fit without reallocating.
+    pub fn reserve(&mut self, additional: usize) -> Result<(), TxError> {
+        let required = self.len.checked_add(additional).ok_or(ErrorKind::SizeError)?;
+        if required <= self.capacity {
+            return Ok(());
+        }
+        self.grow_to(required.max(self.capacity * 2).max(4))
+    }
+
+    /// Like `reserve` but does not over-allocate.
+    pub fn reserve_exact(&mut self, additional: usize) -> Result<(), TxError> {
+        let required = self.len.checked_add(additional).ok_or(ErrorKind::SizeError)?;
+        if required <= self.capacity {
+            return Ok(());
+        }
+        self.grow_to(required)
+    }
+
+    fn grow_to(&mut self, capacity: usize) -> Result<(), TxError> {
+        let layout = Layout::array::<T>(capacity).map_err(|_| ErrorKind::SizeError)?;
+        // the pool pointer was taken from a valid handle in `new_in`
+        let pool = BytePoolHandle::new(self.pool);
+        let ptr = pool.allocate_layout(layout, self.wait)?.cast::<T>();
+        unsafe { core::ptr::copy_nonoverlapping(self.ptr.as_ptr(), ptr.as_ptr(), self.len) };
+        self.release();
+        self.ptr = ptr;
+        self.capacity = capacity;
+        Ok(())
+    }
+
+    /// Append an element, growing the vector if needed. On failure `value` is dropped.
+    pub fn push(&mut self, value: T) -> Result<(), TxError> {
+        self.reserve(1)?;
+        unsafe { self.ptr.as_ptr().add(self.len).write(value) };
+        self.len += 1;
+        Ok(())
+    }
+
+    pub fn pop(&mut self) -> Option<T> {
+        if self.len == 0 {
+            return None;
+        }
+        self.len -= 1;
+        Some(unsafe { self.ptr.as_ptr().add(self.len).read() })
+    }
+
+    fn release(&mut self) {
+        if self.capacity > 0 && core::mem::size_of::<T>() > 0 {
+            // the layout was valid when the memory was allocated
+            let layout = unsafe { Layout::array::<T>(self.capacity).unwrap_unchecked() };
+            release(self.ptr.cast(), layout);
+        }
+    }
+
+    /// Drop all elements but keep the allocated memory.
+    pub fn clear(&mut self) {
+        let elems: *mut [T] = core::ptr::slice_from_raw_parts_mut(self.ptr.as_ptr(), self.len);
+        self.len = 0;
+        unsafe { core::ptr::drop_in_place(elems) };
+    }
+}
+
+impl<T> Deref for PoolVec<T> {
+    type Target = [T];
+
+    fn deref(&self) -> &[T] {
+        unsafe { core::slice::from_raw_parts(self.ptr.as_ptr(), self.len) }
+    }
+}
+
+impl<T> DerefMut for PoolVec<T> {
+    fn deref_mut(&mut self) -> &mut [T] {
+        unsafe { core::slice::from_raw_parts_mut(self.ptr.as_ptr(), self.len) }
+    }
+}
+
+impl<T> Drop for PoolVec<T> {
+    fn drop(&mut self) {
+        self.clear();
+        self.release();
+    }
+}
+
+impl<T: fmt::Debug> fmt::Debug for PoolVec<T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        fmt::Debug::fmt(&**self, f)
+    }
+}
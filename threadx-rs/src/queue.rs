@@ -17,74 +17,274 @@ UINT        _tx_queue_front_send(TX_QUEUE *queue_ptr, VOID *source_ptr, ULONG wa
 
 */
 
+use core::fmt;
 use core::mem::size_of;
 use core::{mem::MaybeUninit, ffi::CStr, marker::PhantomData};
-use threadx_sys::{TX_QUEUE, _tx_queue_create, ULONG, _tx_queue_send, _tx_queue_receive};
+use threadx_sys::{TX_QUEUE, _tx_queue_create, UINT, ULONG, _tx_queue_send, _tx_queue_receive, _tx_queue_performance_info_get};
+use crate::error::{wait_error, ErrorKind};
 use crate::pool::MemoryBlock;
 use crate::tx_checked_call;
 use super::{error::TxError, WaitOption};
-use defmt::debug;
-use defmt::error;
-use num_traits::FromPrimitive;
 
-pub struct Queue<T>(MaybeUninit<TX_QUEUE>,core::marker::PhantomData<T>);
+pub struct Queue<T> {
+    queue: MaybeUninit<TX_QUEUE>,
+    initialized: bool,
+    _marker: PhantomData<T>,
+}
+
+// SAFETY: the queue buffer holds `T` messages that are received by whichever thread
+// asks for them, so the queue may move between threads when `T` may.
+unsafe impl<T: Send> Send for Queue<T> {}
 
 impl <T>Queue<T> {
     // according to the threadx docs, the supported messages sizes are 1 to 16 32 bit words
     const SIZE_OK: () = assert!(size_of::<T>() >= size_of::<u32>() && size_of::<T>() <= (size_of::<u32>()*16));
 
+    /// ThreadX message size in words. Messages are copied in whole words, so `T` is
+    /// sent through a word buffer if its size is not a multiple of the word size.
+    pub(crate) const MESSAGE_WORDS: usize = size_of::<T>().div_ceil(size_of::<ULONG>());
+
     pub const fn new() -> Self {
         let _ = Self::SIZE_OK;
-        Queue(core::mem::MaybeUninit::uninit(),core::marker::PhantomData)
+        Queue { queue: MaybeUninit::uninit(), initialized: false, _marker: PhantomData }
     }
 
     pub fn initialize(
         &'static mut self,
-        name: &CStr,
+        name: &'static CStr,
         queue_memory: MemoryBlock,
     ) -> Result<(QueueSender<T>,QueueReceiver<T>), TxError> {       
-        let queue_ptr = self.0.as_mut_ptr();
-        let queue_memory = queue_memory.consume();
-        if queue_ptr.is_null() {
-            panic!("Queue ptr is null");
+        if self.initialized {
+            panic!("Queue is already initialized");
         }
-        unsafe {
-            if !(*queue_ptr).tx_queue_start.is_null() {
-                panic!("Queue is already initialized");
-            }
-        }
-        tx_checked_call!(_tx_queue_create(
+        let queue_ptr = self.queue.as_mut_ptr();
+        let queue_memory = queue_memory.consume();
+        tx_checked_call!(name => _tx_queue_create(
             queue_ptr,
             name.as_ptr() as *mut i8,
-            size_of::<T>() as ULONG,
+            Self::MESSAGE_WORDS as UINT,
             queue_memory.as_mut_ptr() as *mut core::ffi::c_void,
             queue_memory.len() as ULONG
         ))
-        .map(|_| (QueueSender(queue_ptr,core::marker::PhantomData),QueueReceiver(queue_ptr,core::marker::PhantomData)))
+        .map(|_| {
+            self.initialized = true;
+            (QueueSender(queue_ptr,core::marker::PhantomData),QueueReceiver(queue_ptr,core::marker::PhantomData))
+        })
     }
 }
 
 pub struct QueueSender<T>(*mut TX_QUEUE,core::marker::PhantomData<T>);
 pub struct QueueReceiver<T>(*mut TX_QUEUE,core::marker::PhantomData<T>);
 
+// Messages are copied into and out of the queue by the kernel, so both ends can be
+// shared between threads as long as the message can be sent to another thread
+unsafe impl<T: Send> Send for QueueSender<T> {}
+unsafe impl<T: Send> Sync for QueueSender<T> {}
+unsafe impl<T: Send> Send for QueueReceiver<T> {}
+unsafe impl<T: Send> Sync for QueueReceiver<T> {}
+
+impl<T> Clone for QueueSender<T> {
+    fn clone(&self) -> Self {
+        QueueSender(self.0, PhantomData)
+    }
+}
+
+impl<T> Clone for QueueReceiver<T> {
+    fn clone(&self) -> Self {
+        QueueReceiver(self.0, PhantomData)
+    }
+}
+
+/// Performance counters of a queue
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct QueuePerformanceInfo {
+    pub messages_sent: ULONG,
+    pub messages_received: ULONG,
+    pub empty_suspensions: ULONG,
+    pub full_suspensions: ULONG,
+    pub full_errors: ULONG,
+    pub timeouts: ULONG,
+}
+
+/// The counters are only kept if the kernel is built with queue performance info
+/// (the `performance-info` feature), otherwise this fails with `FeatureNotEnabled`
+/// without calling into the kernel.
+fn performance_info(queue: *mut TX_QUEUE) -> Result<QueuePerformanceInfo, TxError> {
+    if !threadx_sys::config::QUEUE_PERFORMANCE_INFO {
+        return Err(ErrorKind::FeatureNotEnabled.into());
+    }
+    let mut info = QueuePerformanceInfo::default();
+    tx_checked_call!(queue => _tx_queue_performance_info_get(
+        queue,
+        &mut info.messages_sent,
+        &mut info.messages_received,
+        &mut info.empty_suspensions,
+        &mut info.full_suspensions,
+        &mut info.full_errors,
+        &mut info.timeouts
+    ))?;
+    Ok(info)
+}
+
 impl <T>QueueSender<T> {
-    pub fn send(&self, message: T, wait: WaitOption) -> Result<(), TxError> {
-        
-        tx_checked_call!(_tx_queue_send(
+    pub fn performance_info(&self) -> Result<QueuePerformanceInfo, TxError> {
+        performance_info(self.0)
+    }
+
+    /// Send `message`. If it is not sent the error gives it back.
+    pub fn send(&self, message: T, wait: WaitOption) -> Result<(), QueueSendError<T>> {
+        let mut words = into_words(message);
+        tx_checked_call!(self.0 => _tx_queue_send(
             self.0,
-            &message as *const T as *mut core::ffi::c_void,
+            words.as_mut_ptr() as *mut core::ffi::c_void,
             wait as ULONG
         ))
+        .map_err(|error| {
+            // The kernel did not take the message, so it is still owned by the buffer
+            let message = unsafe { from_words(&words) };
+            QueueSendError::new(message, error)
+        })
     }
 }
 
 impl <T> QueueReceiver<T> {
-    pub fn receive(&self, wait: WaitOption) -> Result<T, TxError> {
-        let mut message = core::mem::MaybeUninit::uninit();
-        tx_checked_call!(_tx_queue_receive(
+    pub fn performance_info(&self) -> Result<QueuePerformanceInfo, TxError> {
+        performance_info(self.0)
+    }
+
+    pub fn receive(&self, wait: WaitOption) -> Result<T, QueueReceiveError> {
+        let mut words = [0 as ULONG; 16];
+        tx_checked_call!(self.0 => _tx_queue_receive(
             self.0,
-            message.as_mut_ptr() as *mut core::ffi::c_void,
+            words.as_mut_ptr() as *mut core::ffi::c_void,
             wait as ULONG
-        )).map(|_| unsafe{message.assume_init()})
+        ))
+        .map(|_| unsafe{from_words(&words)})
+        .map_err(QueueReceiveError::from)
     }
-}
\ No newline at end of file
+}
+
+/// Move `message` into a buffer of the largest ThreadX message, 16 words
+fn into_words<T>(message: T) -> [ULONG; 16] {
+    let mut words = [0 as ULONG; 16];
+    unsafe { (words.as_mut_ptr() as *mut T).write_unaligned(message) };
+    words
+}
+
+/// Move the message out of `words`. Safety: `words` holds a `T` that is not moved out
+/// twice.
+unsafe fn from_words<T>(words: &[ULONG; 16]) -> T {
+    (words.as_ptr() as *const T).read_unaligned()
+}
+
+/// Error of `QueueSender::send`, which holds the message that was not sent
+pub enum QueueSendError<T> {
+    /// The queue is full and the sender did not wait
+    Full(T),
+    /// The queue was deleted while the sender waited
+    Deleted(T),
+    /// The wait was aborted by `tx_thread_wait_abort`
+    WaitAborted(T),
+    Kernel(T, TxError),
+}
+
+impl<T> QueueSendError<T> {
+    fn new(message: T, error: TxError) -> Self {
+        match error.kind() {
+            ErrorKind::QueueFull => QueueSendError::Full(message),
+            ErrorKind::Deleted => QueueSendError::Deleted(message),
+            ErrorKind::WaitAborted => QueueSendError::WaitAborted(message),
+            _ => QueueSendError::Kernel(message, error),
+        }
+    }
+
+    /// The message that was not sent, e.g. to send it again
+    pub fn into_message(self) -> T {
+        match self {
+            QueueSendError::Full(message)
+            | QueueSendError::Deleted(message)
+            | QueueSendError::WaitAborted(message)
+            | QueueSendError::Kernel(message, _) => message,
+        }
+    }
+}
+
+// Not derived, so the message does not need to be Debug
+impl<T> fmt::Debug for QueueSendError<T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            QueueSendError::Full(_) => f.write_str("Full(..)"),
+            QueueSendError::Deleted(_) => f.write_str("Deleted(..)"),
+            QueueSendError::WaitAborted(_) => f.write_str("WaitAborted(..)"),
+            QueueSendError::Kernel(_, error) => f.debug_tuple("Kernel").field(&format_args!("..")).field(error).finish(),
+        }
+    }
+}
+
+impl<T> fmt::Display for QueueSendError<T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            QueueSendError::Full(_) => f.write_str("queue full"),
+            QueueSendError::Deleted(_) => f.write_str("deleted while waiting"),
+            QueueSendError::WaitAborted(_) => f.write_str("wait aborted"),
+            QueueSendError::Kernel(_, error) => fmt::Display::fmt(error, f),
+        }
+    }
+}
+
+#[cfg(feature = "defmt")]
+impl<T> defmt::Format for QueueSendError<T> {
+    fn format(&self, f: defmt::Formatter) {
+        match self {
+            QueueSendError::Full(_) => defmt::write!(f, "Full(..)"),
+            QueueSendError::Deleted(_) => defmt::write!(f, "Deleted(..)"),
+            QueueSendError::WaitAborted(_) => defmt::write!(f, "WaitAborted(..)"),
+            QueueSendError::Kernel(_, error) => defmt::write!(f, "Kernel(.., {})", error),
+        }
+    }
+}
+
+#[cfg(feature = "core-error")]
+impl<T> core::error::Error for QueueSendError<T> {}
+
+wait_error! {
+    /// Error of `QueueReceiver::receive`
+    pub enum QueueReceiveError {
+        /// The queue is empty and the receiver did not wait
+        Empty = QueueEmpty, "queue empty"
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use core::cell::Cell;
+
+    use super::*;
+
+    #[test]
+    fn message_size_is_rounded_up_to_words() {
+        // A word is a ULONG, 4 bytes on the targets and 8 on 64 bit hosts
+        let word = size_of::<ULONG>();
+        assert_eq!(Queue::<u32>::MESSAGE_WORDS, 1);
+        assert_eq!(Queue::<[u8; 6]>::MESSAGE_WORDS, 6_usize.div_ceil(word));
+        assert_eq!(Queue::<[u32; 16]>::MESSAGE_WORDS, 64 / word);
+    }
+
+    #[test]
+    fn messages_move_through_the_word_buffer_once() {
+        struct Counted<'a>(&'a Cell<u32>, [u8; 6]);
+        impl Drop for Counted<'_> {
+            fn drop(&mut self) {
+                self.0.set(self.0.get() + 1);
+            }
+        }
+        let drops = Cell::new(0);
+        let words = into_words(Counted(&drops, *b"abcdef"));
+        assert_eq!(drops.get(), 0);
+        let message = unsafe { from_words::<Counted>(&words) };
+        assert_eq!(&message.1, b"abcdef");
+        drop(message);
+        assert_eq!(drops.get(), 1);
+    }
+}
@@ -1,12 +1,10 @@
 
 use core::mem::size_of;
 use core::{mem::MaybeUninit, ffi::CStr, marker::PhantomData};
+use crate::error::wait_error;
 use crate::tx_checked_call;
 use super::{error::TxError, WaitOption};
-use defmt::debug;
-use defmt::error;
-use num_traits::FromPrimitive;
-use threadx_sys::{TX_SEMAPHORE, _tx_semaphore_create, _tx_semaphore_delete, _tx_semaphore_get, _tx_semaphore_put, _tx_semaphore_prioritize, _tx_semaphore_put_notify};
+use threadx_sys::{ULONG, TX_SEMAPHORE, _tx_semaphore_create, _tx_semaphore_delete, _tx_semaphore_get, _tx_semaphore_put, _tx_semaphore_prioritize, _tx_semaphore_put_notify};
 
 /*
 #define tx_semaphore_ceiling_put                    _tx_semaphore_ceiling_put
@@ -21,33 +19,38 @@ use threadx_sys::{TX_SEMAPHORE, _tx_semaphore_create, _tx_semaphore_delete, _tx_
 #define tx_semaphore_put_notify                     _tx_semaphore_put_notify
 */
 
-pub struct Semaphore(MaybeUninit<TX_SEMAPHORE>);
+pub struct Semaphore {
+    semaphore: MaybeUninit<TX_SEMAPHORE>,
+    initialized: bool,
+}
+
+// SAFETY: a semaphore is a count and the kernel's list of suspended threads. Any
+// thread may create it, the count belongs to no thread in particular.
+unsafe impl Send for Semaphore {}
 
 impl Semaphore {
     pub const fn new() -> Self {
-        Semaphore(MaybeUninit::<TX_SEMAPHORE>::uninit())
+        Semaphore { semaphore: MaybeUninit::<TX_SEMAPHORE>::uninit(), initialized: false }
     }
 
     pub fn initialize(
         &'static mut self,
-        name: &CStr,
+        name: &'static CStr,
         initial_count: u32,
     ) -> Result<SemaphoreOwnerHandle, TxError> {
-        let sem_ptr = self.0.as_mut_ptr();
-        if sem_ptr.is_null() {
-            panic!("Semaphore ptr is null");
-        }
-        unsafe {
-            if !(*sem_ptr).tx_semaphore_name.is_null() {
-                panic!("Semaphore is already initialized");
-            }
+        if self.initialized {
+            panic!("Semaphore is already initialized");
         }
-        tx_checked_call!(_tx_semaphore_create(
+        let sem_ptr = self.semaphore.as_mut_ptr();
+        tx_checked_call!(name => _tx_semaphore_create(
             sem_ptr,
             name.as_ptr() as *mut i8,
-            initial_count as u32
+            initial_count as ULONG
         ))
-        .map(|_| SemaphoreOwnerHandle::new(sem_ptr))
+        .map(|_| {
+            self.initialized = true;
+            SemaphoreOwnerHandle::new(sem_ptr)
+        })
     }
 }
 
@@ -55,13 +58,27 @@ impl Semaphore {
 pub struct SemaphoreOwnerHandle(*mut TX_SEMAPHORE);
 pub struct SemaphoreUserHandle(*mut TX_SEMAPHORE);
 
+// The handles only call into the kernel, which serializes access to the semaphore
+unsafe impl Send for SemaphoreOwnerHandle {}
+unsafe impl Sync for SemaphoreOwnerHandle {}
+unsafe impl Send for SemaphoreUserHandle {}
+unsafe impl Sync for SemaphoreUserHandle {}
+
+wait_error! {
+    /// Error of `SemaphoreUser::get`
+    pub enum SemaphoreGetError {
+        /// The count is 0 and the caller did not wait
+        NoInstance = NoInstance, "no instance"
+    }
+}
+
 pub trait SemaphoreOwner {
     fn delete(self) -> Result<(),TxError> ;
     fn get_semaphore_user(&self) -> SemaphoreUserHandle;
 }
 
 pub trait SemaphoreUser {
-    fn get(&self, wait: WaitOption) -> Result<(), TxError>;
+    fn get(&self, wait: WaitOption) -> Result<(), SemaphoreGetError>;
     fn put(&self) -> Result<(), TxError>;
     fn prioritize(&self) -> Result<(), TxError>;
     fn semaphore_put_notify(&self, notify: fn(SemaphoreUserHandle)) -> Result<(), TxError>;
@@ -76,7 +93,7 @@ impl SemaphoreOwnerHandle {
 
 impl SemaphoreOwner for SemaphoreOwnerHandle {
     fn delete(self) -> Result<(),TxError> {
-        tx_checked_call!(_tx_semaphore_delete(self.0))
+        tx_checked_call!(self.0 => _tx_semaphore_delete(self.0))
     }
     fn get_semaphore_user(&self) -> SemaphoreUserHandle {
         SemaphoreUserHandle(self.0.clone())
@@ -84,27 +101,28 @@ impl SemaphoreOwner for SemaphoreOwnerHandle {
 }
 
 impl SemaphoreUser for SemaphoreUserHandle {
-    fn get(&self, wait: WaitOption) -> Result<(), TxError> {
-        tx_checked_call!(_tx_semaphore_get(
+    fn get(&self, wait: WaitOption) -> Result<(), SemaphoreGetError> {
+        tx_checked_call!(self.0 => _tx_semaphore_get(
             self.0,
-            wait as u32
+            wait as ULONG
         ))
+        .map_err(SemaphoreGetError::from)
     }
     fn put(&self) -> Result<(), TxError> {
-        tx_checked_call!(_tx_semaphore_put(
+        tx_checked_call!(self.0 => _tx_semaphore_put(
             self.0
         ))
     }
 
     fn prioritize(&self) -> Result<(), TxError> {
-        tx_checked_call!(_tx_semaphore_prioritize(
+        tx_checked_call!(self.0 => _tx_semaphore_prioritize(
             self.0
         ))
     }
 
     fn semaphore_put_notify(&self, notify: fn(SemaphoreUserHandle)) -> Result<(), TxError> {
         let trampoline = get_notify_trampoline(&notify);
-        tx_checked_call!(_tx_semaphore_put_notify(
+        tx_checked_call!(self.0 => _tx_semaphore_put_notify(
             self.0,
             Some(trampoline)
         ))
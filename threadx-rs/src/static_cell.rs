@@ -0,0 +1,166 @@
+//! Kernel objects in plain `static`s.
+//!
+//! ThreadX objects must live at a fixed address for as long as the kernel uses them, so
+//! they are initialized through `&'static mut self`. Getting that reference from a
+//! `static mut` needs `unsafe` and nothing stops two call sites from taking it twice.
+//! [`TxStatic`] holds the object in a regular `static` and hands out the mutable
+//! reference exactly once.
+//!
+//! The handles returned by `initialize` are created in the `AppDefineCb` but are usually
+//! needed by threads. [`TxOnceCell`] is a `static` that is set once and then read from
+//! any thread.
+//!
+//! ```ignore
+//! static QUEUE: TxStatic<Queue<u32>> = TxStatic::new(Queue::new());
+//! static MUTEX: TxStatic<Mutex<u32>> = TxStatic::new(Mutex::new(0));
+//!
+//! let (sender, receiver) = QUEUE.take().unwrap().initialize(tx_str!("queue"), mem)?;
+//! let mutex: &'static Mutex<u32> = MUTEX.take().unwrap().initialize(tx_str!("mutex"), true)?;
+//!
+//! static SENDER: TxOnceCell<QueueSender<u32>> = TxOnceCell::new();
+//! let _ = SENDER.set(sender);
+//! ```
+
+use core::cell::UnsafeCell;
+use core::mem::MaybeUninit;
+use core::sync::atomic::{AtomicBool, Ordering};
+
+/// A static kernel object that can be taken once
+pub struct TxStatic<T> {
+    taken: AtomicBool,
+    value: UnsafeCell<T>,
+}
+
+// The value is only reachable through the single reference returned by `take`
+unsafe impl<T: Send> Sync for TxStatic<T> {}
+
+impl<T> TxStatic<T> {
+    pub const fn new(value: T) -> Self {
+        TxStatic { taken: AtomicBool::new(false), value: UnsafeCell::new(value) }
+    }
+
+    /// Returns the object on the first call and `None` on every call after that.
+    // The flag guarantees that the mutable reference is only handed out once
+    #[allow(clippy::mut_from_ref)]
+    pub fn take(&'static self) -> Option<&'static mut T> {
+        if claim(&self.taken) {
+            Some(unsafe { &mut *self.value.get() })
+        } else {
+            None
+        }
+    }
+
+    /// True once the object was handed out by `take`
+    pub fn is_taken(&self) -> bool {
+        self.taken.load(Ordering::Acquire)
+    }
+}
+
+/// A value that is set once, typically a handle created in the `AppDefineCb`, and
+/// read from any thread afterwards
+pub struct TxOnceCell<T> {
+    claimed: AtomicBool,
+    ready: AtomicBool,
+    value: UnsafeCell<MaybeUninit<T>>,
+}
+
+// The value is written once before `ready` is set and only read after that
+unsafe impl<T: Send + Sync> Sync for TxOnceCell<T> {}
+
+impl<T> TxOnceCell<T> {
+    pub const fn new() -> Self {
+        TxOnceCell {
+            claimed: AtomicBool::new(false),
+            ready: AtomicBool::new(false),
+            value: UnsafeCell::new(MaybeUninit::uninit()),
+        }
+    }
+
+    /// Store `value`. Returns it back if the cell was set before.
+    pub fn set(&self, value: T) -> Result<(), T> {
+        if !claim(&self.claimed) {
+            return Err(value);
+        }
+        unsafe { (*self.value.get()).write(value) };
+        self.ready.store(true, Ordering::Release);
+        Ok(())
+    }
+
+    /// The value, or `None` if the cell was not set yet
+    pub fn get(&self) -> Option<&T> {
+        if self.ready.load(Ordering::Acquire) {
+            Some(unsafe { (*self.value.get()).assume_init_ref() })
+        } else {
+            None
+        }
+    }
+}
+
+impl<T> Default for TxOnceCell<T> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<T> Drop for TxOnceCell<T> {
+    fn drop(&mut self) {
+        if *self.ready.get_mut() {
+            unsafe { self.value.get_mut().assume_init_drop() };
+        }
+    }
+}
+
+/// Set `flag` and return true if it was not set before
+#[cfg(target_has_atomic = "8")]
+fn claim(flag: &AtomicBool) -> bool {
+    flag.compare_exchange(false, true, Ordering::AcqRel, Ordering::Acquire).is_ok()
+}
+
+/// Cores without compare and swap, such as Cortex-M0, do the test and set with
+/// interrupts disabled.
+#[cfg(not(target_has_atomic = "8"))]
+fn claim(flag: &AtomicBool) -> bool {
+    use threadx_sys::{TX_INT_DISABLE, _tx_thread_interrupt_control};
+    let posture = unsafe { _tx_thread_interrupt_control(TX_INT_DISABLE) };
+    let claimed = !flag.load(Ordering::Acquire);
+    flag.store(true, Ordering::Release);
+    unsafe { _tx_thread_interrupt_control(posture) };
+    claimed
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn take_succeeds_once() {
+        static CELL: TxStatic<u32> = TxStatic::new(5);
+        assert!(!CELL.is_taken());
+        let value = CELL.take().unwrap();
+        *value += 1;
+        assert_eq!(*value, 6);
+        assert!(CELL.is_taken());
+        assert!(CELL.take().is_none());
+    }
+
+    #[test]
+    fn once_cell_is_set_once() {
+        static CELL: TxOnceCell<u32> = TxOnceCell::new();
+        assert!(CELL.get().is_none());
+        assert_eq!(CELL.set(1), Ok(()));
+        assert_eq!(CELL.set(2), Err(2));
+        assert_eq!(CELL.get(), Some(&1));
+    }
+
+    #[test]
+    fn kernel_objects_can_be_static() {
+        fn is_sync<T: Sync>() {}
+        is_sync::<TxStatic<crate::queue::Queue<u32>>>();
+        is_sync::<TxStatic<crate::mutex::Mutex<u32>>>();
+        is_sync::<TxStatic<crate::pool::BytePool>>();
+        is_sync::<TxStatic<crate::thread::Thread>>();
+        is_sync::<TxOnceCell<crate::queue::QueueSender<u32>>>();
+        is_sync::<TxOnceCell<&'static crate::event_flags::EventFlagsGroup>>();
+        is_sync::<TxOnceCell<crate::semaphore::SemaphoreOwnerHandle>>();
+    }
+}
@@ -1,49 +1,70 @@
-use core::ffi::{CStr, c_void};
+use core::ffi::CStr;
 use core::mem::MaybeUninit;
 use core::time::Duration;
 
-use threadx_sys::{_tx_thread_suspend, _tx_thread_delete, _tx_thread_sleep};
-use threadx_sys::{TX_THREAD, ULONG, _tx_thread_create, _tx_thread_resume};
+use threadx_sys::{_tx_thread_suspend, _tx_thread_delete, _tx_thread_sleep, _tx_thread_identify};
+use threadx_sys::{TX_THREAD, UINT, ULONG, _tx_thread_create, _tx_thread_resume};
 
 use crate::pool::MemoryBlock;
-use crate::time::TxTicks;
+use crate::time::{Instant, TxTicks};
 use crate::tx_checked_call;
 
-use super::error::TxError;
-use defmt::debug;
-use defmt::error;
-use num_traits::FromPrimitive;
+use super::error::{ErrorKind, TxError};
 
-pub struct Thread(MaybeUninit<TX_THREAD>);
+pub struct Thread {
+    thread: MaybeUninit<TX_THREAD>,
+    initialized: bool,
+}
+
+// SAFETY: until `initialize` the control block is uninitialized memory. After that it
+// points at the `'static` stack and is only used by the scheduler, which runs the new
+// thread regardless of which thread created it.
+unsafe impl Send for Thread {}
 
 type TxThreadEntry = unsafe extern "C" fn(ULONG);
 
 impl Thread {
     pub const fn new() -> Self {
-        Thread(core::mem::MaybeUninit::uninit())
+        Thread { thread: core::mem::MaybeUninit::uninit(), initialized: false }
     }
 }
 
+/// Called by the kernel with the address of the entry closure as the input
 unsafe extern "C" fn thread_trampoline<F>(arg: ULONG)
 where F: Fn()
 {
-    let closure = &mut *(arg as *mut F);
+    let closure = &*(arg as usize as *const F);
     closure();
 }
 
-fn get_trampoline<F>(closure: &F) -> TxThreadEntry
-where
-    F: Fn(),
-{
-    thread_trampoline::<F>
+/// Stacks handed to the kernel start on this alignment
+const STACK_ALIGN: usize = 8;
+
+/// Move `entry_function` to the low end of `stack` and return the memory after it,
+/// rounded to `STACK_ALIGN`, as the stack. The stack grows down towards the closure,
+/// so it stays in place for as long as the thread can run.
+fn store_entry<F>(stack: &'static mut [u8], entry_function: F) -> Result<(*mut F, &'static mut [u8]), TxError> {
+    let offset = stack.as_ptr().align_offset(core::mem::align_of::<F>());
+    let end = offset.checked_add(core::mem::size_of::<F>()).ok_or(ErrorKind::SizeError)?;
+    let stack_start = (stack.as_ptr() as usize + end).next_multiple_of(STACK_ALIGN) - stack.as_ptr() as usize;
+    if stack_start > stack.len() {
+        return Err(ErrorKind::SizeError.into());
+    }
+    let (closure, stack) = stack.split_at_mut(stack_start);
+    let closure = closure[offset..].as_mut_ptr() as *mut F;
+    unsafe { closure.write(entry_function) };
+    Ok((closure, stack))
 }
 
 impl Thread {
 
-    pub fn initialize<F: Fn()>(
+    /// Create the thread. `entry_function` is moved into the start of `stack`, where it
+    /// lives for as long as the thread, so it may capture state. The rest of `stack` is
+    /// the stack of the thread.
+    pub fn initialize<F: Fn() + Send + 'static>(
         &'static mut self,
-        name: &CStr,
-        mut entry_function: F,
+        name: &'static CStr,
+        entry_function: F,
         stack :MemoryBlock,
         priority: u32,
         preempt_threshold: u32,
@@ -51,37 +72,39 @@ impl Thread {
         auto_start: bool,
     ) -> Result<ThreadHandle,TxError> {
         
-        // check if already initialized.
-        let s = unsafe{&*self.0.as_ptr()};
-        if !s.tx_thread_name.is_null() {
+        if self.initialized {
             panic!("Thread must be initialized only once");
         }
 
-        let stack = stack.consume();
-        //convert entry function into a pointer
-        let entry_function_ptr = &mut entry_function as *mut _ as *mut c_void;
+        let (closure, stack) = store_entry(stack.consume(), entry_function)?;
         //convert to a ULONG
-        let entry_function_arg = entry_function_ptr as ULONG;
-        let trampoline = get_trampoline(&entry_function);
+        let entry_function_arg = closure as usize as ULONG;
+        let trampoline = thread_trampoline::<F> as TxThreadEntry;
 
-        tx_checked_call!(_tx_thread_create(
+        tx_checked_call!(name => _tx_thread_create(
             // TODO: Ensure that threadx api does not modify this
-            self.0.as_mut_ptr(),
+            self.thread.as_mut_ptr(),
             name.as_ptr() as *mut i8,
             Some(trampoline),
             entry_function_arg,
             stack.as_mut_ptr() as *mut core::ffi::c_void,
             stack.len() as ULONG,
-            priority as ULONG,
-            preempt_threshold as ULONG,
+            priority as UINT,
+            preempt_threshold as UINT,
             time_slice as ULONG,
             if auto_start { 1 } else { 0 }
-        )).map(|_| ThreadHandle::new(unsafe{&mut *self.0.as_mut_ptr()}))  
+        ))
+        // The thread never runs, so the closure is dropped here
+        .inspect_err(|_| unsafe { core::ptr::drop_in_place(closure) })
+        .map(|_| {
+            self.initialized = true;
+            ThreadHandle::new(self.thread.as_mut_ptr())
+        })
 
     }
     pub fn create_with_c_func(
         &mut self,
-        name: &CStr,
+        name: &'static CStr,
         entry_function: Option<unsafe extern "C" fn(ULONG)>,
         arg: ULONG,
         stack :&mut [u8],
@@ -90,24 +113,25 @@ impl Thread {
         time_slice: u32,
         auto_start: bool,
     ) -> Result<ThreadHandle, TxError> {
-            // check if already initialized.
-            let s = unsafe{&*self.0.as_ptr()};
-            if !s.tx_thread_name.is_null() {
+            if self.initialized {
                 panic!("Thread must be initialized only once");
             }
-            tx_checked_call!(_tx_thread_create(
+            tx_checked_call!(name => _tx_thread_create(
                 // TODO: Ensure that threadx api does not modify this
-                self.0.as_mut_ptr(),
+                self.thread.as_mut_ptr(),
                 name.as_ptr() as *mut i8,
                 entry_function,
                 arg,
                 stack.as_mut_ptr() as *mut core::ffi::c_void,
                 stack.len() as ULONG,
-                priority as ULONG,
-                preempt_threshold as ULONG,
+                priority as UINT,
+                preempt_threshold as UINT,
                 time_slice as ULONG,
                 if auto_start { 1 } else { 0 }
-            )).map(|_| ThreadHandle::new(unsafe{&mut *self.0.as_mut_ptr()}))            
+            )).map(|_| {
+                self.initialized = true;
+                ThreadHandle::new(self.thread.as_mut_ptr())
+            })
     }
 }
 
@@ -123,17 +147,17 @@ impl ThreadHandle {
     }
 
     pub fn start(&mut self) -> Result<(),TxError>{
-        tx_checked_call!(_tx_thread_resume(self.0))
+        tx_checked_call!(self.0 => _tx_thread_resume(self.0))
     }
 
     pub fn suspend(&mut self) -> Result<(),TxError>{
-        tx_checked_call!(_tx_thread_suspend(self.0))
+        tx_checked_call!(self.0 => _tx_thread_suspend(self.0))
     }
 
     /// Deletes the thread. You need to pass ownership
     /// of the thread handle to this function.
     pub fn delete(self) -> Result<(),TxError>{
-        tx_checked_call!(_tx_thread_delete(self.0))
+        tx_checked_call!(self.0 => _tx_thread_delete(self.0))
     }
 }
 
@@ -144,5 +168,71 @@ impl ThreadHandle {
 /// for 10ms. The number of ticks per second is a compile time constant
 /// available at `threadx-sys::TX_TICKS_PER_SECOND`
 pub fn sleep(d: Duration) -> Result<(),TxError> {
-    tx_checked_call!(_tx_thread_sleep(TxTicks::from(d).into()))
-}
\ No newline at end of file
+    tx_checked_call!(_tx_thread_sleep(TxTicks::from(d).ticks() as ULONG))
+}
+
+/// Put the current task to sleep until the system clock reaches `deadline`.
+/// Returns immediately if the deadline has already passed. Because the deadline
+/// is absolute, a periodic loop that advances it by a fixed period does not
+/// accumulate drift from the time spent running between sleeps.
+///
+/// ```ignore
+/// let mut next = Instant::now();
+/// loop {
+///     next += Duration::from_millis(10);
+///     sleep_until(next)?;
+///     // periodic work
+/// }
+/// ```
+pub fn sleep_until(deadline: Instant) -> Result<(),TxError> {
+    let now = Instant::now();
+    if deadline <= now {
+        return Ok(());
+    }
+    let ticks = deadline.ticks().wrapping_sub(now.ticks());
+    tx_checked_call!(_tx_thread_sleep(ticks as ULONG))
+}
+
+/// The context that the calling code is executing in. Many ThreadX services
+/// may only suspend when called from a thread, and some may not be called
+/// from interrupts at all.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum ExecutionContext {
+    /// Before the kernel is started or inside the application define callback
+    Initialization,
+    /// An application thread
+    Thread,
+    /// A timer expiration function running on the ThreadX timer thread
+    Timer,
+    /// An interrupt service routine
+    Isr,
+}
+
+/// Determine the context that the caller is executing in.
+pub fn execution_context() -> ExecutionContext {
+    #[cfg(target_arch = "arm")]
+    {
+        let ipsr: u32;
+        unsafe { core::arch::asm!("mrs {}, IPSR", out(reg) ipsr, options(nomem, nostack, preserves_flags)) };
+        if ipsr & 0x1FF != 0 {
+            return ExecutionContext::Isr;
+        }
+    }
+    let state = unsafe { core::ptr::read_volatile(core::ptr::addr_of!(threadx_sys::_tx_thread_system_state)) };
+    match state {
+        threadx_sys::TX_INITIALIZE_IN_PROGRESS | threadx_sys::TX_INITIALIZE_ALMOST_DONE => {
+            return ExecutionContext::Initialization
+        }
+        threadx_sys::TX_INITIALIZE_IS_FINISHED => {}
+        _ => return ExecutionContext::Isr,
+    }
+    let current = unsafe { _tx_thread_identify() };
+    if current.is_null() {
+        ExecutionContext::Initialization
+    } else if current == core::ptr::addr_of_mut!(threadx_sys::_tx_timer_thread) {
+        ExecutionContext::Timer
+    } else {
+        ExecutionContext::Thread
+    }
+}
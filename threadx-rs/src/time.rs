@@ -1,24 +1,62 @@
+use core::ops::{Add, AddAssign, Sub, SubAssign};
 use core::time::Duration;
 
-use threadx_sys::{TX_TIMER_TICKS_PER_SECOND, _tx_thread_sleep};
+use threadx_sys::{TX_TIMER_TICKS_PER_SECOND, _tx_time_get, _tx_time_set};
 
-use crate::tx_checked_call;
-
-use super::error::TxError;
-use defmt::debug;
-use defmt::error;
-use num_traits::FromPrimitive;
+const NANOS_PER_SEC: u128 = 1_000_000_000;
 
+/// A number of ThreadX timer ticks.
+///
+/// `threadx_sys::TX_TIMER_TICKS_PER_SECOND` is a constant that is set by the
+/// ThreadX build configuration. The default is 100. It is 1000 with the
+/// `tick-rate-1000` feature and any other rate can be set in a `TX_USER_FILE`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord, Hash)]
 pub struct TxTicks(u32);
 
-/// `threadx_sys::TX_TIMER_TICKS_PER_SECOND` is a constant that is set by the
-/// ThreadX build configuration. The default is 100 and it can be
-/// changed by providing a user defined `tx_user.h` file.
-const MILLIS_PER_TICK: u128 = 1000 / TX_TIMER_TICKS_PER_SECOND as u128;
+impl TxTicks {
+    pub const fn new(ticks: u32) -> Self {
+        TxTicks(ticks)
+    }
+
+    pub const fn ticks(&self) -> u32 {
+        self.0
+    }
+}
+
+/// Convert a duration into ticks at the given tick rate, rounding up to the
+/// next whole tick. A non-zero duration never becomes zero ticks and the result
+/// saturates at `u32::MAX`.
+pub(crate) const fn duration_to_ticks(d: Duration, ticks_per_second: u32) -> u32 {
+    let nanos = d.as_nanos() * ticks_per_second as u128;
+    let ticks = nanos.div_ceil(NANOS_PER_SEC);
+    if ticks > u32::MAX as u128 {
+        u32::MAX
+    } else {
+        ticks as u32
+    }
+}
+
+/// Convert a number of ticks at the given tick rate into a duration. This is
+/// exact down to the nanosecond, rounding down.
+pub(crate) const fn ticks_to_duration(ticks: u32, ticks_per_second: u32) -> Duration {
+    let nanos = ticks as u128 * NANOS_PER_SEC / ticks_per_second as u128;
+    Duration::new(
+        (nanos / NANOS_PER_SEC) as u64,
+        (nanos % NANOS_PER_SEC) as u32,
+    )
+}
 
+/// Converting a `Duration` rounds up to the nearest tick, so that waiting for
+/// the resulting number of ticks never takes less time than requested.
 impl From<Duration> for TxTicks {
     fn from(d: Duration) -> Self {
-        TxTicks((d.as_millis() / MILLIS_PER_TICK) as u32)
+        TxTicks(duration_to_ticks(d, TX_TIMER_TICKS_PER_SECOND))
+    }
+}
+
+impl From<TxTicks> for Duration {
+    fn from(t: TxTicks) -> Self {
+        ticks_to_duration(t.0, TX_TIMER_TICKS_PER_SECOND)
     }
 }
 
@@ -28,3 +66,285 @@ impl Into<u32> for TxTicks {
     }
 }
 
+/// A point in time as measured by the ThreadX system clock.
+///
+/// The system clock is a 32 bit tick counter that wraps around. All arithmetic
+/// on `Instant` is wraparound-safe as long as the instants being compared are
+/// less than half the counter range (2^31 ticks) apart. At the default 100 ticks
+/// per second that is a little under 250 days.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub struct Instant(u32);
+
+impl Instant {
+    /// Read the current value of the ThreadX system clock.
+    pub fn now() -> Self {
+        Instant(unsafe { _tx_time_get() } as u32)
+    }
+
+    pub const fn from_ticks(ticks: u32) -> Self {
+        Instant(ticks)
+    }
+
+    pub const fn ticks(&self) -> u32 {
+        self.0
+    }
+
+    /// The signed distance in ticks from `earlier` to `self`. Negative if
+    /// `earlier` is actually later than `self`.
+    pub const fn ticks_since(&self, earlier: Instant) -> i32 {
+        self.0.wrapping_sub(earlier.0) as i32
+    }
+
+    /// The time elapsed from `earlier` to `self`. Returns zero if `earlier`
+    /// is actually later than `self`.
+    pub fn duration_since(&self, earlier: Instant) -> Duration {
+        self.checked_duration_since(earlier).unwrap_or(Duration::ZERO)
+    }
+
+    /// The time elapsed from `earlier` to `self`, or `None` if `earlier`
+    /// is later than `self`.
+    pub fn checked_duration_since(&self, earlier: Instant) -> Option<Duration> {
+        let ticks = self.ticks_since(earlier);
+        if ticks < 0 {
+            None
+        } else {
+            Some(TxTicks(ticks as u32).into())
+        }
+    }
+
+    /// The time elapsed since this instant was taken.
+    pub fn elapsed(&self) -> Duration {
+        Instant::now().duration_since(*self)
+    }
+
+    pub fn checked_add(&self, d: Duration) -> Option<Instant> {
+        let ticks = duration_to_ticks(d, TX_TIMER_TICKS_PER_SECOND);
+        if ticks > i32::MAX as u32 {
+            None
+        } else {
+            Some(Instant(self.0.wrapping_add(ticks)))
+        }
+    }
+
+    pub fn checked_sub(&self, d: Duration) -> Option<Instant> {
+        let ticks = duration_to_ticks(d, TX_TIMER_TICKS_PER_SECOND);
+        if ticks > i32::MAX as u32 {
+            None
+        } else {
+            Some(Instant(self.0.wrapping_sub(ticks)))
+        }
+    }
+}
+
+/// Instants are ordered by their signed distance, so an instant taken just
+/// after the tick counter wrapped compares greater than one taken just before.
+/// That order is not transitive across the whole counter range, so `Instant`
+/// is deliberately not `Ord`; two instants exactly half the range apart are
+/// unordered.
+impl PartialOrd for Instant {
+    fn partial_cmp(&self, other: &Self) -> Option<core::cmp::Ordering> {
+        match self.ticks_since(*other) {
+            i32::MIN => None,
+            ticks => Some(ticks.cmp(&0)),
+        }
+    }
+}
+
+impl Add<Duration> for Instant {
+    type Output = Instant;
+
+    fn add(self, d: Duration) -> Instant {
+        self.checked_add(d).expect("overflow when adding duration to instant")
+    }
+}
+
+impl AddAssign<Duration> for Instant {
+    fn add_assign(&mut self, d: Duration) {
+        *self = *self + d;
+    }
+}
+
+impl Sub<Duration> for Instant {
+    type Output = Instant;
+
+    fn sub(self, d: Duration) -> Instant {
+        self.checked_sub(d).expect("overflow when subtracting duration from instant")
+    }
+}
+
+impl SubAssign<Duration> for Instant {
+    fn sub_assign(&mut self, d: Duration) {
+        *self = *self - d;
+    }
+}
+
+impl Sub<Instant> for Instant {
+    type Output = Duration;
+
+    fn sub(self, earlier: Instant) -> Duration {
+        self.duration_since(earlier)
+    }
+}
+
+/// Set the ThreadX system clock. Any `Instant` taken before this call is no
+/// longer comparable with instants taken after it.
+pub fn set_time(ticks: TxTicks) {
+    unsafe { _tx_time_set(ticks.0 as _) }
+}
+
+/// `fugit` duration with the ThreadX tick as its unit.
+#[cfg(feature = "fugit")]
+pub type TxDuration = fugit::TimerDurationU32<TX_TIMER_TICKS_PER_SECOND>;
+/// `fugit` instant with the ThreadX tick as its unit.
+#[cfg(feature = "fugit")]
+pub type TxInstant = fugit::TimerInstantU32<TX_TIMER_TICKS_PER_SECOND>;
+
+/// Converting from an arbitrary `fugit` duration rounds up to the nearest tick
+/// like the conversion from `core::time::Duration`.
+#[cfg(feature = "fugit")]
+impl<const NOM: u32, const DENOM: u32> From<fugit::Duration<u32, NOM, DENOM>> for TxTicks {
+    fn from(d: fugit::Duration<u32, NOM, DENOM>) -> Self {
+        let ticks = (d.ticks() as u64 * NOM as u64 * TX_TIMER_TICKS_PER_SECOND as u64)
+            .div_ceil(DENOM as u64);
+        TxTicks(ticks.min(u32::MAX as u64) as u32)
+    }
+}
+
+#[cfg(feature = "fugit")]
+impl<const NOM: u32, const DENOM: u32> From<fugit::Duration<u64, NOM, DENOM>> for TxTicks {
+    fn from(d: fugit::Duration<u64, NOM, DENOM>) -> Self {
+        let ticks = (d.ticks() as u128 * NOM as u128 * TX_TIMER_TICKS_PER_SECOND as u128)
+            .div_ceil(DENOM as u128);
+        TxTicks(ticks.min(u32::MAX as u128) as u32)
+    }
+}
+
+#[cfg(feature = "fugit")]
+impl From<TxTicks> for TxDuration {
+    fn from(t: TxTicks) -> Self {
+        TxDuration::from_ticks(t.0)
+    }
+}
+
+#[cfg(feature = "fugit")]
+impl From<Instant> for TxInstant {
+    fn from(i: Instant) -> Self {
+        TxInstant::from_ticks(i.0)
+    }
+}
+
+#[cfg(feature = "fugit")]
+impl From<TxInstant> for Instant {
+    fn from(i: TxInstant) -> Self {
+        Instant(i.ticks())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn duration_to_ticks_rounds_up() {
+        assert_eq!(duration_to_ticks(Duration::ZERO, 100), 0);
+        assert_eq!(duration_to_ticks(Duration::from_nanos(1), 100), 1);
+        assert_eq!(duration_to_ticks(Duration::from_millis(5), 100), 1);
+        assert_eq!(duration_to_ticks(Duration::from_millis(10), 100), 1);
+        assert_eq!(duration_to_ticks(Duration::from_millis(11), 100), 2);
+        assert_eq!(duration_to_ticks(Duration::from_secs(1), 100), 100);
+    }
+
+    #[test]
+    fn duration_to_ticks_non_divisor_rates() {
+        // 1024 Hz: one tick is 976.5625 us
+        assert_eq!(duration_to_ticks(Duration::from_millis(1), 1024), 2);
+        assert_eq!(duration_to_ticks(Duration::from_nanos(976_562), 1024), 1);
+        assert_eq!(duration_to_ticks(Duration::from_nanos(976_563), 1024), 2);
+        assert_eq!(duration_to_ticks(Duration::from_secs(1), 1024), 1024);
+        // 3 Hz
+        assert_eq!(duration_to_ticks(Duration::from_millis(333), 3), 1);
+        assert_eq!(duration_to_ticks(Duration::from_millis(334), 3), 2);
+    }
+
+    #[test]
+    fn duration_to_ticks_high_rates() {
+        assert_eq!(duration_to_ticks(Duration::from_micros(1), 10_000), 1);
+        assert_eq!(duration_to_ticks(Duration::from_micros(100), 10_000), 1);
+        assert_eq!(duration_to_ticks(Duration::from_micros(101), 10_000), 2);
+        assert_eq!(duration_to_ticks(Duration::from_secs(1), 32_768), 32_768);
+    }
+
+    #[test]
+    fn duration_to_ticks_saturates() {
+        assert_eq!(duration_to_ticks(Duration::MAX, 100), u32::MAX);
+        assert_eq!(duration_to_ticks(Duration::from_secs(u32::MAX as u64), 1000), u32::MAX);
+    }
+
+    #[test]
+    fn ticks_to_duration_exact() {
+        assert_eq!(ticks_to_duration(0, 100), Duration::ZERO);
+        assert_eq!(ticks_to_duration(1, 100), Duration::from_millis(10));
+        assert_eq!(ticks_to_duration(1, 1024), Duration::from_nanos(976_562));
+        assert_eq!(ticks_to_duration(1024, 1024), Duration::from_secs(1));
+        assert_eq!(ticks_to_duration(1, 3), Duration::from_nanos(333_333_333));
+        assert_eq!(
+            ticks_to_duration(u32::MAX, 1000),
+            Duration::from_millis(u32::MAX as u64)
+        );
+    }
+
+    #[test]
+    fn round_trip_never_shortens() {
+        for rate in [3, 100, 1000, 1024, 32_768] {
+            for ticks in [0, 1, 2, 7, 99, 100, 1023, 1_000_000] {
+                let d = ticks_to_duration(ticks, rate);
+                assert_eq!(duration_to_ticks(d, rate), ticks, "rate {rate} ticks {ticks}");
+            }
+        }
+    }
+
+    #[test]
+    fn instant_wraparound() {
+        let before = Instant::from_ticks(u32::MAX - 4);
+        let after = Instant::from_ticks(5);
+        assert!(after > before);
+        assert_eq!(after.ticks_since(before), 10);
+        assert_eq!(
+            after.checked_duration_since(before),
+            Some(ticks_to_duration(10, TX_TIMER_TICKS_PER_SECOND))
+        );
+        assert_eq!(before.checked_duration_since(after), None);
+        assert_eq!(before.duration_since(after), Duration::ZERO);
+    }
+
+    #[test]
+    fn instants_half_the_range_apart_are_unordered() {
+        let a = Instant::from_ticks(0);
+        let b = Instant::from_ticks(1 << 31);
+        assert_eq!(a.partial_cmp(&b), None);
+        assert_eq!(b.partial_cmp(&a), None);
+        assert!(Instant::from_ticks((1 << 31) - 1) > a);
+    }
+
+    #[test]
+    fn instant_add_wraps() {
+        let start = Instant::from_ticks(u32::MAX);
+        let later = start + ticks_to_duration(2, TX_TIMER_TICKS_PER_SECOND);
+        assert_eq!(later.ticks(), 1);
+        assert_eq!(later - start, ticks_to_duration(2, TX_TIMER_TICKS_PER_SECOND));
+        assert_eq!(start.checked_add(Duration::MAX), None);
+    }
+
+    #[cfg(feature = "fugit")]
+    #[test]
+    fn fugit_conversions() {
+        let t: TxTicks = fugit::MillisDurationU32::millis(1).into();
+        assert_eq!(t, TxTicks::from(Duration::from_millis(1)));
+        let t: TxTicks = fugit::SecsDurationU64::secs(2).into();
+        assert_eq!(t.ticks(), 2 * TX_TIMER_TICKS_PER_SECOND);
+        let d: TxDuration = TxTicks::new(7).into();
+        assert_eq!(TxTicks::from(d).ticks(), 7);
+        let i: TxInstant = Instant::from_ticks(42).into();
+        assert_eq!(Instant::from(i), Instant::from_ticks(42));
+    }
+}
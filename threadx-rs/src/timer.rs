@@ -17,73 +17,90 @@ ULONG       _tx_time_get(VOID);
 VOID        _tx_time_set(ULONG new_time);
 */
 
-use core::ffi::c_void;
 use core::ffi::CStr;
 use crate::time::TxTicks;
 use crate::tx_checked_call;
 
-use super::WaitOption;
 use super::error::TxError;
-use defmt::debug;
-use defmt::error;
-use num_traits::FromPrimitive;
-use thiserror_no_std::Error;
 use threadx_sys::_tx_timer_create;
 use threadx_sys::ULONG;
 
 use core::mem::MaybeUninit;
 use threadx_sys::TX_TIMER;
 
-type TimerCallbackType = unsafe extern "C" fn(ULONG);
+/// The expiration function and its input, kept in the `Timer` so that the kernel only
+/// needs the address of the timer
+#[derive(Clone, Copy)]
+struct Expiration {
+    function: *const (),
+    call: unsafe fn(*const (), ULONG),
+    input: ULONG,
+}
 
-unsafe extern "C" fn timer_callback_trampoline<F>(arg: ULONG)
-where F: Fn(ULONG)
-{
-    let closure = &mut *(arg as *mut F);
-    closure(arg);
+unsafe fn call<F: Fn(ULONG)>(function: *const (), input: ULONG) {
+    (*(function as *const F))(input)
 }
 
-fn get_trampoline<F>(closure_: &F) -> TimerCallbackType
-where
-    F: Fn(ULONG),
-{
-    timer_callback_trampoline::<F>
+/// Called by the kernel with the address of the `Timer` as the expiration input
+unsafe extern "C" fn timer_trampoline(timer: ULONG) {
+    let timer = timer as usize as *const Timer;
+    if let Some(Expiration { function, call, input }) = (*timer).expiration {
+        call(function, input);
+    }
 }
 
-pub struct Timer(MaybeUninit<TX_TIMER>);
+pub struct Timer {
+    timer: MaybeUninit<TX_TIMER>,
+    expiration: Option<Expiration>,
+    initialized: bool,
+}
+
+// SAFETY: the expiration function is a `'static` `Sync` closure that always runs on the
+// ThreadX timer thread, never on the thread that created the timer.
+unsafe impl Send for Timer {}
 
 impl Timer {
     pub const fn new() -> Self {
-        Timer(MaybeUninit::uninit())
+        Timer { timer: MaybeUninit::uninit(), expiration: None, initialized: false }
     }
 
-    pub fn initialize<F: Fn(ULONG)>(
+    /// Create the timer. `expiration_function` is called with `expiration_input` on the
+    /// ThreadX timer thread whenever the timer expires, so it must live forever, e.g. a
+    /// closure literal that captures nothing or a `static`.
+    pub fn initialize<F: Fn(ULONG) + Sync + 'static>(
         &'static mut self,
-        name: &CStr,
-        expiration_function: F,
+        name: &'static CStr,
+        expiration_function: &'static F,
         expiration_input: ULONG,
         initial_ticks: core::time::Duration,
         reschedule_ticks: core::time::Duration,
         auto_activate: bool,
     ) -> Result<(), TxError> {
-        let timer = unsafe { &mut *self.0.as_mut_ptr() };
-        
-        //convert to a ULONG
-        let trampoline = get_trampoline(&expiration_function);
+        if self.initialized {
+            panic!("Timer is already initialized");
+        }
+        self.expiration = Some(Expiration {
+            function: expiration_function as *const F as *const (),
+            call: call::<F>,
+            input: expiration_input,
+        });
+        let timer = self.timer.as_mut_ptr();
+        let this = self as *const Timer as usize as ULONG;
 
-        let initial_ticks = TxTicks::from(initial_ticks).into();
-        let reschedule_ticks = TxTicks::from(reschedule_ticks).into();
+        let initial_ticks = TxTicks::from(initial_ticks).ticks() as ULONG;
+        let reschedule_ticks = TxTicks::from(reschedule_ticks).ticks() as ULONG;
         let auto_activate = if auto_activate { 1 } else { 0 };
         
-        tx_checked_call!(_tx_timer_create(
+        tx_checked_call!(name => _tx_timer_create(
                 timer,
                 name.as_ptr() as *mut i8,
-                Some(trampoline),
-                expiration_input,
+                Some(timer_trampoline as unsafe extern "C" fn(ULONG)),
+                this,
                 initial_ticks,
                 reschedule_ticks,
                 auto_activate
-            )).map(|_| ())?;
+            ))?;
+        self.initialized = true;
         Ok(())
     }
 }
\ No newline at end of file
@@ -0,0 +1,320 @@
+//! Deterministic O(1) allocator based on the TLSF (two-level segregated fit) algorithm.
+//!
+//! `_tx_byte_allocate` searches a first-fit free list, so its run time grows with the
+//! number of fragments in the pool. [`TlsfPool`] finds a suitable free block in constant
+//! time regardless of fragmentation, which bounds the latency of allocations made from
+//! time critical threads.
+//!
+//! The pool is protected by a ThreadX mutex with priority inheritance. It can be used
+//! as the global allocator, through the same `allocate`/`release` API as
+//! `BytePoolHandle` and, with the `nightly` or `allocator-api2` features, as an
+//! `Allocator` for collections.
+//!
+//! ```ignore
+//! #[global_allocator]
+//! static mut GLOBAL: TlsfPool = TlsfPool::new();
+//! unsafe { GLOBAL.initialize(tx_str!("heap"), heap_mem).unwrap() };
+//! ```
+
+use core::alloc::{GlobalAlloc, Layout};
+use core::cell::UnsafeCell;
+use core::ffi::CStr;
+use core::mem::MaybeUninit;
+use core::ptr::NonNull;
+use core::sync::atomic::{AtomicBool, Ordering};
+
+use rlsf::Tlsf;
+use threadx_sys::{TX_INHERIT, TX_MUTEX, TX_NO_WAIT, TX_WAIT_FOREVER, _tx_mutex_create, _tx_mutex_get, _tx_mutex_put};
+
+use crate::pool::MemoryBlock;
+use crate::thread::{execution_context, ExecutionContext};
+use crate::tx_checked_call;
+
+use super::error::TxError;
+use crate::error::ErrorKind;
+use crate::log::error;
+use crate::pool::AllocateError;
+
+/// Alignment of the memory returned by `TlsfPool::allocate`. This is suitable for
+/// thread stacks and queue storage.
+const MEMORY_BLOCK_ALIGN: usize = 8;
+
+/// Fragmentation and usage statistics of a `TlsfPool`
+#[derive(Clone, Copy, Debug, Default)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct TlsfStats {
+    /// Bytes managed by the pool, including block headers
+    pub total_bytes: usize,
+    /// Bytes in free blocks that can be handed out
+    pub free_bytes: usize,
+    /// Number of free blocks. More free blocks for the same number of free bytes
+    /// means more fragmentation.
+    pub free_blocks: usize,
+    /// The largest allocation with default alignment that currently succeeds
+    pub largest_free_block: usize,
+    /// Number of live allocations
+    pub allocations: usize,
+    /// Sum of the sizes requested by live allocations
+    pub requested_bytes: usize,
+    /// High water mark of `requested_bytes`
+    pub peak_requested_bytes: usize,
+    /// Number of allocations that failed for lack of memory
+    pub failed_allocations: usize,
+}
+
+impl TlsfStats {
+    /// Fragmentation of the free memory in percent. 0 means all free memory is in
+    /// one block, values close to 100 mean it is split into many small blocks.
+    pub fn fragmentation_percent(&self) -> u8 {
+        if self.free_bytes == 0 {
+            return 0;
+        }
+        (100 - (self.largest_free_block * 100 / self.free_bytes)) as u8
+    }
+}
+
+struct Inner<const FLLEN: usize, const SLLEN: usize> {
+    tlsf: Tlsf<'static, u32, u32, FLLEN, SLLEN>,
+    memory: Option<NonNull<[u8]>>,
+    stats: TlsfStats,
+}
+
+/// A TLSF memory pool. `FLLEN` and `SLLEN` set the number of first and second level
+/// free lists. The largest block is `rlsf::GRANULARITY << FLLEN` bytes, which is 64KiB
+/// with the defaults on a 32 bit target. Larger values need a bigger control block.
+pub struct TlsfPool<const FLLEN: usize = 12, const SLLEN: usize = 16> {
+    inner: UnsafeCell<Inner<FLLEN, SLLEN>>,
+    mutex: UnsafeCell<MaybeUninit<TX_MUTEX>>,
+    /// Set once the mutex exists, so `lock` can check it without touching `inner`
+    initialized: AtomicBool,
+}
+
+unsafe impl<const FLLEN: usize, const SLLEN: usize> Sync for TlsfPool<FLLEN, SLLEN> {}
+
+/// Holds the pool mutex while the pool is accessed
+struct Guard {
+    mutex: *mut TX_MUTEX,
+}
+
+impl Drop for Guard {
+    fn drop(&mut self) {
+        if !self.mutex.is_null() && tx_checked_call!(self.mutex => _tx_mutex_put(self.mutex)).is_err() {
+            error!("TlsfPool: failed to put mutex");
+        }
+    }
+}
+
+impl<const FLLEN: usize, const SLLEN: usize> TlsfPool<FLLEN, SLLEN> {
+    pub const fn new() -> Self {
+        TlsfPool {
+            inner: UnsafeCell::new(Inner {
+                tlsf: Tlsf::new(),
+                memory: None,
+                stats: TlsfStats {
+                    total_bytes: 0,
+                    free_bytes: 0,
+                    free_blocks: 0,
+                    largest_free_block: 0,
+                    allocations: 0,
+                    requested_bytes: 0,
+                    peak_requested_bytes: 0,
+                    failed_allocations: 0,
+                },
+            }),
+            mutex: UnsafeCell::new(MaybeUninit::uninit()),
+            initialized: AtomicBool::new(false),
+        }
+    }
+
+    /// Create the pool mutex and hand `pool_memory` to the allocator. This must be
+    /// called before any thread uses the pool, typically in the application define
+    /// callback.
+    pub fn initialize(&'static mut self, name: &'static CStr, pool_memory: &'static mut [u8]) -> Result<(), TxError> {
+        if *self.initialized.get_mut() {
+            panic!("TlsfPool is already initialized");
+        }
+        let inner = self.inner.get_mut();
+        // The pool may end before the memory does. `stats` has to walk exactly the
+        // bytes that rlsf took.
+        let start = NonNull::from(&mut *pool_memory);
+        let pool_len = unsafe { inner.tlsf.insert_free_block_ptr(start) }.ok_or(ErrorKind::SizeError)?;
+        inner.memory = Some(NonNull::slice_from_raw_parts(start.cast::<u8>(), pool_len.get()));
+        inner.stats.total_bytes = pool_len.get();
+        tx_checked_call!(name => _tx_mutex_create(
+            self.mutex.get_mut().as_mut_ptr(),
+            name.as_ptr() as *mut i8,
+            TX_INHERIT
+        ))?;
+        self.initialized.store(true, Ordering::Release);
+        Ok(())
+    }
+
+    /// Take the pool mutex. Returns `None` if the caller is not allowed to use the
+    /// pool or the mutex could not be obtained.
+    // The mutex guarantees that only one caller holds the returned reference
+    #[allow(clippy::mut_from_ref)]
+    fn lock(&self, wait: bool) -> Option<(Guard, &mut Inner<FLLEN, SLLEN>)> {
+        if !self.initialized.load(Ordering::Acquire) {
+            return None;
+        }
+        let mutex = match execution_context() {
+            // There is only one thread of execution during initialization
+            ExecutionContext::Initialization => core::ptr::null_mut(),
+            ExecutionContext::Thread => {
+                let mutex = unsafe { (*self.mutex.get()).as_mut_ptr() };
+                tx_checked_call!(mutex => _tx_mutex_get(mutex, if wait { TX_WAIT_FOREVER } else { TX_NO_WAIT })).ok()?;
+                mutex
+            }
+            ExecutionContext::Timer | ExecutionContext::Isr => return None,
+        };
+        // Only now that the mutex is held may the pool state be borrowed
+        Some((Guard { mutex }, unsafe { &mut *self.inner.get() }))
+    }
+
+    fn allocate_layout(&self, layout: Layout, wait: bool) -> Option<NonNull<u8>> {
+        let (_guard, inner) = self.lock(wait)?;
+        match inner.tlsf.allocate(layout) {
+            Some(ptr) => {
+                inner.stats.allocations += 1;
+                inner.stats.requested_bytes += layout.size();
+                inner.stats.peak_requested_bytes =
+                    inner.stats.peak_requested_bytes.max(inner.stats.requested_bytes);
+                Some(ptr)
+            }
+            None => {
+                inner.stats.failed_allocations += 1;
+                None
+            }
+        }
+    }
+
+    unsafe fn release_layout(&self, ptr: NonNull<u8>, layout: Layout) -> bool {
+        // Releasing must not fail because of a busy pool, so always wait
+        let Some((_guard, inner)) = self.lock(true) else {
+            return false;
+        };
+        inner.tlsf.deallocate(ptr, layout.align());
+        inner.stats.allocations -= 1;
+        inner.stats.requested_bytes -= layout.size();
+        true
+    }
+
+    unsafe fn reallocate_layout(&self, ptr: NonNull<u8>, layout: Layout, new_size: usize) -> Option<NonNull<u8>> {
+        let new_layout = Layout::from_size_align(new_size, layout.align()).ok()?;
+        let (_guard, inner) = self.lock(true)?;
+        match inner.tlsf.reallocate(ptr, new_layout) {
+            Some(new_ptr) => {
+                inner.stats.requested_bytes = inner.stats.requested_bytes - layout.size() + new_size;
+                inner.stats.peak_requested_bytes =
+                    inner.stats.peak_requested_bytes.max(inner.stats.requested_bytes);
+                Some(new_ptr)
+            }
+            None => {
+                inner.stats.failed_allocations += 1;
+                None
+            }
+        }
+    }
+
+    /// Allocate `size` bytes. Unlike a ThreadX byte pool a TLSF pool cannot suspend
+    /// until memory is released, so `wait` only decides whether the caller waits for
+    /// the pool mutex when another thread is using the pool.
+    pub fn allocate(&self, size: usize, wait: bool) -> Result<MemoryBlock, AllocateError> {
+        let layout = Layout::from_size_align(size, MEMORY_BLOCK_ALIGN).map_err(|_| TxError::from(ErrorKind::SizeError))?;
+        self.allocate_layout(layout, wait)
+            .map(|ptr| MemoryBlock::new(unsafe { core::slice::from_raw_parts_mut(ptr.as_ptr(), size) }))
+            .ok_or(AllocateError::NoMemory)
+    }
+
+    /// Release memory that was obtained from `allocate`.
+    pub fn release(&self, mem: &mut [u8]) -> Result<(), TxError> {
+        let layout = Layout::from_size_align(mem.len(), MEMORY_BLOCK_ALIGN).map_err(|_| ErrorKind::SizeError)?;
+        let ptr = NonNull::new(mem.as_mut_ptr()).ok_or(ErrorKind::PtrError)?;
+        if unsafe { self.release_layout(ptr, layout) } {
+            Ok(())
+        } else {
+            Err(ErrorKind::CallerError.into())
+        }
+    }
+
+    /// Walk the pool and collect usage and fragmentation statistics. This takes time
+    /// proportional to the number of blocks and holds the pool mutex meanwhile, so it
+    /// should not be called from time critical code.
+    pub fn stats(&self) -> Result<TlsfStats, TxError> {
+        let (_guard, inner) = self.lock(true).ok_or(ErrorKind::CallerError)?;
+        let mut stats = inner.stats;
+        stats.free_bytes = 0;
+        stats.free_blocks = 0;
+        stats.largest_free_block = 0;
+        if let Some(memory) = inner.memory {
+            for block in unsafe { inner.tlsf.iter_blocks(memory) } {
+                if !block.is_occupied() {
+                    stats.free_blocks += 1;
+                    stats.free_bytes += block.max_payload_size();
+                    stats.largest_free_block = stats.largest_free_block.max(block.max_payload_size());
+                }
+            }
+        }
+        Ok(stats)
+    }
+}
+
+impl<const FLLEN: usize, const SLLEN: usize> Default for TlsfPool<FLLEN, SLLEN> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// Like `ThreadXAllocator`, the TLSF pool never suspends for memory and returns null
+/// when called from a timer or an ISR.
+unsafe impl<const FLLEN: usize, const SLLEN: usize> GlobalAlloc for TlsfPool<FLLEN, SLLEN> {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        match self.allocate_layout(layout, true) {
+            Some(ptr) => ptr.as_ptr(),
+            None => {
+                error!("TlsfPool: out of memory allocating {} bytes", layout.size());
+                core::ptr::null_mut()
+            }
+        }
+    }
+
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        if !self.release_layout(NonNull::new_unchecked(ptr), layout) {
+            error!("TlsfPool: unable to release {}", ptr);
+        }
+    }
+
+    unsafe fn realloc(&self, ptr: *mut u8, layout: Layout, new_size: usize) -> *mut u8 {
+        self.reallocate_layout(NonNull::new_unchecked(ptr), layout, new_size)
+            .map_or(core::ptr::null_mut(), |p| p.as_ptr())
+    }
+}
+
+#[cfg(any(feature = "nightly", feature = "allocator-api2"))]
+macro_rules! impl_allocator {
+    ($allocator:path, $error:path) => {
+        unsafe impl<const FLLEN: usize, const SLLEN: usize> $allocator for TlsfPool<FLLEN, SLLEN> {
+            fn allocate(&self, layout: Layout) -> Result<NonNull<[u8]>, $error> {
+                if layout.size() == 0 {
+                    let dangling = unsafe { NonNull::new_unchecked(layout.align() as *mut u8) };
+                    return Ok(NonNull::slice_from_raw_parts(dangling, 0));
+                }
+                self.allocate_layout(layout, true)
+                    .map(|ptr| NonNull::slice_from_raw_parts(ptr, layout.size()))
+                    .ok_or($error)
+            }
+
+            unsafe fn deallocate(&self, ptr: NonNull<u8>, layout: Layout) {
+                if layout.size() != 0 && !self.release_layout(ptr, layout) {
+                    error!("TlsfPool: unable to release {}", ptr.as_ptr());
+                }
+            }
+        }
+    };
+}
+
+#[cfg(feature = "nightly")]
+impl_allocator!(core::alloc::Allocator, core::alloc::AllocError);
+
+#[cfg(feature = "allocator-api2")]
+impl_allocator!(allocator_api2::alloc::Allocator, allocator_api2::alloc::AllocError);
@@ -0,0 +1,70 @@
+// Delays against threadx-sim. The simulated kernel ticks at the start of each tick, so
+// the extra tick that `Delay` sleeps shows up in full here.
+//
+//     cargo test --no-default-features --features sim,macros,embedded-hal --test delay
+
+use std::future::Future;
+use std::pin::pin;
+use std::sync::{Arc, Mutex as StdMutex};
+use std::task::{Context, Poll, Wake, Waker};
+
+use threadx_rs::delay::Delay;
+use threadx_rs::semaphore::{Semaphore, SemaphoreUser, SemaphoreUserHandle};
+use threadx_rs::sim::Harness;
+use threadx_rs::time::Instant;
+use threadx_rs::WaitOption;
+
+static DELAY_LOG: StdMutex<Vec<(&str, u32)>> = StdMutex::new(Vec::new());
+
+fn log(event: &'static str) {
+    DELAY_LOG.lock().unwrap().push((event, Instant::now().ticks()));
+}
+
+/// Puts the semaphore that the polling thread waits on
+struct SemaphoreWaker(&'static SemaphoreUserHandle);
+
+impl Wake for SemaphoreWaker {
+    fn wake(self: Arc<Self>) {
+        let _ = self.0.put();
+    }
+}
+
+#[threadx_rs::app]
+mod delay_app {
+    use super::*;
+
+    #[semaphore(initial = 0)]
+    static WAKE: Semaphore;
+
+    #[thread(priority = 1, stack = 4096)]
+    fn delayer(wake: SemaphoreUserHandle) {
+        let mut delay = Delay::new();
+        log("start");
+        embedded_hal::delay::DelayNs::delay_ms(&mut delay, 10);
+        log("blocking");
+
+        let wake: &'static SemaphoreUserHandle = Box::leak(Box::new(wake));
+        let waker = Waker::from(Arc::new(SemaphoreWaker(wake)));
+        let mut cx = Context::from_waker(&waker);
+        let mut future = pin!(embedded_hal_async::delay::DelayNs::delay_ms(&mut delay, 20));
+        while future.as_mut().poll(&mut cx) == Poll::Pending {
+            log("pending");
+            wake.get(WaitOption::WaitForever).unwrap();
+        }
+        log("async");
+    }
+
+    #[thread(priority = 2, stack = 4096)]
+    fn bystander() {
+        log("bystander");
+    }
+}
+
+#[test]
+fn delays_sleep_one_tick_more_than_requested() {
+    Harness::run(delay_app::start);
+    assert_eq!(
+        *DELAY_LOG.lock().unwrap(),
+        [("start", 0), ("bystander", 0), ("blocking", 2), ("pending", 2), ("async", 5)]
+    );
+}
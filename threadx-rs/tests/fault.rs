@@ -0,0 +1,106 @@
+// Fault injection against threadx-sim. The scripts are installed by the threads, so the
+// calls that create the application are not counted.
+//
+//     cargo test --no-default-features --features sim,macros,fault-injection --test fault
+
+use std::sync::Mutex as StdMutex;
+
+use threadx_rs::error::ErrorKind;
+use threadx_rs::fault::{self, Fault};
+use threadx_rs::pool::{AllocateError, BytePool, BytePoolHandle};
+use threadx_rs::queue::{Queue, QueueReceiver, QueueSendError, QueueSender};
+use threadx_rs::sim::Harness;
+use threadx_rs::WaitOption;
+
+static SEND_LOG: StdMutex<Vec<String>> = StdMutex::new(Vec::new());
+
+#[threadx_rs::app]
+mod queue_app {
+    use super::*;
+
+    #[queue(capacity = 4)]
+    static QUEUE: Queue<u32>;
+
+    #[thread(priority = 1, stack = 4096)]
+    fn producer(queue: QueueSender<u32>) {
+        static SCRIPT: [Fault; 2] =
+            [Fault::nth("_tx_queue_send", 2, ErrorKind::QueueFull), Fault::nth("_tx_queue_send", 3, ErrorKind::QueueError)];
+        fault::install(&SCRIPT, 1);
+        for message in 1..=4 {
+            let entry = match queue.send(message, WaitOption::NoWait) {
+                Ok(()) => format!("sent {message}"),
+                Err(QueueSendError::Full(unsent)) => format!("full, kept {unsent}"),
+                Err(QueueSendError::Kernel(unsent, error)) => format!("{error}, kept {unsent}"),
+                Err(error) => format!("{error:?}"),
+            };
+            SEND_LOG.lock().unwrap().push(entry);
+        }
+    }
+
+    #[thread(priority = 2, stack = 4096)]
+    fn consumer(queue: QueueReceiver<u32>) {
+        while let Ok(message) = queue.receive(WaitOption::NoWait) {
+            SEND_LOG.lock().unwrap().push(format!("received {message}"));
+        }
+        SEND_LOG.lock().unwrap().push(format!("injected {}", fault::injected()));
+        fault::clear();
+    }
+}
+
+#[test]
+fn injected_send_fails_without_sending() {
+    Harness::run(queue_app::start);
+    assert_eq!(
+        *SEND_LOG.lock().unwrap(),
+        [
+            "sent 1",
+            "full, kept 2",
+            "_tx_queue_send on `QUEUE` failed: QueueError (0x9), kept 3",
+            "sent 4",
+            "received 1",
+            "received 4",
+            "injected 2",
+        ]
+    );
+}
+
+/// Which of 32 allocations failed and the injected faults, once for each run of the script
+static ALLOCATE_LOG: StdMutex<Vec<(Vec<bool>, u32)>> = StdMutex::new(Vec::new());
+
+#[threadx_rs::app]
+mod pool_app {
+    use super::*;
+
+    #[byte_pool(size = 1024)]
+    static BYTES: BytePool;
+
+    #[thread(priority = 1, stack = 4096)]
+    fn user(bytes: &'static BytePoolHandle) {
+        static SCRIPT: [Fault; 1] = [Fault::one_in("_tx_byte_allocate", 2, ErrorKind::NoMemory)];
+        for _ in 0..2 {
+            fault::install(&SCRIPT, 42);
+            let failed = (0..32)
+                .map(|_| match bytes.allocate(16, false) {
+                    Ok(memory) => {
+                        bytes.release(memory.consume()).unwrap();
+                        false
+                    }
+                    Err(error) => error == AllocateError::NoMemory,
+                })
+                .collect::<Vec<_>>();
+            ALLOCATE_LOG.lock().unwrap().push((failed, fault::injected()));
+        }
+        fault::clear();
+    }
+}
+
+#[test]
+fn random_faults_repeat_with_the_seed() {
+    Harness::run(pool_app::start);
+    let log = ALLOCATE_LOG.lock().unwrap();
+    assert_eq!(log.len(), 2);
+    assert_eq!(log[0], log[1]);
+    let (failed, injected) = &log[0];
+    assert_eq!(failed.iter().filter(|&&failed| failed).count() as u32, *injected);
+    assert!(failed.contains(&true) && failed.contains(&false));
+}
@@ -0,0 +1,80 @@
+// The heap debugger against threadx-sim. The live allocations are global, so this is the
+// only application in the binary.
+//
+//     cargo test --no-default-features --features sim,macros,heap-debug --test heap
+
+use std::sync::Mutex as StdMutex;
+use std::time::Duration;
+
+use threadx_rs::heap::{report, thread_usage};
+use threadx_rs::pool::{BytePool, BytePoolHandle};
+use threadx_rs::sim::Harness;
+use threadx_rs::thread::sleep;
+
+static HEAP_LOG: StdMutex<Vec<String>> = StdMutex::new(Vec::new());
+
+fn log(entry: String) {
+    HEAP_LOG.lock().unwrap().push(entry);
+}
+
+#[threadx_rs::app]
+mod heap_app {
+    use super::*;
+
+    #[byte_pool(size = 2048)]
+    static BYTES: BytePool;
+
+    #[thread(priority = 1, stack = 4096)]
+    fn owner(bytes: &'static BytePoolHandle) {
+        let first = bytes.allocate(100, false).unwrap().consume();
+        let second = bytes.allocate(20, false).unwrap().consume();
+        let usage = thread_usage();
+        log(format!("owner: {} allocations, {} bytes", usage.allocations, usage.bytes));
+
+        // Run the other thread with both allocations live
+        sleep(Duration::from_millis(10)).unwrap();
+
+        // Overrun the second allocation by one byte, then repair it so it can be released
+        let end = unsafe { second.as_mut_ptr().add(second.len()) };
+        let guard = unsafe { end.read() };
+        unsafe { end.write(0) };
+        let damaged = report();
+        log(format!("damaged: {} allocations, {} corrupted", damaged.allocations, damaged.corrupted));
+        unsafe { end.write(guard) };
+
+        bytes.release(first).unwrap();
+        bytes.release(second).unwrap();
+        let usage = thread_usage();
+        log(format!("owner released: {} allocations, {} bytes", usage.allocations, usage.bytes));
+    }
+
+    #[thread(priority = 2, stack = 4096)]
+    fn other(bytes: &'static BytePoolHandle) {
+        let memory = bytes.allocate(50, false).unwrap().consume();
+        let usage = thread_usage();
+        log(format!("other: {} allocations, {} bytes", usage.allocations, usage.bytes));
+        let live = report();
+        log(format!("live: {} allocations, {} bytes, {} corrupted", live.allocations, live.bytes, live.corrupted));
+        // Released after the owner is done
+        sleep(Duration::from_millis(30)).unwrap();
+        bytes.release(memory).unwrap();
+        let done = report();
+        log(format!("done: {} allocations, {} bytes, peak {}", done.allocations, done.bytes, done.peak_bytes));
+    }
+}
+
+#[test]
+fn report_lists_live_allocations_and_finds_overruns() {
+    Harness::run(heap_app::start);
+    assert_eq!(
+        *HEAP_LOG.lock().unwrap(),
+        [
+            "owner: 2 allocations, 120 bytes",
+            "other: 1 allocations, 50 bytes",
+            "live: 3 allocations, 170 bytes, 0 corrupted",
+            "damaged: 3 allocations, 1 corrupted",
+            "owner released: 0 allocations, 0 bytes",
+            "done: 0 allocations, 0 bytes, peak 170",
+        ]
+    );
+}
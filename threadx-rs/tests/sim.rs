@@ -0,0 +1,498 @@
+// Runs applications against threadx-sim. `Harness::run` returns once every thread has
+// completed or is suspended for good, so the results are recorded in statics and checked
+// afterwards. With `Harness::start` the test advances the time and checks in between.
+// A panic in a thread aborts the process, so the threads only record.
+//
+//     cargo test --no-default-features --features sim,macros --test sim
+
+use core::alloc::{GlobalAlloc, Layout};
+use core::ptr::{addr_of, addr_of_mut};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Mutex as StdMutex;
+use std::time::Duration;
+
+use threadx_rs::allocator::{OomReport, ThreadXAllocator};
+use threadx_rs::event_flags::{EventFlagsGroup, GetOption};
+use threadx_rs::mutex::Mutex;
+use threadx_rs::pool::{BlockPool, BlockPoolHandle, BytePool, BytePoolHandle};
+use threadx_rs::pool_box::{PoolBox, PoolVec};
+use threadx_rs::queue::{Queue, QueueReceiver, QueueSender};
+use threadx_rs::semaphore::{Semaphore, SemaphoreGetError, SemaphoreUser, SemaphoreUserHandle};
+use threadx_rs::sim::Harness;
+use threadx_rs::planner::MemoryPlanner;
+use threadx_rs::thread::{sleep, Thread};
+use threadx_rs::time::Instant;
+use threadx_rs::timer::Timer;
+use threadx_rs::WaitOption;
+use threadx_sys::{_tx_thread_sleep, _tx_thread_suspend, ULONG};
+
+static QUEUE_LOG: StdMutex<Vec<(u32, u32)>> = StdMutex::new(Vec::new());
+
+#[threadx_rs::app]
+mod queue_app {
+    use super::*;
+
+    #[queue(capacity = 2)]
+    static QUEUE: Queue<u32>;
+
+    #[thread(priority = 1, stack = 4096)]
+    fn producer(queue: QueueSender<u32>) {
+        for message in 1..=5 {
+            queue.send(message, WaitOption::WaitForever).unwrap();
+        }
+    }
+
+    #[thread(priority = 2, stack = 4096)]
+    fn consumer(queue: QueueReceiver<u32>) {
+        for _ in 1..=5 {
+            let message = queue.receive(WaitOption::WaitForever).unwrap();
+            QUEUE_LOG.lock().unwrap().push((message, Instant::now().ticks()));
+            sleep(Duration::from_millis(10)).unwrap();
+        }
+    }
+}
+
+#[test]
+fn queue_blocks_the_sender_while_full() {
+    Harness::run(queue_app::start);
+    // The producer fills the queue and blocks, the consumer takes one message per tick
+    assert_eq!(*QUEUE_LOG.lock().unwrap(), [(1, 0), (2, 1), (3, 2), (4, 3), (5, 4)]);
+}
+
+static SEMAPHORE_LOG: StdMutex<Vec<&str>> = StdMutex::new(Vec::new());
+
+#[threadx_rs::app]
+mod semaphore_app {
+    use super::*;
+
+    #[semaphore(initial = 0)]
+    static SIGNAL: Semaphore;
+
+    #[thread(priority = 1, stack = 4096)]
+    fn waiter(signal: SemaphoreUserHandle) {
+        SEMAPHORE_LOG.lock().unwrap().push("waiting");
+        signal.get(WaitOption::WaitForever).unwrap();
+        SEMAPHORE_LOG.lock().unwrap().push("signalled");
+        let status = signal.get(WaitOption::NoWait);
+        SEMAPHORE_LOG.lock().unwrap().push(if matches!(status, Err(SemaphoreGetError::NoInstance)) { "empty" } else { "not empty" });
+    }
+
+    #[thread(priority = 2, stack = 4096)]
+    fn signaller(signal: SemaphoreUserHandle) {
+        SEMAPHORE_LOG.lock().unwrap().push("put");
+        signal.put().unwrap();
+        SEMAPHORE_LOG.lock().unwrap().push("after put");
+    }
+}
+
+#[test]
+fn semaphore_put_preempts_for_a_higher_priority_waiter() {
+    Harness::run(semaphore_app::start);
+    assert_eq!(*SEMAPHORE_LOG.lock().unwrap(), ["waiting", "put", "signalled", "empty", "after put"]);
+}
+
+#[threadx_rs::app]
+mod mutex_app {
+    use super::*;
+
+    #[mutex(inherit = true)]
+    static COUNTER: Mutex<u32> = Mutex::new(0);
+
+    #[thread(priority = 1, stack = 4096)]
+    fn first(counter: &'static Mutex<u32>) {
+        increment(counter);
+    }
+
+    #[thread(priority = 1, stack = 4096)]
+    fn second(counter: &'static Mutex<u32>) {
+        increment(counter);
+    }
+
+    fn increment(counter: &'static Mutex<u32>) {
+        for _ in 0..10 {
+            let mut guard = counter.lock(WaitOption::WaitForever).unwrap();
+            let value = *guard;
+            // Let the other thread run while the mutex is held
+            sleep(Duration::from_millis(10)).unwrap();
+            *guard = value + 1;
+        }
+        // Not while holding MUTEX_RESULT, which would block the simulated CPU
+        let value = *counter.lock(WaitOption::WaitForever).unwrap();
+        MUTEX_RESULT.lock().unwrap().push(value);
+    }
+}
+
+static MUTEX_RESULT: StdMutex<Vec<u32>> = StdMutex::new(Vec::new());
+
+#[test]
+fn mutex_serializes_read_modify_write() {
+    Harness::run(mutex_app::start);
+    assert_eq!(*MUTEX_RESULT.lock().unwrap().last().unwrap(), 20);
+}
+
+static EVENT_RESULT: StdMutex<Option<(u32, u32)>> = StdMutex::new(None);
+
+#[threadx_rs::app]
+mod event_flags_app {
+    use super::*;
+
+    #[event_flags]
+    static EVENTS: EventFlagsGroup;
+
+    #[thread(priority = 1, stack = 4096)]
+    fn waiter(events: &'static EventFlagsGroup) {
+        let flags = events.get(0b11, GetOption::WaitAllAndClear, WaitOption::WaitForever).unwrap();
+        *EVENT_RESULT.lock().unwrap() = Some((flags, Instant::now().ticks()));
+    }
+
+    #[thread(priority = 2, stack = 4096)]
+    fn setter(events: &'static EventFlagsGroup) {
+        events.publish(0b01).unwrap();
+        sleep(Duration::from_millis(30)).unwrap();
+        events.publish(0b10).unwrap();
+    }
+}
+
+#[test]
+fn event_flags_wait_for_all_requested_flags() {
+    Harness::run(event_flags_app::start);
+    assert_eq!(*EVENT_RESULT.lock().unwrap(), Some((0b11, 3)));
+}
+
+static POOL_RESULT: StdMutex<Vec<String>> = StdMutex::new(Vec::new());
+
+#[threadx_rs::app]
+mod pool_app {
+    use super::*;
+
+    #[byte_pool(size = 1024)]
+    static BYTES: BytePool;
+
+    #[block_pool(block_size = 32, blocks = 2)]
+    static BLOCKS: BlockPool;
+
+    #[thread(priority = 1, stack = 4096)]
+    fn user(bytes: &'static BytePoolHandle, blocks: &'static BlockPoolHandle) {
+        let mut log = Vec::new();
+        let memory = bytes.allocate(256, false).unwrap().consume();
+        memory.fill(0xA5);
+        log.push(format!("too large: {:?}", bytes.allocate(4096, false).err()));
+        bytes.release(memory).unwrap();
+        log.push(format!("fragments: {}", bytes.info().unwrap().fragments));
+
+        let first = blocks.allocate(false).unwrap();
+        let second = blocks.allocate(false).unwrap();
+        log.push(format!("block size: {}", first.len()));
+        log.push(format!("exhausted: {:?}", blocks.allocate(false).err()));
+        blocks.release(first).unwrap();
+        blocks.release(second).unwrap();
+        log.push(format!("available: {}", blocks.info().unwrap().available_blocks));
+        *POOL_RESULT.lock().unwrap() = log;
+    }
+}
+
+#[test]
+fn pools_allocate_and_release() {
+    Harness::run(pool_app::start);
+    assert_eq!(
+        *POOL_RESULT.lock().unwrap(),
+        [
+            "too large: Some(NoMemory)",
+            // The released block is merged back on the next allocation
+            "fragments: 3",
+            "block size: 32",
+            "exhausted: Some(NoMemory)",
+            "available: 2",
+        ]
+    );
+}
+
+static POOL_BOX_RESULT: StdMutex<Vec<String>> = StdMutex::new(Vec::new());
+static DROPS: AtomicUsize = AtomicUsize::new(0);
+
+struct Counted(u32);
+
+impl Drop for Counted {
+    fn drop(&mut self) {
+        DROPS.fetch_add(1, Ordering::Relaxed);
+    }
+}
+
+#[threadx_rs::app]
+mod pool_box_app {
+    use super::*;
+
+    #[byte_pool(size = 1024)]
+    static BYTES: BytePool;
+
+    #[thread(priority = 1, stack = 4096)]
+    fn user(bytes: &'static BytePoolHandle) {
+        let mut log = Vec::new();
+        let available = || bytes.info().unwrap().available_bytes;
+        let full = available();
+
+        let boxed = PoolBox::new_in(Counted(1), bytes, false).unwrap();
+        log.push(format!("boxed {} uses pool: {}", boxed.0, available() < full));
+        drop(boxed);
+        log.push(format!("dropped: {} released: {}", DROPS.load(Ordering::Relaxed), available() == full));
+
+        let too_large = PoolBox::new_in((Counted(2), [0u8; 2048]), bytes, false);
+        log.push(format!("too large: {:?}, dropped: {}", too_large.err().map(|error| error.kind()), DROPS.load(Ordering::Relaxed)));
+
+        let mut vec = PoolVec::new_in(bytes, false);
+        log.push(format!("empty capacity: {} allocated: {}", vec.capacity(), available() < full));
+        let mut capacities = Vec::new();
+        for value in 0..10 {
+            vec.push(Counted(value)).unwrap();
+            capacities.push(vec.capacity());
+        }
+        log.push(format!("capacities: {capacities:?}"));
+        log.push(format!("values: {:?}", vec.iter().map(|counted| counted.0).collect::<Vec<_>>()));
+        drop(vec);
+        log.push(format!("dropped: {} released: {}", DROPS.load(Ordering::Relaxed), available() == full));
+        *POOL_BOX_RESULT.lock().unwrap() = log;
+    }
+}
+
+#[test]
+fn pool_box_and_vec_release_to_the_pool() {
+    Harness::run(pool_box_app::start);
+    assert_eq!(
+        *POOL_BOX_RESULT.lock().unwrap(),
+        [
+            "boxed 1 uses pool: true",
+            "dropped: 1 released: true",
+            // The value is dropped when the allocation fails
+            "too large: Some(NoMemory), dropped: 2",
+            "empty capacity: 0 allocated: false",
+            "capacities: [4, 4, 4, 4, 8, 8, 8, 8, 16, 16]",
+            "values: [0, 1, 2, 3, 4, 5, 6, 7, 8, 9]",
+            "dropped: 12 released: true",
+        ]
+    );
+}
+
+static ALLOCATOR_RESULT: StdMutex<Vec<String>> = StdMutex::new(Vec::new());
+static OOM_REPORTS: StdMutex<Vec<String>> = StdMutex::new(Vec::new());
+
+fn record_oom(report: &OomReport) {
+    OOM_REPORTS.lock().unwrap().push(format!("{} bytes from {:?}", report.layout.size(), report.context));
+}
+
+#[threadx_rs::app]
+mod allocator_app {
+    use super::*;
+
+    static mut ALLOCATOR: ThreadXAllocator = ThreadXAllocator::with_oom_hook(record_oom);
+    static mut HEAP: [u8; 2048] = [0; 2048];
+
+    #[thread(priority = 1, stack = 4096)]
+    fn user() {
+        unsafe { (*addr_of_mut!(ALLOCATOR)).initialize(&mut *addr_of_mut!(HEAP)).unwrap() };
+        let allocator = unsafe { &*addr_of!(ALLOCATOR) };
+        let available = || allocator.info().unwrap().available_bytes;
+        let full = available();
+        let mut log = Vec::new();
+
+        unsafe {
+            let aligned = Layout::from_size_align(24, 64).unwrap();
+            let ptr = allocator.alloc(aligned);
+            log.push(format!("over-aligned: {}", ptr as usize % 64));
+            ptr.write_bytes(0xA5, 24);
+
+            // Growing past the block copies and keeps the alignment
+            let grown = allocator.realloc(ptr, aligned, 300);
+            let kept = (0..24).all(|i| *grown.add(i) == 0xA5);
+            log.push(format!("grown moved: {} aligned: {} kept: {kept}", grown != ptr, grown as usize % 64));
+
+            // Shrinking stays in place, the block header says how much is usable
+            let shrunk = allocator.realloc(grown, Layout::from_size_align(300, 64).unwrap(), 8);
+            log.push(format!("shrunk in place: {}", shrunk == grown));
+            // Growing back up to the original size also fits in the block
+            let regrown = allocator.realloc(shrunk, Layout::from_size_align(8, 64).unwrap(), 300);
+            log.push(format!("regrown in place: {}", regrown == grown));
+            allocator.dealloc(regrown, Layout::from_size_align(300, 64).unwrap());
+            log.push(format!("released: {}", available() == full));
+
+            let exhausted = allocator.alloc(Layout::from_size_align(4096, 8).unwrap());
+            log.push(format!("exhausted: {}", exhausted.is_null()));
+        }
+        *ALLOCATOR_RESULT.lock().unwrap() = log;
+    }
+}
+
+#[test]
+fn allocator_aligns_reallocates_and_reports_oom() {
+    Harness::run(allocator_app::start);
+    assert_eq!(
+        *ALLOCATOR_RESULT.lock().unwrap(),
+        [
+            "over-aligned: 0",
+            "grown moved: true aligned: 0 kept: true",
+            "shrunk in place: true",
+            "regrown in place: true",
+            "released: true",
+            "exhausted: true",
+        ]
+    );
+    assert_eq!(*OOM_REPORTS.lock().unwrap(), ["4096 bytes from Thread"]);
+}
+
+static THREAD_LOG: StdMutex<Vec<String>> = StdMutex::new(Vec::new());
+
+#[threadx_rs::app(heap = 4096 * 3)]
+mod thread_closure_app {
+    use super::*;
+
+    #[define]
+    fn define(memory: &'static mut [u8]) {
+        static mut WORKERS: [Thread; 2] = [Thread::new(), Thread::new()];
+        let mut plan = MemoryPlanner::new(memory);
+        let workers = unsafe { &mut *addr_of_mut!(WORKERS) };
+        for (index, worker) in workers.iter_mut().enumerate() {
+            // Captured by value, the frame of `define` is gone when the threads run
+            let name = format!("worker {index}");
+            let delay = Duration::from_millis(10 * (index as u64 + 1));
+            let entry = move || {
+                sleep(delay).unwrap();
+                THREAD_LOG.lock().unwrap().push(format!("{name} at {}", Instant::now().ticks()));
+            };
+            let stack = plan.stack(4096).unwrap();
+            let thread_name = if index == 0 { c"worker 0" } else { c"worker 1" };
+            worker.initialize(thread_name, entry, stack, 1, 1, 0, true).unwrap();
+        }
+    }
+}
+
+#[test]
+fn threads_run_capturing_closures() {
+    Harness::run(thread_closure_app::start);
+    assert_eq!(*THREAD_LOG.lock().unwrap(), ["worker 0 at 1", "worker 1 at 2"]);
+}
+
+static TIMER_TICKS: StdMutex<Vec<(&str, u32)>> = StdMutex::new(Vec::new());
+
+fn expired(input: ULONG) {
+    let name = if input == 1 { "early" } else { "late" };
+    TIMER_TICKS.lock().unwrap().push((name, Instant::now().ticks()));
+}
+
+#[threadx_rs::app]
+mod timer_app {
+    use super::*;
+
+    #[define]
+    fn define(_memory: &'static mut [u8]) {
+        static mut EARLY: Timer = Timer::new();
+        static mut LATE: Timer = Timer::new();
+        let late = unsafe { &mut *addr_of_mut!(LATE) };
+        late.initialize(c"late", &expired, 2, Duration::from_millis(50), Duration::ZERO, true).unwrap();
+        let early = unsafe { &mut *addr_of_mut!(EARLY) };
+        early.initialize(c"early", &expired, 1, Duration::from_millis(20), Duration::ZERO, true).unwrap();
+    }
+
+    #[thread(priority = 1, stack = 4096)]
+    fn sleeper() {
+        sleep(Duration::from_millis(30)).unwrap();
+        TIMER_TICKS.lock().unwrap().push(("sleeper", Instant::now().ticks()));
+    }
+}
+
+#[test]
+fn timers_expire_in_virtual_time() {
+    Harness::run(timer_app::start);
+    assert_eq!(*TIMER_TICKS.lock().unwrap(), [("early", 2), ("sleeper", 3), ("late", 5)]);
+}
+
+static WRAPPER_TICKS: StdMutex<Vec<u32>> = StdMutex::new(Vec::new());
+
+#[threadx_rs::app]
+mod timer_wrapper_app {
+    use super::*;
+
+    #[timer(initial_ms = 20, period_ms = 30)]
+    fn periodic() {
+        WRAPPER_TICKS.lock().unwrap().push(Instant::now().ticks());
+    }
+}
+
+#[test]
+fn timer_wrapper_runs_the_closure() {
+    let harness = Harness::start(timer_wrapper_app::start);
+    harness.advance(Duration::from_millis(80));
+    assert_eq!(*WRAPPER_TICKS.lock().unwrap(), [2, 5, 8]);
+}
+
+static CHECKED_CALLS: StdMutex<Vec<String>> = StdMutex::new(Vec::new());
+
+// `tx_checked_call!` is exported for services that have no wrapper
+#[threadx_rs::app]
+mod checked_call_app {
+    use super::*;
+
+    #[thread(priority = 1, stack = 4096)]
+    fn caller() {
+        let slept = threadx_rs::tx_checked_call!(_tx_thread_sleep(2));
+        let mut calls = CHECKED_CALLS.lock().unwrap();
+        calls.push(format!("{:?} at {}", slept, Instant::now().ticks()));
+        let name = c"missing";
+        let error = threadx_rs::tx_checked_call!(name => _tx_thread_suspend(core::ptr::null_mut())).unwrap_err();
+        calls.push(format!("{:?} {:?} {:?}", error.kind(), error.service(), error.object()));
+    }
+}
+
+#[test]
+fn checked_calls_work_outside_the_crate() {
+    Harness::run(checked_call_app::start);
+    assert_eq!(
+        *CHECKED_CALLS.lock().unwrap(),
+        ["Ok(()) at 2", r#"ThreadError Some("_tx_thread_suspend") Some("missing")"#]
+    );
+}
+
+static RETRANSMISSIONS: StdMutex<Vec<(u32, u32)>> = StdMutex::new(Vec::new());
+static ACKNOWLEDGED: StdMutex<bool> = StdMutex::new(false);
+
+#[threadx_rs::app]
+mod retransmit_app {
+    use super::*;
+
+    #[queue(capacity = 4)]
+    static LINK: Queue<u32>;
+
+    /// Sends the frame every 200 ms until it is acknowledged
+    #[thread(priority = 1, stack = 4096)]
+    fn sender(link: QueueSender<u32>) {
+        let mut attempt = 0;
+        while !*ACKNOWLEDGED.lock().unwrap() {
+            attempt += 1;
+            link.send(attempt, WaitOption::NoWait).unwrap();
+            sleep(Duration::from_millis(200)).unwrap();
+        }
+    }
+
+    #[thread(priority = 2, stack = 4096)]
+    fn receiver(link: QueueReceiver<u32>) {
+        loop {
+            let attempt = link.receive(WaitOption::WaitForever).unwrap();
+            RETRANSMISSIONS.lock().unwrap().push((attempt, Instant::now().ticks()));
+        }
+    }
+}
+
+#[test]
+fn harness_advances_time_manually() {
+    let harness = Harness::start(retransmit_app::start);
+    assert_eq!(*RETRANSMISSIONS.lock().unwrap(), [(1, 0)]);
+
+    harness.advance(Duration::from_millis(199));
+    assert_eq!(harness.ticks(), 20);
+    assert_eq!(*RETRANSMISSIONS.lock().unwrap(), [(1, 0), (2, 20)]);
+
+    harness.advance(Duration::from_millis(500));
+    assert_eq!(*RETRANSMISSIONS.lock().unwrap(), [(1, 0), (2, 20), (3, 40), (4, 60)]);
+
+    *ACKNOWLEDGED.lock().unwrap() = true;
+    harness.advance(Duration::from_secs(1));
+    assert_eq!(RETRANSMISSIONS.lock().unwrap().len(), 4);
+}
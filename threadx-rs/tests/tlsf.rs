@@ -0,0 +1,111 @@
+// TlsfPool against threadx-sim, from threads that share the pool.
+//
+//     cargo test --no-default-features --features sim,macros,tlsf --test tlsf
+
+use core::alloc::{GlobalAlloc, Layout};
+use core::ptr::{addr_of, addr_of_mut};
+use std::sync::Mutex as StdMutex;
+use std::time::Duration;
+
+use threadx_rs::sim::Harness;
+use threadx_rs::thread::sleep;
+use threadx_rs::tlsf::TlsfPool;
+
+static TLSF_RESULT: StdMutex<Vec<String>> = StdMutex::new(Vec::new());
+static CONTENTION_ERRORS: StdMutex<Vec<String>> = StdMutex::new(Vec::new());
+
+static mut POOL: TlsfPool = TlsfPool::new();
+static mut POOL_MEMORY: [u8; 4096] = [0; 4096];
+
+fn pool() -> &'static TlsfPool {
+    unsafe { &*addr_of!(POOL) }
+}
+
+/// Allocate, fill, sleep so the other thread runs with the block live, check and release
+fn churn(fill: u8) {
+    for round in 0..20 {
+        let memory = pool().allocate(64 + round * 8, true).unwrap().consume();
+        memory.fill(fill);
+        sleep(Duration::from_millis(10)).unwrap();
+        if memory.iter().any(|byte| *byte != fill) {
+            CONTENTION_ERRORS.lock().unwrap().push(format!("thread {fill} round {round} overwritten"));
+        }
+        pool().release(memory).unwrap();
+    }
+}
+
+#[threadx_rs::app]
+mod tlsf_app {
+    use super::*;
+
+    #[define]
+    fn define(_memory: &'static mut [u8]) {
+        unsafe { (*addr_of_mut!(POOL)).initialize(c"tlsf", &mut *addr_of_mut!(POOL_MEMORY)).unwrap() };
+    }
+
+    #[thread(priority = 1, stack = 4096)]
+    fn user() {
+        let mut log = Vec::new();
+        let total = pool().stats().unwrap().free_bytes;
+
+        let memory = pool().allocate(100, false).unwrap().consume();
+        let stats = pool().stats().unwrap();
+        log.push(format!("allocations: {} requested: {}", stats.allocations, stats.requested_bytes));
+        log.push(format!("too large: {:?}", pool().allocate(8192, false).err()));
+        log.push(format!("failed: {}", pool().stats().unwrap().failed_allocations));
+        pool().release(memory).unwrap();
+        let stats = pool().stats().unwrap();
+        log.push(format!(
+            "released: {} peak: {} merged: {}",
+            stats.allocations,
+            stats.peak_requested_bytes,
+            stats.free_bytes == total && stats.free_blocks == 1
+        ));
+
+        unsafe {
+            let layout = Layout::from_size_align(16, 8).unwrap();
+            let ptr = pool().alloc(layout);
+            ptr.copy_from(b"0123456789abcdef".as_ptr(), 16);
+            let aligned = Layout::from_size_align(32, 256).unwrap();
+            let over_aligned = pool().alloc(aligned);
+            let grown = pool().realloc(ptr, layout, 512);
+            log.push(format!(
+                "over-aligned: {} kept: {}",
+                over_aligned as usize % 256,
+                core::slice::from_raw_parts(grown, 16) == b"0123456789abcdef"
+            ));
+            pool().dealloc(over_aligned, aligned);
+            pool().dealloc(grown, Layout::from_size_align(512, 8).unwrap());
+        }
+        log.push(format!("live: {}", pool().stats().unwrap().allocations));
+        *TLSF_RESULT.lock().unwrap() = log;
+    }
+
+    #[thread(priority = 2, stack = 4096)]
+    fn first() {
+        churn(1);
+    }
+
+    #[thread(priority = 2, stack = 4096)]
+    fn second() {
+        churn(2);
+    }
+}
+
+#[test]
+fn tlsf_pool_allocates_and_keeps_statistics() {
+    Harness::run(tlsf_app::start);
+    assert_eq!(
+        *TLSF_RESULT.lock().unwrap(),
+        [
+            "allocations: 1 requested: 100",
+            "too large: Some(NoMemory)",
+            "failed: 1",
+            "released: 0 peak: 100 merged: true",
+            "over-aligned: 0 kept: true",
+            "live: 0",
+        ]
+    );
+    assert!(CONTENTION_ERRORS.lock().unwrap().is_empty(), "{:?}", CONTENTION_ERRORS.lock().unwrap());
+    assert_eq!(pool().stats().unwrap().allocations, 0);
+}
@@ -0,0 +1,205 @@
+//! Block pools. Like in ThreadX each block is preceded by a pointer, which links the free
+//! blocks and points to the pool while the block is allocated.
+
+use core::ffi::c_void;
+use core::mem::size_of;
+use core::ptr;
+use std::collections::VecDeque;
+
+use threadx_sys::{
+    CHAR, TX_BLOCK_POOL, TX_CLEAR_ID, TX_DELETED, TX_FEATURE_NOT_ENABLED, TX_NO_MEMORY, TX_NO_WAIT,
+    TX_POOL_ERROR, TX_PTR_ERROR, TX_SIZE_ERROR, TX_SUCCESS, TX_THREAD, TX_WAIT_ERROR, UCHAR, UINT,
+    ULONG,
+};
+
+use crate::kernel::{
+    current_thread, lock, preempt, prioritize, put, suspend, timeout, Kernel, State, Tid, Wait,
+    TX_BLOCK_POOL_ID,
+};
+
+const HEADER: usize = size_of::<*mut u8>();
+
+pub(crate) struct BlockPool {
+    control_block: *mut TX_BLOCK_POOL,
+    /// First free block, including its header
+    free: *mut u8,
+    available: usize,
+    total: usize,
+    pub(crate) suspended: VecDeque<Tid>,
+}
+
+unsafe fn header(block: *mut u8) -> *mut *mut u8 {
+    block as *mut *mut u8
+}
+
+impl BlockPool {
+    unsafe fn allocate(&mut self) -> Option<*mut c_void> {
+        if self.free.is_null() {
+            return None;
+        }
+        let block = self.free;
+        self.free = *header(block);
+        *header(block) = self.control_block as *mut u8;
+        self.available -= 1;
+        Some(block.add(HEADER) as *mut c_void)
+    }
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn _tx_block_pool_create(
+    pool_ptr: *mut TX_BLOCK_POOL,
+    name_ptr: *mut CHAR,
+    block_size: ULONG,
+    pool_start: *mut c_void,
+    pool_size: ULONG,
+) -> UINT {
+    if pool_ptr.is_null() {
+        return TX_POOL_ERROR;
+    }
+    if pool_start.is_null() {
+        return TX_PTR_ERROR;
+    }
+    let block_size = (block_size as usize).div_ceil(size_of::<ULONG>()) * size_of::<ULONG>();
+    let total = pool_size as usize / (block_size + HEADER);
+    if total == 0 {
+        return TX_SIZE_ERROR;
+    }
+    let mut kernel = lock();
+    if kernel.block_pools.contains_key(&(pool_ptr as usize)) {
+        return TX_POOL_ERROR;
+    }
+    let start = pool_start as *mut u8;
+    for index in 0..total {
+        let next = if index + 1 < total { start.add((index + 1) * (block_size + HEADER)) } else { ptr::null_mut() };
+        *header(start.add(index * (block_size + HEADER))) = next;
+    }
+
+    ptr::write_bytes(pool_ptr, 0, 1);
+    let control_block = &mut *pool_ptr;
+    control_block.tx_block_pool_id = TX_BLOCK_POOL_ID;
+    control_block.tx_block_pool_name = name_ptr;
+    control_block.tx_block_pool_start = start as *mut UCHAR;
+    control_block.tx_block_pool_size = pool_size;
+    control_block.tx_block_pool_block_size = block_size as UINT;
+    control_block.tx_block_pool_total = total as UINT;
+    kernel.block_pools.insert(
+        pool_ptr as usize,
+        BlockPool { control_block: pool_ptr, free: start, available: total, total, suspended: VecDeque::new() },
+    );
+    TX_SUCCESS
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn _tx_block_pool_delete(pool_ptr: *mut TX_BLOCK_POOL) -> UINT {
+    let mut kernel = lock();
+    let Some(pool) = kernel.block_pools.remove(&(pool_ptr as usize)) else {
+        return TX_POOL_ERROR;
+    };
+    for tid in pool.suspended {
+        kernel.resume(tid, TX_DELETED);
+    }
+    (*pool.control_block).tx_block_pool_id = TX_CLEAR_ID as ULONG;
+    drop(preempt(kernel));
+    TX_SUCCESS
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn _tx_block_allocate(
+    pool_ptr: *mut TX_BLOCK_POOL,
+    block_ptr: *mut *mut c_void,
+    wait_option: ULONG,
+) -> UINT {
+    let key = pool_ptr as usize;
+    let mut kernel = lock();
+    let Some(pool) = kernel.block_pools.get_mut(&key) else {
+        return TX_POOL_ERROR;
+    };
+    if let Some(block) = pool.allocate() {
+        *block_ptr = block;
+        return TX_SUCCESS;
+    }
+    if wait_option == TX_NO_WAIT {
+        return TX_NO_MEMORY;
+    }
+    let Some(tid) = current_thread() else {
+        return TX_WAIT_ERROR;
+    };
+    pool.suspended.push_back(tid);
+    let wait = Wait::BlockPool { pool: key, block: block_ptr };
+    let (_kernel, status) = suspend(kernel, tid, State::Waiting(wait), timeout(wait_option));
+    status
+}
+
+/// The pool is found through the block header. A suspended thread gets the block directly.
+#[no_mangle]
+pub unsafe extern "C" fn _tx_block_release(block_ptr: *mut c_void) -> UINT {
+    if block_ptr.is_null() {
+        return TX_PTR_ERROR;
+    }
+    let block = (block_ptr as *mut u8).sub(HEADER);
+    let key = *header(block) as usize;
+    let mut kernel = lock();
+    let Some(pool) = kernel.block_pools.get_mut(&key) else {
+        return TX_PTR_ERROR;
+    };
+    match pool.suspended.pop_front() {
+        Some(tid) => {
+            if let State::Waiting(Wait::BlockPool { block, .. }) = kernel.threads[&tid].state {
+                *block = block_ptr;
+            }
+            kernel.resume(tid, TX_SUCCESS);
+        }
+        None => {
+            *header(block) = pool.free;
+            pool.free = block;
+            pool.available += 1;
+        }
+    }
+    drop(preempt(kernel));
+    TX_SUCCESS
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn _tx_block_pool_prioritize(pool_ptr: *mut TX_BLOCK_POOL) -> UINT {
+    let mut kernel = lock();
+    let Kernel { threads, block_pools, .. } = &mut *kernel;
+    let Some(pool) = block_pools.get_mut(&(pool_ptr as usize)) else {
+        return TX_POOL_ERROR;
+    };
+    prioritize(threads, &mut pool.suspended);
+    TX_SUCCESS
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn _tx_block_pool_info_get(
+    pool_ptr: *mut TX_BLOCK_POOL,
+    name: *mut *mut CHAR,
+    available_blocks: *mut ULONG,
+    total_blocks: *mut ULONG,
+    first_suspended: *mut *mut TX_THREAD,
+    suspended_count: *mut ULONG,
+    next_pool: *mut *mut TX_BLOCK_POOL,
+) -> UINT {
+    let kernel = lock();
+    let Some(pool) = kernel.block_pools.get(&(pool_ptr as usize)) else {
+        return TX_POOL_ERROR;
+    };
+    put(name, (*pool_ptr).tx_block_pool_name);
+    put(available_blocks, pool.available as ULONG);
+    put(total_blocks, pool.total as ULONG);
+    put(first_suspended, kernel.first_suspended(&pool.suspended));
+    put(suspended_count, pool.suspended.len() as ULONG);
+    put(next_pool, ptr::null_mut());
+    TX_SUCCESS
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn _tx_block_pool_performance_info_get(
+    _pool_ptr: *mut TX_BLOCK_POOL,
+    _allocates: *mut ULONG,
+    _releases: *mut ULONG,
+    _suspensions: *mut ULONG,
+    _timeouts: *mut ULONG,
+) -> UINT {
+    TX_FEATURE_NOT_ENABLED
+}
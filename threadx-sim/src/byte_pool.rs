@@ -0,0 +1,242 @@
+//! Byte pools. The blocks are kept in the pool memory with the ThreadX block headers:
+//! a pointer to the next block, followed by the owning pool or a free marker.
+
+use core::ffi::c_void;
+use core::mem::size_of;
+use core::ptr;
+use std::collections::VecDeque;
+
+use threadx_sys::{
+    CHAR, TX_BYTE_POOL, TX_CLEAR_ID, TX_DELETED, TX_NO_MEMORY, TX_NO_WAIT, TX_POOL_ERROR,
+    TX_PTR_ERROR, TX_SIZE_ERROR, TX_SUCCESS, TX_THREAD, TX_WAIT_ERROR, UINT, ULONG,
+};
+
+use crate::kernel::{
+    current_thread, lock, preempt, prioritize, put, suspend, timeout, Kernel, State, Tid, Wait,
+    TX_BYTE_POOL_ID,
+};
+
+/// ThreadX aligns the blocks to `ALIGN_TYPE`, which is a ULONG
+const ALIGN: usize = size_of::<ULONG>();
+const HEADER: usize = size_of::<*mut u8>() + size_of::<ULONG>();
+/// Owner field of a free block
+const FREE: ULONG = 0xFFFF_EEEE;
+/// Owner field of the block that marks the end of the pool
+const ALLOCATED: ULONG = 0xAAAA_AAAA;
+/// A block is only split if at least this much is left over
+const BLOCK_MIN: usize = 20;
+const POOL_MIN: usize = 100;
+
+pub(crate) struct BytePool {
+    control_block: *mut TX_BYTE_POOL,
+    start: *mut u8,
+    available: usize,
+    fragments: usize,
+    pub(crate) suspended: VecDeque<Tid>,
+}
+
+unsafe fn next(block: *mut u8) -> *mut u8 {
+    (block as *mut *mut u8).read()
+}
+
+unsafe fn set_next(block: *mut u8, next: *mut u8) {
+    (block as *mut *mut u8).write(next)
+}
+
+unsafe fn owner(block: *mut u8) -> ULONG {
+    (block.add(size_of::<*mut u8>()) as *mut ULONG).read()
+}
+
+unsafe fn set_owner(block: *mut u8, owner: ULONG) {
+    (block.add(size_of::<*mut u8>()) as *mut ULONG).write(owner)
+}
+
+impl BytePool {
+    /// First fit. Adjacent free blocks are merged while searching.
+    unsafe fn allocate(&mut self, size: ULONG) -> Option<*mut c_void> {
+        let size = (size as usize).div_ceil(ALIGN) * ALIGN;
+        let mut block = self.start;
+        loop {
+            if owner(block) == FREE {
+                while owner(next(block)) == FREE {
+                    set_next(block, next(next(block)));
+                    self.fragments -= 1;
+                }
+                let available = next(block) as usize - block as usize - HEADER;
+                if available >= size {
+                    if available - size >= BLOCK_MIN {
+                        let rest = block.add(HEADER + size);
+                        set_next(rest, next(block));
+                        set_owner(rest, FREE);
+                        set_next(block, rest);
+                        self.fragments += 1;
+                    }
+                    set_owner(block, self.control_block as usize as ULONG);
+                    self.available -= next(block) as usize - block as usize;
+                    return Some(block.add(HEADER) as *mut c_void);
+                }
+            }
+            block = next(block);
+            if block == self.start {
+                return None;
+            }
+        }
+    }
+}
+
+impl Kernel {
+    /// Hand released memory to the suspended threads in order, as long as it suffices
+    unsafe fn serve_byte_pool(&mut self, key: usize) {
+        while let Some(&tid) = self.byte_pools[&key].suspended.front() {
+            let State::Waiting(Wait::BytePool { size, memory, .. }) = self.threads[&tid].state else {
+                break;
+            };
+            let pool = self.byte_pools.get_mut(&key).unwrap();
+            let Some(allocated) = pool.allocate(size) else {
+                break;
+            };
+            *memory = allocated;
+            pool.suspended.pop_front();
+            self.resume(tid, TX_SUCCESS);
+        }
+    }
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn _tx_byte_pool_create(
+    pool_ptr: *mut TX_BYTE_POOL,
+    name_ptr: *mut CHAR,
+    pool_start: *mut c_void,
+    pool_size: ULONG,
+) -> UINT {
+    if pool_ptr.is_null() {
+        return TX_POOL_ERROR;
+    }
+    if pool_start.is_null() {
+        return TX_PTR_ERROR;
+    }
+    let size = pool_size as usize / ALIGN * ALIGN;
+    if size < POOL_MIN {
+        return TX_SIZE_ERROR;
+    }
+    let mut kernel = lock();
+    if kernel.byte_pools.contains_key(&(pool_ptr as usize)) {
+        return TX_POOL_ERROR;
+    }
+    let start = pool_start as *mut u8;
+    let end = start.add(size - HEADER);
+    set_next(start, end);
+    set_owner(start, FREE);
+    set_next(end, start);
+    set_owner(end, ALLOCATED);
+
+    ptr::write_bytes(pool_ptr, 0, 1);
+    let control_block = &mut *pool_ptr;
+    control_block.tx_byte_pool_id = TX_BYTE_POOL_ID;
+    control_block.tx_byte_pool_name = name_ptr;
+    control_block.tx_byte_pool_start = start;
+    control_block.tx_byte_pool_size = size as ULONG;
+    kernel.byte_pools.insert(
+        pool_ptr as usize,
+        BytePool { control_block: pool_ptr, start, available: size - 2 * HEADER, fragments: 2, suspended: VecDeque::new() },
+    );
+    TX_SUCCESS
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn _tx_byte_pool_delete(pool_ptr: *mut TX_BYTE_POOL) -> UINT {
+    let mut kernel = lock();
+    let Some(pool) = kernel.byte_pools.remove(&(pool_ptr as usize)) else {
+        return TX_POOL_ERROR;
+    };
+    for tid in pool.suspended {
+        kernel.resume(tid, TX_DELETED);
+    }
+    (*pool.control_block).tx_byte_pool_id = TX_CLEAR_ID as ULONG;
+    drop(preempt(kernel));
+    TX_SUCCESS
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn _tx_byte_allocate(
+    pool_ptr: *mut TX_BYTE_POOL,
+    memory_ptr: *mut *mut c_void,
+    memory_size: ULONG,
+    wait_option: ULONG,
+) -> UINT {
+    if memory_size == 0 {
+        return TX_SIZE_ERROR;
+    }
+    let key = pool_ptr as usize;
+    let mut kernel = lock();
+    let Some(pool) = kernel.byte_pools.get_mut(&key) else {
+        return TX_POOL_ERROR;
+    };
+    if let Some(memory) = pool.allocate(memory_size) {
+        *memory_ptr = memory;
+        return TX_SUCCESS;
+    }
+    if wait_option == TX_NO_WAIT {
+        return TX_NO_MEMORY;
+    }
+    let Some(tid) = current_thread() else {
+        return TX_WAIT_ERROR;
+    };
+    pool.suspended.push_back(tid);
+    let wait = Wait::BytePool { pool: key, size: memory_size, memory: memory_ptr };
+    let (_kernel, status) = suspend(kernel, tid, State::Waiting(wait), timeout(wait_option));
+    status
+}
+
+/// The pool is found through the owner field of the block header
+#[no_mangle]
+pub unsafe extern "C" fn _tx_byte_release(memory_ptr: *mut c_void) -> UINT {
+    if memory_ptr.is_null() {
+        return TX_PTR_ERROR;
+    }
+    let block = (memory_ptr as *mut u8).sub(HEADER);
+    let key = owner(block) as usize;
+    let mut kernel = lock();
+    let Some(pool) = kernel.byte_pools.get_mut(&key) else {
+        return TX_PTR_ERROR;
+    };
+    set_owner(block, FREE);
+    pool.available += next(block) as usize - block as usize;
+    kernel.serve_byte_pool(key);
+    drop(preempt(kernel));
+    TX_SUCCESS
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn _tx_byte_pool_prioritize(pool_ptr: *mut TX_BYTE_POOL) -> UINT {
+    let mut kernel = lock();
+    let Kernel { threads, byte_pools, .. } = &mut *kernel;
+    let Some(pool) = byte_pools.get_mut(&(pool_ptr as usize)) else {
+        return TX_POOL_ERROR;
+    };
+    prioritize(threads, &mut pool.suspended);
+    TX_SUCCESS
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn _tx_byte_pool_info_get(
+    pool_ptr: *mut TX_BYTE_POOL,
+    name: *mut *mut CHAR,
+    available_bytes: *mut ULONG,
+    fragments: *mut ULONG,
+    first_suspended: *mut *mut TX_THREAD,
+    suspended_count: *mut ULONG,
+    next_pool: *mut *mut TX_BYTE_POOL,
+) -> UINT {
+    let kernel = lock();
+    let Some(pool) = kernel.byte_pools.get(&(pool_ptr as usize)) else {
+        return TX_POOL_ERROR;
+    };
+    put(name, (*pool_ptr).tx_byte_pool_name);
+    put(available_bytes, pool.available as ULONG);
+    put(fragments, pool.fragments as ULONG);
+    put(first_suspended, kernel.first_suspended(&pool.suspended));
+    put(suspended_count, pool.suspended.len() as ULONG);
+    put(next_pool, ptr::null_mut());
+    TX_SUCCESS
+}
@@ -0,0 +1,156 @@
+//! Event flags groups
+
+use core::ptr;
+use std::collections::VecDeque;
+
+use threadx_sys::{
+    CHAR, TX_AND, TX_AND_CLEAR, TX_CLEAR_ID, TX_DELETED, TX_EVENT_FLAGS_GROUP, TX_GROUP_ERROR,
+    TX_NO_EVENTS, TX_NO_WAIT, TX_OPTION_ERROR, TX_OR, TX_OR_CLEAR, TX_SUCCESS, TX_WAIT_ERROR,
+    UINT, ULONG,
+};
+
+use crate::kernel::{current_thread, lock, preempt, suspend, timeout, State, Tid, Wait, TX_EVENT_FLAGS_ID};
+
+type SetNotify = unsafe extern "C" fn(*mut TX_EVENT_FLAGS_GROUP);
+
+pub(crate) struct EventFlagsGroup {
+    control_block: *mut TX_EVENT_FLAGS_GROUP,
+    current: ULONG,
+    pub(crate) suspended: VecDeque<Tid>,
+    set_notify: Option<SetNotify>,
+}
+
+impl EventFlagsGroup {
+    /// Try to satisfy a get request. Clears the requested flags if the option says so.
+    fn get(&mut self, requested: ULONG, option: UINT) -> Option<ULONG> {
+        let current = self.current;
+        let satisfied = match option {
+            TX_AND | TX_AND_CLEAR => current & requested == requested,
+            _ => current & requested != 0,
+        };
+        if !satisfied {
+            return None;
+        }
+        if option == TX_AND_CLEAR || option == TX_OR_CLEAR {
+            self.current &= !requested;
+        }
+        Some(current)
+    }
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn _tx_event_flags_create(group_ptr: *mut TX_EVENT_FLAGS_GROUP, name_ptr: *mut CHAR) -> UINT {
+    if group_ptr.is_null() {
+        return TX_GROUP_ERROR;
+    }
+    let mut kernel = lock();
+    if kernel.event_flags.contains_key(&(group_ptr as usize)) {
+        return TX_GROUP_ERROR;
+    }
+    ptr::write_bytes(group_ptr, 0, 1);
+    (*group_ptr).tx_event_flags_group_id = TX_EVENT_FLAGS_ID;
+    (*group_ptr).tx_event_flags_group_name = name_ptr;
+    kernel.event_flags.insert(
+        group_ptr as usize,
+        EventFlagsGroup { control_block: group_ptr, current: 0, suspended: VecDeque::new(), set_notify: None },
+    );
+    TX_SUCCESS
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn _tx_event_flags_delete(group_ptr: *mut TX_EVENT_FLAGS_GROUP) -> UINT {
+    let mut kernel = lock();
+    let Some(group) = kernel.event_flags.remove(&(group_ptr as usize)) else {
+        return TX_GROUP_ERROR;
+    };
+    for tid in group.suspended {
+        kernel.resume(tid, TX_DELETED);
+    }
+    (*group.control_block).tx_event_flags_group_id = TX_CLEAR_ID as ULONG;
+    drop(preempt(kernel));
+    TX_SUCCESS
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn _tx_event_flags_get(
+    group_ptr: *mut TX_EVENT_FLAGS_GROUP,
+    requested_flags: ULONG,
+    get_option: UINT,
+    actual_flags_ptr: *mut ULONG,
+    wait_option: ULONG,
+) -> UINT {
+    if ![TX_AND, TX_AND_CLEAR, TX_OR, TX_OR_CLEAR].contains(&get_option) {
+        return TX_OPTION_ERROR;
+    }
+    let key = group_ptr as usize;
+    let mut kernel = lock();
+    let Some(group) = kernel.event_flags.get_mut(&key) else {
+        return TX_GROUP_ERROR;
+    };
+    if let Some(actual) = group.get(requested_flags, get_option) {
+        *actual_flags_ptr = actual;
+        return TX_SUCCESS;
+    }
+    if wait_option == TX_NO_WAIT {
+        return TX_NO_EVENTS;
+    }
+    let Some(tid) = current_thread() else {
+        return TX_WAIT_ERROR;
+    };
+    group.suspended.push_back(tid);
+    let wait = Wait::EventFlags { group: key, requested: requested_flags, option: get_option, actual: actual_flags_ptr };
+    let (_kernel, status) = suspend(kernel, tid, State::Waiting(wait), timeout(wait_option));
+    status
+}
+
+/// `TX_AND` clears the flags that are not in `flags_to_set`, `TX_OR` sets them. Then the
+/// suspended threads are resumed in order if their request is satisfied.
+#[no_mangle]
+pub unsafe extern "C" fn _tx_event_flags_set(
+    group_ptr: *mut TX_EVENT_FLAGS_GROUP,
+    flags_to_set: ULONG,
+    set_option: UINT,
+) -> UINT {
+    let key = group_ptr as usize;
+    let mut kernel = lock();
+    let Some(group) = kernel.event_flags.get_mut(&key) else {
+        return TX_GROUP_ERROR;
+    };
+    match set_option {
+        TX_AND => group.current &= flags_to_set,
+        TX_OR => group.current |= flags_to_set,
+        _ => return TX_OPTION_ERROR,
+    }
+    for tid in group.suspended.clone() {
+        let State::Waiting(Wait::EventFlags { requested, option, actual, .. }) = kernel.threads[&tid].state else {
+            continue;
+        };
+        let group = kernel.event_flags.get_mut(&key).unwrap();
+        if let Some(flags) = group.get(requested, option) {
+            *actual = flags;
+            group.suspended.retain(|waiting| *waiting != tid);
+            kernel.resume(tid, TX_SUCCESS);
+        }
+    }
+    let set_notify = kernel.event_flags[&key].set_notify;
+    if let Some(set_notify) = set_notify {
+        drop(kernel);
+        set_notify(group_ptr);
+        kernel = lock();
+    }
+    drop(preempt(kernel));
+    TX_SUCCESS
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn _tx_event_flags_set_notify(
+    group_ptr: *mut TX_EVENT_FLAGS_GROUP,
+    events_set_notify: Option<SetNotify>,
+) -> UINT {
+    let mut kernel = lock();
+    let Some(group) = kernel.event_flags.get_mut(&(group_ptr as usize)) else {
+        return TX_GROUP_ERROR;
+    };
+    group.set_notify = events_set_notify;
+    TX_SUCCESS
+}
@@ -0,0 +1,102 @@
+//! Running applications from tests, with automatic or manual time
+
+use std::sync::{Mutex, MutexGuard, PoisonError};
+use std::thread::{self, JoinHandle};
+use std::time::Duration;
+
+use threadx_sys::TX_TIMER_TICKS_PER_SECOND;
+
+use crate::kernel::{lock, notify, wait, _tx_timer_interrupt};
+
+/// `Builder` keeps the application callbacks in statics, so only one application may be
+/// started at a time
+static SERIAL: Mutex<()> = Mutex::new(());
+
+/// An application running on a kernel thread whose time only advances when the test says
+/// so.
+///
+/// ```ignore
+/// let harness = Harness::start(app::start);
+/// harness.advance(Duration::from_millis(500));
+/// assert_eq!(*RECEIVED.lock().unwrap(), [1, 2]);
+/// ```
+///
+/// Each tick is a call to `_tx_timer_interrupt`, after which the harness waits until the
+/// threads and timers that the tick made ready have run and the application is idle again.
+/// Dropping the harness stops the kernel. Threads that are still suspended then are
+/// abandoned.
+pub struct Harness {
+    kernel: Option<JoinHandle<()>>,
+    _serial: MutexGuard<'static, ()>,
+}
+
+impl Harness {
+    /// Run an application to the end with automatic time, like calling `start` directly.
+    /// Serialized with the other applications started through the harness.
+    pub fn run(start: fn()) {
+        let _serial = serial();
+        start();
+    }
+
+    /// Start an application with manual time. `start` initializes the kernel, e.g. the
+    /// `start` function of an `app` module. Returns once the application is defined and
+    /// idle at tick 0.
+    pub fn start(start: fn()) -> Harness {
+        let serial = serial();
+        lock().manual_time = true;
+        let kernel = thread::Builder::new()
+            .name("kernel".into())
+            .spawn(start)
+            .expect("Unable to spawn the kernel thread");
+        let harness = Harness { kernel: Some(kernel), _serial: serial };
+        harness.settle();
+        harness
+    }
+
+    /// Advance the time by `duration`, rounded up to whole ticks like `TxTicks`
+    pub fn advance(&self, duration: Duration) {
+        let ticks = (duration.as_nanos() * TX_TIMER_TICKS_PER_SECOND as u128).div_ceil(1_000_000_000);
+        self.advance_ticks(ticks as u64);
+    }
+
+    /// Advance the time one tick at a time, letting the application settle after each
+    pub fn advance_ticks(&self, ticks: u64) {
+        for _ in 0..ticks {
+            unsafe { _tx_timer_interrupt() };
+            self.settle();
+        }
+    }
+
+    /// Ticks since the application was started
+    pub fn ticks(&self) -> u64 {
+        lock().ticks
+    }
+
+    /// Wait until nothing runs before the next tick
+    fn settle(&self) {
+        let mut kernel = lock();
+        while !kernel.is_idle() {
+            kernel = wait(kernel);
+        }
+    }
+}
+
+impl Drop for Harness {
+    fn drop(&mut self) {
+        let mut kernel = lock();
+        kernel.stop = true;
+        notify();
+        drop(kernel);
+        let result = self.kernel.take().unwrap().join();
+        lock().manual_time = false;
+        if let Err(panic) = result {
+            if !thread::panicking() {
+                std::panic::resume_unwind(panic);
+            }
+        }
+    }
+}
+
+fn serial() -> MutexGuard<'static, ()> {
+    SERIAL.lock().unwrap_or_else(PoisonError::into_inner)
+}
@@ -0,0 +1,565 @@
+//! Kernel state, scheduling and virtual time
+
+use core::ffi::c_void;
+use core::{mem, ptr};
+use std::cell::Cell;
+use std::collections::{BTreeMap, VecDeque};
+use std::sync::PoisonError;
+
+use threadx_sys::{
+    TX_INITIALIZE_IN_PROGRESS, TX_INITIALIZE_IS_FINISHED, TX_NOT_AVAILABLE, TX_NO_EVENTS,
+    TX_NO_INSTANCE, TX_NO_MEMORY, TX_QUEUE_EMPTY, TX_QUEUE_FULL, TX_SUCCESS, TX_THREAD,
+    TX_WAIT_FOREVER, UINT, ULONG,
+};
+
+use crate::block_pool::BlockPool;
+use crate::byte_pool::BytePool;
+use crate::event_flags::EventFlagsGroup;
+use crate::mutex::Mutex as TxMutex;
+use crate::queue::Queue;
+use crate::semaphore::Semaphore;
+use crate::sync::{thread, thread_local, Condvar, Mutex, MutexGuard};
+use crate::timer::Timer;
+
+/// Identifies a simulated thread. Unlike the address of the control block it is never
+/// reused, not even by the next kernel.
+pub(crate) type Tid = u64;
+
+/// Who holds the simulated CPU
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub(crate) enum Cpu {
+    /// The thread that entered the kernel. It runs the application definition and the
+    /// timer expirations, and advances the time when no thread is ready.
+    Kernel,
+    Thread(Tid),
+}
+
+/// What the calling OS thread is to the kernel
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub(crate) enum Context {
+    /// Initialization, or a thread that the kernel did not create
+    Other,
+    Thread(Tid),
+    /// A timer expiration function
+    Timer,
+}
+
+thread_local! {
+    // loom's thread_local! does not take const initializers
+    #[allow(clippy::missing_const_for_thread_local)]
+    static CONTEXT: Cell<Context> = Cell::new(Context::Other);
+}
+
+pub(crate) fn context() -> Context {
+    CONTEXT.with(|context| context.get())
+}
+
+pub(crate) fn set_context(context: Context) {
+    CONTEXT.with(|cell| cell.set(context))
+}
+
+/// The calling thread, if it is allowed to suspend
+pub(crate) fn current_thread() -> Option<Tid> {
+    match context() {
+        Context::Thread(tid) => Some(tid),
+        Context::Other | Context::Timer => None,
+    }
+}
+
+// Values of the ID field of the control blocks
+pub(crate) const TX_THREAD_ID: ULONG = 0x5448_5244;
+pub(crate) const TX_QUEUE_ID: ULONG = 0x5155_4555;
+pub(crate) const TX_SEMAPHORE_ID: ULONG = 0x5345_4D41;
+pub(crate) const TX_MUTEX_ID: ULONG = 0x4D55_5445;
+pub(crate) const TX_EVENT_FLAGS_ID: ULONG = 0x4456_444E;
+pub(crate) const TX_BYTE_POOL_ID: ULONG = 0x4259_5445;
+pub(crate) const TX_BLOCK_POOL_ID: ULONG = 0x424C_4F43;
+pub(crate) const TX_TIMER_ID: ULONG = 0x4154_494D;
+
+/// A service a thread is suspended on, with what the service needs to complete it
+pub(crate) enum Wait {
+    QueueReceive { queue: usize, destination: *mut ULONG },
+    QueueSend { queue: usize, message: Vec<ULONG>, front: bool },
+    Semaphore(usize),
+    Mutex(usize),
+    EventFlags { group: usize, requested: ULONG, option: UINT, actual: *mut ULONG },
+    BytePool { pool: usize, size: ULONG, memory: *mut *mut c_void },
+    BlockPool { pool: usize, block: *mut *mut c_void },
+}
+
+impl Wait {
+    /// What the service returns if the suspension times out
+    fn timeout_status(&self) -> UINT {
+        match self {
+            Wait::QueueReceive { .. } => TX_QUEUE_EMPTY,
+            Wait::QueueSend { .. } => TX_QUEUE_FULL,
+            Wait::Semaphore(_) => TX_NO_INSTANCE,
+            Wait::Mutex(_) => TX_NOT_AVAILABLE,
+            Wait::EventFlags { .. } => TX_NO_EVENTS,
+            Wait::BytePool { .. } | Wait::BlockPool { .. } => TX_NO_MEMORY,
+        }
+    }
+}
+
+pub(crate) enum State {
+    /// Ready or running
+    Ready,
+    Suspended,
+    Sleeping,
+    Waiting(Wait),
+    Completed,
+}
+
+pub(crate) struct Thread {
+    pub(crate) control_block: *mut TX_THREAD,
+    /// Priority including the priority inherited through mutexes
+    pub(crate) priority: UINT,
+    pub(crate) user_priority: UINT,
+    pub(crate) preempt_threshold: UINT,
+    pub(crate) state: State,
+    /// Order in the ready list of its priority
+    seq: i64,
+    /// Tick at which the sleep or suspension times out
+    deadline: Option<u64>,
+    /// Status that the last suspension ended with
+    status: UINT,
+    /// Suspended with `_tx_thread_suspend` while it was waiting or sleeping
+    pub(crate) delayed_suspend: bool,
+}
+
+pub(crate) struct Kernel {
+    pub(crate) cpu: Cpu,
+    /// Ticks since the kernel was entered
+    pub(crate) ticks: u64,
+    /// The time of `_tx_time_get`
+    clock: ULONG,
+    posture: UINT,
+    next_tid: Tid,
+    next_seq: i64,
+    front_seq: i64,
+    pub(crate) threads: BTreeMap<Tid, Thread>,
+    /// Threads by the address of their control block
+    pub(crate) thread_ids: BTreeMap<usize, Tid>,
+    // The other objects by the address of their control block
+    pub(crate) queues: BTreeMap<usize, Queue>,
+    pub(crate) semaphores: BTreeMap<usize, Semaphore>,
+    pub(crate) mutexes: BTreeMap<usize, TxMutex>,
+    pub(crate) event_flags: BTreeMap<usize, EventFlagsGroup>,
+    pub(crate) byte_pools: BTreeMap<usize, BytePool>,
+    pub(crate) block_pools: BTreeMap<usize, BlockPool>,
+    pub(crate) timers: BTreeMap<usize, Timer>,
+    /// Timers that expired and whose expiration function has not run yet
+    pub(crate) expired_timers: VecDeque<usize>,
+    /// Time only advances through `_tx_timer_interrupt`. Kept by `reset`.
+    pub(crate) manual_time: bool,
+    /// The kernel thread waits for the next tick with manual time
+    pub(crate) idle: bool,
+    /// Return from `_tx_initialize_kernel_enter` once idle, with manual time
+    pub(crate) stop: bool,
+}
+
+// The pointers refer to control blocks and memory that the application handed to the
+// kernel, and are only used with the kernel locked
+unsafe impl Send for Kernel {}
+
+pub(crate) type KernelGuard = MutexGuard<'static, Kernel>;
+
+#[cfg(not(loom))]
+static KERNEL: Mutex<Kernel> = Mutex::new(Kernel::new());
+/// Signalled when the CPU changes hands or a thread becomes ready
+#[cfg(not(loom))]
+static SCHEDULE: Condvar = Condvar::new();
+/// Held while a kernel runs. The tests of a crate run in parallel, their kernels in turn.
+#[cfg(not(loom))]
+static RUN: Mutex<()> = Mutex::new(());
+
+#[cfg(loom)]
+loom::lazy_static! {
+    static ref KERNEL: Mutex<Kernel> = Mutex::new(Kernel::new());
+    static ref SCHEDULE: Condvar = Condvar::new();
+}
+
+pub(crate) fn lock() -> KernelGuard {
+    KERNEL.lock().unwrap_or_else(PoisonError::into_inner)
+}
+
+// Kernel variables that threadx-rs reads directly
+
+#[no_mangle]
+pub static mut _tx_thread_system_state: ULONG = 0;
+#[no_mangle]
+pub static mut _tx_thread_system_stack_ptr: *mut c_void = ptr::null_mut();
+/// Never runs, `_tx_thread_identify` returns it in timer expiration functions like ThreadX
+#[no_mangle]
+pub static mut _tx_timer_thread: TX_THREAD = unsafe { mem::zeroed() };
+
+extern "C" {
+    fn tx_application_define(first_unused_memory: *mut c_void);
+}
+
+impl Kernel {
+    const fn new() -> Self {
+        Kernel {
+            cpu: Cpu::Kernel,
+            ticks: 0,
+            clock: 0,
+            posture: 0,
+            next_tid: 1,
+            next_seq: 0,
+            front_seq: 0,
+            threads: BTreeMap::new(),
+            thread_ids: BTreeMap::new(),
+            queues: BTreeMap::new(),
+            semaphores: BTreeMap::new(),
+            mutexes: BTreeMap::new(),
+            event_flags: BTreeMap::new(),
+            byte_pools: BTreeMap::new(),
+            block_pools: BTreeMap::new(),
+            timers: BTreeMap::new(),
+            expired_timers: VecDeque::new(),
+            manual_time: false,
+            idle: false,
+            stop: false,
+        }
+    }
+
+    /// Forget all objects. Threads that are still suspended find out that they are gone
+    /// and park for good.
+    fn reset(&mut self) {
+        *self = Kernel { next_tid: self.next_tid, manual_time: self.manual_time, ..Kernel::new() };
+        SCHEDULE.notify_all();
+    }
+
+    pub(crate) fn thread(&mut self, tid: Tid) -> &mut Thread {
+        self.threads.get_mut(&tid).expect("The thread does not exist")
+    }
+
+    pub(crate) fn thread_id(&self, control_block: *mut TX_THREAD) -> Option<Tid> {
+        self.thread_ids.get(&(control_block as usize)).copied()
+    }
+
+    /// Order for the back of a ready list, also used to order the timers
+    pub(crate) fn next_seq(&mut self) -> i64 {
+        self.next_seq += 1;
+        self.next_seq
+    }
+
+    pub(crate) fn create_thread(
+        &mut self,
+        control_block: *mut TX_THREAD,
+        priority: UINT,
+        preempt_threshold: UINT,
+        auto_start: bool,
+    ) -> Tid {
+        let tid = self.next_tid;
+        self.next_tid += 1;
+        let seq = self.next_seq();
+        self.threads.insert(
+            tid,
+            Thread {
+                control_block,
+                priority,
+                user_priority: priority,
+                preempt_threshold,
+                state: if auto_start { State::Ready } else { State::Suspended },
+                seq,
+                deadline: None,
+                status: TX_SUCCESS,
+                delayed_suspend: false,
+            },
+        );
+        self.thread_ids.insert(control_block as usize, tid);
+        SCHEDULE.notify_all();
+        tid
+    }
+
+    /// The control block of the first thread of a suspension list, for the info services
+    pub(crate) fn first_suspended(&self, suspended: &VecDeque<Tid>) -> *mut TX_THREAD {
+        suspended.front().map_or(ptr::null_mut(), |tid| self.threads[tid].control_block)
+    }
+
+    /// Nothing runs until the next tick: the kernel thread waits for it, no thread is
+    /// ready and all expired timers have run
+    #[cfg_attr(loom, allow(dead_code))]
+    pub(crate) fn is_idle(&self) -> bool {
+        self.idle && self.cpu == Cpu::Kernel && self.expired_timers.is_empty() && self.best_ready().is_none()
+    }
+
+    fn best_ready(&self) -> Option<Tid> {
+        self.threads
+            .iter()
+            .filter(|(_, thread)| matches!(thread.state, State::Ready))
+            .min_by_key(|(_, thread)| (thread.priority, thread.seq))
+            .map(|(tid, _)| *tid)
+    }
+
+    /// End the sleep or suspension of a thread with `status`. A suspension through
+    /// `_tx_thread_suspend` that came in the meantime takes effect now.
+    pub(crate) fn resume(&mut self, tid: Tid, status: UINT) {
+        let seq = self.next_seq();
+        let thread = self.thread(tid);
+        thread.status = status;
+        thread.deadline = None;
+        if mem::take(&mut thread.delayed_suspend) {
+            thread.state = State::Suspended;
+        } else {
+            thread.state = State::Ready;
+            thread.seq = seq;
+        }
+        SCHEDULE.notify_all();
+    }
+
+    /// Hand the CPU to the expired timers or the highest priority ready thread. Called by
+    /// the holder of the CPU when it gives it up.
+    pub(crate) fn dispatch(&mut self) {
+        self.cpu = match self.best_ready() {
+            Some(tid) if self.expired_timers.is_empty() => Cpu::Thread(tid),
+            _ => Cpu::Kernel,
+        };
+        SCHEDULE.notify_all();
+    }
+
+    /// Remove a thread from the suspension list of the object it waits on
+    fn cancel_wait(&mut self, tid: Tid, wait: &Wait) {
+        let suspended = match wait {
+            Wait::QueueReceive { queue, .. } | Wait::QueueSend { queue, .. } => {
+                self.queues.get_mut(queue).map(|queue| &mut queue.suspended)
+            }
+            Wait::Semaphore(semaphore) => self.semaphores.get_mut(semaphore).map(|semaphore| &mut semaphore.suspended),
+            Wait::Mutex(mutex) => self.mutexes.get_mut(mutex).map(|mutex| &mut mutex.suspended),
+            Wait::EventFlags { group, .. } => self.event_flags.get_mut(group).map(|group| &mut group.suspended),
+            Wait::BytePool { pool, .. } => self.byte_pools.get_mut(pool).map(|pool| &mut pool.suspended),
+            Wait::BlockPool { pool, .. } => self.block_pools.get_mut(pool).map(|pool| &mut pool.suspended),
+        };
+        if let Some(suspended) = suspended {
+            suspended.retain(|waiting| *waiting != tid);
+        }
+        if let Wait::Mutex(mutex) = wait {
+            self.mutex_waiters_changed(*mutex);
+        }
+    }
+
+    fn time_out(&mut self, tid: Tid) {
+        let status = match mem::replace(&mut self.thread(tid).state, State::Ready) {
+            State::Sleeping => TX_SUCCESS,
+            State::Waiting(wait) => {
+                self.cancel_wait(tid, &wait);
+                wait.timeout_status()
+            }
+            state => {
+                self.thread(tid).state = state;
+                return;
+            }
+        };
+        self.resume(tid, status);
+    }
+
+    /// Advance the time by `ticks`. Threads whose sleep or suspension timed out are
+    /// resumed and expired timers are queued for the kernel thread.
+    pub(crate) fn advance(&mut self, ticks: u64) {
+        self.ticks += ticks;
+        self.clock = self.clock.wrapping_add(ticks as ULONG);
+        let now = self.ticks;
+
+        let mut timed_out: Vec<(u64, Tid)> = self
+            .threads
+            .iter()
+            .filter_map(|(tid, thread)| thread.deadline.filter(|deadline| *deadline <= now).map(|deadline| (deadline, *tid)))
+            .collect();
+        timed_out.sort_unstable();
+        for (_, tid) in timed_out {
+            self.time_out(tid);
+        }
+
+        let mut expired: Vec<(u64, i64, usize)> = self
+            .timers
+            .iter()
+            .filter_map(|(key, timer)| {
+                timer.deadline.filter(|deadline| *deadline <= now).map(|deadline| (deadline, timer.seq, *key))
+            })
+            .collect();
+        expired.sort_unstable();
+        for (_, _, key) in expired {
+            self.timers.get_mut(&key).unwrap().expire(now);
+            self.expired_timers.push_back(key);
+        }
+        SCHEDULE.notify_all();
+    }
+
+    fn next_deadline(&self) -> Option<u64> {
+        let threads = self.threads.values().filter_map(|thread| thread.deadline);
+        let timers = self.timers.values().filter_map(|timer| timer.deadline);
+        threads.chain(timers).min()
+    }
+}
+
+/// Park an OS thread whose kernel has returned. It is never scheduled again.
+fn abandon() -> ! {
+    loop {
+        thread::park();
+    }
+}
+
+#[cfg_attr(loom, allow(dead_code))]
+pub(crate) fn notify() {
+    SCHEDULE.notify_all();
+}
+
+/// Wait for the scheduler to change something
+pub(crate) fn wait(kernel: KernelGuard) -> KernelGuard {
+    SCHEDULE.wait(kernel).unwrap_or_else(PoisonError::into_inner)
+}
+
+/// Wait until the scheduler hands the CPU to `tid`
+pub(crate) fn wait_for_cpu(mut kernel: KernelGuard, tid: Tid) -> KernelGuard {
+    while kernel.cpu != Cpu::Thread(tid) {
+        if !kernel.threads.contains_key(&tid) {
+            drop(kernel);
+            abandon();
+        }
+        kernel = wait(kernel);
+    }
+    kernel
+}
+
+/// The timeout of a suspension for a wait option, `None` to wait forever
+pub(crate) fn timeout(wait_option: ULONG) -> Option<ULONG> {
+    (wait_option != TX_WAIT_FOREVER).then_some(wait_option)
+}
+
+/// Suspend the calling thread in `state` until it is resumed or `timeout` ticks have
+/// passed. Returns the status it was resumed with.
+pub(crate) fn suspend(mut kernel: KernelGuard, tid: Tid, state: State, timeout: Option<ULONG>) -> (KernelGuard, UINT) {
+    let now = kernel.ticks;
+    let thread = kernel.thread(tid);
+    thread.state = state;
+    thread.deadline = timeout.map(|ticks| now + ticks as u64);
+    kernel.dispatch();
+    let mut kernel = wait_for_cpu(kernel, tid);
+    let status = kernel.thread(tid).status;
+    (kernel, status)
+}
+
+/// Called by the services after they made threads ready. If that preempts the calling
+/// thread, it gives up the CPU and this returns once it has it back.
+pub(crate) fn preempt(mut kernel: KernelGuard) -> KernelGuard {
+    let Context::Thread(tid) = context() else {
+        return kernel;
+    };
+    if kernel.cpu != Cpu::Thread(tid) {
+        return kernel;
+    }
+    let thread = &kernel.threads[&tid];
+    let threshold = thread.preempt_threshold.min(thread.priority);
+    let preempted = !kernel.expired_timers.is_empty()
+        || kernel
+            .threads
+            .iter()
+            .any(|(other, thread)| *other != tid && matches!(thread.state, State::Ready) && thread.priority < threshold);
+    if !preempted {
+        return kernel;
+    }
+    // A preempted thread stays at the front of its priority
+    kernel.front_seq -= 1;
+    let seq = kernel.front_seq;
+    kernel.thread(tid).seq = seq;
+    kernel.dispatch();
+    wait_for_cpu(kernel, tid)
+}
+
+/// Store `value` in an optional output argument of an info service
+pub(crate) unsafe fn put<T>(destination: *mut T, value: T) {
+    if !destination.is_null() {
+        destination.write(value);
+    }
+}
+
+/// Move the highest priority thread of a suspension list to the front, FIFO among equals
+pub(crate) fn prioritize(threads: &BTreeMap<Tid, Thread>, suspended: &mut VecDeque<Tid>) {
+    if let Some(index) = (0..suspended.len()).min_by_key(|index| threads[&suspended[*index]].priority) {
+        let tid = suspended.remove(index).unwrap();
+        suspended.push_front(tid);
+    }
+}
+
+/// Run timer expirations and threads on the thread that entered the kernel until there
+/// is nothing left to do
+fn run(mut kernel: KernelGuard) -> KernelGuard {
+    loop {
+        while kernel.cpu != Cpu::Kernel {
+            kernel = wait(kernel);
+        }
+        if let Some(key) = kernel.expired_timers.pop_front() {
+            let Some((function, input)) = kernel.timers.get(&key).map(|timer| (timer.function, timer.input)) else {
+                continue;
+            };
+            drop(kernel);
+            set_context(Context::Timer);
+            if let Some(function) = function {
+                unsafe { function(input) };
+            }
+            set_context(Context::Other);
+            kernel = lock();
+            continue;
+        }
+        if let Some(tid) = kernel.best_ready() {
+            kernel.cpu = Cpu::Thread(tid);
+            SCHEDULE.notify_all();
+            continue;
+        }
+        if kernel.manual_time {
+            if kernel.stop {
+                return kernel;
+            }
+            kernel.idle = true;
+            SCHEDULE.notify_all();
+            kernel = wait(kernel);
+            kernel.idle = false;
+            continue;
+        }
+        match kernel.next_deadline() {
+            Some(deadline) => {
+                let ticks = deadline.saturating_sub(kernel.ticks);
+                kernel.advance(ticks);
+            }
+            None => return kernel,
+        }
+    }
+}
+
+/// Define the application and run it. Returns when all threads have completed or are
+/// suspended without a timeout, and no timer is active. With manual time it returns when
+/// the harness stops it.
+#[no_mangle]
+pub unsafe extern "C" fn _tx_initialize_kernel_enter() {
+    #[cfg(not(loom))]
+    let _run = RUN.lock().unwrap_or_else(PoisonError::into_inner);
+    lock().reset();
+    ptr::addr_of_mut!(_tx_thread_system_state).write_volatile(TX_INITIALIZE_IN_PROGRESS);
+    tx_application_define(ptr::null_mut());
+    ptr::addr_of_mut!(_tx_thread_system_state).write_volatile(TX_INITIALIZE_IS_FINISHED);
+    run(lock()).reset();
+}
+
+/// One tick of the timer interrupt. A running thread is preempted by the threads and
+/// timers that this makes ready when it next calls a service.
+#[no_mangle]
+pub unsafe extern "C" fn _tx_timer_interrupt() {
+    lock().advance(1);
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn _tx_time_get() -> ULONG {
+    lock().clock
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn _tx_time_set(new_time: ULONG) {
+    lock().clock = new_time;
+}
+
+/// Interrupts are not simulated, only the posture is kept
+#[no_mangle]
+pub unsafe extern "C" fn _tx_thread_interrupt_control(new_posture: UINT) -> UINT {
+    mem::replace(&mut lock().posture, new_posture)
+}
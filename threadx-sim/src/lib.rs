@@ -0,0 +1,60 @@
+//! Host simulation of the ThreadX kernel services.
+//!
+//! The crate defines the `_tx_*` functions and kernel variables that threadx-rs uses, so
+//! the safe wrappers run on the host without ThreadX, a C compiler or a target. It is
+//! linked in by the `sim` feature of threadx-rs:
+//!
+//! ```text
+//! cargo test --no-default-features --features sim
+//! ```
+//!
+//! Every ThreadX thread is an OS thread, but only one of them runs at a time. They are
+//! scheduled under a global kernel lock by priority, FIFO within a priority, and respect
+//! the preemption threshold. A thread is only preempted when it calls a service, time
+//! slicing is not simulated and the stack given to `_tx_thread_create` is not used.
+//!
+//! Time is virtual. When no thread is ready, the kernel advances the tick to the next
+//! sleep, timeout or timer expiration, so a test that sleeps for an hour finishes at once.
+//! `_tx_timer_interrupt` advances it by one tick. Started through [`Harness::start`], time
+//! only advances when the test calls [`Harness::advance`], so it can check the state of
+//! the application at every point in time.
+//!
+//! `_tx_initialize_kernel_enter` calls `tx_application_define` and then runs the threads.
+//! Unlike ThreadX it returns when there is nothing left to do: all threads have completed
+//! or are suspended without a timeout, and no timer is active. Threads that are still
+//! suspended at that point are abandoned. Kernels run one after the other, so every test
+//! can start its own.
+//!
+//! Queue messages, byte pool blocks and block pool blocks live in the memory given to the
+//! services, with the ThreadX block headers, so code that works on that memory sees the
+//! same layout as on the target. The control blocks are filled in with the ID, the name
+//! and the creation parameters. Their run time fields are not kept up to date, use the
+//! info services instead.
+//!
+//! A panic in a thread, a timer or a notification aborts the process, because the
+//! entry functions are `extern "C"`. Record the results and assert after
+//! `_tx_initialize_kernel_enter` returned.
+//!
+//! With `--cfg loom` the kernel uses the loom primitives, so a whole application can be
+//! run inside `loom::model`. Every thread must complete for the model to finish. Miri can
+//! run the services, but not code that reads the kernel variables, such as
+//! `threadx_rs::thread::execution_context`, because Miri does not support extern statics.
+
+// ULONG is 64 bits on most hosts, but not on all of them
+#![allow(clippy::unnecessary_cast)]
+
+mod block_pool;
+mod byte_pool;
+mod event_flags;
+#[cfg(not(loom))]
+mod harness;
+mod kernel;
+mod mutex;
+mod queue;
+mod semaphore;
+mod sync;
+mod thread;
+mod timer;
+
+#[cfg(not(loom))]
+pub use harness::Harness;
@@ -0,0 +1,155 @@
+//! Recursive mutexes with optional priority inheritance
+
+use core::ptr;
+use std::collections::VecDeque;
+
+use threadx_sys::{
+    CHAR, TX_CLEAR_ID, TX_DELETED, TX_INHERIT, TX_MUTEX, TX_MUTEX_ERROR, TX_NOT_AVAILABLE,
+    TX_NOT_OWNED, TX_NO_WAIT, TX_SUCCESS, TX_WAIT_ERROR, UINT, ULONG,
+};
+
+use crate::kernel::{
+    context, lock, preempt, prioritize, suspend, timeout, Context, Kernel, State, Tid, Wait,
+    TX_MUTEX_ID,
+};
+
+pub(crate) struct Mutex {
+    control_block: *mut TX_MUTEX,
+    inherit: bool,
+    owner: Option<Context>,
+    ownership_count: UINT,
+    pub(crate) suspended: VecDeque<Tid>,
+}
+
+impl Kernel {
+    /// The priority of a thread: its own, or the highest priority of the threads that
+    /// wait for the inheriting mutexes it owns
+    fn inherited_priority(&self, tid: Tid) -> UINT {
+        self.mutexes
+            .values()
+            .filter(|mutex| mutex.inherit && mutex.owner == Some(Context::Thread(tid)))
+            .flat_map(|mutex| mutex.suspended.iter())
+            .map(|waiting| self.threads[waiting].priority)
+            .fold(self.threads[&tid].user_priority, UINT::min)
+    }
+
+    fn update_priority(&mut self, tid: Tid) {
+        let priority = self.inherited_priority(tid);
+        self.thread(tid).priority = priority;
+    }
+
+    /// Called when a thread stopped waiting for `mutex` without getting it
+    pub(crate) fn mutex_waiters_changed(&mut self, mutex: usize) {
+        if let Some(Context::Thread(owner)) = self.mutexes.get(&mutex).and_then(|mutex| mutex.owner) {
+            self.update_priority(owner);
+        }
+    }
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn _tx_mutex_create(mutex_ptr: *mut TX_MUTEX, name_ptr: *mut CHAR, inherit: UINT) -> UINT {
+    if mutex_ptr.is_null() {
+        return TX_MUTEX_ERROR;
+    }
+    let mut kernel = lock();
+    if kernel.mutexes.contains_key(&(mutex_ptr as usize)) {
+        return TX_MUTEX_ERROR;
+    }
+    ptr::write_bytes(mutex_ptr, 0, 1);
+    (*mutex_ptr).tx_mutex_id = TX_MUTEX_ID;
+    (*mutex_ptr).tx_mutex_name = name_ptr;
+    (*mutex_ptr).tx_mutex_inherit = inherit;
+    kernel.mutexes.insert(
+        mutex_ptr as usize,
+        Mutex {
+            control_block: mutex_ptr,
+            inherit: inherit == TX_INHERIT,
+            owner: None,
+            ownership_count: 0,
+            suspended: VecDeque::new(),
+        },
+    );
+    TX_SUCCESS
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn _tx_mutex_delete(mutex_ptr: *mut TX_MUTEX) -> UINT {
+    let mut kernel = lock();
+    let Some(mutex) = kernel.mutexes.remove(&(mutex_ptr as usize)) else {
+        return TX_MUTEX_ERROR;
+    };
+    for tid in mutex.suspended {
+        kernel.resume(tid, TX_DELETED);
+    }
+    if let Some(Context::Thread(owner)) = mutex.owner {
+        kernel.update_priority(owner);
+    }
+    (*mutex.control_block).tx_mutex_id = TX_CLEAR_ID as ULONG;
+    drop(preempt(kernel));
+    TX_SUCCESS
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn _tx_mutex_get(mutex_ptr: *mut TX_MUTEX, wait_option: ULONG) -> UINT {
+    let key = mutex_ptr as usize;
+    let caller = context();
+    let mut kernel = lock();
+    let Some(mutex) = kernel.mutexes.get_mut(&key) else {
+        return TX_MUTEX_ERROR;
+    };
+    match mutex.owner {
+        None => {
+            mutex.owner = Some(caller);
+            mutex.ownership_count = 1;
+            return TX_SUCCESS;
+        }
+        Some(owner) if owner == caller => {
+            mutex.ownership_count += 1;
+            return TX_SUCCESS;
+        }
+        Some(_) if wait_option == TX_NO_WAIT => return TX_NOT_AVAILABLE,
+        Some(_) => {}
+    }
+    let Context::Thread(tid) = caller else {
+        return TX_WAIT_ERROR;
+    };
+    mutex.suspended.push_back(tid);
+    kernel.mutex_waiters_changed(key);
+    let (_kernel, status) = suspend(kernel, tid, State::Waiting(Wait::Mutex(key)), timeout(wait_option));
+    status
+}
+
+/// With priority inheritance the mutex goes to the highest priority waiting thread,
+/// otherwise to the first
+#[no_mangle]
+pub unsafe extern "C" fn _tx_mutex_put(mutex_ptr: *mut TX_MUTEX) -> UINT {
+    let key = mutex_ptr as usize;
+    let caller = context();
+    let mut kernel = lock();
+    let Kernel { threads, mutexes, .. } = &mut *kernel;
+    let Some(mutex) = mutexes.get_mut(&key) else {
+        return TX_MUTEX_ERROR;
+    };
+    if mutex.owner != Some(caller) {
+        return TX_NOT_OWNED;
+    }
+    mutex.ownership_count -= 1;
+    if mutex.ownership_count != 0 {
+        return TX_SUCCESS;
+    }
+    if mutex.inherit {
+        prioritize(threads, &mut mutex.suspended);
+    }
+    let next = mutex.suspended.pop_front();
+    mutex.owner = next.map(Context::Thread);
+    mutex.ownership_count = next.map_or(0, |_| 1);
+    if let Context::Thread(tid) = caller {
+        kernel.update_priority(tid);
+    }
+    if let Some(next) = next {
+        kernel.update_priority(next);
+        kernel.resume(next, TX_SUCCESS);
+    }
+    drop(preempt(kernel));
+    TX_SUCCESS
+}
@@ -0,0 +1,296 @@
+//! Message queues. The messages are kept in the memory given to `_tx_queue_create`.
+
+use core::ffi::c_void;
+use core::ptr;
+use std::collections::VecDeque;
+
+use threadx_sys::{
+    CHAR, TX_CLEAR_ID, TX_DELETED, TX_FEATURE_NOT_ENABLED, TX_NO_WAIT, TX_PTR_ERROR, TX_QUEUE,
+    TX_QUEUE_EMPTY, TX_QUEUE_ERROR, TX_QUEUE_FULL, TX_SIZE_ERROR, TX_SUCCESS, TX_WAIT_ERROR, UINT,
+    ULONG,
+};
+
+use crate::kernel::{
+    current_thread, lock, preempt, prioritize, put, suspend, timeout, Kernel, State, Tid, Wait,
+    TX_QUEUE_ID,
+};
+
+type SendNotify = unsafe extern "C" fn(*mut TX_QUEUE);
+
+pub(crate) struct Queue {
+    control_block: *mut TX_QUEUE,
+    /// Message size in words
+    message_size: usize,
+    capacity: usize,
+    start: *mut ULONG,
+    /// Slot of the oldest message
+    read: usize,
+    enqueued: usize,
+    /// Receivers while the queue is empty, senders while it is full
+    pub(crate) suspended: VecDeque<Tid>,
+    send_notify: Option<SendNotify>,
+}
+
+impl Queue {
+    fn slot(&self, index: usize) -> *mut ULONG {
+        unsafe { self.start.add((index % self.capacity) * self.message_size) }
+    }
+
+    unsafe fn push(&mut self, message: *const ULONG, front: bool) {
+        let slot = if front {
+            self.read = (self.read + self.capacity - 1) % self.capacity;
+            self.read
+        } else {
+            self.read + self.enqueued
+        };
+        ptr::copy_nonoverlapping(message, self.slot(slot), self.message_size);
+        self.enqueued += 1;
+    }
+
+    unsafe fn pop(&mut self, destination: *mut ULONG) {
+        ptr::copy_nonoverlapping(self.slot(self.read), destination, self.message_size);
+        self.read = (self.read + 1) % self.capacity;
+        self.enqueued -= 1;
+    }
+}
+
+impl Kernel {
+    /// The first thread suspended on the queue, if it waits to receive
+    fn waiting_receiver(&self, queue: &Queue) -> Option<(Tid, *mut ULONG)> {
+        let tid = *queue.suspended.front()?;
+        match self.threads[&tid].state {
+            State::Waiting(Wait::QueueReceive { destination, .. }) => Some((tid, destination)),
+            _ => None,
+        }
+    }
+
+    /// Move the message of the first suspended sender into the queue
+    unsafe fn accept_waiting_sender(&mut self, key: usize) {
+        let queue = &self.queues[&key];
+        let Some(&tid) = queue.suspended.front() else {
+            return;
+        };
+        let State::Waiting(Wait::QueueSend { message, front, .. }) = &self.threads[&tid].state else {
+            return;
+        };
+        let (message, front) = (message.as_ptr(), *front);
+        let queue = self.queues.get_mut(&key).unwrap();
+        queue.push(message, front);
+        queue.suspended.pop_front();
+        self.resume(tid, TX_SUCCESS);
+    }
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn _tx_queue_create(
+    queue_ptr: *mut TX_QUEUE,
+    name_ptr: *mut CHAR,
+    message_size: UINT,
+    queue_start: *mut c_void,
+    queue_size: ULONG,
+) -> UINT {
+    if queue_ptr.is_null() {
+        return TX_QUEUE_ERROR;
+    }
+    if queue_start.is_null() {
+        return TX_PTR_ERROR;
+    }
+    if !(1..=16).contains(&message_size) {
+        return TX_SIZE_ERROR;
+    }
+    let capacity = queue_size as usize / (message_size as usize * core::mem::size_of::<ULONG>());
+    if capacity == 0 {
+        return TX_SIZE_ERROR;
+    }
+    let mut kernel = lock();
+    if kernel.queues.contains_key(&(queue_ptr as usize)) {
+        return TX_QUEUE_ERROR;
+    }
+    ptr::write_bytes(queue_ptr, 0, 1);
+    let control_block = &mut *queue_ptr;
+    control_block.tx_queue_id = TX_QUEUE_ID;
+    control_block.tx_queue_name = name_ptr;
+    control_block.tx_queue_message_size = message_size;
+    control_block.tx_queue_capacity = capacity as UINT;
+    control_block.tx_queue_start = queue_start as *mut ULONG;
+    control_block.tx_queue_end = (queue_start as *mut ULONG).add(capacity * message_size as usize);
+    kernel.queues.insert(
+        queue_ptr as usize,
+        Queue {
+            control_block: queue_ptr,
+            message_size: message_size as usize,
+            capacity,
+            start: queue_start as *mut ULONG,
+            read: 0,
+            enqueued: 0,
+            suspended: VecDeque::new(),
+            send_notify: None,
+        },
+    );
+    TX_SUCCESS
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn _tx_queue_delete(queue_ptr: *mut TX_QUEUE) -> UINT {
+    let mut kernel = lock();
+    let Some(queue) = kernel.queues.remove(&(queue_ptr as usize)) else {
+        return TX_QUEUE_ERROR;
+    };
+    for tid in queue.suspended {
+        kernel.resume(tid, TX_DELETED);
+    }
+    (*queue.control_block).tx_queue_id = TX_CLEAR_ID as ULONG;
+    drop(preempt(kernel));
+    TX_SUCCESS
+}
+
+/// Discards the messages. Suspended senders are resumed as if their message was sent.
+#[no_mangle]
+pub unsafe extern "C" fn _tx_queue_flush(queue_ptr: *mut TX_QUEUE) -> UINT {
+    let mut kernel = lock();
+    let Some(queue) = kernel.queues.get_mut(&(queue_ptr as usize)) else {
+        return TX_QUEUE_ERROR;
+    };
+    queue.read = 0;
+    if queue.enqueued != 0 {
+        queue.enqueued = 0;
+        for tid in core::mem::take(&mut queue.suspended) {
+            kernel.resume(tid, TX_SUCCESS);
+        }
+    }
+    drop(preempt(kernel));
+    TX_SUCCESS
+}
+
+unsafe fn send(queue_ptr: *mut TX_QUEUE, source_ptr: *mut c_void, wait_option: ULONG, front: bool) -> UINT {
+    let key = queue_ptr as usize;
+    let mut kernel = lock();
+    let Some(queue) = kernel.queues.get(&key) else {
+        return TX_QUEUE_ERROR;
+    };
+    let message = source_ptr as *const ULONG;
+    if let Some((tid, destination)) = kernel.waiting_receiver(queue) {
+        ptr::copy_nonoverlapping(message, destination, queue.message_size);
+        kernel.queues.get_mut(&key).unwrap().suspended.pop_front();
+        kernel.resume(tid, TX_SUCCESS);
+    } else if queue.enqueued < queue.capacity {
+        kernel.queues.get_mut(&key).unwrap().push(message, front);
+    } else if wait_option == TX_NO_WAIT {
+        return TX_QUEUE_FULL;
+    } else {
+        let Some(tid) = current_thread() else {
+            return TX_WAIT_ERROR;
+        };
+        let message = core::slice::from_raw_parts(message, queue.message_size).to_vec();
+        let queue = kernel.queues.get_mut(&key).unwrap();
+        if front {
+            queue.suspended.push_front(tid);
+        } else {
+            queue.suspended.push_back(tid);
+        }
+        let wait = Wait::QueueSend { queue: key, message, front };
+        let (_kernel, status) = suspend(kernel, tid, State::Waiting(wait), timeout(wait_option));
+        return status;
+    }
+    let send_notify = kernel.queues[&key].send_notify;
+    if let Some(send_notify) = send_notify {
+        drop(kernel);
+        send_notify(queue_ptr);
+        kernel = lock();
+    }
+    drop(preempt(kernel));
+    TX_SUCCESS
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn _tx_queue_send(queue_ptr: *mut TX_QUEUE, source_ptr: *mut c_void, wait_option: ULONG) -> UINT {
+    send(queue_ptr, source_ptr, wait_option, false)
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn _tx_queue_front_send(queue_ptr: *mut TX_QUEUE, source_ptr: *mut c_void, wait_option: ULONG) -> UINT {
+    send(queue_ptr, source_ptr, wait_option, true)
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn _tx_queue_receive(queue_ptr: *mut TX_QUEUE, destination_ptr: *mut c_void, wait_option: ULONG) -> UINT {
+    let key = queue_ptr as usize;
+    let mut kernel = lock();
+    let Some(queue) = kernel.queues.get_mut(&key) else {
+        return TX_QUEUE_ERROR;
+    };
+    if queue.enqueued != 0 {
+        queue.pop(destination_ptr as *mut ULONG);
+        kernel.accept_waiting_sender(key);
+        drop(preempt(kernel));
+        return TX_SUCCESS;
+    }
+    if wait_option == TX_NO_WAIT {
+        return TX_QUEUE_EMPTY;
+    }
+    let Some(tid) = current_thread() else {
+        return TX_WAIT_ERROR;
+    };
+    queue.suspended.push_back(tid);
+    let wait = Wait::QueueReceive { queue: key, destination: destination_ptr as *mut ULONG };
+    let (_kernel, status) = suspend(kernel, tid, State::Waiting(wait), timeout(wait_option));
+    status
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn _tx_queue_prioritize(queue_ptr: *mut TX_QUEUE) -> UINT {
+    let mut kernel = lock();
+    let Kernel { threads, queues, .. } = &mut *kernel;
+    let Some(queue) = queues.get_mut(&(queue_ptr as usize)) else {
+        return TX_QUEUE_ERROR;
+    };
+    prioritize(threads, &mut queue.suspended);
+    TX_SUCCESS
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn _tx_queue_send_notify(queue_ptr: *mut TX_QUEUE, queue_send_notify: Option<SendNotify>) -> UINT {
+    let mut kernel = lock();
+    let Some(queue) = kernel.queues.get_mut(&(queue_ptr as usize)) else {
+        return TX_QUEUE_ERROR;
+    };
+    queue.send_notify = queue_send_notify;
+    TX_SUCCESS
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn _tx_queue_info_get(
+    queue_ptr: *mut TX_QUEUE,
+    name: *mut *mut CHAR,
+    enqueued: *mut ULONG,
+    available_storage: *mut ULONG,
+    first_suspended: *mut *mut threadx_sys::TX_THREAD,
+    suspended_count: *mut ULONG,
+    next_queue: *mut *mut TX_QUEUE,
+) -> UINT {
+    let kernel = lock();
+    let Some(queue) = kernel.queues.get(&(queue_ptr as usize)) else {
+        return TX_QUEUE_ERROR;
+    };
+    put(name, (*queue_ptr).tx_queue_name);
+    put(enqueued, queue.enqueued as ULONG);
+    put(available_storage, (queue.capacity - queue.enqueued) as ULONG);
+    put(first_suspended, kernel.first_suspended(&queue.suspended));
+    put(suspended_count, queue.suspended.len() as ULONG);
+    put(next_queue, ptr::null_mut());
+    TX_SUCCESS
+}
+
+/// The sim is built with the default configuration, without performance counters
+#[no_mangle]
+pub unsafe extern "C" fn _tx_queue_performance_info_get(
+    _queue_ptr: *mut TX_QUEUE,
+    _messages_sent: *mut ULONG,
+    _messages_received: *mut ULONG,
+    _empty_suspensions: *mut ULONG,
+    _full_suspensions: *mut ULONG,
+    _full_errors: *mut ULONG,
+    _timeouts: *mut ULONG,
+) -> UINT {
+    TX_FEATURE_NOT_ENABLED
+}
@@ -0,0 +1,143 @@
+//! Counting semaphores
+
+use core::ptr;
+use std::collections::VecDeque;
+
+use threadx_sys::{
+    CHAR, TX_CLEAR_ID, TX_DELETED, TX_NO_INSTANCE, TX_NO_WAIT, TX_SEMAPHORE, TX_SEMAPHORE_ERROR,
+    TX_SUCCESS, TX_THREAD, TX_WAIT_ERROR, UINT, ULONG,
+};
+
+use crate::kernel::{
+    current_thread, lock, preempt, prioritize, put, suspend, timeout, Kernel, State, Tid, Wait,
+    TX_SEMAPHORE_ID,
+};
+
+type PutNotify = unsafe extern "C" fn(*mut TX_SEMAPHORE);
+
+pub(crate) struct Semaphore {
+    control_block: *mut TX_SEMAPHORE,
+    count: ULONG,
+    pub(crate) suspended: VecDeque<Tid>,
+    put_notify: Option<PutNotify>,
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn _tx_semaphore_create(semaphore_ptr: *mut TX_SEMAPHORE, name_ptr: *mut CHAR, initial_count: ULONG) -> UINT {
+    if semaphore_ptr.is_null() {
+        return TX_SEMAPHORE_ERROR;
+    }
+    let mut kernel = lock();
+    if kernel.semaphores.contains_key(&(semaphore_ptr as usize)) {
+        return TX_SEMAPHORE_ERROR;
+    }
+    ptr::write_bytes(semaphore_ptr, 0, 1);
+    (*semaphore_ptr).tx_semaphore_id = TX_SEMAPHORE_ID;
+    (*semaphore_ptr).tx_semaphore_name = name_ptr;
+    kernel.semaphores.insert(
+        semaphore_ptr as usize,
+        Semaphore { control_block: semaphore_ptr, count: initial_count, suspended: VecDeque::new(), put_notify: None },
+    );
+    TX_SUCCESS
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn _tx_semaphore_delete(semaphore_ptr: *mut TX_SEMAPHORE) -> UINT {
+    let mut kernel = lock();
+    let Some(semaphore) = kernel.semaphores.remove(&(semaphore_ptr as usize)) else {
+        return TX_SEMAPHORE_ERROR;
+    };
+    for tid in semaphore.suspended {
+        kernel.resume(tid, TX_DELETED);
+    }
+    (*semaphore.control_block).tx_semaphore_id = TX_CLEAR_ID as ULONG;
+    drop(preempt(kernel));
+    TX_SUCCESS
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn _tx_semaphore_get(semaphore_ptr: *mut TX_SEMAPHORE, wait_option: ULONG) -> UINT {
+    let key = semaphore_ptr as usize;
+    let mut kernel = lock();
+    let Some(semaphore) = kernel.semaphores.get_mut(&key) else {
+        return TX_SEMAPHORE_ERROR;
+    };
+    if semaphore.count != 0 {
+        semaphore.count -= 1;
+        return TX_SUCCESS;
+    }
+    if wait_option == TX_NO_WAIT {
+        return TX_NO_INSTANCE;
+    }
+    let Some(tid) = current_thread() else {
+        return TX_WAIT_ERROR;
+    };
+    semaphore.suspended.push_back(tid);
+    let (_kernel, status) = suspend(kernel, tid, State::Waiting(Wait::Semaphore(key)), timeout(wait_option));
+    status
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn _tx_semaphore_put(semaphore_ptr: *mut TX_SEMAPHORE) -> UINT {
+    let mut kernel = lock();
+    let Some(semaphore) = kernel.semaphores.get_mut(&(semaphore_ptr as usize)) else {
+        return TX_SEMAPHORE_ERROR;
+    };
+    match semaphore.suspended.pop_front() {
+        Some(tid) => kernel.resume(tid, TX_SUCCESS),
+        None => semaphore.count = semaphore.count.wrapping_add(1),
+    }
+    let put_notify = kernel.semaphores[&(semaphore_ptr as usize)].put_notify;
+    if let Some(put_notify) = put_notify {
+        drop(kernel);
+        put_notify(semaphore_ptr);
+        kernel = lock();
+    }
+    drop(preempt(kernel));
+    TX_SUCCESS
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn _tx_semaphore_prioritize(semaphore_ptr: *mut TX_SEMAPHORE) -> UINT {
+    let mut kernel = lock();
+    let Kernel { threads, semaphores, .. } = &mut *kernel;
+    let Some(semaphore) = semaphores.get_mut(&(semaphore_ptr as usize)) else {
+        return TX_SEMAPHORE_ERROR;
+    };
+    prioritize(threads, &mut semaphore.suspended);
+    TX_SUCCESS
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn _tx_semaphore_put_notify(
+    semaphore_ptr: *mut TX_SEMAPHORE,
+    semaphore_put_notify: Option<PutNotify>,
+) -> UINT {
+    let mut kernel = lock();
+    let Some(semaphore) = kernel.semaphores.get_mut(&(semaphore_ptr as usize)) else {
+        return TX_SEMAPHORE_ERROR;
+    };
+    semaphore.put_notify = semaphore_put_notify;
+    TX_SUCCESS
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn _tx_semaphore_info_get(
+    semaphore_ptr: *mut TX_SEMAPHORE,
+    name: *mut *mut CHAR,
+    current_value: *mut ULONG,
+    first_suspended: *mut *mut TX_THREAD,
+    suspended_count: *mut ULONG,
+    next_semaphore: *mut *mut TX_SEMAPHORE,
+) -> UINT {
+    let kernel = lock();
+    let Some(semaphore) = kernel.semaphores.get(&(semaphore_ptr as usize)) else {
+        return TX_SEMAPHORE_ERROR;
+    };
+    put(name, (*semaphore_ptr).tx_semaphore_name);
+    put(current_value, semaphore.count);
+    put(first_suspended, kernel.first_suspended(&semaphore.suspended));
+    put(suspended_count, semaphore.suspended.len() as ULONG);
+    put(next_semaphore, ptr::null_mut());
+    TX_SUCCESS
+}
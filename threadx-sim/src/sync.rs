@@ -0,0 +1,13 @@
+//! Synchronization primitives of the kernel, taken from loom when built with `--cfg loom`
+
+#[cfg(not(loom))]
+pub(crate) use std::{
+    sync::{Condvar, Mutex, MutexGuard},
+    thread, thread_local,
+};
+
+#[cfg(loom)]
+pub(crate) use loom::{
+    sync::{Condvar, Mutex, MutexGuard},
+    thread, thread_local,
+};
@@ -0,0 +1,170 @@
+//! Thread services. Every thread runs on its own OS thread once the scheduler hands it
+//! the CPU.
+
+use core::ffi::{c_void, CStr};
+use core::ptr;
+
+use threadx_sys::{
+    CHAR, TX_AUTO_START, TX_CALLER_ERROR, TX_CLEAR_ID, TX_DELETE_ERROR, TX_MAX_PRIORITIES,
+    TX_MINIMUM_STACK, TX_PRIORITY_ERROR, TX_PTR_ERROR, TX_RESUME_ERROR, TX_SIZE_ERROR,
+    TX_SUCCESS, TX_SUSPEND_ERROR, TX_THREAD, TX_THREAD_ERROR, TX_THRESH_ERROR, UINT, ULONG,
+};
+
+use crate::kernel::{
+    context, current_thread, lock, preempt, set_context, suspend, wait_for_cpu, Context, Cpu,
+    State, Tid, TX_THREAD_ID, _tx_timer_thread,
+};
+use crate::sync::thread;
+
+type ThreadEntry = unsafe extern "C" fn(ULONG);
+
+fn run_thread(tid: Tid, entry: ThreadEntry, entry_input: ULONG) {
+    drop(wait_for_cpu(lock(), tid));
+    set_context(Context::Thread(tid));
+    unsafe { entry(entry_input) };
+    let mut kernel = lock();
+    kernel.thread(tid).state = State::Completed;
+    kernel.dispatch();
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn _tx_thread_create(
+    thread_ptr: *mut TX_THREAD,
+    name_ptr: *mut CHAR,
+    entry_function: Option<ThreadEntry>,
+    entry_input: ULONG,
+    stack_start: *mut c_void,
+    stack_size: ULONG,
+    priority: UINT,
+    preempt_threshold: UINT,
+    time_slice: ULONG,
+    auto_start: UINT,
+) -> UINT {
+    let Some(entry) = entry_function else {
+        return TX_PTR_ERROR;
+    };
+    if thread_ptr.is_null() {
+        return TX_THREAD_ERROR;
+    }
+    if stack_start.is_null() {
+        return TX_PTR_ERROR;
+    }
+    if stack_size < TX_MINIMUM_STACK as ULONG {
+        return TX_SIZE_ERROR;
+    }
+    if priority >= TX_MAX_PRIORITIES {
+        return TX_PRIORITY_ERROR;
+    }
+    if preempt_threshold > priority {
+        return TX_THRESH_ERROR;
+    }
+    let mut kernel = lock();
+    if kernel.thread_id(thread_ptr).is_some() {
+        return TX_THREAD_ERROR;
+    }
+
+    ptr::write_bytes(thread_ptr, 0, 1);
+    let thread = &mut *thread_ptr;
+    thread.tx_thread_id = TX_THREAD_ID;
+    thread.tx_thread_name = name_ptr;
+    thread.tx_thread_priority = priority;
+    thread.tx_thread_user_priority = priority;
+    thread.tx_thread_preempt_threshold = preempt_threshold;
+    thread.tx_thread_user_preempt_threshold = preempt_threshold;
+    thread.tx_thread_time_slice = time_slice;
+    thread.tx_thread_new_time_slice = time_slice;
+    thread.tx_thread_entry = entry_function;
+    thread.tx_thread_entry_parameter = entry_input;
+    thread.tx_thread_stack_start = stack_start;
+    thread.tx_thread_stack_end = (stack_start as *mut u8).add(stack_size as usize - 1) as *mut c_void;
+    thread.tx_thread_stack_size = stack_size;
+
+    let tid = kernel.create_thread(thread_ptr, priority, preempt_threshold, auto_start == TX_AUTO_START);
+    let mut builder = thread::Builder::new();
+    if !name_ptr.is_null() {
+        builder = builder.name(CStr::from_ptr(name_ptr).to_string_lossy().into_owned());
+    }
+    builder.spawn(move || run_thread(tid, entry, entry_input)).expect("Unable to spawn the thread");
+    drop(preempt(kernel));
+    TX_SUCCESS
+}
+
+/// Only completed threads can be deleted, there is no `_tx_thread_terminate`
+#[no_mangle]
+pub unsafe extern "C" fn _tx_thread_delete(thread_ptr: *mut TX_THREAD) -> UINT {
+    let mut kernel = lock();
+    let Some(tid) = kernel.thread_id(thread_ptr) else {
+        return TX_THREAD_ERROR;
+    };
+    if !matches!(kernel.thread(tid).state, State::Completed) {
+        return TX_DELETE_ERROR;
+    }
+    kernel.threads.remove(&tid);
+    kernel.thread_ids.remove(&(thread_ptr as usize));
+    (*thread_ptr).tx_thread_id = TX_CLEAR_ID as ULONG;
+    TX_SUCCESS
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn _tx_thread_identify() -> *mut TX_THREAD {
+    match context() {
+        Context::Thread(tid) => lock().thread(tid).control_block,
+        Context::Timer => ptr::addr_of_mut!(_tx_timer_thread),
+        Context::Other => ptr::null_mut(),
+    }
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn _tx_thread_resume(thread_ptr: *mut TX_THREAD) -> UINT {
+    let mut kernel = lock();
+    let Some(tid) = kernel.thread_id(thread_ptr) else {
+        return TX_THREAD_ERROR;
+    };
+    let thread = kernel.thread(tid);
+    if matches!(thread.state, State::Suspended) {
+        kernel.resume(tid, TX_SUCCESS);
+    } else if thread.delayed_suspend {
+        thread.delayed_suspend = false;
+    } else {
+        return TX_RESUME_ERROR;
+    }
+    drop(preempt(kernel));
+    TX_SUCCESS
+}
+
+/// A thread that sleeps or waits is suspended when the sleep or the wait ends. The
+/// running thread can only suspend itself, as the sim can not stop it from the outside.
+#[no_mangle]
+pub unsafe extern "C" fn _tx_thread_suspend(thread_ptr: *mut TX_THREAD) -> UINT {
+    let mut kernel = lock();
+    let Some(tid) = kernel.thread_id(thread_ptr) else {
+        return TX_THREAD_ERROR;
+    };
+    if current_thread() == Some(tid) {
+        let (_kernel, status) = suspend(kernel, tid, State::Suspended, None);
+        return status;
+    }
+    if kernel.cpu == Cpu::Thread(tid) {
+        return TX_SUSPEND_ERROR;
+    }
+    let thread = kernel.thread(tid);
+    match thread.state {
+        State::Ready => thread.state = State::Suspended,
+        State::Sleeping | State::Waiting(_) => thread.delayed_suspend = true,
+        State::Suspended => {}
+        State::Completed => return TX_SUSPEND_ERROR,
+    }
+    TX_SUCCESS
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn _tx_thread_sleep(timer_ticks: ULONG) -> UINT {
+    let Some(tid) = current_thread() else {
+        return TX_CALLER_ERROR;
+    };
+    if timer_ticks == 0 {
+        return TX_SUCCESS;
+    }
+    let (_kernel, status) = suspend(lock(), tid, State::Sleeping, Some(timer_ticks));
+    status
+}
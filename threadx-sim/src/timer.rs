@@ -0,0 +1,178 @@
+//! Application timers. Expiration functions run on the thread that entered the kernel.
+
+use core::ptr;
+
+use threadx_sys::{
+    CHAR, TX_ACTIVATE_ERROR, TX_AUTO_ACTIVATE, TX_CLEAR_ID, TX_FEATURE_NOT_ENABLED,
+    TX_NO_ACTIVATE, TX_SUCCESS, TX_TICK_ERROR, TX_TIMER, TX_TIMER_ERROR, UINT, ULONG,
+};
+
+use crate::kernel::{lock, preempt, put, TX_TIMER_ID};
+
+type ExpirationFunction = unsafe extern "C" fn(ULONG);
+
+pub(crate) struct Timer {
+    pub(crate) function: Option<ExpirationFunction>,
+    pub(crate) input: ULONG,
+    /// Tick at which the timer expires, `None` while it is inactive
+    pub(crate) deadline: Option<u64>,
+    /// Ticks from the activation to the expiration
+    remaining: ULONG,
+    pub(crate) reschedule_ticks: ULONG,
+    /// Order among timers that expire at the same tick
+    pub(crate) seq: i64,
+}
+
+impl Timer {
+    /// Called by the kernel when the timer expired at `now`
+    pub(crate) fn expire(&mut self, now: u64) {
+        self.remaining = self.reschedule_ticks;
+        self.deadline = (self.reschedule_ticks != 0).then(|| now + self.reschedule_ticks as u64);
+    }
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn _tx_timer_create(
+    timer_ptr: *mut TX_TIMER,
+    name_ptr: *mut CHAR,
+    expiration_function: Option<ExpirationFunction>,
+    expiration_input: ULONG,
+    initial_ticks: ULONG,
+    reschedule_ticks: ULONG,
+    auto_activate: UINT,
+) -> UINT {
+    if timer_ptr.is_null() {
+        return TX_TIMER_ERROR;
+    }
+    if initial_ticks == 0 {
+        return TX_TICK_ERROR;
+    }
+    if auto_activate != TX_AUTO_ACTIVATE && auto_activate != TX_NO_ACTIVATE {
+        return TX_ACTIVATE_ERROR;
+    }
+    let mut kernel = lock();
+    if kernel.timers.contains_key(&(timer_ptr as usize)) {
+        return TX_TIMER_ERROR;
+    }
+    ptr::write_bytes(timer_ptr, 0, 1);
+    let control_block = &mut *timer_ptr;
+    control_block.tx_timer_id = TX_TIMER_ID;
+    control_block.tx_timer_name = name_ptr;
+    control_block.tx_timer_internal.tx_timer_internal_remaining_ticks = initial_ticks;
+    control_block.tx_timer_internal.tx_timer_internal_re_initialize_ticks = reschedule_ticks;
+    control_block.tx_timer_internal.tx_timer_internal_timeout_function = expiration_function;
+    control_block.tx_timer_internal.tx_timer_internal_timeout_param = expiration_input;
+    let seq = kernel.next_seq();
+    let deadline = (auto_activate == TX_AUTO_ACTIVATE).then(|| kernel.ticks + initial_ticks as u64);
+    kernel.timers.insert(
+        timer_ptr as usize,
+        Timer {
+            function: expiration_function,
+            input: expiration_input,
+            deadline,
+            remaining: initial_ticks,
+            reschedule_ticks,
+            seq,
+        },
+    );
+    TX_SUCCESS
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn _tx_timer_delete(timer_ptr: *mut TX_TIMER) -> UINT {
+    let mut kernel = lock();
+    let key = timer_ptr as usize;
+    if kernel.timers.remove(&key).is_none() {
+        return TX_TIMER_ERROR;
+    }
+    kernel.expired_timers.retain(|expired| *expired != key);
+    (*timer_ptr).tx_timer_id = TX_CLEAR_ID as ULONG;
+    TX_SUCCESS
+}
+
+/// An active timer can not be activated again. A one-shot timer that expired stays
+/// inactive until it is changed.
+#[no_mangle]
+pub unsafe extern "C" fn _tx_timer_activate(timer_ptr: *mut TX_TIMER) -> UINT {
+    let mut kernel = lock();
+    let now = kernel.ticks;
+    let seq = kernel.next_seq();
+    let Some(timer) = kernel.timers.get_mut(&(timer_ptr as usize)) else {
+        return TX_TIMER_ERROR;
+    };
+    if timer.deadline.is_some() {
+        return TX_ACTIVATE_ERROR;
+    }
+    if timer.remaining != 0 {
+        timer.deadline = Some(now + timer.remaining as u64);
+        timer.seq = seq;
+    }
+    drop(preempt(kernel));
+    TX_SUCCESS
+}
+
+/// Keeps the remaining ticks for the next activation
+#[no_mangle]
+pub unsafe extern "C" fn _tx_timer_deactivate(timer_ptr: *mut TX_TIMER) -> UINT {
+    let mut kernel = lock();
+    let now = kernel.ticks;
+    let Some(timer) = kernel.timers.get_mut(&(timer_ptr as usize)) else {
+        return TX_TIMER_ERROR;
+    };
+    if let Some(deadline) = timer.deadline.take() {
+        timer.remaining = (deadline - now) as ULONG;
+    }
+    TX_SUCCESS
+}
+
+/// Only changes an inactive timer, like ThreadX
+#[no_mangle]
+pub unsafe extern "C" fn _tx_timer_change(timer_ptr: *mut TX_TIMER, initial_ticks: ULONG, reschedule_ticks: ULONG) -> UINT {
+    if initial_ticks == 0 {
+        return TX_TICK_ERROR;
+    }
+    let mut kernel = lock();
+    let Some(timer) = kernel.timers.get_mut(&(timer_ptr as usize)) else {
+        return TX_TIMER_ERROR;
+    };
+    if timer.deadline.is_none() {
+        timer.remaining = initial_ticks;
+        timer.reschedule_ticks = reschedule_ticks;
+        (*timer_ptr).tx_timer_internal.tx_timer_internal_re_initialize_ticks = reschedule_ticks;
+    }
+    TX_SUCCESS
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn _tx_timer_info_get(
+    timer_ptr: *mut TX_TIMER,
+    name: *mut *mut CHAR,
+    active: *mut UINT,
+    remaining_ticks: *mut ULONG,
+    reschedule_ticks: *mut ULONG,
+    next_timer: *mut *mut TX_TIMER,
+) -> UINT {
+    let kernel = lock();
+    let Some(timer) = kernel.timers.get(&(timer_ptr as usize)) else {
+        return TX_TIMER_ERROR;
+    };
+    let remaining = timer.deadline.map_or(timer.remaining, |deadline| (deadline - kernel.ticks) as ULONG);
+    put(name, (*timer_ptr).tx_timer_name);
+    put(active, timer.deadline.is_some() as UINT);
+    put(remaining_ticks, remaining);
+    put(reschedule_ticks, timer.reschedule_ticks);
+    put(next_timer, ptr::null_mut());
+    TX_SUCCESS
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn _tx_timer_performance_info_get(
+    _timer_ptr: *mut TX_TIMER,
+    _activates: *mut ULONG,
+    _reactivates: *mut ULONG,
+    _deactivates: *mut ULONG,
+    _expirations: *mut ULONG,
+    _expiration_adjusts: *mut ULONG,
+) -> UINT {
+    TX_FEATURE_NOT_ENABLED
+}
@@ -1,63 +1,404 @@
 // Build script for Building threadx and to create the bindings
 
+use std::collections::BTreeSet;
 use std::io::{Write, BufRead};
 use std::os::unix::fs::OpenOptionsExt;
-use std::path::PathBuf;
+use std::path::{Path, PathBuf};
 use std::process::Command;
 use std::env;
 use std::sync::{Arc, Mutex};
 use bindgen::Builder;
 use bindgen::callbacks::ParseCallbacks;
 use cmake::Config;
+use sha2::{Digest, Sha256};
+
+/// Upstream repository and the release these bindings are written against
+const THREADX_REPO: &str = "https://github.com/eclipse-threadx/threadx.git";
+const THREADX_TAG: &str = "v6.4.0_rel";
+const THREADX_VERSION: (u32, u32, u32) = (6, 4, 0);
+
+/// The parts of the ThreadX tree that are vendored and checksummed: the common code, the
+/// ports supported by `Port` and the cmake files. Must match PATHS in scripts/vendor-threadx.sh.
+const THREADX_PATHS: &[&str] = &[
+    "CMakeLists.txt",
+    "LICENSE.txt",
+    "cmake",
+    "common",
+    "ports/cortex_m0/gnu",
+    "ports/cortex_m3/gnu",
+    "ports/cortex_m4/gnu",
+    "ports/cortex_m7/gnu",
+    "ports/cortex_m23/gnu",
+    "ports/cortex_m33/gnu",
+    "ports/cortex_m55/gnu",
+    "ports/cortex_m85/gnu",
+    "ports/linux/gnu",
+];
+
+/// The committed checksum of `THREADX_PATHS` at `THREADX_TAG`. Both the vendored copy and
+/// a fresh clone are checked against it. Only `scripts/vendor-threadx.sh --pin` writes it,
+/// when `THREADX_TAG` changes.
+const THREADX_CHECKSUM_FILE: &str = "threadx.sha256";
 
 fn main() {
 
     let out_dir = PathBuf::from(env::var("OUT_DIR").expect("OUT_DIR is not set"));
     let tx_user_file = env::var("TX_USER_FILE").ok();
-    let src_path = out_dir.join("threadx"); // source code of threadx is vendored here
-    let threadx_gh = "https://github.com/eclipse-threadx/threadx.git";
-    let threadx_tag = "v6.4.0_rel";
+    println!("cargo:rerun-if-env-changed=TX_USER_FILE");
+    println!("cargo:rerun-if-env-changed=THREADX_SRC_DIR");
+    println!("cargo:rerun-if-env-changed=THREADX_PREBUILT_LIB");
+    println!("cargo:rerun-if-env-changed=THREADX_PREBUILT_LIB_SHA256");
+    println!("cargo:rerun-if-env-changed=THREADX_CORE");
+
+    let kernel_config = KernelConfig::from_features();
+
+    if env::var_os("CARGO_FEATURE_SIM").is_some() {
+        // threadx-sim implements the services against the committed src/sim_bindings.rs
+        // with the default configuration, so there is nothing to build, generate or link
+        if tx_user_file.is_some() || kernel_config != KernelConfig::default() {
+            panic!("The sim feature uses the default kernel configuration and can not be combined with TX_USER_FILE or the kernel configuration features");
+        }
+        return;
+    }
 
     let tx_user_file_path = 
     if let Some(tx_user_file) = tx_user_file {
+        if kernel_config != KernelConfig::default() {
+            panic!("TX_USER_FILE can not be combined with the kernel configuration features ({kernel_config:?}). Put the configuration in TX_USER_FILE instead.");
+        }
         let tx_user_file = PathBuf::from(tx_user_file).canonicalize().expect("Unable to find TX_USER_FILE");
         println!("cargo:info=Using TX_USER_FILE: {}", tx_user_file.display());
         println!("cargo:rerun-if-changed={}",tx_user_file.display());
-        Some(tx_user_file)
+        tx_user_file
+    } else {
+        println!("cargo:info=No TX_USER_FILE specified, generating tx_user.h from {kernel_config:?}");
+        kernel_config.write_tx_user_h(&out_dir)
+    };
+
+    let src_path = threadx_source(&out_dir);
+    verify_version(&src_path);
+
+    let target = env::var("TARGET").expect("TARGET is not set");
+
+    let port = Port::for_target(&target);
+    println!("cargo:info=Building ThreadX port {}", port.dir());
+
+    let (include_dirs, defines, compiler) = if let Ok(prebuilt_lib) = env::var("THREADX_PREBUILT_LIB") {
+        link_prebuilt(&out_dir, PathBuf::from(prebuilt_lib));
+        prebuilt_flags(&out_dir, &src_path, &port, &tx_user_file_path)
     } else {
-        println!("cargo:info=No TX_USER_FILE specified, using defaults");
-        None
+        build_threadx(&out_dir, &src_path, &port, &tx_user_file_path)
     };
+    if let Port::Linux = port {
+        // The Linux port runs every ThreadX thread and the timer tick on a pthread
+        println!("cargo:rustc-link-lib=pthread");
+    }
+
+    let threadx_api_path = src_path.join("common/inc/tx_api.h");
+    let kernel_macros = kernel_macros(&compiler, &port.flags(), &threadx_api_path, &include_dirs, &defines);
+    let bindings_path = out_dir.join("generated.rs");
+    let mut bindings = bindgen::Builder::default()
+        .header(threadx_api_path.to_str().unwrap())
+        .use_core()
+        .layout_tests(false)
+        .allowlist_function("_tx.*")
+        .allowlist_recursively(true);
+    for include_dir in include_dirs {
+        bindings = bindings.clang_arg(format!("-I{}", include_dir));
+    }
+    for define in defines {
+        bindings = bindings.clang_arg(format!("-D{}", define));
+    }
     
-    // Clone threadx
-    std::process::Command::new("git")
-        .arg("clone")
-        .arg(threadx_gh)
-        .current_dir(out_dir.clone())
+    let int_macros = Arc::new(Mutex::new(Vec::new()));
+
+    let mut bindings = configure_builder(bindings, int_macros.clone());
+
+    // Get the standard include paths from the compiler
+    // Create an empty file to pass to the compiler
+    std::fs::OpenOptions::new().create(true).truncate(true).write(true).open(out_dir.join("empty.c")).expect("Unable to create empty.c");
+    let output = Command::new(compiler)
+        .arg("-xc")
+        .arg("-E")
+        .arg("-v")
+        .arg(out_dir.join("empty.c"))
         .output()
-    .expect("Failed to fetch git submodules!");
+        .expect("Unable to run compiler");
+
+    let output = String::from_utf8(output.stderr).expect("Unable to parse compiler output");
+    
+    let mut inside_include_dirs = false;
+    let mut compiler_include_dirs = Vec::new();
+    for line in output.lines() {
+        if line.contains("search starts here:") {
+            inside_include_dirs = true;
+            continue;
+        }
+        if line.contains("End of search list.") {
+            //inside_include_dirs = false;
+            break;
+        }
+        if inside_include_dirs {
+            compiler_include_dirs.push(line.trim().to_string());
+        }
+    }
+
+    println!("Found compiler include dirs: {:?}", compiler_include_dirs);
+
+    for include_dir in compiler_include_dirs {
+        bindings = bindings.clang_arg(format!("-I{}", include_dir));
+    }
+
+    let bindings = bindings
+        .generate()
+        .expect("Unable to generate bindings");
+
+    bindings.write_to_file(bindings_path.clone())
+        .expect("Couldn't write bindings");
 
-    // checkout tag
-    std::process::Command::new("git")
-        .arg("checkout")
-        .arg(threadx_tag)
-        .current_dir(src_path.clone())
+    // now generate the int macros
+    let mut out_file = std::fs::OpenOptions::new().create(false).append(true).write(true).open(&bindings_path).expect("Unable to open bindings file");
+
+    writeln!(out_file,"// Constants extracted from TX_API.H and TX_PORT.H with overridden values").expect("Unable to write int macros");
+    for (name, value) in int_macros.lock().unwrap().iter() {
+        writeln!(out_file, "pub const {} : UINT = {};", name, value).expect("Unable to write int macro");
+    }
+
+    write_config_module(&mut out_file, &kernel_macros);
+}
+
+/// Locate the ThreadX source. In order of preference: `THREADX_SRC_DIR`, the copy in
+/// `vendor/threadx` with the `vendored` feature, or a clone of `THREADX_TAG`.
+fn threadx_source(out_dir: &Path) -> PathBuf {
+    if let Ok(src_dir) = env::var("THREADX_SRC_DIR") {
+        let src_dir = PathBuf::from(&src_dir)
+            .canonicalize()
+            .unwrap_or_else(|e| panic!("THREADX_SRC_DIR={src_dir} can not be used: {e}"));
+        println!("cargo:info=Using ThreadX source from THREADX_SRC_DIR: {}", src_dir.display());
+        println!("cargo:rerun-if-changed={}", src_dir.join("common/inc/tx_api.h").display());
+        return src_dir;
+    }
+
+    if env::var_os("CARGO_FEATURE_VENDORED").is_some() {
+        let manifest_dir = PathBuf::from(env::var("CARGO_MANIFEST_DIR").expect("CARGO_MANIFEST_DIR is not set"));
+        let src_dir = manifest_dir.join("vendor/threadx");
+        if !src_dir.join("common/inc/tx_api.h").is_file() {
+            panic!(
+                "The vendored feature is enabled but {} does not contain ThreadX. Run scripts/vendor-threadx.sh to populate it.",
+                src_dir.display()
+            );
+        }
+        verify_checksum(&src_dir, "Restore it with scripts/vendor-threadx.sh.");
+        println!("cargo:info=Using vendored ThreadX source {}", THREADX_TAG);
+        return src_dir;
+    }
+
+    let src_path = clone_threadx(out_dir);
+    verify_checksum(&src_path, "Remove it to clone again.");
+    src_path
+}
+
+/// The checksum pinned in `THREADX_CHECKSUM_FILE`, if it has been pinned
+fn pinned_checksum() -> Option<String> {
+    let manifest_dir = PathBuf::from(env::var("CARGO_MANIFEST_DIR").expect("CARGO_MANIFEST_DIR is not set"));
+    let checksum_file = manifest_dir.join(THREADX_CHECKSUM_FILE);
+    println!("cargo:rerun-if-changed={}", checksum_file.display());
+    match std::fs::read_to_string(&checksum_file) {
+        Ok(checksum) => Some(checksum.trim().to_string()),
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => None,
+        Err(e) => panic!("Unable to read {}: {e}", checksum_file.display()),
+    }
+}
+
+/// Check `THREADX_PATHS` in `src_dir` against the pinned checksum. Without a pinned
+/// checksum the source is used unchecked and a warning is printed.
+fn verify_checksum(src_dir: &Path, remedy: &str) {
+    let actual = tree_checksum(src_dir);
+    let Some(expected) = pinned_checksum() else {
+        println!(
+            "cargo:warning=No checksum of ThreadX {} is pinned, the source in {} ({}) is not verified. Pin it with scripts/vendor-threadx.sh --pin and commit {}.",
+            THREADX_TAG,
+            src_dir.display(),
+            actual,
+            THREADX_CHECKSUM_FILE
+        );
+        return;
+    };
+    if expected != actual {
+        panic!(
+            "The ThreadX source in {} does not match {} from {}: expected {}, found {}. {}",
+            src_dir.display(),
+            THREADX_TAG,
+            THREADX_CHECKSUM_FILE,
+            expected,
+            actual,
+            remedy
+        );
+    }
+}
+
+/// Run a command and panic with its output if it fails
+fn run(command: &mut Command) -> String {
+    let output = command.output().unwrap_or_else(|e| panic!("Unable to run {command:?}: {e}"));
+    if !output.status.success() {
+        panic!(
+            "{command:?} failed with {}\n{}{}",
+            output.status,
+            String::from_utf8_lossy(&output.stdout),
+            String::from_utf8_lossy(&output.stderr)
+        );
+    }
+    String::from_utf8_lossy(&output.stdout).trim().to_string()
+}
+
+/// The tag checked out in `src_path`, if it is a git checkout sitting exactly on a tag
+fn checked_out_tag(src_path: &Path) -> Option<String> {
+    let output = Command::new("git")
+        .args(["describe", "--tags", "--exact-match", "HEAD"])
+        .current_dir(src_path)
         .output()
-        .expect("Unable to checkout threadx tag");
+        .ok()?;
+    output.status.success().then(|| String::from_utf8_lossy(&output.stdout).trim().to_string())
+}
 
+/// Clone `THREADX_TAG` into `OUT_DIR`. A previous clone is reused if it is still on the tag.
+fn clone_threadx(out_dir: &Path) -> PathBuf {
+    let src_path = out_dir.join("threadx");
+    if src_path.exists() {
+        if checked_out_tag(&src_path).as_deref() == Some(THREADX_TAG) {
+            return src_path;
+        }
+        println!("cargo:warning=Removing ThreadX checkout in {} that is not on {}", src_path.display(), THREADX_TAG);
+        std::fs::remove_dir_all(&src_path).expect("Unable to remove the old ThreadX checkout");
+    }
 
-    let target = env::var("TARGET").expect("TARGET is not set");
+    run(Command::new("git")
+        .args(["clone", "--depth", "1", "--branch", THREADX_TAG, "-c", "advice.detachedHead=false", THREADX_REPO])
+        .arg(&src_path));
+
+    match checked_out_tag(&src_path) {
+        Some(tag) if tag == THREADX_TAG => src_path,
+        tag => panic!("The ThreadX clone in {} is on {:?} instead of {}", src_path.display(), tag, THREADX_TAG),
+    }
+}
+
+/// Check the version macros in tx_api.h against `THREADX_VERSION`
+fn verify_version(src_path: &Path) {
+    let tx_api = src_path.join("common/inc/tx_api.h");
+    let header = std::fs::read_to_string(&tx_api)
+        .unwrap_or_else(|e| panic!("Unable to read {}: {e}. Is this a ThreadX source tree?", tx_api.display()));
+    let version = |name: &str| -> u32 {
+        header
+            .lines()
+            .filter_map(|line| line.trim().strip_prefix("#define"))
+            .find_map(|define| {
+                let mut parts = define.split_whitespace();
+                (parts.next() == Some(name)).then(|| parts.next()?.parse().ok()).flatten()
+            })
+            .unwrap_or_else(|| panic!("{} does not define {}", tx_api.display(), name))
+    };
+    let found = (version("THREADX_MAJOR_VERSION"), version("THREADX_MINOR_VERSION"), version("THREADX_PATCH_VERSION"));
+    if found != THREADX_VERSION {
+        panic!(
+            "The ThreadX source in {} is version {}.{}.{}, but these bindings need {}.{}.{} ({})",
+            src_path.display(),
+            found.0,
+            found.1,
+            found.2,
+            THREADX_VERSION.0,
+            THREADX_VERSION.1,
+            THREADX_VERSION.2,
+            THREADX_TAG
+        );
+    }
+}
+
+fn sha256_hex(data: &[u8]) -> String {
+    Sha256::digest(data).iter().map(|b| format!("{b:02x}")).collect()
+}
+
+/// Checksum of `THREADX_PATHS` in a source tree. It is the SHA-256 of the `sha256sum`
+/// listing of their files, sorted by path, so it can be reproduced with
+/// `find $THREADX_PATHS -type f | LC_ALL=C sort | xargs sha256sum | sha256sum`.
+fn tree_checksum(root: &Path) -> String {
+    fn collect(dir: &Path, files: &mut Vec<PathBuf>) {
+        for entry in std::fs::read_dir(dir).unwrap_or_else(|e| panic!("Unable to read {}: {e}", dir.display())) {
+            let path = entry.expect("Unable to read directory entry").path();
+            if path.is_dir() {
+                collect(&path, files);
+            } else {
+                files.push(path);
+            }
+        }
+    }
+    let mut files = Vec::new();
+    for path in THREADX_PATHS {
+        let path = root.join(path);
+        if path.is_dir() {
+            collect(&path, &mut files);
+        } else if path.is_file() {
+            files.push(path);
+        }
+    }
+    let mut listing: Vec<String> = files
+        .iter()
+        .map(|path| path.strip_prefix(root).unwrap().display().to_string())
+        .collect();
+    listing.sort();
+    let mut sums = String::new();
+    for name in listing {
+        let data = std::fs::read(root.join(&name)).unwrap_or_else(|e| panic!("Unable to read {name}: {e}"));
+        sums.push_str(&format!("{}  {}\n", sha256_hex(&data), name));
+    }
+    sha256_hex(sums.as_bytes())
+}
 
-    /*
-    # target = "thumbv6m-none-eabi"    # Cortex-M0 and Cortex-M0+
-    # target = "thumbv7m-none-eabi"    # Cortex-M3
-    # target = "thumbv7em-none-eabi"   # Cortex-M4 and Cortex-M7 (no FPU)
-    # target = "thumbv7em-none-eabihf" # Cortex-M4F and Cortex-M7F (with FPU)
-    # target = "thumbv8m.base-none-eabi"   # Cortex-M23
-    # target = "thumbv8m.main-none-eabi"   # Cortex-M33 (no FPU)
-    # target = "thumbv8m.main-none-eabihf" # Cortex-M33 (with FPU)
-     */
+/// Link a prebuilt `libthreadx.a` instead of building ThreadX. The library must have been
+/// built from the same source and kernel configuration, which can not be checked here. Its
+/// checksum is checked if `THREADX_PREBUILT_LIB_SHA256` is set.
+fn link_prebuilt(out_dir: &Path, lib: PathBuf) {
+    let data = std::fs::read(&lib).unwrap_or_else(|e| panic!("THREADX_PREBUILT_LIB={} can not be read: {e}", lib.display()));
+    println!("cargo:rerun-if-changed={}", lib.display());
+    if let Ok(expected) = env::var("THREADX_PREBUILT_LIB_SHA256") {
+        let actual = sha256_hex(&data);
+        if !expected.trim().eq_ignore_ascii_case(&actual) {
+            panic!(
+                "THREADX_PREBUILT_LIB={} has SHA-256 {}, but THREADX_PREBUILT_LIB_SHA256 is {}",
+                lib.display(),
+                actual,
+                expected.trim()
+            );
+        }
+    }
+    // rustc looks for libthreadx.a, so the library is copied under that name
+    let lib_dir = out_dir.join("prebuilt");
+    std::fs::create_dir_all(&lib_dir).expect("Unable to create the prebuilt library directory");
+    std::fs::write(lib_dir.join("libthreadx.a"), data).expect("Unable to copy the prebuilt library");
+    println!("cargo:info=Linking prebuilt ThreadX library {}", lib.display());
+    println!("cargo:rustc-link-search=native={}", lib_dir.display());
+    println!("cargo:rustc-link-lib=static=threadx");
+}
 
+/// The include directories, defines and compiler the cmake build would have used, for
+/// generating the bindings of a prebuilt library
+fn prebuilt_flags(out_dir: &Path, src_path: &Path, port: &Port, tx_user_file_path: &Path) -> (Vec<String>, Vec<String>, String) {
+    let mut include_dirs = vec![
+        src_path.join("common/inc").display().to_string(),
+        src_path.join(port.dir()).join("inc").display().to_string(),
+    ];
+    // Same as the cmake build, which copies the file to tx_user.h in its build directory
+    let user_dir = out_dir.join("prebuilt");
+    std::fs::create_dir_all(&user_dir).expect("Unable to create the prebuilt library directory");
+    std::fs::copy(tx_user_file_path, user_dir.join("tx_user.h")).expect("Unable to copy tx_user.h");
+    include_dirs.push(user_dir.display().to_string());
+    let mut defines = vec!["TX_INCLUDE_USER_DEFINE_FILE".to_string()];
+    defines.extend(port.defines().iter().map(|define| define.to_string()));
+    (include_dirs, defines, port.compiler().to_string())
+}
+
+/// Build ThreadX with cmake and link it. Returns the include directories, defines and
+/// compiler used for the build, which are needed to generate matching bindings.
+fn build_threadx(out_dir: &Path, src_path: &Path, port: &Port, tx_user_file_path: &Path) -> (Vec<String>, Vec<String>, String) {
     let build_commands = out_dir.join("build_commands.txt");
 
     // We create a wrapper script to capture the commands passed to the compiler
@@ -78,33 +419,31 @@ fn main() {
     file.flush().expect("Unable to flush wrapper");
     drop(file);
 
-
-    let toolchain_file = match target.as_str() {
-        "thumbv6m-none-eabi" => "cmake/cortex_m0.cmake",
-        "thumbv7m-none-eabi" => "cmake/cortex_m3.cmake",
-        "thumbv7em-none-eabi" => "cmake/cortex_m4.cmake",
-        "thumbv7em-none-eabihf" => "cmake/cortex_m7.cmake",
-        "thumbv8m.base-none-eabi" => "cmake/cortex_m23.cmake",
-        "x86_64-unknown-linux-gnu" => "cmake/linux/gnu/CMakeLists.txt",
-        _ => {
-            println!("cargo:error=Unsupported cortex M target: {}", target);
-            panic!("Unsupported cortex M target: {}", target);
-            
-        }
-    };
-
     // Build threadx
-    let mut cfg = Config::new(src_path.to_owned());
+    let mut cfg = Config::new(src_path);
 
-        cfg.define("CMAKE_TOOLCHAIN_FILE", toolchain_file)
+        cfg.define("CMAKE_TOOLCHAIN_FILE", port.toolchain_file(out_dir, src_path))
         .generator("Ninja")
         .build_target("threadx")
         .env("CMAKE_C_COMPILER_LAUNCHER", compiler_wrapper_path.as_path())
         .env("CMAKE_CXX_COMPILER_LAUNCHER", compiler_wrapper_path.as_path());
 
-    if tx_user_file_path.is_some() {
-        cfg.define("TX_USER_FILE", tx_user_file_path.unwrap().to_str().unwrap());
-    };
+    // cmake-rs sets CMAKE_C_FLAGS itself, so the core flags of the toolchain file are
+    // passed again to make sure they are used. Its own target flags (-march, -mfpu)
+    // would override the core, so they are left out.
+    if let Port::CortexM { .. } = port {
+        cfg.no_default_flags(true)
+            .cflag("-ffunction-sections")
+            .cflag("-fdata-sections");
+    }
+    for flag in port.flags() {
+        cfg.cflag(&flag).asmflag(&flag);
+    }
+    for define in port.defines() {
+        cfg.cflag(format!("-D{define}")).asmflag(format!("-D{define}"));
+    }
+
+    cfg.define("TX_USER_FILE", tx_user_file_path.to_str().unwrap());
 
     let dst= cfg.build().join("build");
 
@@ -146,77 +485,250 @@ fn main() {
     defines.sort();
     defines.dedup();
 
-    let threadx_api_path = src_path.join("common/inc/tx_api.h");
-    let bindings_path = out_dir.join("generated.rs");
-    let mut bindings = bindgen::Builder::default()
-        .header(threadx_api_path.to_str().unwrap())
-        .use_core()
-        .layout_tests(false)
-        .allowlist_function("_tx.*")
-        .allowlist_recursively(true);
-    for include_dir in include_dirs {
-        bindings = bindings.clang_arg(format!("-I{}", include_dir));
-    }
-    for define in defines {
-        bindings = bindings.clang_arg(format!("-D{}", define));
-    }
-    
-    let int_macros = Arc::new(Mutex::new(Vec::new()));
+    (include_dirs, defines, compiler.expect("No compiler invocation was recorded while building threadx"))
+}
 
-    let mut bindings = configure_builder(bindings, int_macros.clone());
+/// Cortex-M cores for each target, named as in gcc's -mcpu. The first one is the default
+/// and `THREADX_CORE` selects another one.
+const CORTEX_M_TARGETS: [(&str, &[&str]); 7] = [
+    ("thumbv6m-none-eabi", &["cortex-m0", "cortex-m0plus"]),
+    ("thumbv7m-none-eabi", &["cortex-m3"]),
+    ("thumbv7em-none-eabi", &["cortex-m4", "cortex-m7"]),
+    ("thumbv7em-none-eabihf", &["cortex-m4", "cortex-m7"]),
+    ("thumbv8m.base-none-eabi", &["cortex-m23"]),
+    ("thumbv8m.main-none-eabi", &["cortex-m33", "cortex-m55", "cortex-m85"]),
+    ("thumbv8m.main-none-eabihf", &["cortex-m33", "cortex-m55", "cortex-m85"]),
+];
+
+/// The ThreadX port for the target
+enum Port {
+    CortexM { core: &'static str, hard_float: bool },
+    Linux,
+}
 
-    // Get the standard include paths from the compiler
-    // Create an empty file to pass to the compiler
-    std::fs::OpenOptions::new().create(true).truncate(true).write(true).open(out_dir.join("empty.c")).expect("Unable to create empty.c");
-    let output = Command::new(compiler.unwrap())
-        .arg("-xc")
-        .arg("-E")
-        .arg("-v")
-        .arg(out_dir.join("empty.c"))
-        .output()
-        .expect("Unable to run compiler");
+impl Port {
+    fn for_target(target: &str) -> Self {
+        if target == "x86_64-unknown-linux-gnu" {
+            return Port::Linux;
+        }
+        let Some((_, cores)) = CORTEX_M_TARGETS.iter().find(|(name, _)| *name == target) else {
+            println!("cargo:error=Unsupported target: {}", target);
+            panic!("Unsupported target: {}", target);
+        };
+        let core = match env::var("THREADX_CORE") {
+            Ok(core) => cores.iter().find(|c| **c == core).unwrap_or_else(|| {
+                panic!("THREADX_CORE={core} is not a core of {target}, expected one of {cores:?}")
+            }),
+            Err(_) => &cores[0],
+        };
+        Port::CortexM { core, hard_float: target.ends_with("eabihf") }
+    }
 
-    let output = String::from_utf8(output.stderr).expect("Unable to parse compiler output");
-    
-    let mut inside_include_dirs = false;
-    let mut compiler_include_dirs = Vec::new();
-    for line in output.lines() {
-        if line.contains("search starts here:") {
-            inside_include_dirs = true;
-            continue;
+    /// Port directory relative to the ThreadX source
+    fn dir(&self) -> String {
+        match self {
+            // The Cortex-M0 port covers the M0+
+            Port::CortexM { core: "cortex-m0plus", .. } => "ports/cortex_m0/gnu".to_string(),
+            Port::CortexM { core, .. } => format!("ports/{}/gnu", core.replace('-', "_")),
+            Port::Linux => "ports/linux/gnu".to_string(),
         }
-        if line.contains("End of search list.") {
-            //inside_include_dirs = false;
-            break;
+    }
+
+    /// Core and floating point flags for the compiler and assembler
+    fn flags(&self) -> Vec<String> {
+        match self {
+            Port::CortexM { core, hard_float } => {
+                let mut flags = vec!["-mthumb".to_string(), format!("-mcpu={core}")];
+                if *hard_float {
+                    let fpu = match *core {
+                        "cortex-m4" => "fpv4-sp-d16",
+                        "cortex-m7" => "fpv5-d16",
+                        "cortex-m33" => "fpv5-sp-d16",
+                        _ => "auto",
+                    };
+                    flags.push(format!("-mfpu={fpu}"));
+                    flags.push("-mfloat-abi=hard".to_string());
+                } else {
+                    flags.push("-mfloat-abi=soft".to_string());
+                }
+                flags
+            }
+            Port::Linux => Vec::new(),
         }
-        if inside_include_dirs {
-            compiler_include_dirs.push(line.trim().to_string());
+    }
+
+    /// Defines the port needs in addition to tx_user.h. The Armv8-M ports are built
+    /// for a single, non-secure image without TrustZone secure stacks.
+    fn defines(&self) -> &'static [&'static str] {
+        match self {
+            Port::CortexM { core: "cortex-m23" | "cortex-m33" | "cortex-m55" | "cortex-m85", .. } => {
+                &["TX_SINGLE_MODE_NON_SECURE=1"]
+            }
+            _ => &[],
         }
     }
 
-    println!("Found compiler include dirs: {:?}", compiler_include_dirs);
+    fn compiler(&self) -> &'static str {
+        match self {
+            Port::CortexM { .. } => "arm-none-eabi-gcc",
+            Port::Linux => "cc",
+        }
+    }
 
-    for include_dir in compiler_include_dirs {
-        bindings = bindings.clang_arg(format!("-I{}", include_dir));
+    /// The cmake toolchain file, generated into `out_dir`. For Cortex-M this is done like
+    /// the upstream cmake/cortex_m*.cmake files, which only exist for some of the cores.
+    /// The Linux port is built with the host compiler and only needs the port selected.
+    fn toolchain_file(&self, out_dir: &Path, src_path: &Path) -> PathBuf {
+        let arch = self.dir().trim_start_matches("ports/").trim_end_matches("/gnu").to_string();
+        let toolchain = match self {
+            Port::CortexM { core, .. } => format!(
+                "# Generated by the threadx-sys build script\n\
+                 set(CMAKE_SYSTEM_PROCESSOR {core})\n\
+                 set(THREADX_ARCH \"{arch}\")\n\
+                 set(THREADX_TOOLCHAIN \"gnu\")\n\
+                 set(MCPU_FLAGS \"{}\")\n\
+                 set(VFP_FLAGS \"\")\n\
+                 set(SPEC_FLAGS \"--specs=nosys.specs\")\n\
+                 include(\"{}\")\n",
+                self.flags().join(" "),
+                src_path.join("cmake/arm-none-eabi.cmake").display()
+            ),
+            Port::Linux => format!(
+                "# Generated by the threadx-sys build script\n\
+                 set(THREADX_ARCH \"{arch}\")\n\
+                 set(THREADX_TOOLCHAIN \"gnu\")\n"
+            ),
+        };
+        let path = out_dir.join("threadx_toolchain.cmake");
+        std::fs::write(&path, toolchain).expect("Unable to write the cmake toolchain file");
+        path
     }
+}
 
-    let bindings = bindings
-        .generate()
-        .expect("Unable to generate bindings");
+/// Kernel configuration selected with Cargo features. Used to generate `tx_user.h` when
+/// no TX_USER_FILE is given.
+#[derive(Debug, Default, PartialEq, Eq)]
+struct KernelConfig {
+    max_priorities_64: bool,
+    stack_checking: bool,
+    performance_info: bool,
+    disable_error_checking: bool,
+    trace: bool,
+    tick_rate_1000: bool,
+}
 
-    bindings.write_to_file(bindings_path.clone())
-        .expect("Couldn't write bindings");
+/// ThreadX objects that have a TX_<OBJECT>_ENABLE_PERFORMANCE_INFO option
+const PERFORMANCE_INFO_OBJECTS: [&str; 8] = [
+    "BLOCK_POOL", "BYTE_POOL", "EVENT_FLAGS", "MUTEX", "QUEUE", "SEMAPHORE", "THREAD", "TIMER",
+];
+
+impl KernelConfig {
+    fn from_features() -> Self {
+        let feature = |name: &str| env::var_os(format!("CARGO_FEATURE_{name}")).is_some();
+        KernelConfig {
+            max_priorities_64: feature("MAX_PRIORITIES_64"),
+            stack_checking: feature("STACK_CHECKING"),
+            performance_info: feature("PERFORMANCE_INFO"),
+            disable_error_checking: feature("DISABLE_ERROR_CHECKING"),
+            trace: feature("TRACE"),
+            tick_rate_1000: feature("TICK_RATE_1000"),
+        }
+    }
 
-    // now generate the int macros
-    let mut out_file = std::fs::OpenOptions::new().create(false).append(true).write(true).open(&bindings_path).expect("Unable to open bindings file");
+    fn tx_user_h(&self) -> String {
+        let mut defines = Vec::new();
+        if self.max_priorities_64 {
+            defines.push("#define TX_MAX_PRIORITIES 64".to_string());
+        }
+        if self.tick_rate_1000 {
+            defines.push("#define TX_TIMER_TICKS_PER_SECOND ((ULONG) 1000)".to_string());
+        }
+        if self.stack_checking {
+            defines.push("#define TX_ENABLE_STACK_CHECKING".to_string());
+        }
+        if self.performance_info {
+            for object in PERFORMANCE_INFO_OBJECTS {
+                defines.push(format!("#define TX_{object}_ENABLE_PERFORMANCE_INFO"));
+            }
+        }
+        if self.disable_error_checking {
+            defines.push("#define TX_DISABLE_ERROR_CHECKING".to_string());
+        }
+        if self.trace {
+            defines.push("#define TX_ENABLE_EVENT_TRACE".to_string());
+        }
+        format!(
+            "/* Generated by the threadx-sys build script from {self:?} */\n\n\
+             #ifndef TX_USER_H\n#define TX_USER_H\n\n{}\n\n#endif\n",
+            defines.join("\n")
+        )
+    }
 
-    writeln!(out_file,"// Constants extracted from TX_API.H and TX_PORT.H with overridden values").expect("Unable to write int macros");
-    for (name, value) in int_macros.lock().unwrap().iter() {
-        writeln!(out_file, "pub const {} : UINT = {};", name, value).expect("Unable to write int macro");
+    /// Write the generated `tx_user.h` and return its path
+    fn write_tx_user_h(&self, out_dir: &Path) -> PathBuf {
+        let config_dir = out_dir.join("config");
+        std::fs::create_dir_all(&config_dir).expect("Unable to create the config directory");
+        let path = config_dir.join("tx_user.h");
+        std::fs::write(&path, self.tx_user_h()).expect("Unable to write tx_user.h");
+        path
     }
+}
 
-    // Copy the file to src/generated.rs to keep the documentation build happy
-    std::fs::copy(PathBuf::from(bindings_path), PathBuf::from("src/generated.rs")).unwrap();
+/// The names of all macros defined after preprocessing `tx_api.h` with the flags of the
+/// build. This covers both the generated `tx_user.h` and a TX_USER_FILE.
+fn kernel_macros(compiler: &str, flags: &[String], header: &Path, include_dirs: &[String], defines: &[String]) -> BTreeSet<String> {
+    let output = Command::new(compiler)
+        .arg("-xc")
+        .arg("-E")
+        .arg("-dM")
+        .args(flags)
+        .args(include_dirs.iter().map(|dir| format!("-I{dir}")))
+        .args(defines.iter().map(|define| format!("-D{define}")))
+        .arg(header)
+        .output()
+        .expect("Unable to run compiler");
+    if !output.status.success() {
+        panic!(
+            "Unable to preprocess {}:\n{}",
+            header.display(),
+            String::from_utf8_lossy(&output.stderr)
+        );
+    }
+    String::from_utf8(output.stdout)
+        .expect("Unable to parse preprocessor output")
+        .lines()
+        .filter_map(|line| line.strip_prefix("#define "))
+        .filter_map(|line| line.split([' ', '(']).next())
+        .map(str::to_string)
+        .collect()
+}
+
+/// Write the `config` module, which exposes the kernel configuration the library was
+/// built with as constants
+fn write_config_module(out_file: &mut impl Write, kernel_macros: &BTreeSet<String>) {
+    let defined = |name: &str| kernel_macros.contains(name);
+    let mut consts = vec![
+        ("STACK_CHECKING".to_string(), defined("TX_ENABLE_STACK_CHECKING")),
+        ("ERROR_CHECKING".to_string(), !defined("TX_DISABLE_ERROR_CHECKING")),
+        ("EVENT_TRACE".to_string(), defined("TX_ENABLE_EVENT_TRACE")),
+    ];
+    for object in PERFORMANCE_INFO_OBJECTS {
+        consts.push((
+            format!("{object}_PERFORMANCE_INFO"),
+            defined(&format!("TX_{object}_ENABLE_PERFORMANCE_INFO")),
+        ));
+    }
+
+    let mut module = String::new();
+    module.push_str("\n/// Kernel configuration the ThreadX library was built with\n");
+    module.push_str("pub mod config {\n");
+    module.push_str("    pub const MAX_PRIORITIES : super::UINT = super::TX_MAX_PRIORITIES;\n");
+    module.push_str("    pub const TICKS_PER_SECOND : super::UINT = super::TX_TIMER_TICKS_PER_SECOND;\n");
+    for (name, value) in consts {
+        module.push_str(&format!("    pub const {name} : bool = {value};\n"));
+    }
+    module.push_str("}\n");
+    out_file.write_all(module.as_bytes()).expect("Unable to write the config module");
 }
 
 // Configure the builder
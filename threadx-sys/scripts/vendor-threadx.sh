@@ -0,0 +1,61 @@
+#!/bin/sh
+# Copy the ThreadX release used by threadx-sys into vendor/threadx for builds with the
+# `vendored` feature. Only the common code, the ports supported by build.rs and the cmake
+# files are kept. The copy is checked against the checksum committed in threadx.sha256,
+# if one has been pinned.
+#
+# With --pin the checksum of the download is written to threadx.sha256 instead. Only do
+# that when moving to a new TAG, and check the new checksum against an independent
+# download before committing it.
+set -eu
+
+pin=false
+if [ "${1:-}" = "--pin" ]; then
+    pin=true
+fi
+
+TAG=v6.4.0_rel
+REPO=https://github.com/eclipse-threadx/threadx.git
+# Must match THREADX_PATHS in build.rs
+PATHS="CMakeLists.txt LICENSE.txt cmake common
+ports/cortex_m0/gnu ports/cortex_m3/gnu ports/cortex_m4/gnu ports/cortex_m7/gnu
+ports/cortex_m23/gnu ports/cortex_m33/gnu ports/cortex_m55/gnu ports/cortex_m85/gnu
+ports/linux/gnu"
+
+cd "$(dirname "$0")/.."
+tmp=$(mktemp -d)
+trap 'rm -rf "$tmp"' EXIT
+
+git clone --depth 1 --branch "$TAG" -c advice.detachedHead=false "$REPO" "$tmp/threadx"
+tag=$(git -C "$tmp/threadx" describe --tags --exact-match HEAD)
+if [ "$tag" != "$TAG" ]; then
+    echo "clone is on $tag instead of $TAG" >&2
+    exit 1
+fi
+
+rm -rf vendor/threadx
+mkdir -p vendor/threadx
+for path in $PATHS; do
+    mkdir -p "vendor/threadx/$(dirname "$path")"
+    cp -R "$tmp/threadx/$path" "vendor/threadx/$path"
+done
+
+# Must match tree_checksum in build.rs
+# shellcheck disable=SC2086
+actual=$(cd vendor/threadx && find $PATHS -type f | LC_ALL=C sort | xargs sha256sum | sha256sum | cut -d ' ' -f 1)
+if $pin; then
+    echo "$actual" > threadx.sha256
+    echo "Pinned ThreadX $TAG, checksum $actual"
+    exit 0
+fi
+if [ ! -f threadx.sha256 ]; then
+    echo "warning: no checksum of ThreadX $TAG is pinned, vendored it unchecked with checksum $actual" >&2
+    exit 0
+fi
+expected=$(cat threadx.sha256)
+if [ "$actual" != "$expected" ]; then
+    rm -rf vendor/threadx
+    echo "ThreadX $TAG has checksum $actual instead of the pinned $expected" >&2
+    exit 1
+fi
+echo "Vendored ThreadX $TAG, checksum $actual"
@@ -2,20 +2,34 @@
 
 use core::ffi::c_void;
 
-include!("generated.rs");
+// The sim feature uses fixed bindings for the default configuration, every other build
+// the bindings generated for its target and configuration
+#[cfg(feature = "sim")]
+include!("sim_bindings.rs");
+#[cfg(not(feature = "sim"))]
+include!(concat!(env!("OUT_DIR"), "/generated.rs"));
 
 // Functions that are implemented in assembly that are missed by bindgen
-// TODO: the SVCall and PendSV call are probably specific to Arm Cortex
-// and should be enabled based on the selected target.
 extern "C" {
     #[no_mangle]
     pub fn _tx_timer_interrupt() -> ();
+    #[no_mangle]
+    pub static mut _tx_thread_system_stack_ptr : *mut c_void;
+    // Kernel state that is not exposed through a service call
+    #[no_mangle]
+    pub static mut _tx_thread_system_state : ULONG;
+    #[no_mangle]
+    pub static mut _tx_timer_thread : TX_THREAD;
+}
+
+// Exception handlers of the Cortex-M ports. The build only supports Cortex-M for Arm
+// targets, so the architecture is enough to tell them apart from the other ports.
+#[cfg(target_arch = "arm")]
+extern "C" {
     #[no_mangle]
     pub fn __tx_SVCallHandler() -> ();
     #[no_mangle]
     pub fn __tx_PendSVHandler() -> ();
-    #[no_mangle]
-    pub static mut _tx_thread_system_stack_ptr : *mut c_void;
 }
 
 // Constants that are not parsed by bindgen
@@ -49,6 +63,12 @@ pub const TX_NO_MESSAGES : UINT = 0;
 pub const TX_EMPTY : UINT = 0;
 pub const TX_CLEAR_ID : UINT = 0;
 
+// Values of _tx_thread_system_state during initialization
+
+pub const TX_INITIALIZE_IS_FINISHED : ULONG = 0;
+pub const TX_INITIALIZE_IN_PROGRESS : ULONG = 0xF0F0F0F0;
+pub const TX_INITIALIZE_ALMOST_DONE : ULONG = 0xF0F0F0F1;
+
 // Thread execution states
 
 pub const TX_READY : UINT = 0;
@@ -1159,3 +1159,20 @@ pub const TX_TRACE_THREAD_EVENTS : UINT = 256;
 pub const TX_TRACE_TIME_EVENTS : UINT = 512;
 pub const TX_TRACE_TIMER_EVENTS : UINT = 1024;
 pub const TX_TRACE_USER_EVENTS : UINT = 2147483648;
+
+/// Kernel configuration the ThreadX library was built with
+pub mod config {
+    pub const MAX_PRIORITIES : super::UINT = super::TX_MAX_PRIORITIES;
+    pub const TICKS_PER_SECOND : super::UINT = super::TX_TIMER_TICKS_PER_SECOND;
+    pub const STACK_CHECKING : bool = false;
+    pub const ERROR_CHECKING : bool = true;
+    pub const EVENT_TRACE : bool = false;
+    pub const BLOCK_POOL_PERFORMANCE_INFO : bool = false;
+    pub const BYTE_POOL_PERFORMANCE_INFO : bool = false;
+    pub const EVENT_FLAGS_PERFORMANCE_INFO : bool = false;
+    pub const MUTEX_PERFORMANCE_INFO : bool = false;
+    pub const QUEUE_PERFORMANCE_INFO : bool = false;
+    pub const SEMAPHORE_PERFORMANCE_INFO : bool = false;
+    pub const THREAD_PERFORMANCE_INFO : bool = false;
+    pub const TIMER_PERFORMANCE_INFO : bool = false;
+}